@@ -17,20 +17,72 @@ export function activate(context: vsc.ExtensionContext) {
 	const GRAMMAR_PATH = context.asAbsolutePath(path.join("public", "orchid.tmLanguage.json"));
 	const clientOptions: lsp.LanguageClientOptions = {
 		documentSelector: [{ scheme: "file", language: "orchid" }],
+		synchronize: { configurationSection: "orchidls" },
+		initializationOptions: {
+			cacheDir: path.join(context.globalStorageUri.fsPath, "cache"),
+			closeBrackets: vsc.workspace.getConfiguration().get("orchidls.closeBrackets", true),
+			coldLoadMaxKb: vsc.workspace.getConfiguration().get("orchidls.coldLoadMaxKb", 8192),
+			coldLoadTimeoutMs: vsc.workspace.getConfiguration().get("orchidls.coldLoadTimeoutMs", 3000),
+			colorConstructors: vsc.workspace.getConfiguration().get("orchidls.colorConstructors", ["rgb"]),
+			continueComments: vsc.workspace.getConfiguration().get("orchidls.continueComments", true),
+			debounceMs: vsc.workspace.getConfiguration().get("orchidls.debounceMs", 100),
+			jobTimeoutMs: vsc.workspace.getConfiguration().get("orchidls.jobTimeoutMs", 120000),
+			journalSize: vsc.workspace.getConfiguration().get("orchidls.journalSize", 0),
+			latencyBudgetMs: vsc.workspace.getConfiguration().get("orchidls.latencyBudgetMs", 1000),
+			lintSeverity: vsc.workspace.getConfiguration().get("orchidls.lintSeverity", {}),
+			macroGas: vsc.workspace.getConfiguration().get("orchidls.macroGas", 10000),
+			moduleStats: vsc.workspace.getConfiguration().get("orchidls.moduleStats", false),
+			print: vsc.workspace.getConfiguration().get("orchidls.print", {}),
+			slowRequestMs: vsc.workspace.getConfiguration().get("orchidls.slowRequestMs", 500),
+			sortMembers: vsc.workspace.getConfiguration().get("orchidls.sortMembers", {}),
+			telemetry: vsc.env.isTelemetryEnabled
+				&& vsc.workspace.getConfiguration().get("orchidls.telemetry", false),
+			tokenTypes: vsc.workspace.getConfiguration().get("orchidls.tokenTypes", {}),
+			unusedExports: vsc.workspace.getConfiguration().get("orchidls.unusedExports", true),
+		},
 	};
 	const client = new lsp.LanguageClient(
 		"OrchidLS",
 		"Orchid Language Server",
 		() => {
-			const proc = childp.exec(LS_PATH);
+			const logFormat = vsc.workspace.getConfiguration().get("orchidls.logFormat", "text");
+			const logLevel = vsc.workspace.getConfiguration().get("orchidls.logLevel", "info");
+			const proc = childp.execFile(LS_PATH, ["--log-format", logFormat, "--log-level", logLevel]);
 			return Promise.resolve(proc);
 		},
 		clientOptions
 	);
 	client.setTrace(lsp.Trace.Verbose).catch(console.error);
+	/** The version of a custom method the server advertises, or undefined if it doesn't */
+	function orchidFeature(kind: "requests"|"notifications", method: string): number|undefined {
+		return client.initializeResult?.capabilities.experimental?.orchid?.[kind]?.[method];
+	}
+	// Declarations provided by systems have no file, the server generates stubs for them
+	context.subscriptions.push(vsc.workspace.registerTextDocumentContentProvider("orchid-stub", {
+		provideTextDocumentContent: async uri => {
+			if (orchidFeature("requests", "orchid/stub") !== 1) return undefined;
+			const res = await client.sendRequest<{ text: string }>("orchid/stub", { uri: uri.toString() });
+			return res.text;
+		}
+	}));
 	const statusBarItem = vsc.window.createStatusBarItem(vsc.StatusBarAlignment.Left, 1);
 	statusBarItem.show();
 	statusBarItem.command = "orchidls.restart-server";
+	context.subscriptions.push(client.onTelemetry(event => console.log("Telemetry event", event)));
+	// Project loads in flight by id, and the error count of the last load of each project
+	const analyses = new Map<number, string>();
+	const projectErrors = new Map<string, number>();
+	context.subscriptions.push(client.onNotification("orchid/analysisStarted", data => {
+		if (orchidFeature("notifications", "orchid/analysisStarted") !== 1) return;
+		analyses.set(data.id, data.project);
+		handleState();
+	}));
+	context.subscriptions.push(client.onNotification("orchid/analysisFinished", data => {
+		if (orchidFeature("notifications", "orchid/analysisFinished") !== 1) return;
+		analyses.delete(data.id);
+		if (data.outcome !== "cancelled") projectErrors.set(data.project, data.errors);
+		handleState();
+	}));
 	client.start().catch(console.error);
 	context.subscriptions.push(client);
 	context.subscriptions.push(client.onDidChangeState(handleState));
@@ -46,6 +98,7 @@ export function activate(context: vsc.ExtensionContext) {
 		["keyword", decor(new vsc.ThemeColor("syntax.keyword.orchid"))],
 	])
 	context.subscriptions.push(client.onNotification("client/syntacticTokens", data => {
+		if (orchidFeature("notifications", "client/syntacticTokens") !== 1) return;
 		console.log("Received syntactic tokens:", data);
 		const groups: [string, vsc.Range[]][] = data.legend.map((name: string) => [name, []]);
 		console.log(data.tokens.length, "tokens received");
@@ -72,6 +125,16 @@ export function activate(context: vsc.ExtensionContext) {
 			}
 		}
 	}));
+	// Lets the server push the results of an edit for the visible lines first
+	context.subscriptions.push(vsc.window.onDidChangeTextEditorVisibleRanges(event => {
+		if (event.textEditor.document.languageId !== "orchid") return;
+		if (client.state !== lsp.State.Running) return;
+		if (orchidFeature("notifications", "orchid/didChangeVisibleRanges") !== 1) return;
+		client.sendNotification("orchid/didChangeVisibleRanges", {
+			textDocument: { uri: event.textEditor.document.uri.toString() },
+			ranges: event.visibleRanges.map(r => client.code2ProtocolConverter.asRange(r)),
+		}).catch(console.error);
+	}));
 	async function awaitClientNotStarting(): Promise<lsp.State> {
 		while(true) {
 			if (client.state !== lsp.State.Starting) return client.state;
@@ -92,12 +155,26 @@ export function activate(context: vsc.ExtensionContext) {
 		if (vsc.workspace.getConfiguration().get("orchidls.output.preopen", false)) {
 
 		}
+		if (client.state != lsp.State.Running) {
+			analyses.clear();
+			projectErrors.clear();
+		}
+		const errors = [...projectErrors.values()].reduce((a, b) => a + b, 0);
 		if (client.state == lsp.State.Stopped) {
 			statusBarItem.text = "$(error) OrchidLS stopped"
+			statusBarItem.tooltip = undefined;
 		} else if (client.state == lsp.State.Starting) {
 			statusBarItem.text = `$(loading~spin) OrchidLS`;
+			statusBarItem.tooltip = undefined;
+		} else if (0 < analyses.size) {
+			statusBarItem.text = `$(loading~spin) OrchidLS`;
+			statusBarItem.tooltip = `Analyzing ${[...new Set(analyses.values())].join(", ")}`;
+		} else if (0 < errors) {
+			statusBarItem.text = `$(warning) OrchidLS`;
+			statusBarItem.tooltip = `${errors} errors`;
 		} else {
 			statusBarItem.text = "$(check) OrchidLS";
+			statusBarItem.tooltip = undefined;
 		}
 	}
 	function updateTrace() {
@@ -136,6 +213,84 @@ export function activate(context: vsc.ExtensionContext) {
 		client.outputChannel.show();
 		// channel.show();
 	}));
+	context.subscriptions.push(vsc.commands.registerCommand('orchidls.rename-keyword', async () => {
+		const editor = vsc.window.activeTextEditor;
+		if (!editor) return;
+		if (orchidFeature("requests", "orchid/renameKeyword") !== 1) {
+			void vsc.window.showErrorMessage("This version of the Orchid language server can't rename keywords");
+			return;
+		}
+		const wordRange = editor.document.getWordRangeAtPosition(editor.selection.active, /[^\s()[\]{}"]+/);
+		const keyword = await vsc.window.showInputBox({
+			prompt: "Macro keyword or operator to rename",
+			value: wordRange && editor.document.getText(wordRange),
+		});
+		if (!keyword) return;
+		const newName = await vsc.window.showInputBox({ prompt: `Rename ${keyword} to`, value: keyword });
+		if (!newName || newName === keyword) return;
+		const res = await client.sendRequest<{ edit: lsp.WorkspaceEdit, conflicts: lsp.Location[] }>(
+			"orchid/renameKeyword",
+			{ textDocument: { uri: editor.document.uri.toString() }, keyword, newName }
+		).catch((e: Error) => void vsc.window.showErrorMessage(`Could not rename ${keyword}: ${e.message}`));
+		if (!res) return;
+		if (0 < res.conflicts.length) {
+			const choice = await vsc.window.showWarningMessage(
+				`${newName} already occurs ${res.conflicts.length} times in the project`,
+				{ modal: true },
+				"Rename anyway"
+			);
+			if (choice !== "Rename anyway") return;
+		}
+		await vsc.workspace.applyEdit(await client.protocol2CodeConverter.asWorkspaceEdit(res.edit));
+	}));
+	context.subscriptions.push(vsc.commands.registerCommand('orchidls.show-hotspots', async () => {
+		if (orchidFeature("requests", "orchid/hotspots") !== 1) {
+			void vsc.window.showErrorMessage("This version of the Orchid language server can't measure macro gas");
+			return;
+		}
+		const uri = vsc.window.activeTextEditor?.document.uri.toString();
+		const hotspots = await client.sendRequest<{
+			name: string, uri: string, range: lsp.Range, steps: number, exhausted: boolean, limit: number,
+			rules: lsp.Location[],
+		}[]>(
+			"orchid/hotspots",
+			{ textDocument: uri && { uri }, sortBy: "steps" }
+		).catch((e: Error) => void vsc.window.showErrorMessage(`Could not measure macro gas: ${e.message}`));
+		if (!hotspots) return;
+		const picked = await vsc.window.showQuickPick(hotspots.map(h => ({
+			label: h.name,
+			description: h.exhausted ? `gave up after ${h.steps} steps` : `${h.steps} steps`,
+			detail: `${h.rules.length} rules can fire on it`,
+			hotspot: h,
+		})), { placeHolder: "Constants by the macro steps their expansion takes" });
+		if (!picked) return;
+		const range = client.protocol2CodeConverter.asRange(picked.hotspot.range);
+		await vsc.window.showTextDocument(vsc.Uri.parse(picked.hotspot.uri), { selection: range });
+	}));
+	context.subscriptions.push(vsc.commands.registerCommand('orchidls.lint-workspace', async () => {
+		const summary = await client.sendRequest<{
+			failedProjects: number, errors: number, warnings: number, information: number, hints: number,
+			byRule: Record<string, number>, worstFiles: { uri: string, errors: number, findings: number }[],
+		} | undefined>(
+			"workspace/executeCommand",
+			{ command: "orchid.lintWorkspace", arguments: [] }
+		).catch((e: Error) => void vsc.window.showErrorMessage(`Could not lint the workspace: ${e.message}`));
+		if (!summary) return;
+		const rules = Object.entries(summary.byRule).sort(([, a], [, b]) => b - a);
+		const picked = await vsc.window.showQuickPick([
+			...summary.worstFiles.map(f => ({
+				label: vsc.workspace.asRelativePath(vsc.Uri.parse(f.uri)),
+				description: `${f.errors} errors, ${f.findings} findings`,
+				uri: f.uri,
+			})),
+			...rules.map(([rule, count]) => ({ label: rule, description: `${count} findings`, uri: undefined })),
+		], {
+			placeHolder: `${summary.errors} errors, ${summary.warnings} warnings`
+				+ (summary.failedProjects ? `, ${summary.failedProjects} projects failed to load` : ""),
+		});
+		if (picked?.uri) await vsc.window.showTextDocument(vsc.Uri.parse(picked.uri));
+		else if (picked) await vsc.commands.executeCommand("workbench.actions.view.problems");
+	}));
 }
 
 // This method is called when your extension is deactivated
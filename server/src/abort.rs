@@ -0,0 +1,24 @@
+//! A flag that tells a task to stop, shared between the task and whoever may
+//! stop it
+
+use std::sync::{atomic, Arc};
+
+/// Cancels a task. Requests, project loads and edits requested from the client
+/// each carry one.
+#[derive(Clone, Default)]
+pub struct Abort(Arc<atomic::AtomicBool>);
+impl Abort {
+  pub fn new() -> Self { Self(Arc::new(atomic::AtomicBool::new(false))) }
+  pub fn abort(&self) { self.0.store(true, atomic::Ordering::Release) }
+  /// Check if the abort flag has been set with relaxed memory ordering. The
+  /// relaxed order avoids blocking optimizations, so this can be called
+  /// frequently in the processing pipeline to ensure timely abort detection.
+  pub fn aborted(&self) -> bool { self.0.load(atomic::Ordering::Relaxed) }
+  /// Check if the abort flag has been set with strict memory ordering. This
+  /// prevents many optimizations across the load so it should be used
+  /// sparingly, but it enables the abort to double as an expiry marker. If the
+  /// task locks a shared work tracker then calls this function, it can be
+  /// certain that no other task has fulfilled its task, provided that doing so
+  /// would involve aborting this task.
+  pub fn is_valid(&self) -> bool { !self.0.load(atomic::Ordering::Acquire) }
+}
@@ -0,0 +1,149 @@
+//! The format of files in the on-disk cache. Every file starts with a header
+//! holding a magic number, the version of the format and of the server that
+//! wrote it, and the length and checksum of the payload. Files that don't match
+//! are deleted when the cache is pruned, so an upgraded server or a torn write
+//! never yields stale or garbled data, only a cache miss.
+
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"ORCLSCAC";
+/// Bumped whenever the header changes
+const FORMAT_VERSION: u32 = 1;
+/// Payloads are written with the types of this version, which are not
+/// guaranteed to be readable by any other
+const SERVER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Why a cache file was rejected
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Invalid {
+  /// Not a cache file, or one from before the header was introduced
+  Magic,
+  /// Written by a different format or server version
+  Version,
+  /// Shorter than the header says, for example after a torn write
+  Truncated,
+  /// The payload doesn't match the checksum
+  Checksum,
+}
+
+/// 64-bit FNV-1a, which unlike the standard hasher is guaranteed to be the same
+/// in every build
+fn checksum(data: &[u8]) -> u64 {
+  let mut hash = 0xcbf29ce484222325u64;
+  for byte in data {
+    hash ^= u64::from(*byte);
+    hash = hash.wrapping_mul(0x100000001b3);
+  }
+  hash
+}
+
+/// Check the header and return the payload
+pub fn decode(data: &[u8]) -> Result<&[u8], Invalid> {
+  fn take<'a>(data: &mut &'a [u8], n: usize) -> Result<&'a [u8], Invalid> {
+    if data.len() < n {
+      return Err(Invalid::Truncated);
+    }
+    let (head, tail) = data.split_at(n);
+    *data = tail;
+    Ok(head)
+  }
+  let mut rest = data;
+  if take(&mut rest, MAGIC.len()).map_err(|_| Invalid::Magic)? != MAGIC {
+    return Err(Invalid::Magic);
+  }
+  let format = u32::from_le_bytes(take(&mut rest, 4)?.try_into().unwrap());
+  let server_len = take(&mut rest, 1)?[0] as usize;
+  let server = take(&mut rest, server_len)?;
+  if format != FORMAT_VERSION || server != SERVER_VERSION.as_bytes() {
+    return Err(Invalid::Version);
+  }
+  let len = u64::from_le_bytes(take(&mut rest, 8)?.try_into().unwrap());
+  let sum = u64::from_le_bytes(take(&mut rest, 8)?.try_into().unwrap());
+  let payload = take(&mut rest, usize::try_from(len).map_err(|_| Invalid::Truncated)?)?;
+  if checksum(payload) != sum {
+    return Err(Invalid::Checksum);
+  }
+  Ok(payload)
+}
+
+/// Delete every invalid file in the cache directory. Returns the number of
+/// files deleted.
+pub fn prune(dir: &Path) -> io::Result<usize> {
+  let mut removed = 0;
+  for entry in read_dir(dir)? {
+    let path = entry?.path();
+    if path.is_file() && fs::read(&path).map_or(true, |data| decode(&data).is_err()) {
+      fs::remove_file(&path)?;
+      removed += 1;
+    }
+  }
+  Ok(removed)
+}
+
+/// Delete every file in the cache directory. Returns the number of files
+/// deleted.
+pub fn clear(dir: &Path) -> io::Result<usize> {
+  let mut removed = 0;
+  for entry in read_dir(dir)? {
+    let path = entry?.path();
+    if path.is_file() {
+      fs::remove_file(&path)?;
+      removed += 1;
+    }
+  }
+  Ok(removed)
+}
+
+/// The entries of the directory, none if it doesn't exist yet
+fn read_dir(dir: &Path) -> io::Result<Vec<io::Result<fs::DirEntry>>> {
+  match fs::read_dir(dir) {
+    Ok(entries) => Ok(entries.collect()),
+    Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+    Err(e) => Err(e),
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{checksum, decode, Invalid, FORMAT_VERSION, MAGIC, SERVER_VERSION};
+
+  fn encode_as(server: &str, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(MAGIC.len() + 21 + server.len() + payload.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    out.push(server.len() as u8);
+    out.extend_from_slice(server.as_bytes());
+    out.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    out.extend_from_slice(&checksum(payload).to_le_bytes());
+    out.extend_from_slice(payload);
+    out
+  }
+
+  /// Prefix the payload with the header
+  fn encode(payload: &[u8]) -> Vec<u8> { encode_as(SERVER_VERSION, payload) }
+
+  #[test]
+  fn round_trip() {
+    assert_eq!(decode(&encode(b"tokens")), Ok(&b"tokens"[..]));
+    assert_eq!(decode(&encode(b"")), Ok(&b""[..]));
+  }
+
+  #[test]
+  fn version() {
+    assert_eq!(decode(&encode_as("0.0.0-old", b"tokens")), Err(Invalid::Version));
+  }
+
+  #[test]
+  fn corruption() {
+    let data = encode(b"tokens");
+    assert_eq!(decode(&data[..data.len() - 1]), Err(Invalid::Truncated));
+    assert_eq!(decode(&data[..MAGIC.len() + 2]), Err(Invalid::Truncated));
+    assert_eq!(decode(&data[..3]), Err(Invalid::Magic));
+    assert_eq!(decode(b"not a cache file"), Err(Invalid::Magic));
+    let mut flipped = data.clone();
+    *flipped.last_mut().unwrap() ^= 1;
+    assert_eq!(decode(&flipped), Err(Invalid::Checksum));
+  }
+}
@@ -0,0 +1,41 @@
+//! Printing what the server advertises in `initialize` without an editor. Run
+//! with `orchid-ls --capabilities [client-capabilities.json [options.json]]`;
+//! the first file holds the `capabilities` a hypothetical client would send
+//! and the second its `initializationOptions`. An empty object is assumed for
+//! a missing file. Nothing is started, so the dump has no side effects.
+
+use std::fs;
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::cmd::fs::token_legend;
+use crate::cmd::init::init_result;
+use crate::protocol::client::ClientProfile;
+
+fn read_json(path: Option<&Path>) -> Result<Value, String> {
+  let Some(path) = path else { return Ok(json!({})) };
+  let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+  serde_json::from_str(&text).map_err(|e| e.to_string())
+}
+
+/// Print the result of `initialize` for the client capabilities and the
+/// initialization options in the files. Returns the exit code.
+pub fn run(caps: Option<&Path>, opts: Option<&Path>) -> i32 {
+  let mut inputs = Vec::new();
+  for (path, what) in [(caps, "client capabilities"), (opts, "initialization options")] {
+    match read_json(path) {
+      Ok(value) => inputs.push(value),
+      Err(e) => {
+        let path = path.map_or(String::new(), |p| p.display().to_string());
+        eprintln!("Failed to read {what} from {path}: {e}");
+        return 2;
+      },
+    }
+  }
+  let profile = ClientProfile::new(&inputs[0]);
+  let legend = token_legend(profile.token_types.as_deref(), &inputs[1]);
+  let result = init_result(&profile, &legend);
+  println!("{}", serde_json::to_string_pretty(&result).expect("Values serialize"));
+  0
+}
@@ -0,0 +1,159 @@
+//! Sequences of requests to the client. Handlers that need several round trips
+//! describe them as a [Chain] of steps and run it once, instead of nesting
+//! response callbacks. Errors and aborts skip the remaining steps and reach
+//! the callback passed to [Chain::run] exactly once.
+
+use crate::abort::Abort;
+use crate::jrpc::{ResponseError, Session};
+use crate::protocol::error::LSPErrCode;
+use crate::protocol::messages::Request;
+
+type Cont<T> = Box<dyn FnOnce(Result<T, ResponseError>) + Send>;
+
+/// The error a chain finishes with if it was aborted
+pub fn cancelled() -> ResponseError {
+  let message = "Aborted by the server".to_string();
+  ResponseError { code: LSPErrCode::RequestCancelled, message, data: None }
+}
+
+/// A description of requests to send to the client and what to do with the
+/// responses. Nothing is sent until the chain is run.
+pub struct Chain<T>(Box<dyn FnOnce(Session, Abort, Cont<T>) + Send>);
+impl<T: Send + 'static> Chain<T> {
+  fn new(start: impl FnOnce(Session, Abort, Cont<T>) + Send + 'static) -> Self {
+    Self(Box::new(start))
+  }
+
+  /// Send a request listed in [crate::protocol::messages]
+  pub fn call<R: Request<Result = T>>(params: R::Params) -> Self
+  where R::Params: Send + 'static {
+    Self::new(move |session, abort, k| {
+      if abort.aborted() {
+        return k(Err(cancelled()));
+      }
+      let mut k = Some(k);
+      session.call::<R>(params, move |res| {
+        let k = k.take().expect("Each request is answered once");
+        k(if abort.aborted() { Err(cancelled()) } else { res })
+      })
+    })
+  }
+
+  /// Start the chain. The callback is called once with the result of the last
+  /// step, the first error, or [cancelled] if the abort was set between steps
+  /// or while a response was awaited. Responses that arrive after the abort
+  /// are discarded.
+  pub fn run(
+    self,
+    session: &Session,
+    abort: &Abort,
+    callback: impl FnOnce(Result<T, ResponseError>) + Send + 'static,
+  ) {
+    (self.0)(session.clone(), abort.clone(), Box::new(callback))
+  }
+
+  /// Continue with another chain built from the result
+  pub fn then<U: Send + 'static>(
+    self,
+    next: impl FnOnce(T) -> Chain<U> + Send + 'static,
+  ) -> Chain<U> {
+    Chain::new(move |session, abort, k| {
+      let (session2, abort2) = (session.clone(), abort.clone());
+      (self.0)(session, abort, Box::new(move |res| match res {
+        Err(e) => k(Err(e)),
+        Ok(_) if abort2.aborted() => k(Err(cancelled())),
+        Ok(val) => (next(val).0)(session2, abort2, k),
+      }))
+    })
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::sync::{mpsc, Arc, Mutex};
+  use std::time::Duration;
+
+  use serde_json::{json, Value};
+
+  use super::Chain;
+  use crate::abort::Abort;
+  use crate::jrpc::{JrpcServer, ResponseError, Session};
+  use crate::protocol::error::LSPErrCode;
+  use crate::protocol::messages::Request;
+
+  type Sent = Arc<Mutex<Vec<Value>>>;
+
+  enum First {}
+  impl Request for First {
+    const METHOD: &'static str = "first";
+    type Params = Value;
+    type Result = Value;
+  }
+  enum Second {}
+  impl Request for Second {
+    const METHOD: &'static str = "second";
+    type Params = Value;
+    type Result = Value;
+  }
+
+  /// A server that runs the chain when notified and sends the result to the
+  /// channel
+  fn run_chain(
+    chain: Chain<Value>,
+    abort: Abort,
+  ) -> (JrpcServer, Sent, mpsc::Receiver<Result<Value, ResponseError>>) {
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let sent2 = sent.clone();
+    let mut srv = JrpcServer::new(move |m| sent2.lock().unwrap().push(m));
+    let (res_tx, res) = mpsc::channel();
+    let mut chain = Some(chain);
+    srv.on_notif("start", move |_, session: Session| {
+      let res_tx = res_tx.clone();
+      chain.take().unwrap().run(&session, &abort, move |r| res_tx.send(r).unwrap())
+    });
+    srv.recv(json!({ "method": "start" }));
+    (srv, sent, res)
+  }
+
+  fn respond(srv: &mut JrpcServer, sent: &Sent, idx: usize, result: Value) {
+    let id = sent.lock().unwrap()[idx]["id"].clone();
+    srv.recv(json!({ "id": id, "result": result }));
+  }
+
+  fn wait<T>(res: &mpsc::Receiver<T>) -> T { res.recv_timeout(Duration::from_secs(5)).unwrap() }
+
+  #[test]
+  fn sequence() {
+    let chain = Chain::call::<First>(Value::Null).then(|v| Chain::call::<Second>(json!([v])));
+    let (mut srv, sent, res) = run_chain(chain, Abort::new());
+    assert_eq!(sent.lock().unwrap().len(), 1, "Steps start after the previous one finished");
+    respond(&mut srv, &sent, 0, json!(1));
+    while sent.lock().unwrap().len() < 2 {
+      std::thread::yield_now()
+    }
+    assert_eq!(sent.lock().unwrap()[1]["params"], json!([1]));
+    respond(&mut srv, &sent, 1, json!(2));
+    assert_eq!(wait(&res).unwrap(), json!(2));
+  }
+
+  #[test]
+  fn abort() {
+    let abort = Abort::new();
+    let chain = Chain::call::<First>(Value::Null);
+    let chain = chain.then(|_| Chain::call::<Second>(Value::Null));
+    let (mut srv, sent, res) = run_chain(chain, abort.clone());
+    abort.abort();
+    respond(&mut srv, &sent, 0, Value::Null);
+    assert_eq!(wait(&res).unwrap_err().code, LSPErrCode::RequestCancelled);
+    assert_eq!(sent.lock().unwrap().len(), 1, "Aborted chains send nothing more");
+  }
+
+  #[test]
+  fn error() {
+    let chain = Chain::call::<First>(Value::Null).then(|_| -> Chain<Value> { unreachable!() });
+    let (mut srv, sent, res) = run_chain(chain, Abort::new());
+    let id = sent.lock().unwrap()[0]["id"].clone();
+    srv.recv(json!({ "id": id, "error": { "code": -32603, "message": "Nope" } }));
+    assert_eq!(wait(&res).unwrap_err().message, "Nope");
+  }
+}
@@ -0,0 +1,101 @@
+//! Checking the projects under a directory without an editor, for CI and
+//! pre-commit hooks. Run with `orchid-ls check <path> [--json]`. Projects are
+//! loaded, linted and configured exactly as in the editor, so the findings are
+//! the diagnostics the editor would show.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use itertools::Itertools;
+use orchidlang::name::VPath;
+use serde_json::json;
+
+use crate::abort::Abort;
+use crate::cmd::config::{read_project_file, ProjectConfig};
+use crate::cmd::diagnostics;
+use crate::orc::project::{find_all_projects, LoadedProject, ProjectFiles, ProjectRoot};
+use crate::patches::PatchStore;
+use crate::protocol::document::FileUri;
+use crate::protocol::messages::PublishDiagnosticsParams;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+  /// `file:line:column: severity[code]: message`, one per line
+  Human,
+  /// A single JSON object with the diagnostics by file
+  Json,
+}
+
+fn severity_name(severity: u8) -> &'static str {
+  match severity {
+    1 => "error",
+    2 => "warning",
+    3 => "info",
+    _ => "hint",
+  }
+}
+
+/// The diagnostics of one project. Problems with the project file are printed
+/// as warnings. `roots` are all the projects being checked.
+fn check_project(
+  store: &Arc<PatchStore>,
+  root: ProjectRoot,
+  roots: &[ProjectRoot],
+) -> Vec<PublishDiagnosticsParams> {
+  let files = ProjectFiles { patches: store.clone(), root: root.clone() };
+  let dir = files.config_dir();
+  let (opts, problems) = read_project_file(&dir).unwrap_or_default();
+  for problem in problems {
+    eprintln!("warning: ignoring part of {}: {problem}", dir.display());
+  }
+  let config = ProjectConfig::default().merged(&opts);
+  match LoadedProject::new(store.clone(), root, config.macro_gas, Abort::new(), &mut |_, _| ()) {
+    Ok(lpr) => diagnostics::compute(&lpr, &config, &[], roots),
+    Err(errors) => diagnostics::load_errors(&files, &errors, &[]),
+  }
+}
+
+/// Check every project under the path and print the findings. Returns the
+/// exit code, which is nonzero if there were errors.
+pub fn run(path: &Path, format: Format) -> i32 {
+  let dir = if path.is_file() { path.parent().unwrap_or(path) } else { path };
+  let Some(root) = dir.canonicalize().ok().and_then(|dir| FileUri::from_path(&dir)) else {
+    eprintln!("Cannot check {}: not a directory", path.display());
+    return 2;
+  };
+  let store = PatchStore::new(root.clone());
+  let vfs = store.clone().mk_vfs(&root).expect("The root is in the store");
+  let mut diagnostics = Vec::new();
+  let roots = find_all_projects(VPath::new([]), &vfs);
+  for project in roots.iter().cloned() {
+    let found = check_project(&store, project, &roots);
+    diagnostics.extend(found.into_iter().filter(|p| !p.diagnostics.is_empty()));
+  }
+  diagnostics.sort_by(|a, b| a.uri.cmp(&b.uri));
+  let all = diagnostics.iter().flat_map(|p| &p.diagnostics);
+  let errors = all.filter(|d| d.severity == 1).count();
+  match format {
+    Format::Json => {
+      let output = json!({ "files": diagnostics });
+      println!("{}", serde_json::to_string_pretty(&output).expect("Values serialize"))
+    },
+    Format::Human => {
+      let base = root.to_path();
+      for params in diagnostics.iter() {
+        let file = FileUri::parse(&params.uri).map_or(params.uri.clone(), |uri| {
+          let path = uri.to_path();
+          path.strip_prefix(&base).unwrap_or(&path).display().to_string()
+        });
+        let sorted = (params.diagnostics.iter()).sorted_by_key(|d| d.range.start);
+        for diag in sorted {
+          let (line, char) = (diag.range.start.line + 1, diag.range.start.char + 1);
+          let severity = severity_name(diag.severity);
+          println!("{file}:{line}:{char}: {severity}[{}]: {}", diag.code, diag.message);
+        }
+      }
+      let count = diagnostics.iter().map(|p| p.diagnostics.len()).sum::<usize>();
+      eprintln!("{count} findings, {errors} errors");
+    },
+  }
+  i32::from(0 < errors)
+}
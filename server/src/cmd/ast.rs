@@ -0,0 +1,62 @@
+//! `orchid/selectionAst`, the smallest node of the parsed tree that encloses a
+//! range and the nodes above it. Structural editing plugins, such as slurping
+//! and barfing in the style of paredit, build on this instead of parsing the
+//! text themselves.
+//!
+//! The tree is the one of the last load, so the response carries the version
+//! of the document it describes. If the document changed since, the request
+//! fails with `ContentModified` rather than describe text that's gone.
+
+use anyhow::{anyhow, Context};
+use itertools::Itertools;
+use orchidlang::parse::parsed;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::fs::{snapshot, Snapshot};
+use crate::jrpc::JrpcServer;
+use crate::orc::ast::{children, enclosing, kind};
+use crate::protocol::document::{DocRange, FileUri};
+use crate::protocol::error::LSPErrCode;
+use crate::protocol::line_index::LineIndex;
+
+fn node(ex: &parsed::Expr, lines: &LineIndex) -> Value {
+  let children = children(ex).into_iter().map(|c| lines.doc_range(c.range.range()));
+  let mut node = json!({
+    "kind": kind(ex),
+    "range": lines.doc_range(ex.range.range()),
+    "children": children.collect_vec(),
+  });
+  if let parsed::Clause::S(bracket, _) = &ex.value {
+    node["bracket"] = json!(bracket);
+  }
+  node
+}
+
+pub fn attach(srv: &mut JrpcServer) {
+  srv.on_req_sync("orchid/selectionAst", |params, session| {
+    let params = params.unwrap_or(&Value::Null);
+    let uri = FileUri::deserialize(&params["textDocument"]["uri"])
+      .context(LSPErrCode::InvalidParams)?;
+    let range = DocRange::deserialize(&params["range"]).context(LSPErrCode::InvalidParams)?;
+    let snap = snapshot(&session, &uri);
+    let Some(Snapshot { store, path: Some(path), loaded: Some(lpr) }) = snap else {
+      return Ok(Value::Null);
+    };
+    let current = store.text(&uri);
+    if current.is_some_and(|text| lpr.source(&path).is_some_and(|loaded| *loaded != text)) {
+      let message = "The document changed since the project was loaded";
+      return Err(anyhow!(LSPErrCode::ContentModified).context(message));
+    }
+    let Some(lines) = lpr.line_index(&path) else { return Ok(Value::Null) };
+    let start = lines.docpos2bpos(range.start).context(LSPErrCode::InvalidParams)?;
+    let end = lines.docpos2bpos(range.end).context(LSPErrCode::InvalidParams)?;
+    let chain = enclosing(&lpr, &path, start..end.max(start));
+    let Some((innermost, parents)) = chain.split_first() else { return Ok(Value::Null) };
+    Ok(json!({
+      "version": lpr.files.versioned(&path).version,
+      "node": node(innermost, &lines),
+      "parents": parents.iter().map(|ex| node(ex, &lines)).collect_vec(),
+    }))
+  });
+}
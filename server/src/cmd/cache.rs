@@ -0,0 +1,46 @@
+//! The location of the on-disk cache and the `orchid.clearCache` command. The
+//! cache is kept in the directory named by the `cacheDir` initialization
+//! option, nothing is cached on disk without it. Files left by other versions
+//! of the server are pruned in the background on startup.
+
+use std::path::PathBuf;
+use std::thread;
+
+use anyhow::Context;
+use serde_json::{json, Value};
+
+use crate::cache;
+use crate::jrpc::Session;
+use crate::log;
+use crate::protocol::messages::{MessageType, ShowMessage, ShowMessageParams};
+
+pub const CLEAR_COMMAND: &str = "orchid.clearCache";
+
+/// `cacheDir` initialization option
+#[derive(Clone, Debug, Default)]
+pub struct CacheDir(pub Option<PathBuf>);
+
+/// Read the cache directory from the initialization options and prune it
+pub fn init_cache(session: &Session, opts: &Value) {
+  let dir = opts["cacheDir"].as_str().filter(|s| !s.is_empty()).map(PathBuf::from);
+  session.set(CacheDir(dir.clone()));
+  let Some(dir) = dir else { return };
+  thread::Builder::new()
+    .name("cache-prune".into())
+    .spawn(move || match cache::prune(&dir) {
+      Ok(0) => (),
+      Ok(n) => log!(Info, "Removed {n} outdated or corrupt files from {}", dir.display()),
+      Err(e) => log!(Error, "Failed to prune the cache in {}: {e}", dir.display()),
+    })
+    .expect("Failed to spawn cache pruning thread");
+}
+
+/// Delete every file in the cache
+pub fn clear_command(session: &Session) -> anyhow::Result<Value> {
+  let dir = session.lock().get::<CacheDir>().and_then(|d| d.0.clone());
+  let dir = dir.context("No cache directory, set cacheDir to keep a cache")?;
+  let removed = cache::clear(&dir).context("Failed to clear the cache")?;
+  let message = format!("Removed {removed} files from the Orchid language server cache");
+  session.send::<ShowMessage>(ShowMessageParams { typ: MessageType::Info, message });
+  Ok(json!({ "removed": removed }))
+}
@@ -0,0 +1,308 @@
+//! `textDocument/codeAction` with lambda refactors, export toggling, constant
+//! extraction, member sorting, formatting, imports for pasted code, comments
+//! that suppress lints, and fixes for macro priorities, references to private
+//! constants, imports of moved files and files outside of any project folder.
+//! Generated files are never edited, since the build step that writes them
+//! would undo the edits.
+
+use anyhow::Context;
+use itertools::Itertools;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::config::project_config;
+use super::diagnostics::LOAD_FAILED;
+use super::fs::{workspace, DISCOVER_COMMAND};
+use super::viewport;
+use crate::documents::DocumentStore;
+use crate::fmt;
+use crate::jrpc::{JrpcServer, Session};
+use crate::log;
+use crate::orc::imports::missing_imports;
+use crate::orc::lambda::rewrites;
+use crate::orc::lint::{stale_imports, PrivateUse, LOOSE_FILE};
+use crate::orc::members::{sort_members, toggle_export, MemberOrder};
+use crate::orc::printer::extract_const;
+use crate::orc::project::{LoadedProject, ProjectRoot};
+use crate::orc::project_info::{joining_folders, FILE_NAME, TEMPLATE};
+use crate::orc::rules::{arrow_text, conflicts, nudged_prio, source_rules};
+use crate::orc::suppress::{suppress_file, suppress_line};
+use crate::protocol::client::ClientProfile;
+use crate::protocol::docpos::DocPos;
+use crate::protocol::document::{
+  DocRange, FileUri, ResourceOp, TextEdit, VersionedDocumentRef, WorkspaceEdit,
+};
+use crate::protocol::error::LSPErrCode;
+use crate::protocol::messages::{
+  ApplyEdit, ApplyEditParams, ApplyEditResult, MessageType, ShowMessage, ShowMessageParams,
+};
+
+/// A code action and its edits by file URI
+struct Action {
+  title: String,
+  kind: &'static str,
+  edits: Vec<(String, DocRange, String)>,
+}
+impl Action {
+  fn matches(&self, only: &Option<Vec<String>>) -> bool { requested(self.kind, only) }
+  fn to_json(&self) -> Value {
+    let edit = (self.edits.iter()).fold(WorkspaceEdit::default(), |edit, (uri, range, text)| {
+      edit.change(uri, TextEdit::new(*range, text))
+    });
+    json!({ "title": self.title, "kind": self.kind, "edit": edit })
+  }
+}
+
+/// Whether the client asked for a kind of action. A requested kind includes
+/// its subkinds.
+fn requested(kind: &str, only: &Option<Vec<String>>) -> bool {
+  let Some(only) = only else { return true };
+  only.iter().any(|k| kind == k || kind.starts_with(&format!("{k}.")))
+}
+
+/// Create a project file in a folder and find the projects again, which makes
+/// the single file projects in the folder one project
+fn create_project(folder: &str, uri: String, files: usize, diagnostic: &Value) -> Value {
+  let start = DocPos::new(0, 0);
+  let edit = (WorkspaceEdit::default())
+    .resource(ResourceOp::Create { uri: uri.clone() })
+    .edit(
+      VersionedDocumentRef { uri: uri.clone(), version: None },
+      TextEdit::new(DocRange { start, end: start }, TEMPLATE),
+    );
+  json!({
+    "title": format!("Create {FILE_NAME} in {folder} to load its {files} files as one project"),
+    "kind": "quickfix",
+    "diagnostics": [diagnostic],
+    "edit": edit,
+    "command": { "title": "Find projects", "command": DISCOVER_COMMAND, "arguments": [uri] },
+  })
+}
+
+/// Read the `sortMembers` section of a configuration object, defaulting
+/// missing flags to the base
+pub fn member_order(opts: &Value, default: MemberOrder) -> MemberOrder {
+  let opts = &opts["sortMembers"];
+  let flag = |name: &str, default: bool| opts[name].as_bool().unwrap_or(default);
+  MemberOrder {
+    group_imports: flag("groupImports", default.group_imports),
+    exports_first: flag("exportsFirst", default.exports_first),
+    alphabetical: flag("alphabetical", default.alphabetical),
+  }
+}
+
+/// Ask the client to apply edits computed from a version of a document, as
+/// actions that run as commands do. The edits carry the version, so the client
+/// rejects them if the document changed in the meantime, and the answer is
+/// ignored then since it no longer describes the document.
+pub fn apply_edit(
+  session: &Session,
+  uri: &FileUri,
+  version: u64,
+  label: String,
+  edits: Vec<TextEdit>,
+) {
+  let superseded = session.lock().get::<DocumentStore>().and_then(|docs| docs.superseded(uri));
+  let Some(superseded) = superseded else {
+    return log!(Warn, "Not applying {label} to {}, which isn't open", uri.stringify(true));
+  };
+  let doc = VersionedDocumentRef { uri: uri.stringify(true), version: Some(version) };
+  let edit = (edits.into_iter())
+    .fold(WorkspaceEdit::default(), |acc, edit| acc.edit(doc.clone(), edit));
+  let params = ApplyEditParams { label: Some(label.clone()), edit };
+  let session2 = session.clone();
+  session.call_while::<ApplyEdit>(superseded, params, move |res| match res {
+    Ok(ApplyEditResult { applied: true, .. }) => (),
+    Ok(ApplyEditResult { failure_reason, .. }) => {
+      let reason = failure_reason.map_or(String::new(), |r| format!(": {r}"));
+      let message = format!("Failed to apply {label}{reason}");
+      session2.send::<ShowMessage>(ShowMessageParams { typ: MessageType::Warning, message })
+    },
+    Err(e) => log!(Error, "Failed to apply {label}: {e:?}"),
+  })
+}
+
+/// Format an open document as `orchid-ls fmt` would. Runs as a command so that
+/// the latest version of the document is formatted.
+pub const FORMAT_COMMAND: &str = "orchid.formatDocument";
+
+pub fn format_command(session: &Session, args: &Value) -> anyhow::Result<Value> {
+  let uri = FileUri::deserialize(&args[0]).context(LSPErrCode::InvalidParams)?;
+  let g = session.lock();
+  let Some((path, wsp, proj)) = workspace(&g)?.get_proj(&uri) else { return Ok(Value::Null) };
+  let dir = proj.files(wsp.store.clone()).config_dir();
+  drop(g);
+  let config = project_config(session, &dir);
+  if config.is_generated(&path) {
+    return Ok(Value::Null);
+  }
+  let snap = session.lock().get_mut::<DocumentStore>().map(|docs| docs.snapshot(&uri));
+  let snap = snap.context(LSPErrCode::ServerNotInitialized)?.context(LSPErrCode::RequestFailed)?;
+  let formatted = fmt::format(&snap.text, &config);
+  if formatted != *snap.text {
+    let edit = TextEdit::new(snap.lines.doc_range(0..snap.text.len()), formatted);
+    apply_edit(session, &uri, snap.version, "formatting".to_string(), vec![edit]);
+  }
+  Ok(Value::Null)
+}
+
+pub fn attach(srv: &mut JrpcServer) {
+  srv.on_req_sync("textDocument/codeAction", |params, session| {
+    let params = params.unwrap_or(&Value::Null);
+    let uri = FileUri::deserialize(&params["textDocument"]["uri"])
+      .context(LSPErrCode::InvalidParams)?;
+    let range = DocRange::deserialize(&params["range"]).context(LSPErrCode::InvalidParams)?;
+    let only = <Option<Vec<String>>>::deserialize(&params["context"]["only"])
+      .context(LSPErrCode::InvalidParams)?;
+    viewport::requested(&session, &uri, range);
+    let loose = (params["context"]["diagnostics"].as_array().into_iter().flatten())
+      .find(|d| d["code"] == LOOSE_FILE)
+      .filter(|_| requested("quickfix", &only));
+    let g = session.lock();
+    let can_create = g.get::<ClientProfile>().is_some_and(|p| p.create_files);
+    let wctx = workspace(&g)?;
+    let Some((path, wsp, proj)) = wctx.get_proj(&uri) else { return Ok(json!([])) };
+    let dir = proj.files(wsp.store.clone()).config_dir();
+    let mut project_actions = Vec::new();
+    if let (Some(diagnostic), ProjectRoot::File(file), true) = (loose, &proj.root, can_create) {
+      let projects = wsp.projects.iter().map(|p| (&p.path()[..], p.root.is_file())).collect_vec();
+      for (depth, files) in joining_folders(&file[..], &projects) {
+        let folder = &file[..depth];
+        let segments = folder.iter().map(|s| s.as_str()).chain(["project_info"]);
+        let uri = wsp.store.basepath().extended(segments).stringify(true);
+        let folder = match folder {
+          [] => "the workspace folder".to_string(),
+          _ => format!("{}/", folder.iter().join("/")),
+        };
+        project_actions.push(create_project(&folder, uri, files, diagnostic));
+      }
+    }
+    let (lpr, store, moved) = (proj.loaded.clone(), wsp.store.clone(), proj.moved.clone());
+    drop(g);
+    let config = project_config(&session, &dir);
+    if config.is_generated(&path) {
+      return Ok(Value::Array(project_actions));
+    }
+    let doc = uri.stringify(true);
+    let (mut actions, mut commands) = (Vec::new(), Vec::new());
+    if let (Some(text), Some(lines)) = (store.text(&uri), store.line_index(&uri)) {
+      let offset = lines.docpos2bpos(range.start).context(LSPErrCode::InvalidParams)?;
+      let end = lines.docpos2bpos(range.end).context(LSPErrCode::InvalidParams)?;
+      let doc_edit = |(range, text)| (doc.clone(), lines.doc_range(range), text);
+      if let Some((title, edits)) = toggle_export(text, offset) {
+        let edits = edits.into_iter().map(doc_edit).collect();
+        actions.push(Action { title, kind: "refactor.rewrite", edits });
+      }
+      if let Some((name, edits)) = extract_const(text, offset..end, &config.print) {
+        actions.push(Action {
+          title: format!("Extract to constant `{name}`"),
+          kind: "refactor.extract",
+          edits: edits.into_iter().map(doc_edit).collect(),
+        });
+      }
+      let edits = sort_members(text, config.order);
+      if !edits.is_empty() {
+        actions.push(Action {
+          title: "Sort module members".to_string(),
+          kind: "source.sortMembers",
+          edits: edits.into_iter().map(doc_edit).collect(),
+        });
+      }
+      if requested("source.format", &only) && fmt::format(text, &config) != *text {
+        commands.push(json!({
+          "title": "Format document",
+          "kind": "source.format",
+          "command": { "title": "Format document", "command": FORMAT_COMMAND, "arguments": [doc] },
+        }));
+      }
+      // Lints can be silenced where they're reported or in the whole file. The
+      // errors of a failed load aren't lints.
+      let failed_load = |d: &Value| {
+        d["code"] == LOAD_FAILED || (d["code"] == "load-error" && d["severity"] == 1)
+      };
+      let lints = (params["context"]["diagnostics"].as_array().into_iter().flatten())
+        .filter(|d| d["source"] == "orchid" && !failed_load(d));
+      let mut fixes = Vec::new();
+      for diagnostic in lints {
+        let Some(code) = diagnostic["code"].as_str() else { continue };
+        let Ok(range) = DocRange::deserialize(&diagnostic["range"]) else { continue };
+        let Ok(start) = lines.docpos2bpos(range.start) else { continue };
+        fixes.push((format!("Suppress `{code}` here"), suppress_line(text, start, code)));
+        fixes.push((format!("Suppress `{code}` in this file"), suppress_file(text, code)));
+      }
+      // Findings of the same lint share the file directive, and those on the
+      // same line share the line directive too
+      for (title, edit) in fixes.into_iter().unique() {
+        actions.push(Action { title, kind: "quickfix", edits: vec![doc_edit(edit)] });
+      }
+      // Usually requested over a range of freshly pasted code
+      let index = lpr.as_ref().map(|lpr| lpr.exports());
+      let found = index.and_then(|index| missing_imports(text, offset..end, index));
+      if let Some((names, edit)) = found {
+        actions.push(Action {
+          title: match &names[..] {
+            [name] => format!("Import `{name}`"),
+            _ => format!("Add {} missing imports", names.len()),
+          },
+          kind: "source.addMissingImports",
+          edits: vec![doc_edit(edit)],
+        });
+      }
+    }
+    let lines = lpr.as_ref().and_then(|lpr| lpr.line_index(&path));
+    if let (Some(lpr), Some(lines)) = (lpr, lines) {
+      let offset = lines.docpos2bpos(range.start).context(LSPErrCode::InvalidParams)?;
+      actions.extend(rewrites(&lpr, &path, offset).into_iter().map(|rw| Action {
+        title: rw.title,
+        kind: "refactor.rewrite",
+        edits: vec![(doc.clone(), lines.doc_range(rw.range), rw.text)],
+      }));
+      let rules = source_rules(&lpr);
+      let conflicting = (conflicts(&rules).into_iter())
+        .flat_map(|(a, b)| [a, b])
+        .unique()
+        .filter(|i| rules[*i].file == path && rules[*i].range.contains(&offset));
+      for idx in conflicting {
+        let prio = nudged_prio(&rules, idx);
+        actions.push(Action {
+          title: format!("Raise rule priority to {prio}"),
+          kind: "quickfix",
+          edits: vec![(doc.clone(), lines.doc_range(rules[idx].arrow.clone()), arrow_text(prio))],
+        });
+      }
+      let uses = lpr.private_uses().iter();
+      let here = |u: &PrivateUse| u.file == path && u.range.contains(&offset);
+      for private in uses.filter(|u| here(u) && !config.is_generated(&u.decl_file)) {
+        let Some(text) = lpr.source(&private.decl_file) else { continue };
+        let Some(decl_lines) = lpr.line_index(&private.decl_file) else { continue };
+        let Some((_, edits)) = toggle_export(&text, private.decl.start) else { continue };
+        let decl_uri = lpr.doc_uri(&private.decl_file);
+        actions.push(Action {
+          title: format!("Export `{}` from {}", private.name, private.decl_file),
+          kind: "quickfix",
+          edits: (edits.into_iter())
+            .map(|(range, text)| (decl_uri.clone(), decl_lines.doc_range(range), text))
+            .collect(),
+        });
+      }
+      // All stale imports of the file are fixed at once since they stem from
+      // the same rename
+      let end = lines.docpos2bpos(range.end).context(LSPErrCode::InvalidParams)?;
+      let stale = stale_imports(&lpr, &moved).into_iter().filter(|s| s.file == path).collect_vec();
+      if stale.iter().any(|s| s.range.start <= end && offset <= s.range.end) {
+        actions.push(Action {
+          title: match &stale[..] {
+            [single] => format!("Change import to `{}`", single.new),
+            _ => format!("Update {} imports of moved files", stale.len()),
+          },
+          kind: "quickfix",
+          edits: (stale.into_iter())
+            .map(|s| (doc.clone(), lines.doc_range(s.range), s.new))
+            .collect(),
+        });
+      }
+    }
+    let actions = actions.iter().filter(|a| a.matches(&only)).map(|a| a.to_json());
+    Ok(Value::Array(actions.chain(commands).chain(project_actions).collect()))
+  });
+}
@@ -0,0 +1,77 @@
+//! `textDocument/documentColor` and `textDocument/colorPresentation` for the
+//! color literals recognized by [crate::orc::colors]
+
+use std::iter;
+
+use anyhow::Context;
+use itertools::Itertools;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::fs::snapshot;
+use crate::jrpc::JrpcServer;
+use crate::orc::colors::{constructor_call, find_colors, hex_literal, Rgba};
+use crate::protocol::document::{DocRange, FileUri, TextEdit};
+use crate::protocol::error::LSPErrCode;
+
+/// Names of functions whose calls are shown as colors, set from the
+/// `colorConstructors` initialization option
+pub struct ColorConfig {
+  pub constructors: Vec<String>,
+}
+impl ColorConfig {
+  pub fn new(init_opts: &Value) -> Self {
+    let constructors = match init_opts["colorConstructors"].as_array() {
+      None => vec!["rgb".to_string()],
+      Some(names) => names.iter().filter_map(|n| Some(n.as_str()?.to_string())).collect(),
+    };
+    Self { constructors }
+  }
+}
+
+fn rgba(color: &Value) -> Option<Rgba> {
+  let channel = |name: &str| color[name].as_f64();
+  Some(Rgba {
+    red: channel("red")?,
+    green: channel("green")?,
+    blue: channel("blue")?,
+    alpha: channel("alpha")?,
+  })
+}
+
+pub fn attach(srv: &mut JrpcServer) {
+  srv.on_req_sync("textDocument/documentColor", |params, session| {
+    let params = params.unwrap_or(&Value::Null);
+    let uri = FileUri::deserialize(&params["textDocument"]["uri"])
+      .context(LSPErrCode::InvalidParams)?;
+    let constructors =
+      session.lock().get::<ColorConfig>().map(|c| c.constructors.clone()).unwrap_or_default();
+    let Some(snap) = snapshot(&session, &uri) else { return Ok(json!([])) };
+    let (Some(text), Some(lines)) = (snap.store.text(&uri), snap.store.line_index(&uri)) else {
+      return Ok(json!([]));
+    };
+    let colors = (find_colors(text, &constructors).into_iter())
+      .map(|lit| {
+        let Rgba { red, green, blue, alpha } = lit.color;
+        json!({
+          "range": lines.doc_range(lit.range),
+          "color": { "red": red, "green": green, "blue": blue, "alpha": alpha },
+        })
+      })
+      .collect_vec();
+    Ok(Value::Array(colors))
+  });
+  srv.on_req_sync("textDocument/colorPresentation", |params, session| {
+    let params = params.unwrap_or(&Value::Null);
+    let color = rgba(&params["color"]).context(LSPErrCode::InvalidParams)?;
+    let range = DocRange::deserialize(&params["range"]).context(LSPErrCode::InvalidParams)?;
+    let g = session.lock();
+    let constructors = g.get::<ColorConfig>().map_or(&[][..], |c| &c.constructors[..]);
+    let hex = hex_literal(color);
+    let calls = constructors.iter().map(|name| constructor_call(name, color));
+    let presentations = (iter::once(hex).chain(calls))
+      .map(|label| json!({ "label": &label, "textEdit": TextEdit::new(range, label) }))
+      .collect_vec();
+    Ok(Value::Array(presentations))
+  });
+}
@@ -0,0 +1,39 @@
+//! `workspace/executeCommand`, which runs the commands of other modules
+
+use anyhow::{anyhow, Context};
+use serde_json::Value;
+
+use super::{cache, code, fs, journal, memory, sweep};
+use crate::jrpc::JrpcServer;
+use crate::protocol::error::LSPErrCode;
+
+/// Advertised in `executeCommandProvider`
+pub const COMMANDS: &[&str] = &[
+  memory::GC_COMMAND,
+  journal::DUMP_COMMAND,
+  cache::CLEAR_COMMAND,
+  fs::DISCOVER_COMMAND,
+  sweep::LINT_COMMAND,
+  code::FORMAT_COMMAND,
+];
+
+pub fn attach(srv: &mut JrpcServer) {
+  // Asynchronous so that long commands can run on a worker thread
+  srv.on_req_async("workspace/executeCommand", |req| {
+    let session = req.session().clone();
+    let params = req.params().cloned().unwrap_or(Value::Null);
+    let Some(command) = params["command"].as_str() else {
+      return req.resolve(Err(anyhow!("Missing command").context(LSPErrCode::InvalidParams)));
+    };
+    let result = match command {
+      memory::GC_COMMAND => memory::gc(&session),
+      journal::DUMP_COMMAND => journal::dump_command(&session),
+      cache::CLEAR_COMMAND => cache::clear_command(&session),
+      fs::DISCOVER_COMMAND => fs::discover_command(&session, &params["arguments"]),
+      sweep::LINT_COMMAND => return sweep::lint_command(req),
+      code::FORMAT_COMMAND => code::format_command(&session, &params["arguments"]),
+      _ => None.context(LSPErrCode::InvalidParams),
+    };
+    req.resolve(result)
+  });
+}
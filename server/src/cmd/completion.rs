@@ -0,0 +1,104 @@
+//! `textDocument/completion`, which completes paths in `project_info.orc`
+//! from the directory structure of the project, and elsewhere the names
+//! visible at the cursor
+
+use std::fs;
+
+use anyhow::Context;
+use itertools::Itertools;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::fs::snapshot;
+use super::nav::position;
+use crate::jrpc::{JrpcServer, Session};
+use crate::orc::lexical::name_char;
+use crate::orc::project_info::{string_at, FILE_NAME};
+use crate::orc::scopes::ScopeKind;
+use crate::protocol::document::{TextDocumentPositionParams, TextEdit};
+use crate::protocol::error::LSPErrCode;
+
+/// `CompletionItemKind` values from the protocol
+const VARIABLE: u8 = 6;
+const MODULE: u8 = 9;
+const FILE: u8 = 17;
+const FOLDER: u8 = 19;
+const CONSTANT: u8 = 21;
+
+/// The names visible at the position, replacing the identifier before it
+fn names(params: Option<&Value>, session: &Session) -> anyhow::Result<Value> {
+  let Some(pos) = position(params, session)? else { return Ok(Value::Null) };
+  let (Some(scopes), Some(text)) = (pos.lpr.scopes(&pos.path), pos.lpr.source(&pos.path)) else {
+    return Ok(Value::Null);
+  };
+  let typed = text[..pos.offset].trim_end_matches(name_char).len();
+  let range = pos.lines.doc_range(typed..pos.offset);
+  let items = (scopes.visible_at(pos.offset).into_iter())
+    .map(|binder| {
+      let name = binder.name.last().to_string();
+      let (kind, detail) = match binder.kind {
+        ScopeKind::Lambda => (VARIABLE, "parameter"),
+        ScopeKind::Rule => (VARIABLE, "placeholder"),
+        ScopeKind::Module if pos.lpr.get_const(&binder.name).is_some() => (CONSTANT, "constant"),
+        ScopeKind::Module => (MODULE, "module"),
+      };
+      json!({
+        "label": name,
+        "kind": kind,
+        "detail": detail,
+        "textEdit": TextEdit::new(range, name.clone()),
+      })
+    })
+    .collect_vec();
+  Ok(Value::Array(items))
+}
+
+pub fn attach(srv: &mut JrpcServer) {
+  srv.on_req_sync("textDocument/completion", |params, session| {
+    let doc = TextDocumentPositionParams::deserialize(params.unwrap_or(&Value::Null))
+      .context(LSPErrCode::InvalidParams)?;
+    let uri = &doc.text_document.uri;
+    let path = uri.to_path();
+    if path.file_name().is_none_or(|name| name != FILE_NAME) {
+      return names(params, &session);
+    }
+    let Some(snap) = snapshot(&session, uri) else { return Ok(Value::Null) };
+    let (Some(text), Some(lines)) = (snap.store.text(uri), snap.store.line_index(uri)) else {
+      return Ok(Value::Null);
+    };
+    let offset = lines.docpos2bpos(doc.position).context(LSPErrCode::InvalidParams)?;
+    let Some(string) = string_at(text, offset) else { return Ok(Value::Null) };
+    // Only the last segment is replaced, the directory it's in is listed
+    let typed = &text[string.start..offset];
+    let (dir, segment_start) = match typed.rfind('/') {
+      Some(i) => (typed[..=i].to_string(), string.start + i + 1),
+      None => (String::new(), string.start),
+    };
+    let range = lines.doc_range(segment_start..offset);
+    let Some(project_dir) = path.parent() else { return Ok(Value::Null) };
+    let Ok(entries) = fs::read_dir(project_dir.join(dir)) else { return Ok(json!([])) };
+    let items = (entries.filter_map(Result::ok))
+      .filter_map(|ent| {
+        let name = ent.file_name().into_string().ok()?;
+        let is_dir = ent.file_type().ok()?.is_dir();
+        if name.starts_with('.') || !(is_dir || name.ends_with(".orc")) {
+          return None;
+        }
+        Some((is_dir, name))
+      })
+      .sorted_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)))
+      .map(|(is_dir, name)| {
+        let (kind, new_text) = match is_dir {
+          true => (FOLDER, format!("{name}/")),
+          false => (FILE, name.clone()),
+        };
+        json!({
+          "label": name,
+          "kind": kind,
+          "textEdit": TextEdit::new(range, new_text),
+        })
+      })
+      .collect_vec();
+    Ok(Value::Array(items))
+  });
+}
@@ -0,0 +1,275 @@
+//! Configuration that may differ between projects. The workspace configuration
+//! is read from the initialization options and updated from the `orchidls`
+//! settings section, and a `.orchid-ls.json` file in the root of a project
+//! overrides it for that project. Each source only sets the fields it
+//! contains, so the precedence is project over workspace over defaults.
+//!
+//! All configuration is checked against [OPTIONS] or [PROJECT_OPTIONS] before
+//! it's read, and the problems are shown to the user when they differ from
+//! those last shown for the same source.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use hashbrown::HashMap;
+use orchidlang::name::VPath;
+use serde_json::Value;
+
+use super::code::member_order;
+use super::diagnostics::lint_config;
+use crate::jrpc::{Session, SessionGuard};
+use crate::log;
+use crate::orc::lint::{LintConfig, Severity};
+use crate::orc::members::MemberOrder;
+use crate::orc::printer::{OperatorSpacing, PrintConfig};
+use crate::protocol::messages::{MessageType, ShowMessage, ShowMessageParams};
+use crate::protocol::schema::{sanitize, Schema};
+
+pub const FILE_NAME: &str = ".orchid-ls.json";
+
+const SEVERITIES: Schema = Schema::Enum(&["error", "warning", "information", "hint", "off"]);
+const SORT_MEMBERS: Schema = Schema::Object(&[
+  ("alphabetical", Schema::Bool),
+  ("exportsFirst", Schema::Bool),
+  ("groupImports", Schema::Bool),
+  ("onFormat", Schema::Bool),
+]);
+const PRINT: Schema = Schema::Object(&[
+  ("operatorSpacing", Schema::Enum(&["spaced", "tight"])),
+  ("width", Schema::Count),
+]);
+
+/// The options a project file may set
+pub const PROJECT_OPTIONS: Schema = Schema::Object(&[
+  ("exclude", Schema::List(&Schema::Str)),
+  ("generated", Schema::List(&Schema::Str)),
+  ("lintSeverity", Schema::Map(&SEVERITIES)),
+  ("macroGas", Schema::Count),
+  ("print", PRINT),
+  ("sortMembers", SORT_MEMBERS),
+  ("unusedExports", Schema::Bool),
+]);
+
+/// The initialization options and the `orchidls` settings section. Settings
+/// of the client itself are also sent in the section.
+pub const OPTIONS: Schema = Schema::Object(&[
+  ("cacheDir", Schema::Str),
+  ("closeBrackets", Schema::Bool),
+  ("coldLoadMaxKb", Schema::Count),
+  ("coldLoadTimeoutMs", Schema::Count),
+  ("colorConstructors", Schema::List(&Schema::Str)),
+  ("continueComments", Schema::Bool),
+  ("debounceMs", Schema::Count),
+  ("exclude", Schema::List(&Schema::Str)),
+  ("generated", Schema::List(&Schema::Str)),
+  ("jobTimeoutMs", Schema::Count),
+  ("journalSize", Schema::Count),
+  ("latencyBudgetMs", Schema::Count),
+  ("lintSeverity", Schema::Map(&SEVERITIES)),
+  ("macroGas", Schema::Count),
+  ("moduleStats", Schema::Bool),
+  ("output", Schema::Any),
+  ("print", PRINT),
+  ("semanticTokens", Schema::Bool),
+  ("slowRequestMs", Schema::Count),
+  ("sortMembers", SORT_MEMBERS),
+  ("telemetry", Schema::Bool),
+  ("tokenTypes", Schema::Map(&Schema::Str)),
+  ("trace", Schema::Any),
+  ("unusedExports", Schema::Bool),
+]);
+
+/// Show the problems of a configuration source, unless they're the ones last
+/// shown for it
+fn report(session: &Session, source: &str, problems: &[String]) {
+  let mut g = session.lock();
+  if g.get::<ReportedProblems>().is_none() {
+    g.set(ReportedProblems::default());
+  }
+  let reported = &mut g.get_mut::<ReportedProblems>().unwrap().0;
+  if reported.get(source).is_some_and(|last| last[..] == *problems) {
+    return;
+  }
+  reported.insert(source.to_string(), problems.to_vec());
+  drop(g);
+  if problems.is_empty() {
+    return;
+  }
+  let message = format!("Ignoring parts of the {source}: {}", problems.join("; "));
+  log!(Warn, "{message}");
+  session.lock().send::<ShowMessage>(ShowMessageParams { typ: MessageType::Warning, message })
+}
+
+/// Check initialization options or settings against [OPTIONS], reporting the
+/// problems if they differ from those last reported for the source. Invalid
+/// fields are removed so that they take their defaults.
+pub fn validated(session: &Session, source: &str, opts: &Value) -> Value {
+  let (opts, problems) = sanitize(&OPTIONS, opts);
+  report(session, source, &problems);
+  opts
+}
+
+/// The problems last reported for each configuration source, so that the
+/// same ones aren't shown again on every load or settings change
+#[derive(Default)]
+struct ReportedProblems(HashMap<String, Vec<String>>);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProjectConfig {
+  pub lints: LintConfig,
+  /// `lintSeverity`, the severity of findings by code. [None] hides them.
+  pub severities: HashMap<String, Option<Severity>>,
+  /// `exclude`, directories relative to the project root whose files get no
+  /// diagnostics
+  pub exclude: Vec<String>,
+  /// `generated`, directories relative to the project root whose files a build
+  /// step writes. They're analyzed like any other, but never edited.
+  pub generated: Vec<String>,
+  pub order: MemberOrder,
+  /// `sortMembers.onFormat`, whether formatting sorts members as well
+  pub sort_on_format: bool,
+  /// `macroGas`, the number of macro steps after which expansion is abandoned
+  pub macro_gas: usize,
+  /// `print`, the layout of expressions shown in hovers and written by the
+  /// formatter
+  pub print: PrintConfig,
+}
+impl Default for ProjectConfig {
+  fn default() -> Self {
+    Self {
+      lints: LintConfig::default(),
+      severities: HashMap::new(),
+      exclude: Vec::new(),
+      generated: Vec::new(),
+      order: MemberOrder::default(),
+      sort_on_format: false,
+      macro_gas: 10_000,
+      print: PrintConfig::default(),
+    }
+  }
+}
+impl ProjectConfig {
+  /// Override the fields set in a configuration object
+  pub fn merged(&self, opts: &Value) -> Self {
+    let mut severities = self.severities.clone();
+    for (code, value) in opts["lintSeverity"].as_object().into_iter().flatten() {
+      if let Some(severity) = value.as_str().and_then(parse_severity) {
+        severities.insert(code.clone(), severity);
+      }
+    }
+    Self {
+      lints: lint_config(opts, self.lints),
+      severities,
+      exclude: dirs(&opts["exclude"]).unwrap_or_else(|| self.exclude.clone()),
+      generated: dirs(&opts["generated"]).unwrap_or_else(|| self.generated.clone()),
+      order: member_order(opts, self.order),
+      sort_on_format: opts["sortMembers"]["onFormat"].as_bool().unwrap_or(self.sort_on_format),
+      macro_gas: opts["macroGas"].as_u64().map_or(self.macro_gas, |gas| gas as usize),
+      print: print_config(opts, self.print),
+    }
+  }
+
+  /// Whether a file is in an excluded directory
+  pub fn excludes(&self, file: &VPath) -> bool { within(&self.exclude, file) }
+
+  /// Whether a file is in a generated directory, and so mustn't be edited
+  pub fn is_generated(&self, file: &VPath) -> bool { within(&self.generated, file) }
+
+  /// The severity of a finding after overrides, [None] if it's hidden
+  pub fn severity(&self, code: &str, default: Severity) -> Option<Severity> {
+    self.severities.get(code).copied().unwrap_or(Some(default))
+  }
+}
+
+/// Read a list of directories relative to the project root, [None] if the
+/// option isn't set
+fn dirs(opt: &Value) -> Option<Vec<String>> {
+  let dirs = opt.as_array()?.iter();
+  Some(dirs.filter_map(|d| Some(d.as_str()?.trim_matches('/').to_string())).collect())
+}
+
+/// Whether a file is in one of a list of directories
+fn within(dirs: &[String], file: &VPath) -> bool {
+  dirs.iter().any(|dir| {
+    let dir = dir.split('/').collect::<Vec<_>>();
+    dir.len() < file.len() && file.iter().zip(dir).all(|(seg, dir)| seg.as_str() == dir)
+  })
+}
+
+fn parse_severity(name: &str) -> Option<Option<Severity>> {
+  match name {
+    "error" => Some(Some(Severity::Error)),
+    "warning" => Some(Some(Severity::Warning)),
+    "information" => Some(Some(Severity::Information)),
+    "hint" => Some(Some(Severity::Hint)),
+    "off" => Some(None),
+    _ => None,
+  }
+}
+
+/// Read the `print` section of a configuration object, defaulting missing
+/// fields to the base
+fn print_config(opts: &Value, base: PrintConfig) -> PrintConfig {
+  let opts = &opts["print"];
+  let spacing = opts["operatorSpacing"].as_str().and_then(OperatorSpacing::parse);
+  PrintConfig {
+    width: opts["width"].as_u64().map_or(base.width, |width| width as usize),
+    operator_spacing: spacing.unwrap_or(base.operator_spacing),
+  }
+}
+
+/// Read the project file in a directory if there is one, keeping the options
+/// that conform to [PROJECT_OPTIONS] and describing the problems with the rest
+pub fn read_project_file(dir: &Path) -> Option<(Value, Vec<String>)> {
+  let text = fs::read_to_string(dir.join(FILE_NAME)).ok()?;
+  Some(match serde_json::from_str::<Value>(&text) {
+    Ok(opts) => sanitize(&PROJECT_OPTIONS, &opts),
+    Err(e) => (Value::Null, vec![format!("invalid JSON: {e}")]),
+  })
+}
+
+/// Forget the reported problems so that they're reported again
+pub fn forget_problems(g: &mut SessionGuard) { g.remove::<ReportedProblems>(); }
+
+/// The configuration of each project directory read so far. An entry lives
+/// until its project file changes or the workspace configuration does.
+#[derive(Default)]
+struct ConfigCache(HashMap<PathBuf, ProjectConfig>);
+
+/// Forget the configuration read from the project file in a directory, or of
+/// every directory if [None]
+pub fn forget_project_config(g: &mut SessionGuard, dir: Option<&Path>) {
+  match dir {
+    None => drop(g.remove::<ConfigCache>()),
+    Some(dir) => {
+      if let Some(cache) = g.get_mut::<ConfigCache>() {
+        cache.0.remove(dir);
+      }
+    },
+  }
+}
+
+/// The configuration of the project in a directory. Missing project files
+/// leave the workspace configuration unchanged, problems with existing ones
+/// are reported when they change. The project file is only read again after
+/// [forget_project_config].
+pub fn project_config(session: &Session, dir: &Path) -> ProjectConfig {
+  let cached = session.lock().get::<ConfigCache>().and_then(|c| c.0.get(dir).cloned());
+  if let Some(config) = cached {
+    return config;
+  }
+  let workspace = session.lock().get::<ProjectConfig>().cloned().unwrap_or_default();
+  let config = match read_project_file(dir) {
+    None => workspace,
+    Some((opts, problems)) => {
+      report(session, &dir.join(FILE_NAME).display().to_string(), &problems);
+      workspace.merged(&opts)
+    },
+  };
+  let mut g = session.lock();
+  if g.get::<ConfigCache>().is_none() {
+    g.set(ConfigCache::default());
+  }
+  g.get_mut::<ConfigCache>().unwrap().0.insert(dir.to_path_buf(), config.clone());
+  config
+}
@@ -0,0 +1,262 @@
+//! Diagnostics from [crate::orc::lint] and from failed loads, published after
+//! each load of a project. Each project tracks the files it published
+//! diagnostics for, and clears them when the findings are fixed, the file is
+//! deleted or the project goes away. Unchanged diagnostics aren't sent again.
+//!
+//! Clients that pull diagnostics are asked to pull again instead, and get the
+//! findings in every file of the workspace from `workspace/diagnostic`.
+
+use std::mem;
+
+use anyhow::Context;
+use hashbrown::HashMap;
+use itertools::Itertools;
+use orchidlang::error::ProjectErrorObj;
+use orchidlang::name::VPath;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::config::ProjectConfig;
+use super::fs::{text_hash, workspace, WorkspaceCtx};
+use crate::jrpc::{JrpcServer, SessionGuard};
+use crate::log;
+use crate::orc::errors::error_lints;
+use crate::orc::lint::{lint, Lint, LintConfig, Severity};
+use crate::orc::project::{LoadedProject, ProjectFiles, ProjectRoot};
+use crate::orc::suppress::{is_suppressed, suppressions, Suppression};
+use crate::protocol::client::ClientProfile;
+use crate::protocol::document::FileUri;
+use crate::protocol::error::LSPErrCode;
+use crate::protocol::messages::{
+  Diagnostic, DiagnosticData, DiagnosticRefresh, Location, PublishDiagnostics,
+  PublishDiagnosticsParams, RelatedInformation,
+};
+use crate::published::reconcile;
+
+/// Identifies the note on open files of a project that failed to load
+pub const LOAD_FAILED: &str = "load-failed";
+/// The most errors listed in the note on open files
+const MAX_RELATED: usize = 10;
+
+/// Read the lint flags from the initialization options or the `orchidls`
+/// settings section, defaulting missing flags to [LintConfig::default]
+pub fn lint_config(opts: &Value, base: LintConfig) -> LintConfig {
+  LintConfig { unused_exports: opts["unusedExports"].as_bool().unwrap_or(base.unused_exports) }
+}
+
+/// Run the lints on a freshly loaded project and locate the errors it recovered
+/// from, applying the severity overrides, exclusions and suppression comments
+/// of the project. Findings in generated files are only informational, since
+/// fixing them in place would be undone by the build step that writes them.
+/// `roots` are the projects of the workspace.
+pub fn compute(
+  lpr: &LoadedProject,
+  config: &ProjectConfig,
+  moved: &[(VPath, VPath)],
+  roots: &[ProjectRoot],
+) -> Vec<PublishDiagnosticsParams> {
+  let mut lints = error_lints(&lpr.warnings, Severity::Warning, &lpr.files.project_file());
+  lints.extend(lint(lpr, config.lints, moved, roots));
+  let mut suppressed = HashMap::<VPath, Vec<Suppression>>::new();
+  let lints = (lints.into_iter())
+    .filter(|lint| !config.excludes(&lint.file))
+    .filter(|lint| {
+      let file = (suppressed.entry(lint.file.clone()))
+        .or_insert_with(|| lpr.source(&lint.file).map_or_else(Vec::new, |t| suppressions(&t)));
+      !is_suppressed(file, lint.code, &lint.range)
+    })
+    .filter_map(|lint| {
+      let severity = match config.severity(lint.code, lint.severity)? {
+        Severity::Error | Severity::Warning if config.is_generated(&lint.file) =>
+          Severity::Information,
+        severity => severity,
+      };
+      Some(Lint { severity, ..lint })
+    })
+    .collect();
+  to_params(&lpr.files, lints)
+}
+
+/// Locate the errors of a failed load. Errors that can't be located in a file
+/// are placed on the project file. Open files without errors of their own get
+/// a note pointing at the errors, which explain why their analysis is stale.
+pub fn load_errors(
+  files: &ProjectFiles,
+  errors: &[ProjectErrorObj],
+  open: &[VPath],
+) -> Vec<PublishDiagnosticsParams> {
+  let mut lints = error_lints(errors, Severity::Error, &files.project_file());
+  let summary = match lints.len() {
+    0 => return Vec::new(),
+    1 => "an error in another file".to_string(),
+    n => format!("{n} errors in other files"),
+  };
+  let related = (lints.iter())
+    .map(|lint| (lint.file.clone(), lint.range.clone(), lint.message.clone()))
+    .take(MAX_RELATED)
+    .collect_vec();
+  let message = format!(
+    "The project failed to load because of {summary}. Analysis shows the last loaded state."
+  );
+  let clean = (open.iter()).filter(|file| lints.iter().all(|lint| &lint.file != *file));
+  let notes = (clean.cloned().collect_vec().into_iter()).map(|file| Lint {
+    file,
+    range: 0..0,
+    severity: Severity::Information,
+    code: LOAD_FAILED,
+    message: message.clone(),
+    related: related.clone(),
+  });
+  lints.extend(notes);
+  to_params(files, lints)
+}
+
+/// Convert findings to diagnostics grouped by file
+fn to_params(files: &ProjectFiles, lints: Vec<Lint>) -> Vec<PublishDiagnosticsParams> {
+  let location = |file: &VPath, range| {
+    let uri = files.doc_uri(file);
+    Some(Location { uri, range: files.line_index(file)?.doc_range(range) })
+  };
+  let mut diagnostics = HashMap::<VPath, Vec<Diagnostic>>::new();
+  for lint in lints {
+    let Some(Location { range, .. }) = location(&lint.file, lint.range) else { continue };
+    let related_information = (lint.related.into_iter())
+      .filter_map(|(file, range, message)| {
+        Some(RelatedInformation { location: location(&file, range)?, message })
+      })
+      .collect();
+    let (start, end) = (range.start, range.end);
+    let id = format!(
+      "{}@{}:{}-{}:{}#{:016x}",
+      lint.code,
+      start.line,
+      start.char,
+      end.line,
+      end.char,
+      text_hash(&lint.message)
+    );
+    diagnostics.entry(lint.file).or_default().push(Diagnostic {
+      range,
+      severity: lint.severity as u8,
+      code: lint.code,
+      source: "orchid",
+      message: lint.message,
+      related_information,
+      data: DiagnosticData { id },
+    });
+  }
+  (diagnostics.into_iter())
+    .map(|(file, diagnostics)| PublishDiagnosticsParams {
+      uri: files.doc_uri(&file),
+      diagnostics,
+    })
+    .collect()
+}
+
+/// The number of diagnostics with a severity across all files
+pub fn count(diagnostics: &[PublishDiagnosticsParams], severity: u8) -> usize {
+  diagnostics.iter().flat_map(|p| &p.diagnostics).filter(|d| d.severity == severity).count()
+}
+
+/// Send changed diagnostics, or ask clients that pull diagnostics to pull
+/// them again
+fn deliver(g: &mut SessionGuard, changed: Vec<PublishDiagnosticsParams>) {
+  if changed.is_empty() {
+    return;
+  }
+  if g.get::<ClientProfile>().is_some_and(|p| p.pull_diagnostics) {
+    return g.call::<DiagnosticRefresh>((), |res| {
+      if let Err(e) = res {
+        log!(Warn, "Client failed to refresh diagnostics: {e:?}")
+      }
+    });
+  }
+  for params in changed {
+    g.send::<PublishDiagnostics>(params)
+  }
+}
+
+/// Publish the diagnostics of the project containing a file, replacing all
+/// of its previous diagnostics
+pub fn publish(g: &mut SessionGuard, uri: &FileUri, diagnostics: Vec<PublishDiagnosticsParams>) {
+  let Some(wctx) = g.get_mut::<WorkspaceCtx>() else { return };
+  let Some((_, _, proj)) = wctx.get_proj_mut(uri) else { return };
+  let changed = reconcile(&mut proj.diagnosed, diagnostics);
+  deliver(g, changed)
+}
+
+/// Clear the diagnostics of a file, for example because it was deleted
+pub fn forget(g: &mut SessionGuard, uri: &FileUri) {
+  let Some(wctx) = g.get_mut::<WorkspaceCtx>() else { return };
+  let Some((_, _, proj)) = wctx.get_proj_mut(uri) else { return };
+  let uri = uri.stringify(true);
+  if proj.diagnosed.remove(&uri).is_some() {
+    deliver(g, vec![PublishDiagnosticsParams { uri, diagnostics: Vec::new() }])
+  }
+}
+
+/// Clear the diagnostics of every project, before the projects are replaced
+pub fn forget_all(g: &mut SessionGuard) {
+  let Some(wctx) = g.get_mut::<WorkspaceCtx>() else { return };
+  let uris = (wctx.projects_mut())
+    .flat_map(|p| mem::take(&mut p.diagnosed).into_keys())
+    .collect_vec();
+  clear(g, uris)
+}
+
+/// Clear the diagnostics of files by URI, for example those a project that
+/// went away published
+pub fn clear(g: &mut SessionGuard, uris: impl IntoIterator<Item = String>) {
+  let cleared = (uris.into_iter())
+    .map(|uri| PublishDiagnosticsParams { uri, diagnostics: Vec::new() })
+    .collect();
+  deliver(g, cleared)
+}
+
+/// A `textDocument/diagnostic` report of what a project last found in a file.
+/// Files the client already has the current report of are unchanged.
+fn report(diagnosed: Option<&(u64, Vec<Diagnostic>)>, previous: Option<&str>) -> Value {
+  let Some((hash, diagnostics)) = diagnosed else { return json!({ "kind": "full", "items": [] }) };
+  let result_id = format!("{hash:016x}");
+  if previous == Some(result_id.as_str()) {
+    return json!({ "kind": "unchanged", "resultId": result_id });
+  }
+  json!({ "kind": "full", "resultId": result_id, "items": diagnostics })
+}
+
+pub fn attach(srv: &mut JrpcServer) {
+  srv.on_req_sync("textDocument/diagnostic", |params, session| {
+    let params = params.unwrap_or(&Value::Null);
+    let uri = FileUri::deserialize(&params["textDocument"]["uri"])
+      .context(LSPErrCode::InvalidParams)?;
+    let g = session.lock();
+    let wctx = workspace(&g)?;
+    let diagnosed = (wctx.get_proj(&uri))
+      .and_then(|(_, _, proj)| proj.diagnosed.get(&uri.stringify(true)));
+    Ok(report(diagnosed, params["previousResultId"].as_str()))
+  });
+  // Covers the files that aren't open, such as the dependency whose errors
+  // stop the project of an open file from loading
+  srv.on_req_sync("workspace/diagnostic", |params, session| {
+    let params = params.unwrap_or(&Value::Null);
+    let previous = (params["previousResultIds"].as_array().into_iter().flatten())
+      .filter_map(|prev| Some((prev["uri"].as_str()?, prev["value"].as_str()?)))
+      .collect::<HashMap<_, _>>();
+    let g = session.lock();
+    let wctx = workspace(&g)?;
+    let diagnosed = (wctx.projects())
+      .flat_map(|proj| proj.diagnosed.iter().map(|(uri, diag)| (uri.as_str(), diag)))
+      .collect::<HashMap<_, _>>();
+    // Files the client has a report of but that have no findings anymore are
+    // reported empty
+    let uris = diagnosed.keys().chain(previous.keys()).unique();
+    let items = (uris.map(|uri| {
+      let mut item = report(diagnosed.get(uri).copied(), previous.get(uri).copied());
+      item["uri"] = json!(uri);
+      item["version"] = Value::Null;
+      item
+    }))
+    .collect_vec();
+    Ok(json!({ "items": items }))
+  });
+}
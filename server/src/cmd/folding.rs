@@ -0,0 +1,32 @@
+//! `textDocument/foldingRange`, with the imports and comments classified so
+//! that editors can fold them all at once
+
+use anyhow::Context;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::fs::snapshot;
+use crate::jrpc::JrpcServer;
+use crate::orc::folding::folds;
+use crate::protocol::document::FileUri;
+use crate::protocol::error::LSPErrCode;
+
+pub fn attach(srv: &mut JrpcServer) {
+  srv.on_req_sync("textDocument/foldingRange", |params, session| {
+    let params = params.unwrap_or(&Value::Null);
+    let uri = FileUri::deserialize(&params["textDocument"]["uri"])
+      .context(LSPErrCode::InvalidParams)?;
+    let Some(snap) = snapshot(&session, &uri) else { return Ok(json!([])) };
+    let Some(text) = snap.store.text(&uri) else { return Ok(json!([])) };
+    let ranges = (folds(text).into_iter())
+      .map(|fold| {
+        let mut range = json!({ "startLine": fold.start, "endLine": fold.end });
+        if let Some(kind) = fold.kind {
+          range["kind"] = json!(kind.lsp_name());
+        }
+        range
+      })
+      .collect();
+    Ok(Value::Array(ranges))
+  });
+}
@@ -1,20 +1,44 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::atomic::{self, AtomicUsize};
-use std::sync::Arc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
 use std::{mem, thread};
 
+use anyhow::{anyhow, Context};
 use hashbrown::{HashMap, HashSet};
 use intern_all::{i, Tok};
 use itertools::Itertools;
 use orchidlang::name::{PathSlice, VPath};
-use orchidlang::virt_fs::{DirNode, FSResult, Loaded, PrefixFS, VirtFS};
 use serde::Deserialize;
-use serde_json::json;
+use serde_json::Value;
 
-use crate::jrpc::{Abort, JrpcServer, Session};
-use crate::orc::project::{find_all_projects, LoadedProject};
-use crate::protocol::document::{FileUri, WspaceEnt};
+use super::config::{self, project_config};
+use super::latency::{self, Breakdown};
+use super::progress::WorkProgress;
+use super::viewport;
+use super::{diagnostics, logging, telemetry};
+use crate::abort::Abort;
+use crate::documents::DocumentStore;
+use crate::event_loop::TimerId;
+use crate::jrpc::{JrpcServer, Session, SessionGuard};
+use crate::log;
+use crate::orc::project::{find_all_projects, LoadedProject, ProjectFiles, ProjectRoot, MACRO_PHASE};
+use crate::patches::PatchStore;
+use crate::protocol::docpos::DocPos;
+use crate::protocol::document::{ContentChange, FileUri, TextDocumentItem, WspaceEnt};
+use crate::protocol::error::LSPErrCode;
+use crate::protocol::legend::TokenLegend;
+use crate::protocol::messages::{
+  AnalysisFinished, AnalysisFinishedParams, AnalysisOutcome, AnalysisStarted, AnalysisStartedParams,
+  Diagnostic, DocumentRef, SyntacticTokens, SyntacticTokensParams, TelemetryParams,
+};
 use crate::protocol::tokens::SemToken;
 
+/// Upper bound on the number of tokens pushed to the client while holding the
+/// session lock. Files larger than this are pushed alone.
+const PUSH_CHUNK_TOKENS: usize = 20_000;
+
 pub fn ttypes() -> Vec<Tok<String>> {
   vec![
     i!(str: "namespace"),
@@ -29,88 +53,108 @@ pub fn ttypes() -> Vec<Tok<String>> {
   ]
 }
 
-#[derive(Clone, Deserialize)]
-pub struct PatchFile {
-  uri: FileUri,
-  text: String,
-  version: u64,
+/// Map [ttypes] to the types the client knows, see [TokenLegend::new]
+pub fn token_legend(known: Option<&[String]>, opts: &Value) -> TokenLegend {
+  let ttypes = ttypes();
+  TokenLegend::new(&ttypes.iter().map(|t| t.as_str()).collect_vec(), known, &opts["tokenTypes"])
 }
 
-#[derive(Clone, Deserialize)]
-pub struct PatchStore {
-  basepath: FileUri,
-  patches: Vec<PatchFile>,
+/// Translate tokens to document positions, identifying their type by the index
+/// in the legend. Tokens of types the legend leaves out are dropped. See
+/// [SemToken::vscode]
+pub fn encode_tokens(
+  tokens: impl IntoIterator<Item = SemToken>,
+  multiline: bool,
+  legend: &TokenLegend,
+) -> Vec<(DocPos, usize, usize)> {
+  (SemToken::vscode(tokens, multiline).into_iter())
+    .filter_map(|(pos, len, sem)| Some((pos, len, legend.index(sem.typ().as_str())?)))
+    .collect()
 }
-impl PatchStore {
-  pub fn new(basepath: FileUri) -> Arc<Self> { Arc::new(Self { basepath, patches: Vec::new() }) }
-  pub fn unpack(self: Arc<Self>) -> Self { Arc::unwrap_or_clone(self) }
-  pub fn change(self: &mut Arc<Self>, cb: impl FnOnce(&mut Self)) {
-    take_mut::take(self, |arc| {
-      let mut this = arc.unpack();
-      cb(&mut this);
-      Arc::new(this)
-    })
-  }
-  fn index_of(&self, uri: &FileUri) -> Option<usize> {
-    self.patches.iter().find_position(|f| &f.uri == uri).map(|p| p.0)
-  }
-  pub fn basepath(&self) -> &FileUri { &self.basepath }
-  pub fn patch(&mut self, patch: PatchFile) {
-    match self.index_of(&patch.uri) {
-      None => self.patches.push(patch),
-      Some(idx) => {
-        let old = &mut self.patches[idx];
-        if old.version <= patch.version {
-          old.version = patch.version;
-          old.text = patch.text;
-        }
-      },
-    }
-  }
-  pub fn unpatch(&mut self, uri: &FileUri) {
-    match self.index_of(uri) {
-      None => panic!("No existing patch!"),
-      Some(i) => {
-        self.patches.remove(i);
-      },
-    }
-  }
-  pub fn mk_vfs(self: Arc<Self>, path: &FileUri) -> Option<impl VirtFS> {
-    let subpath = path.to_vpath(&self.basepath)?;
-    eprintln!("Building VFS for {subpath} in {}", self.basepath);
-    Some(PrefixFS::new(PatchFS::new(self), "", subpath.to_string()))
-  }
+
+pub fn text_hash(text: &str) -> u64 {
+  let mut hasher = DefaultHasher::new();
+  text.hash(&mut hasher);
+  hasher.finish()
 }
 
-pub struct PatchFS {
-  basedir: DirNode,
-  store: Arc<PatchStore>,
+/// An id for published data that only changes if the data does. The hasher
+/// isn't randomized, so ids are also stable across runs.
+pub fn content_id(data: &impl Hash) -> String {
+  let mut hasher = DefaultHasher::new();
+  data.hash(&mut hasher);
+  format!("{:016x}", hasher.finish())
 }
-impl PatchFS {
-  pub fn new(store: Arc<PatchStore>) -> Self {
-    Self { basedir: DirNode::new(store.basepath().to_path(), ".orc"), store }
-  }
+
+/// Semantic tokens of a file along with the hash of the text and the
+/// [LoadedProject::token_context] they were computed from. Whether a name is
+/// bound depends on the macros and exports of other files, so tokens computed
+/// from the same text in another load may differ.
+#[derive(Clone)]
+pub struct FileTokens {
+  pub hash: u64,
+  pub context: u64,
+  pub tokens: Arc<Vec<SemToken>>,
 }
-impl VirtFS for PatchFS {
-  fn get(&self, path: &[Tok<String>], full_path: &PathSlice) -> FSResult {
-    let pbuf = self.store.basepath();
-    if let Some(i) = self.store.index_of(&pbuf.extended(path.iter().map(|t| t.as_str()))) {
-      return Ok(Loaded::Code(Arc::new(self.store.patches[i].text.clone())));
-    }
-    self.basedir.get(path, full_path)
+impl FileTokens {
+  /// Compute the tokens of a file. The path is relative to the project root.
+  pub fn compute(lpr: &LoadedProject, path: &PathSlice, text: &str) -> Self {
+    let mut tokens = lpr.module_tokens(&path.to_vpath().prefix([i!(str: "tree")]));
+    tokens.sort_unstable();
+    Self { hash: text_hash(text), context: lpr.token_context(), tokens: Arc::new(tokens) }
   }
-  fn display(&self, path: &[Tok<String>]) -> Option<String> { self.basedir.display(path) }
 }
 
 pub struct CtxProj {
-  pub path: VPath,
+  pub root: ProjectRoot,
   pub changes: HashSet<VPath>,
   pub abort: Abort,
+  /// The most recent successfully loaded state of the project. Loads build
+  /// the next one without the lock and replace this when they complete.
+  pub loaded: Option<Arc<LoadedProject>>,
+  /// The [ProjectFiles::fingerprint] of the files [Self::loaded] was loaded
+  /// from. Updates that leave it unchanged don't reload the project.
+  pub fingerprint: Option<u64>,
+  /// Token cache keyed by path within the project, see [FileTokens]
+  pub tokens: HashMap<VPath, FileTokens>,
+  /// URIs of the files the project has published diagnostics for, with the
+  /// hash of the diagnostics and the diagnostics themselves for clients that
+  /// pull them
+  pub diagnosed: HashMap<String, (u64, Vec<Diagnostic>)>,
+  /// Files renamed outside of the editor, from the old path to the new one
+  /// within the project. Imports of the old paths are flagged until fixed.
+  pub moved: Vec<(VPath, VPath)>,
 }
 impl CtxProj {
-  pub fn new(path: VPath) -> Self { Self { path, changes: HashSet::new(), abort: Abort::new() } }
+  pub fn new(root: ProjectRoot) -> Self {
+    let (tokens, diagnosed) = (HashMap::new(), HashMap::new());
+    let (changes, abort) = (HashSet::new(), Abort::new());
+    let (loaded, fingerprint, moved) = (None, None, Vec::new());
+    Self { root, changes, abort, loaded, fingerprint, tokens, diagnosed, moved }
+  }
+  /// Get the cached tokens for a file if they were computed from the same text
+  /// in the same context as the loaded state of the project, possibly by an
+  /// earlier load
+  pub fn cached_tokens(&self, path: &VPath, text: &str) -> Option<Arc<Vec<SemToken>>> {
+    let (ftoks, lpr) = (self.tokens.get(path)?, self.loaded.as_ref()?);
+    let fresh = ftoks.hash == text_hash(text) && ftoks.context == lpr.token_context();
+    fresh.then(|| ftoks.tokens.clone())
+  }
+  /// Path of the project root within the workspace
+  pub fn path(&self) -> &VPath { self.root.path() }
   pub fn path_in<'a>(&self, path: &'a PathSlice) -> Option<&'a PathSlice> {
-    path.strip_prefix(&self.path)
+    path.strip_prefix(self.path())
+  }
+  /// The files of the project as a load sees them
+  pub fn files(&self, patches: Arc<PatchStore>) -> ProjectFiles {
+    ProjectFiles { patches, root: self.root.clone() }
+  }
+  /// Abort the load and drop the loaded project and caches
+  pub fn release(&mut self) {
+    self.abort.abort();
+    self.loaded = None;
+    self.fingerprint = None;
+    self.tokens.clear();
   }
 }
 
@@ -120,6 +164,15 @@ pub struct CtxWsp {
   pub projects: Vec<CtxProj>,
 }
 impl CtxWsp {
+  /// Discover the projects of a workspace folder
+  pub fn new(ent: WspaceEnt) -> Self {
+    let store = PatchStore::new(ent.uri.clone());
+    let wspace_vfs = store.clone().mk_vfs(&store.basepath).unwrap();
+    let projects =
+      find_all_projects(VPath::new([]), &wspace_vfs).into_iter().map(CtxProj::new).collect();
+    CtxWsp { name: ent.name, store, projects }
+  }
+
   pub fn path_in(&self, path: &FileUri) -> Option<VPath> { path.to_vpath(&self.store.basepath) }
 
   pub fn get_proj<'a, 'b>(&'a self, p: &'b PathSlice) -> Option<(&'b PathSlice, &'a CtxProj)> {
@@ -137,19 +190,7 @@ impl CtxWsp {
 pub struct WorkspaceCtx(Vec<CtxWsp>);
 impl WorkspaceCtx {
   pub fn new(wspace_entries: impl IntoIterator<Item = WspaceEnt>) -> Self {
-    Self(
-      wspace_entries
-        .into_iter()
-        .map(|ent| {
-          // let path = uri2path(&ent.uri)?;
-          let store = PatchStore::new(ent.uri.clone());
-          let wspace_vfs = store.clone().mk_vfs(&store.basepath).unwrap();
-          let projects =
-            find_all_projects(VPath::new([]), &wspace_vfs).into_iter().map(CtxProj::new).collect();
-          CtxWsp { name: ent.name, store, projects }
-        })
-        .collect(),
-    )
+    Self(wspace_entries.into_iter().map(CtxWsp::new).collect())
   }
   pub fn get_wsp<'a>(&'a self, path: &FileUri) -> Option<(VPath, &'a CtxWsp)> {
     (self.0.iter())
@@ -161,7 +202,20 @@ impl WorkspaceCtx {
       .filter_map(|e| e.path_in(path).map(|p| (p, e)))
       .max_by_key(|(p, _)| -(p.len() as i32))
   }
-  #[allow(unused)]
+  /// Abort all project loads and drop the loaded projects and caches
+  pub fn release(&mut self) {
+    self.projects_mut().for_each(CtxProj::release)
+  }
+  pub fn roots(&self) -> impl Iterator<Item = &FileUri> {
+    self.0.iter().map(|w| w.store.basepath())
+  }
+  pub fn workspaces(&self) -> impl Iterator<Item = &CtxWsp> { self.0.iter() }
+  pub fn projects(&self) -> impl Iterator<Item = &CtxProj> {
+    self.0.iter().flat_map(|wsp| wsp.projects.iter())
+  }
+  pub fn projects_mut(&mut self) -> impl Iterator<Item = &mut CtxProj> {
+    self.0.iter_mut().flat_map(|wsp| wsp.projects.iter_mut())
+  }
   pub fn get_proj<'a>(&'a self, path: &FileUri) -> Option<(VPath, &'a CtxWsp, &'a CtxProj)> {
     let (subpath, wsp) = self.get_wsp(path)?;
     let (path, proj) = wsp.get_proj(&subpath)?;
@@ -178,9 +232,484 @@ impl WorkspaceCtx {
   }
 }
 
+/// What a request reads about a document: the open documents of its workspace
+/// and the last completed load of its project. Writers replace these rather
+/// than mutate through them, so a snapshot stays consistent while a new load
+/// is built and reading it doesn't hold up anyone else.
+#[derive(Clone)]
+pub struct Snapshot {
+  pub store: Arc<PatchStore>,
+  /// Path of the document relative to its project root, if it's in a project
+  pub path: Option<VPath>,
+  pub loaded: Option<Arc<LoadedProject>>,
+}
+
+/// The workspace state, which only exists once the server is initialized
+pub fn workspace<'a>(g: &'a SessionGuard) -> anyhow::Result<&'a WorkspaceCtx> {
+  g.get::<WorkspaceCtx>().context(LSPErrCode::ServerNotInitialized)
+}
+
+/// Take a snapshot of the state a document is analyzed in, holding the session
+/// lock only for as long as it takes to clone the handles
+pub fn snapshot(session: &Session, uri: &FileUri) -> Option<Snapshot> {
+  let g = session.lock();
+  let wctx = g.get::<WorkspaceCtx>()?;
+  let (subpath, wsp) = wctx.get_wsp(uri)?;
+  let (path, loaded) = match wsp.get_proj(&subpath) {
+    Some((path, proj)) => (Some(path.to_vpath()), proj.loaded.clone()),
+    None => (None, None),
+  };
+  Some(Snapshot { store: wsp.store.clone(), path, loaded })
+}
+
 static THREADCNT: AtomicUsize = AtomicUsize::new(0);
 
-fn process_update(patch: PatchFile, session: Session) {
+/// Drop the cached disk contents of files that changed on disk and reload the
+/// projects among them that have open documents. Each project is reloaded once
+/// with all of its changed files. Files open in the editor are owned by the
+/// client, their changes arrive through `didChange`.
+pub fn files_changed(uris: impl IntoIterator<Item = FileUri>, session: Session) {
+  let mut g = session.lock();
+  // The root of each project to reload and the file that triggers it
+  let mut triggers = Vec::<(FileUri, FileUri)>::new();
+  // Projects without open documents whose pending loads are out of date
+  let mut stale = Vec::new();
+  // Findings of projects without open documents, which no load replaces
+  let mut outdated = Vec::new();
+  for uri in uris {
+    let path = uri.to_path();
+    if !path.exists() {
+      diagnostics::forget(&mut g, &uri);
+    }
+    let is_config = path.ends_with(config::FILE_NAME);
+    if is_config {
+      config::forget_project_config(&mut g, path.parent());
+    }
+    let Some(wctx) = g.get_mut::<WorkspaceCtx>() else { return };
+    if let Some((_, wsp)) = wctx.get_wsp(&uri) {
+      wsp.store.invalidate(&uri);
+    }
+    let Some((path, store, proj)) = wctx.get_proj_mut(&uri) else { continue };
+    // The configuration isn't part of the fingerprint
+    if is_config {
+      proj.fingerprint = None;
+    }
+    // Projects without open documents are analyzed on demand
+    if store.index_of(&uri).is_some() || !store.has_open(&proj.path()[..]) {
+      if !store.has_open(&proj.path()[..]) {
+        outdated.extend(mem::take(&mut proj.diagnosed).into_keys());
+      }
+      stale.push(store.basepath().extended(proj.path().clone()));
+      continue;
+    }
+    if !path.is_empty() {
+      proj.changes.insert(path);
+    }
+    let root = store.basepath().extended(proj.path().clone());
+    if !triggers.iter().any(|(r, _)| r == &root) {
+      triggers.push((root, uri));
+    }
+  }
+  if let Some(cold) = g.get_mut::<ColdLoads>() {
+    stale.iter().for_each(|root| cold.evict(root));
+  }
+  diagnostics::clear(&mut g, outdated);
+  mem::drop(g);
+  for (_, uri) in triggers {
+    process_update(uri, false, session.clone())
+  }
+}
+
+/// Record that a file was renamed outside of the editor. The change to both
+/// paths is reported separately through [files_changed].
+pub fn file_moved(session: &Session, from: &FileUri, to: &FileUri) {
+  let mut g = session.lock();
+  let Some(wctx) = g.get_mut::<WorkspaceCtx>() else { return };
+  let Some((old, _, old_proj)) = wctx.get_proj(from) else { return };
+  let old_root = old_proj.root.clone();
+  let Some((new, _, proj)) = wctx.get_proj_mut(to) else { return };
+  // Moves between projects break imports in ways a rename can't fix
+  if proj.root != old_root {
+    return;
+  }
+  // A file moved back to where it was is no longer moved, and a file moved
+  // again is tracked from its original path
+  proj.moved.retain(|(orig, _)| *orig != new);
+  match proj.moved.iter_mut().find(|(_, cur)| *cur == old) {
+    Some((_, cur)) => *cur = new,
+    None => proj.moved.push((old, new)),
+  }
+}
+
+/// Reload every loaded project, so that a change to the configuration applies
+/// without waiting for an edit
+pub fn reload_all(session: &Session) {
+  let mut g = session.lock();
+  let Some(wctx) = g.get_mut::<WorkspaceCtx>() else { return };
+  // The configuration isn't part of the fingerprint
+  wctx.projects_mut().for_each(|p| p.fingerprint = None);
+  let roots = (wctx.workspaces())
+    .flat_map(|wsp| {
+      let loaded = wsp.projects.iter().filter(|p| p.loaded.is_some());
+      loaded.map(|p| wsp.store.basepath().extended(p.path().clone()))
+    })
+    .collect_vec();
+  mem::drop(g);
+  for root in roots {
+    process_update(root, false, session.clone())
+  }
+}
+
+pub const DISCOVER_COMMAND: &str = "orchid.discoverProjects";
+
+/// Find the projects of every workspace folder again, after a project file
+/// was created or deleted. Projects that are found again keep their state, the
+/// others are dropped along with their diagnostics, and new projects with
+/// open documents are loaded.
+pub fn rediscover(session: &Session) {
+  let mut g = session.lock();
+  let Some(wctx) = g.get_mut::<WorkspaceCtx>() else { return };
+  let (mut dropped, mut roots) = (Vec::new(), Vec::new());
+  for wsp in wctx.0.iter_mut() {
+    let vfs = wsp.store.clone().mk_vfs(&wsp.store.basepath).expect("The base path is in the store");
+    let mut old = mem::take(&mut wsp.projects);
+    for root in find_all_projects(VPath::new([]), &vfs) {
+      match old.iter().position(|p| p.root == root) {
+        Some(i) => wsp.projects.push(old.swap_remove(i)),
+        None => {
+          if wsp.store.has_open(&root.path()[..]) {
+            roots.push(wsp.store.basepath().extended(root.path().clone()));
+          }
+          wsp.projects.push(CtxProj::new(root));
+        },
+      }
+    }
+    dropped.extend(old);
+  }
+  dropped.iter_mut().for_each(CtxProj::release);
+  diagnostics::clear(&mut g, dropped.into_iter().flat_map(|p| p.diagnosed.into_keys()));
+  mem::drop(g);
+  for root in roots {
+    process_update(root, false, session.clone())
+  }
+}
+
+/// Replace the workspace folders, for example with those the client sent on
+/// request. Folders that remain keep their state, the others are dropped along
+/// with their diagnostics, and the open documents of new folders are loaded.
+pub fn set_folders(session: &Session, folders: Vec<WspaceEnt>) {
+  let mut g = session.lock();
+  let Some(wctx) = g.get_mut::<WorkspaceCtx>() else { return };
+  let (mut old, mut added) = (mem::take(&mut wctx.0), Vec::new());
+  for ent in folders {
+    match old.iter().position(|wsp| *wsp.store.basepath() == ent.uri) {
+      Some(i) => wctx.0.push(CtxWsp { name: ent.name, ..old.swap_remove(i) }),
+      None => {
+        added.push(ent.uri.clone());
+        wctx.0.push(CtxWsp::new(ent))
+      },
+    }
+  }
+  let mut dropped = old.into_iter().flat_map(|wsp| wsp.projects).collect_vec();
+  dropped.iter_mut().for_each(CtxProj::release);
+  diagnostics::clear(&mut g, dropped.into_iter().flat_map(|p| p.diagnosed.into_keys()));
+  let docs = g.get::<DocumentStore>().expect("Set during initialization");
+  let open = (docs.uris())
+    .filter(|uri| added.iter().any(|root| uri.to_vpath(root).is_some()))
+    .cloned()
+    .collect_vec();
+  mem::drop(g);
+  for uri in open {
+    process_update(uri, true, session.clone())
+  }
+}
+
+/// Run [rediscover]. The arguments are files that were just created or
+/// deleted, which the disk cache may not have noticed yet.
+pub fn discover_command(session: &Session, args: &Value) -> anyhow::Result<Value> {
+  let uris = <Option<Vec<FileUri>>>::deserialize(args).context(LSPErrCode::InvalidParams)?;
+  let uris = uris.unwrap_or_default();
+  let g = session.lock();
+  let wctx = workspace(&g)?;
+  for uri in uris.iter() {
+    if let Some((_, wsp)) = wctx.get_wsp(uri) {
+      wsp.store.invalidate(uri);
+    }
+  }
+  mem::drop(g);
+  rediscover(session);
+  Ok(Value::Null)
+}
+
+/// Abort every load, cancel the waiting reloads and drop the workspaces along
+/// with their diagnostics and the loads started by requests. Threads that are
+/// still running find an empty workspace list.
+pub fn reset(g: &mut SessionGuard) {
+  diagnostics::forget_all(g);
+  if let Some(wctx) = g.get_mut::<WorkspaceCtx>() {
+    wctx.release();
+  }
+  g.set(WorkspaceCtx::new([]));
+  g.set(DocumentStore::default());
+  if let Some(cold) = g.remove::<ColdLoads>() {
+    cold.0.values().for_each(|load| load.abort.abort());
+  }
+  if let Some(pending) = g.remove::<PendingReloads>() {
+    let scheduler = g.scheduler();
+    for timer in pending.0.into_values() {
+      scheduler.cancel(timer);
+    }
+  }
+}
+
+/// `coldLoadTimeoutMs` and `coldLoadMaxKb`, the ceilings on loads of projects
+/// without open documents that requests wait for
+#[derive(Clone, Copy, Debug)]
+pub struct ColdLoadConfig {
+  /// How long a request waits for the load. The load continues after the
+  /// request failed, and the retry gets its result.
+  pub timeout: Duration,
+  /// Projects with more source than this aren't loaded for a request. Zero
+  /// turns the limit off.
+  pub max_bytes: usize,
+}
+impl Default for ColdLoadConfig {
+  fn default() -> Self { Self { timeout: Duration::from_secs(3), max_bytes: 8 << 20 } }
+}
+
+/// Read the ceilings from the initialization options or the `orchidls`
+/// settings section, defaulting to the base
+pub fn cold_load_config(opts: &Value, base: ColdLoadConfig) -> ColdLoadConfig {
+  let timeout = opts["coldLoadTimeoutMs"].as_u64().map(Duration::from_millis);
+  let max_bytes = opts["coldLoadMaxKb"].as_u64().map(|kb| kb as usize * 1024);
+  ColdLoadConfig {
+    timeout: timeout.unwrap_or(base.timeout),
+    max_bytes: max_bytes.unwrap_or(base.max_bytes),
+  }
+}
+
+/// `debounceMs`, how long a reload that wasn't caused by an edit waits for
+/// further changes before loading, so that bulk changes on disk such as
+/// switching branches load each project once
+#[derive(Clone, Copy, Debug)]
+pub struct Debounce(pub Duration);
+impl Default for Debounce {
+  fn default() -> Self { Self(Duration::from_millis(100)) }
+}
+
+/// Read the debounce from the initialization options or the `orchidls`
+/// settings section, defaulting to the base
+pub fn debounce_config(opts: &Value, base: Debounce) -> Debounce {
+  Debounce(opts["debounceMs"].as_u64().map_or(base.0, Duration::from_millis))
+}
+
+/// The outcome of a load of a project without open documents, shared by every
+/// request waiting for it
+#[derive(Default)]
+struct ColdResult(Mutex<Option<Option<Arc<LoadedProject>>>>, Condvar);
+impl ColdResult {
+  fn set(&self, lpr: Option<Arc<LoadedProject>>) {
+    *self.0.lock().unwrap() = Some(lpr);
+    self.1.notify_all()
+  }
+
+  /// The outcome, or None if the load didn't finish in time
+  fn wait(&self, timeout: Duration) -> Option<Option<Arc<LoadedProject>>> {
+    let g = self.0.lock().unwrap();
+    self.1.wait_timeout_while(g, timeout, |res| res.is_none()).unwrap().0.clone()
+  }
+}
+
+/// A load of a project without open documents
+#[derive(Clone)]
+struct ColdLoad {
+  abort: Abort,
+  result: Arc<ColdResult>,
+}
+
+/// Loads of projects without open documents that are running or outlasted the
+/// request that started them, by project root. Concurrent requests and the
+/// retries of timed out ones wait for the same load instead of starting
+/// another one. Loads are aborted when their project changes.
+#[derive(Default)]
+struct ColdLoads(HashMap<FileUri, ColdLoad>);
+impl ColdLoads {
+  /// Abort and forget the load of a project
+  fn evict(&mut self, root: &FileUri) {
+    if let Some(load) = self.0.remove(root) {
+      load.abort.abort()
+    }
+  }
+}
+
+/// The loaded state of the project containing a file. Projects without open
+/// documents aren't kept loaded, so they're loaded for the caller and dropped
+/// once it's done with them. Such loads are subject to [ColdLoadConfig]; if
+/// the load takes too long or the project changes while loading, the request
+/// fails with [LSPErrCode::ServerCancelled] so that the client retries it.
+pub fn analyze(session: &Session, uri: &FileUri) -> anyhow::Result<Arc<LoadedProject>> {
+  let g = session.lock();
+  let wctx = workspace(&g)?;
+  let Some((_, wsp, proj)) = wctx.get_proj(uri) else {
+    let message = format!("{uri} is outside of every workspace folder");
+    return Err(anyhow!(LSPErrCode::InvalidParams).context(message));
+  };
+  if let Some(lpr) = &proj.loaded {
+    return Ok(lpr.clone());
+  }
+  let files = proj.files(wsp.store.clone());
+  let root = files.file_uri(&[]);
+  let limits = g.get::<ColdLoadConfig>().copied().unwrap_or_default();
+  let pending = g.get::<ColdLoads>().and_then(|c| c.0.get(&root)).cloned();
+  mem::drop(g);
+  let load = match pending {
+    Some(load) => load,
+    None => {
+      let sources = files.source_files();
+      let size = sources.iter().filter_map(|f| files.source(f)).map(|t| t.len()).sum::<usize>();
+      if limits.max_bytes != 0 && limits.max_bytes < size {
+        let message = format!(
+          "{} has {}KB of source, which is over the limit for loading it on demand. Open one of \
+           its files to load it in the background.",
+          files.root,
+          size / 1024,
+        );
+        return Err(anyhow!(LSPErrCode::RequestFailed).context(message));
+      }
+      let gas = project_config(session, &files.config_dir()).macro_gas;
+      let mut g = session.lock();
+      if g.get::<ColdLoads>().is_none() {
+        g.set(ColdLoads::default());
+      }
+      let cold = g.get_mut::<ColdLoads>().unwrap();
+      // Another request may have started the load in the meantime
+      if let Some(load) = cold.0.get(&root) {
+        load.clone()
+      } else {
+        let load = ColdLoad { abort: Abort::new(), result: Arc::default() };
+        cold.0.insert(root.clone(), load.clone());
+        mem::drop(g);
+        let (abort, result) = (load.abort.clone(), load.result.clone());
+        thread::Builder::new()
+          .name("cold-load".into())
+          .stack_size(1 << 26)
+          .spawn(move || {
+            let ProjectFiles { patches, root } = files;
+            // Waiting requests are released even if the load panics
+            let lpr = panic::catch_unwind(AssertUnwindSafe(|| {
+              LoadedProject::new(patches, root, gas, abort, &mut |_, _| ())
+            }));
+            result.set(lpr.ok().and_then(Result::ok).map(Arc::new))
+          })
+          .expect("Failed to spawn cold load thread");
+        load
+      }
+    },
+  };
+  let Some(lpr) = load.result.wait(limits.timeout) else {
+    let message = "The project is still loading, try again shortly";
+    return Err(anyhow!(LSPErrCode::ServerCancelled).context(message));
+  };
+  // The load is done, later requests load the project again
+  let mut g = session.lock();
+  if let Some(cold) = g.get_mut::<ColdLoads>() {
+    if cold.0.get(&root).is_some_and(|l| Arc::ptr_eq(&l.result, &load.result)) {
+      cold.0.remove(&root);
+    }
+  }
+  match lpr {
+    Some(lpr) => Ok(lpr),
+    None if load.abort.aborted() => {
+      let message = "The project changed while loading, try again";
+      Err(anyhow!(LSPErrCode::ServerCancelled).context(message))
+    },
+    None => None.context(LSPErrCode::RequestFailed),
+  }
+}
+
+/// A project load reported to the client with `orchid/analysisStarted`. The
+/// load is reported as cancelled when this is dropped without [Self::finish].
+struct Analysis {
+  session: Session,
+  id: usize,
+  project: String,
+  start: Instant,
+  finished: bool,
+}
+impl Analysis {
+  fn begin(session: &Session, id: usize, files: &ProjectFiles) -> Self {
+    let project = files.file_uri(&[]).stringify(files.root.is_file());
+    session.send::<AnalysisStarted>(AnalysisStartedParams { id, project: project.clone() });
+    Self { session: session.clone(), id, project, start: Instant::now(), finished: false }
+  }
+
+  /// The report of the load with no statistics
+  fn params(&self, outcome: AnalysisOutcome) -> AnalysisFinishedParams {
+    AnalysisFinishedParams {
+      id: self.id,
+      project: self.project.clone(),
+      outcome,
+      duration_ms: self.start.elapsed().as_millis() as u64,
+      files: 0,
+      constants: 0,
+      errors: 0,
+      warnings: 0,
+    }
+  }
+
+  fn finish(mut self, g: &mut SessionGuard, params: AnalysisFinishedParams) {
+    self.finished = true;
+    g.send::<AnalysisFinished>(params)
+  }
+}
+impl Drop for Analysis {
+  fn drop(&mut self) {
+    if !self.finished {
+      self.session.send::<AnalysisFinished>(self.params(AnalysisOutcome::Cancelled))
+    }
+  }
+}
+
+/// Reloads waiting out the [Debounce], by project root
+#[derive(Default)]
+struct PendingReloads(HashMap<FileUri, TimerId>);
+
+/// Reload the project containing `uri`. If the document was edited, its
+/// changes are applied first and the load starts right away. Otherwise the
+/// load waits until the project didn't change for the [Debounce].
+fn process_update(uri: FileUri, edited: bool, session: Session) {
+  let mut g = session.lock();
+  let root = g.get::<WorkspaceCtx>().and_then(|wctx| {
+    let (_, wsp, proj) = wctx.get_proj(&uri)?;
+    Some(proj.files(wsp.store.clone()).file_uri(&[]))
+  });
+  let (debounce, scheduler) = (g.get::<Debounce>().copied().unwrap_or_default(), g.scheduler());
+  if g.get::<PendingReloads>().is_none() {
+    g.set(PendingReloads::default());
+  }
+  let pending = g.get_mut::<PendingReloads>().unwrap();
+  // The waiting reload would only abort this one
+  if let Some(timer) = root.as_ref().and_then(|root| pending.0.remove(root)) {
+    scheduler.cancel(timer);
+  }
+  let Some(root) = root.filter(|_| !edited) else {
+    mem::drop(g);
+    return spawn_update(uri, edited, session);
+  };
+  let root2 = root.clone();
+  let timer = scheduler.after(debounce.0, move |session| {
+    if let Some(pending) = session.lock().get_mut::<PendingReloads>() {
+      pending.0.remove(&root2);
+    }
+    spawn_update(uri, false, session)
+  });
+  pending.0.insert(root, timer);
+}
+
+/// Load the project containing `uri` on a new thread, aborting the previous
+/// load of the project
+fn spawn_update(uri: FileUri, edited: bool, session: Session) {
+  let received = Instant::now();
   // This task thread contains 2 critical sections. The first sets the abort flag
   // for the previous instance and replaces it with its own abort flag, the
   // second checks the state of the abort flag after locking. This ensures that
@@ -189,50 +718,167 @@ fn process_update(patch: PatchFile, session: Session) {
     .stack_size(1 << 26)
     .spawn(move || {
       let id = THREADCNT.fetch_add(1, atomic::Ordering::Relaxed);
-      eprintln!("~{id} Spawned");
+      log!(Debug, "~{id} Spawned");
       // Using session while this is live would deadlock
       let mut g = session.lock();
+      // Changes that arrived since the last snapshot are included, later ones
+      // wait for the next load
+      let snap = match edited {
+        false => None,
+        true => match g.get_mut::<DocumentStore>().unwrap().snapshot(&uri) {
+          Ok(snap) => Some(snap),
+          Err(e) => {
+            log!(Error, "~{id} {uri} stays at its last good version until it's resent: {e}");
+            return;
+          },
+        },
+      };
       let fsctx = g.get_mut::<WorkspaceCtx>().unwrap();
-      let uri = patch.uri.clone();
-      let (in_wsp, entry) = fsctx.get_wsp_mut(&uri).unwrap();
-      entry.store.change(|s| s.patch(patch));
+      let Some((in_wsp, entry)) = fsctx.get_wsp_mut(&uri) else {
+        log!(Info, "~{id} {uri} is outside the workspace, ignoring it");
+        return;
+      };
+      if let Some(snap) = snap {
+        entry.store.change(|s| s.patch(uri.clone(), snap));
+      }
       let patches = entry.store.clone();
+      let roots = entry.projects.iter().map(|p| p.root.clone()).collect_vec();
       let (in_proj, proj) = match entry.get_proj_mut(&in_wsp) {
         Some(p) => p,
+        // For example a file created after the projects were discovered
         None => {
-          eprintln!("Could not find {in_wsp} in {} while resolving {uri}", patches.basepath);
-          panic!("Entry only contains {}", entry.projects.iter().map(|p| &p.path).join(", "))
+          log!(Error, "Could not find {in_wsp} in {} while resolving {uri}", patches.basepath);
+          let projects = entry.projects.iter().map(|p| &p.root).join(", ");
+          log!(Error, "~{id} Entry only contains {projects}");
+          return;
         },
       };
       proj.abort.abort();
       let abort = Abort::new();
       proj.abort = abort.clone();
-      proj.changes.insert(in_proj.to_vpath());
+      // Reloads of the whole project don't change any file
+      if !in_proj.is_empty() {
+        proj.changes.insert(in_proj.to_vpath());
+      }
       let changes = proj.changes.clone();
-      let proj_root = proj.path.clone();
+      let files = proj.files(patches.clone());
+      let moved = proj.moved.clone();
+      let edited_path = in_proj.to_vpath();
+      let visible = if edited { viewport::visible(&g, &uri) } else { None };
+      let legend = g.get::<TokenLegend>().cloned().expect("Set during initialization");
+      mem::drop(g);
+      // Saves without changes, reverted edits and the like would load the same
+      // project again
+      let fingerprint = files.fingerprint();
+      let mut g = session.lock();
+      if !abort.is_valid() {
+        return;
+      }
+      if let Some((_, _, proj)) = g.get_mut::<WorkspaceCtx>().unwrap().get_proj_mut(&uri) {
+        if proj.loaded.is_some() && proj.fingerprint == Some(fingerprint) {
+          log!(Debug, "~{id} {} is unchanged, keeping the loaded state", files.root);
+          proj.changes.clear();
+          return;
+        }
+      }
       mem::drop(g);
-      let lpr = LoadedProject::new(patches.clone(), proj_root, abort.clone())
-        .unwrap_or_else(|ev| panic!("{}", ev.into_iter().join("\n\n")));
-      eprintln!("~{id} loaded project");
-      let ttypes = ttypes();
+      let analysis = Analysis::begin(&session, id, &files);
+      let start = Instant::now();
+      let title = format!("Loading {}", files.root.path());
+      let progress = WorkProgress::begin(&session, &title, Some(abort.clone()));
+      let mut macros_start = None;
+      let mut report = |phase: &str, percentage| {
+        if phase == MACRO_PHASE {
+          macros_start.get_or_insert_with(Instant::now);
+        }
+        progress.report(phase, percentage)
+      };
+      let config = project_config(&session, &files.config_dir());
+      let (gas, abort2, root) = (config.macro_gas, abort.clone(), files.root.clone());
+      let lpr = match LoadedProject::new(patches.clone(), root, gas, abort2, &mut report) {
+        Ok(lpr) => lpr,
+        Err(_) if abort.aborted() => return,
+        Err(ev) => {
+          // The previous state of the project remains available for navigation
+          let sources = files.source_files();
+          let mut g = session.lock();
+          let docs = g.get::<DocumentStore>().unwrap();
+          let open = (sources.into_iter())
+            .filter(|file| docs.is_open(&files.file_uri(file)))
+            .collect_vec();
+          let diagnostics = diagnostics::load_errors(&files, &ev, &open);
+          if abort.is_valid() {
+            // The diagnostics no longer match the loaded state
+            if let Some((_, _, proj)) = g.get_mut::<WorkspaceCtx>().unwrap().get_proj_mut(&uri) {
+              proj.fingerprint = None;
+            }
+            let errors = diagnostics::count(&diagnostics, 1);
+            let warnings = diagnostics::count(&diagnostics, 2);
+            let report = analysis.params(AnalysisOutcome::Failed);
+            diagnostics::publish(&mut g, &uri, diagnostics);
+            analysis.finish(&mut g, AnalysisFinishedParams { errors, warnings, ..report });
+          }
+          return;
+        },
+      };
+      let load_time = start.elapsed();
+      let loaded = Instant::now();
+      log!(Debug, "~{id} loaded project");
       let mut file_tokens = HashMap::new();
-      for path in changes.into_iter() {
+      let file_count = changes.len();
+      // The edited file is tokenized first so that its visible lines can be
+      // pushed ahead of the rest
+      let changes =
+        changes.into_iter().sorted_by_cached_key(|p| (p != &edited_path, p.to_string()));
+      for (i, path) in changes.enumerate() {
         if abort.aborted() {
           return;
         }
-        let mut tokens = lpr.module_tokens(&path.clone().prefix([i!(str: "tree")]));
-        tokens.sort_unstable();
-        if tokens.is_empty() {
-          continue;
+        progress.report(&format!("Tokenizing {path}"), 80 + (20 * i / file_count) as u32);
+        let Some(text) = lpr.source(&path) else { continue };
+        let ftoks = FileTokens::compute(&lpr, &path, &text);
+        if let (Some(visible), true) = (&visible, path == edited_path) {
+          let tokens = (encode_tokens(ftoks.tokens.iter().cloned(), false, &legend).into_iter())
+            .filter(|(pos, ..)| visible(pos.line))
+            .map(|(pos, len, typ)| (pos.line, pos.char, len, typ))
+            .collect_vec();
+          let text_document = DocumentRef { uri: uri.stringify(true) };
+          let mut g = session.lock();
+          if !abort.is_valid() {
+            return;
+          }
+          // Lines scrolled out of view keep their tokens until the full push
+          let tokens = viewport::with_hidden(&g, &text_document.uri, visible, tokens);
+          let result_id = content_id(&tokens);
+          g.send::<SyntacticTokens>(SyntacticTokensParams {
+            text_document,
+            result_id,
+            tokens,
+            legend: legend.types().to_vec(),
+          });
         }
-        let tokens = (SemToken::vscode(tokens).into_iter())
-          .map(|(pos, len, sem)| {
-            let typ = ttypes.iter().position(|x| x == &sem.typ()).expect("ttype not found");
-            (pos.line, pos.char, len, typ)
-          })
-          .collect_vec();
-        file_tokens.insert(path, tokens);
+        file_tokens.insert(path.clone(), ftoks);
       }
+      let diagnostics = diagnostics::compute(&lpr, &config, &moved, &roots);
+      // Open documents are highlighted first, the rest follows as the client
+      // gets to it
+      let mut pushes = (file_tokens.iter())
+        .map(|(path, ftoks)| {
+          // The syntactic token push is consumed by ranges which can't span lines
+          let tokens = (encode_tokens(ftoks.tokens.iter().cloned(), false, &legend).into_iter())
+            .map(|(pos, len, typ)| (pos.line, pos.char, len, typ))
+            .collect_vec();
+          let uri = lpr.file_uri(path);
+          let open = patches.text(&uri).is_some();
+          let text_document = DocumentRef { uri: lpr.doc_uri(path) };
+          let result_id = content_id(&tokens);
+          let legend = legend.types().to_vec();
+          (open, SyntacticTokensParams { text_document, result_id, tokens, legend })
+        })
+        .collect_vec();
+      pushes.sort_by(|(a_open, a), (b_open, b)| {
+        b_open.cmp(a_open).then_with(|| a.text_document.uri.cmp(&b.text_document.uri))
+      });
       let mut g = session.lock();
       // this asserts that between the two regions synchronized over ctx a new process
       // has not been spawned
@@ -240,26 +886,63 @@ fn process_update(patch: PatchFile, session: Session) {
         return;
       }
       let fsctx = g.get_mut::<WorkspaceCtx>().unwrap();
-      let (store, proj) = match fsctx.get_proj_mut(&uri) {
+      let proj = match fsctx.get_proj_mut(&uri) {
         // We find the project via the trigger URI, but the corresponding path is useless
-        Some((_, store, proj)) => (store, proj),
+        Some((_, _, proj)) => proj,
         None => {
-          eprintln!("Syntax not delivered because the project has been deleted");
+          log!(Info, "Syntax not delivered because the project has been deleted");
           return;
         },
       };
       proj.changes = HashSet::new();
-      let proj_root = proj.path.clone();
-      for (path, tokens) in file_tokens {
-        let uri = store.basepath().extended(proj_root.as_slice().iter().chain(path.as_slice()));
-        g.notify(
-          "client/syntacticTokens",
-          json!({
-            "textDocument": { "uri": uri.stringify(true) },
-            "tokens": tokens,
-            "legend": &ttypes,
-          }),
-        )
+      let (files, constants) = lpr.size();
+      proj.loaded = Some(Arc::new(lpr));
+      proj.fingerprint = Some(fingerprint);
+      proj.tokens.extend(file_tokens);
+      let duration_ms = load_time.as_millis() as u64;
+      telemetry::emit(&mut g, TelemetryParams::ProjectLoad { duration_ms, files, constants });
+      let errors = diagnostics::count(&diagnostics, 1);
+      let warnings = diagnostics::count(&diagnostics, 2);
+      let report = analysis.params(AnalysisOutcome::Loaded);
+      diagnostics::publish(&mut g, &uri, diagnostics);
+      let report = AnalysisFinishedParams { files, constants, errors, warnings, ..report };
+      analysis.finish(&mut g, report);
+      mem::drop(g);
+      // The lock is released between chunks so that requests aren't held up by
+      // a large push, for example after switching branches
+      let mut pushes = pushes.into_iter().peekable();
+      while pushes.peek().is_some() {
+        let mut size = 0;
+        let chunk = pushes.peeking_take_while(|(_, p)| {
+          size += p.tokens.len();
+          size <= PUSH_CHUNK_TOKENS || size == p.tokens.len()
+        });
+        let chunk = chunk.collect_vec();
+        let mut g = session.lock();
+        // A newer load will push its own tokens
+        if !abort.is_valid() {
+          return;
+        }
+        for (open, params) in chunk {
+          if open {
+            viewport::pushed(&mut g, &params.text_document.uri, &params.tokens);
+          }
+          logging::log(&mut g, "Pushing syntactic tokens", || {
+            format!("{} {:?}", params.text_document.uri, params.tokens)
+          });
+          g.send::<SyntacticTokens>(params);
+        }
+        mem::drop(g);
+        thread::yield_now();
+      }
+      if edited {
+        let macros_start = macros_start.unwrap_or(loaded);
+        latency::record(&session, &uri.stringify(true), Breakdown {
+          queue: start - received,
+          load: macros_start - start,
+          macros: loaded - macros_start,
+          encode: loaded.elapsed(),
+        });
       }
     })
     .unwrap();
@@ -267,33 +950,84 @@ fn process_update(patch: PatchFile, session: Session) {
 
 pub fn attach(srv: &mut JrpcServer) {
   srv.on_notif("textDocument/didOpen", |req, session| {
-    let text_doc = &req.unwrap()["textDocument"];
-    let lid = text_doc["languageId"].as_str().unwrap();
-    if lid != "orchid" {
-      eprintln!("Document has wrong lid \"{lid}\"");
+    let text_doc = &req.unwrap_or(&Value::Null)["textDocument"];
+    let item = match TextDocumentItem::deserialize(text_doc) {
+      Ok(item) => item,
+      Err(e) => {
+        log!(Error, "Malformed didOpen: {e}");
+        return;
+      },
+    };
+    if item.language_id != "orchid" {
+      log!(Warn, "Document has wrong lid \"{}\"", item.language_id);
       return;
     }
-    let patch = PatchFile::deserialize(text_doc).unwrap();
-    process_update(patch, session)
+    // The client may have missed a didClose or restarted the document. The
+    // version counts from the open, so the old text is dropped.
+    let mut g = session.lock();
+    if g.get_mut::<DocumentStore>().unwrap().open(item.uri.clone(), item.version, item.text) {
+      log!(Warn, "{} opened again, replacing its text", item.uri);
+    }
+    mem::drop(g);
+    process_update(item.uri, true, session)
   });
   srv.on_notif("textDocument/didClose", |req, session| {
-    let uri = FileUri::deserialize(&req.unwrap()["textDocument"]["uri"]).unwrap();
+    let Ok(uri) = FileUri::deserialize(&req.unwrap_or(&Value::Null)["textDocument"]["uri"]) else {
+      log!(Error, "Malformed didClose");
+      return;
+    };
     let mut ctx = session.lock();
+    if !ctx.get_mut::<DocumentStore>().unwrap().close(&uri) {
+      log!(Warn, "Closed {uri} which was never opened");
+      return;
+    }
     let fsctx = ctx.get_mut::<WorkspaceCtx>().unwrap();
-    let (_, entry) = fsctx.get_wsp_mut(&uri).unwrap();
+    let Some((in_wsp, entry)) = fsctx.get_wsp_mut(&uri) else {
+      log!(Info, "Closed {uri} which is outside the workspace");
+      return;
+    };
     // release file so that external updates are received
-    entry.store.change(|s| s.unpatch(&uri));
+    entry.store.change(|s| {
+      s.unpatch(&uri);
+    });
+    // Only projects with open documents are kept loaded
+    let store = entry.store.clone();
+    if let Some((_, proj)) = entry.get_proj_mut(&in_wsp) {
+      if !store.has_open(&proj.path()[..]) {
+        proj.release()
+      }
+    }
   });
   srv.on_notif("textDocument/didChange", |req, session| {
-    let req = req.unwrap();
+    let req = req.unwrap_or(&Value::Null);
     let text_doc = &req["textDocument"];
-    let last_change = req["contentChanges"].as_array().unwrap().last().unwrap();
-    assert!(last_change.get("range").is_none(), "We requested absolute changes only");
-    let patch = PatchFile {
-      uri: FileUri::deserialize(&text_doc["uri"]).unwrap(),
-      version: text_doc["version"].as_u64().unwrap(),
-      text: String::deserialize(&last_change["text"]).unwrap(),
+    let (Ok(uri), Some(version), Ok(mut changes)) = (
+      FileUri::deserialize(&text_doc["uri"]),
+      text_doc["version"].as_u64(),
+      Vec::<ContentChange>::deserialize(&req["contentChanges"]),
+    ) else {
+      log!(Error, "Malformed didChange");
+      return;
     };
-    process_update(patch, session)
+    if changes.is_empty() {
+      log!(Warn, "didChange without changes");
+      return;
+    }
+    let mut g = session.lock();
+    let docs = g.get_mut::<DocumentStore>().unwrap();
+    if !docs.is_open(&uri) {
+      // The full text is enough to open a document the client never announced
+      if changes.last().is_some_and(|c| c.range.is_some()) {
+        log!(Error, "{uri} changed without being opened");
+        return;
+      }
+      log!(Warn, "{uri} changed without being opened, opening it");
+      docs.open(uri.clone(), version, changes.pop().unwrap().text);
+    } else if let Err(e) = docs.change(&uri, version, changes) {
+      log!(Warn, "Ignoring a change to {uri}: {e}");
+      return;
+    }
+    mem::drop(g);
+    process_update(uri, true, session)
   })
 }
@@ -0,0 +1,81 @@
+//! `orchid/hotspots`, the constants that take the most macro steps to expand
+//! and the rules that can fire on them. The steps are recorded while a project
+//! loads, so asking again with a different order is cheap.
+
+use std::cmp::Reverse;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context};
+use itertools::Itertools;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::fs::{snapshot, workspace};
+use crate::jrpc::{JrpcServer, Session};
+use crate::orc::gas::suspects;
+use crate::orc::project::LoadedProject;
+use crate::orc::rules::{file_of, source_rules};
+use crate::protocol::document::FileUri;
+use crate::protocol::error::LSPErrCode;
+
+/// The number of constants listed unless the client asks for a different one
+const DEFAULT_COUNT: usize = 50;
+
+/// The project of the document if one is named, otherwise every loaded project
+fn projects(session: &Session, params: &Value) -> anyhow::Result<Vec<Arc<LoadedProject>>> {
+  let uri = &params["textDocument"]["uri"];
+  if !uri.is_null() {
+    let uri = FileUri::deserialize(uri).context(LSPErrCode::InvalidParams)?;
+    return Ok(snapshot(session, &uri).and_then(|snap| snap.loaded).into_iter().collect());
+  }
+  let g = session.lock();
+  let wctx = workspace(&g)?;
+  Ok(wctx.projects().filter_map(|p| p.loaded.clone()).collect())
+}
+
+pub fn attach(srv: &mut JrpcServer) {
+  srv.on_req_sync("orchid/hotspots", |params, session| {
+    let params = params.unwrap_or(&Value::Null);
+    let by_name = match params["sortBy"].as_str() {
+      None | Some("steps") => false,
+      Some("name") => true,
+      Some(_) => {
+        let err = anyhow!("sortBy must be \"steps\" or \"name\"");
+        return Err(err.context(LSPErrCode::InvalidParams));
+      },
+    };
+    let count = params["count"].as_u64().map_or(DEFAULT_COUNT, |n| n as usize);
+    let projects = projects(&session, params)?;
+    let mut found = (projects.iter().enumerate())
+      .flat_map(|(i, lpr)| lpr.hotspots().iter().map(move |h| (i, h, h.name.iter().join("::"))))
+      .collect_vec();
+    match by_name {
+      true => found.sort_by(|a, b| a.2.cmp(&b.2)),
+      false => found.sort_by_key(|(_, h, name)| (Reverse(h.gas.steps), name.clone())),
+    }
+    let rules = projects.iter().map(|lpr| source_rules(lpr)).collect_vec();
+    let mut hotspots = Vec::new();
+    for (i, hotspot, name) in found.into_iter().take(count) {
+      let lpr = &projects[i];
+      let Some(file) = file_of(&hotspot.range.path()) else { continue };
+      let Some(lines) = lpr.line_index(&file) else { continue };
+      let Some(val) = lpr.get_const(&hotspot.name) else { continue };
+      let suspects = (suspects(&rules[i], val).into_iter())
+        .filter_map(|rule| {
+          let range = lpr.line_index(&rule.file)?.doc_range(rule.pattern.clone());
+          Some(json!({ "uri": lpr.doc_uri(&rule.file), "range": range }))
+        })
+        .collect_vec();
+      hotspots.push(json!({
+        "name": name,
+        "uri": lpr.doc_uri(&file),
+        "range": lines.doc_range(hotspot.range.range()),
+        "steps": hotspot.gas.steps,
+        "exhausted": hotspot.gas.exhausted,
+        "limit": lpr.gas,
+        "rules": suspects,
+      }));
+    }
+    Ok(Value::Array(hotspots))
+  });
+}
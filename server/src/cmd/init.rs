@@ -1,68 +1,220 @@
+use std::path::Path;
 use std::process;
+use std::time::Duration;
 
 use serde::Deserialize;
 use serde_json::{json, Value};
 
-use super::fs::WorkspaceCtx;
-use crate::jrpc::JrpcServer;
+use super::colors::ColorConfig;
+use super::config::{self, validated, ProjectConfig};
+use super::fs::{
+  self, cold_load_config, debounce_config, token_legend, ColdLoadConfig, Debounce, WorkspaceCtx,
+};
+use super::latency::{latency_config, LatencyConfig};
+use super::lens::{lens_config, LensConfig};
+use super::on_type::{on_type_config, OnTypeConfig};
+use super::registry::{self, Registrations};
+use super::watchdog::{self, watchdog_config, WatchdogConfig};
+use super::{cache, commands, journal, semtok, telemetry, viewport, watch};
+use crate::documents::DocumentStore;
+use crate::jrpc::{JrpcServer, Session};
+use crate::log;
+use crate::protocol::client::ClientProfile;
 use crate::protocol::document::{FileUri, WspaceEnt};
+use crate::protocol::legend::TokenLegend;
+use crate::protocol::messages::WorkspaceFolders;
+
+/// Custom methods and notifications with the version of their interface, so
+/// that our client can feature-detect them. Bump the version when the shape of
+/// the params or result changes.
+fn orchid_capabilities() -> Value {
+  json!({
+    "requests": {
+      "orchid/hotspots": 1,
+      "orchid/lex": 1,
+      "orchid/memory": 1,
+      "orchid/renameKeyword": 1,
+      "orchid/selectionAst": 1,
+      "orchid/status": 1,
+      "orchid/stub": 1,
+    },
+    "notifications": {
+      "client/syntacticTokens": 1,
+      "orchid/analysisFinished": 1,
+      "orchid/analysisStarted": 1,
+      "orchid/didChangeVisibleRanges": 1,
+    },
+    // Requests that fail with ServerCancelled if the server stops them, which
+    // the client may retry
+    "serverCancellable": ["orchid/renameKeyword", "workspace/symbol"],
+    // Commands of workspace/executeCommand that fail the same way
+    "serverCancellableCommands": ["orchid.lintWorkspace"],
+  })
+}
+
+/// The workspace folders from `initialize`. Older clients only send a single
+/// `rootUri` or the even older `rootPath`. Folders that aren't local are
+/// skipped.
+fn workspace_entries(init: &Value) -> Vec<WspaceEnt> {
+  if let Some(ents) = init["workspaceFolders"].as_array() {
+    return (ents.iter())
+      .filter_map(|ent| {
+        WspaceEnt::deserialize(ent).inspect_err(|e| log!(Warn, "Skipping folder {ent}: {e}")).ok()
+      })
+      .collect();
+  }
+  let root = match init["rootUri"].as_str() {
+    Some(uri) => FileUri::parse(uri).ok(),
+    None => init["rootPath"].as_str().and_then(|path| FileUri::from_path(Path::new(path))),
+  };
+  let name = |uri: &FileUri| uri.segments().last().map_or(String::new(), |s| s.to_string());
+  root.map(|uri| WspaceEnt { name: name(&uri), uri }).into_iter().collect()
+}
+
+/// Tear down the state built for a previous `initialize`, which some clients
+/// send again after the connection hiccups. The workspaces are rebuilt from
+/// the new request, so nothing of the previous client is kept.
+fn reset(session: &Session) {
+  log!(Info, "Received initialize again, resetting");
+  session.abort_requests();
+  let mut g = session.lock();
+  fs::reset(&mut g);
+  watch::stop_fallback(&mut g);
+  config::forget_problems(&mut g);
+  config::forget_project_config(&mut g, None);
+  viewport::reset(&mut g);
+}
+
+/// The result of `initialize` for a client with these capabilities, using the
+/// token legend chosen from its options
+pub fn init_result(profile: &ClientProfile, legend: &TokenLegend) -> Value {
+  let mut result = json!({
+    "serverInfo": {
+      "name": "OrchidLS",
+      "version": "0.0.1",
+    },
+    "capabilities": {
+      "workspace": {
+        "workspaceFolders": { "supported": true, "changeNotifications": false },
+      },
+      "textDocumentSync": {
+        "openClose": true,
+        "change": 2,
+      },
+      "hoverProvider": true,
+      "definitionProvider": true,
+      "documentHighlightProvider": true,
+      "linkedEditingRangeProvider": true,
+      "renameProvider": { "prepareProvider": true },
+      "documentSymbolProvider": true,
+      "workspaceSymbolProvider": { "resolveProvider": true },
+      "foldingRangeProvider": true,
+      "completionProvider": { "triggerCharacters": ["/", "\""] },
+      "documentOnTypeFormattingProvider": {
+        "firstTriggerCharacter": "\n",
+        "moreTriggerCharacter": ["(", "["],
+      },
+      "executeCommandProvider": { "commands": commands::COMMANDS },
+      "codeLensProvider": { "resolveProvider": false },
+      "codeActionProvider": {
+        "codeActionKinds": [
+          "quickfix",
+          "refactor.extract",
+          "refactor.rewrite",
+          "source.addMissingImports",
+          "source.format",
+          "source.sortMembers",
+        ],
+      },
+      "colorProvider": true,
+      "inlineCompletionProvider": true,
+      "experimental": { "orchid": orchid_capabilities() },
+    }
+  });
+  if !profile.dynamic_semantic_tokens {
+    result["capabilities"]["semanticTokensProvider"] = semtok::options(legend, false);
+  }
+  if profile.pull_diagnostics {
+    result["capabilities"]["diagnosticProvider"] = json!({
+      "identifier": "orchid",
+      "interFileDependencies": true,
+      "workspaceDiagnostics": true,
+    });
+  }
+  result
+}
 
 pub fn attach(srv: &mut JrpcServer) {
   srv.on_req_sync("initialize", |init, session| {
     let init = init.unwrap();
-    let wf = &init["workspaceFolders"];
-    session.set(match wf.as_array() {
-      None => wf.as_null().map(|()| WorkspaceCtx::new([])).unwrap(),
-      Some(ents) => WorkspaceCtx::new((ents.iter()).map(|ent| WspaceEnt {
-        name: String::deserialize(&ent["name"]).unwrap(),
-        uri: FileUri::deserialize(&ent["uri"]).unwrap(),
-      })),
-    });
-    Ok(json!({
-      "serverInfo": {
-        "name": "OrchidLS",
-        "version": "0.0.1",
-      },
-      "capabilities": {
-        "workspace": {
-          "workspaceFolders": { "supported": true, "changeNotifications": false },
-        },
-        "textDocumentSync": {
-          "openClose": true,
-          "change": 1,
-        },
-        // "semanticTokensProvider": semantic_tokens_provider(),
-      }
-    }))
+    let again = session.lock().get::<WorkspaceCtx>().is_some();
+    if again {
+      reset(&session);
+    }
+    let profile = ClientProfile::new(&init["capabilities"]);
+    let opts = &validated(&session, "initialization options", &init["initializationOptions"]);
+    let legend = token_legend(profile.token_types.as_deref(), opts);
+    let result = init_result(&profile, &legend);
+    session.set(profile);
+    session.set(Registrations::default());
+    session.set(legend.clone());
+    if let Some(ms) = opts["slowRequestMs"].as_u64() {
+      session.lock().metrics().slow_threshold = Duration::from_millis(ms);
+    }
+    session.set(ColorConfig::new(opts));
+    session.set(ProjectConfig::default().merged(opts));
+    session.set(lens_config(opts, LensConfig::default()));
+    session.set(watchdog_config(opts, WatchdogConfig::default()));
+    session.set(on_type_config(opts, OnTypeConfig::default()));
+    session.set(latency_config(opts, LatencyConfig::default()));
+    session.set(cold_load_config(opts, ColdLoadConfig::default()));
+    session.set(debounce_config(opts, Debounce::default()));
+    let telemetry = opts["telemetry"].as_bool().unwrap_or(false);
+    telemetry::set_enabled(&session, telemetry);
+    journal::set_journal_size(&session, opts);
+    // Timers and hooks outlive the state they read, so they're only started
+    // once
+    if !again {
+      watchdog::start(session.clone());
+      telemetry::install_crash_hook(session.clone());
+      journal::install_dump_hook(&session);
+    }
+    cache::init_cache(&session, opts);
+    session.set(DocumentStore::default());
+    session.set(WorkspaceCtx::new(workspace_entries(init)));
+    Ok(result)
   });
   srv.on_notif("initialized", move |_v, session| {
-    eprintln!("Received notif");
-    session.request(
-      "client/registerCapability",
-      json!({
-        "registrations": [{
-          "id": "file-watcher-registration-id",
-          "method": "workspace/didChangeWatchedFiles",
-          "registerOptions": {
-            "documentSelector": [{ "language": "orchid", "scheme": "file" }],
-            "watchers": [{
-              "globPattern": "**/*.orc"
-            }]
-          }
-        }],
-      }),
-      |res| {
-        res.unwrap();
-        eprintln!("Resolved file watcher registration");
-      },
-    )
+    log!(Debug, "Received notif");
+    registry::set_semantic_tokens(&session, true);
+    let g = session.lock();
+    let folderless = g.get::<WorkspaceCtx>().is_some_and(|w| w.roots().next().is_none());
+    let can_ask = g.get::<ClientProfile>().is_some_and(|p| p.workspace_folders);
+    drop(g);
+    if !(folderless && can_ask) {
+      return watch::watch_files(&session);
+    }
+    // Some clients only provide the folders on request
+    let session2 = session.clone();
+    session.call::<WorkspaceFolders>((), move |res| {
+      match res {
+        Ok(Some(folders)) => fs::set_folders(&session2, folders),
+        Ok(None) => (),
+        Err(e) => log!(Error, "Failed to get workspace folders: {}", e.message),
+      }
+      watch::watch_files(&session2);
+    });
   });
-  srv.on_req_sync("shutdown", |_, _| {
-    eprintln!("Shutting down");
+  srv.on_req_sync("shutdown", |_, session| {
+    log!(Info, "Shutting down");
+    session.shutdown();
+    if let Some(wctx) = session.lock().get_mut::<WorkspaceCtx>() {
+      wctx.release();
+    }
     Ok(Value::Null)
   });
   srv.on_notif("exit", |_, _| {
-    eprintln!("Exiting");
+    log!(Info, "Exiting");
     process::exit(0)
   });
 }
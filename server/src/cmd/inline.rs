@@ -0,0 +1,31 @@
+//! `textDocument/inlineCompletion` offering the ghost text suggested by
+//! [crate::orc::inline]
+
+use anyhow::Context;
+use itertools::Itertools;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::fs::snapshot;
+use crate::jrpc::JrpcServer;
+use crate::orc::inline::suggest;
+use crate::protocol::document::{DocRange, TextDocumentPositionParams};
+use crate::protocol::error::LSPErrCode;
+
+pub fn attach(srv: &mut JrpcServer) {
+  srv.on_req_sync("textDocument/inlineCompletion", |params, session| {
+    let params = TextDocumentPositionParams::deserialize(params.unwrap_or(&Value::Null))
+      .context(LSPErrCode::InvalidParams)?;
+    let uri = &params.text_document.uri;
+    let Some(snap) = snapshot(&session, uri) else { return Ok(json!({ "items": [] })) };
+    let (Some(text), Some(lines)) = (snap.store.text(uri), snap.store.line_index(uri)) else {
+      return Ok(json!({ "items": [] }));
+    };
+    let offset = lines.docpos2bpos(params.position).context(LSPErrCode::InvalidParams)?;
+    let range = DocRange { start: params.position, end: params.position };
+    let items = (suggest(text, offset).into_iter())
+      .map(|text| json!({ "insertText": text, "range": range }))
+      .collect_vec();
+    Ok(json!({ "items": items }))
+  });
+}
@@ -0,0 +1,67 @@
+//! The `orchid.dumpJournal` command and the panic hook that write the journal
+//! of recent messages to a file. The journal is kept if the `journalSize`
+//! initialization option or setting is above zero.
+
+use std::path::PathBuf;
+use std::sync::{Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, io, panic, process};
+
+use anyhow::Context;
+use serde_json::{json, Value};
+
+use super::window;
+use crate::jrpc::Session;
+use crate::journal::Journal;
+use crate::log;
+use crate::protocol::messages::{MessageType, ShowMessage, ShowMessageParams};
+
+pub const DUMP_COMMAND: &str = "orchid.dumpJournal";
+
+/// Set the number of messages kept from the `journalSize` option, turning the
+/// journal off if the option is missing
+pub fn set_journal_size(session: &Session, opts: &Value) {
+  let size = opts["journalSize"].as_u64().unwrap_or(0);
+  session.journal().lock().unwrap_or_else(PoisonError::into_inner).set_capacity(size as usize)
+}
+
+fn dump(journal: &Mutex<Journal>) -> io::Result<PathBuf> {
+  let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+  let path = env::temp_dir().join(format!("orchid-ls-journal-{}-{secs}.jsonl", process::id()));
+  // A panic while recording poisons the lock, but the journal is still usable
+  journal.lock().unwrap_or_else(PoisonError::into_inner).dump(&path)?;
+  Ok(path)
+}
+
+/// Write the journal to a file in the temporary directory, and open it in the
+/// editor so that the user can attach it to a bug report
+pub fn dump_command(session: &Session) -> anyhow::Result<Value> {
+  let journal = session.journal();
+  let enabled = journal.lock().unwrap_or_else(PoisonError::into_inner).capacity() != 0;
+  enabled.then_some(()).context("The journal is off, set journalSize to keep one")?;
+  let path = dump(&journal).context("Failed to write the journal")?;
+  match url::Url::from_file_path(&path) {
+    Ok(uri) => window::show_document(session, uri.to_string(), None),
+    Err(()) => {
+      let message = format!("Message journal written to {}", path.display());
+      session.send::<ShowMessage>(ShowMessageParams { typ: MessageType::Info, message })
+    },
+  }
+  Ok(json!({ "path": path.to_string_lossy() }))
+}
+
+/// Dump the journal when the server panics
+pub fn install_dump_hook(session: &Session) {
+  let journal = session.journal();
+  let prev = panic::take_hook();
+  panic::set_hook(Box::new(move |info| {
+    let enabled = journal.lock().map_or(true, |j| j.capacity() != 0);
+    if enabled {
+      match dump(&journal) {
+        Ok(path) => log!(Info, "Journal of recent messages written to {}", path.display()),
+        Err(e) => log!(Error, "Failed to write the journal: {e}"),
+      }
+    }
+    prev(info)
+  }))
+}
@@ -0,0 +1,112 @@
+//! Time from an edit to the tokens and diagnostics of its project being
+//! published, the main measure of how responsive the server feels. It's broken
+//! into phases so that a regression can be traced to its cause, and reported
+//! in `orchid/status`. Edits slower than the `latencyBudgetMs` option are
+//! logged.
+
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::jrpc::{Session, SessionGuard};
+use crate::log;
+
+/// `latencyBudgetMs` initialization option and setting. Zero turns the
+/// warning off.
+#[derive(Clone, Copy, Debug)]
+pub struct LatencyConfig {
+  pub budget: Duration,
+}
+impl Default for LatencyConfig {
+  fn default() -> Self { Self { budget: Duration::from_secs(1) } }
+}
+
+/// Read the budget from the initialization options or the `orchidls` settings
+/// section, defaulting to the base
+pub fn latency_config(opts: &Value, base: LatencyConfig) -> LatencyConfig {
+  let budget = opts["latencyBudgetMs"].as_u64().map(Duration::from_millis);
+  LatencyConfig { budget: budget.unwrap_or(base.budget) }
+}
+
+/// Phases between receiving an edit and publishing its results
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Breakdown {
+  /// Waiting for the session and for the previous load to be aborted
+  pub queue: Duration,
+  /// Reading and parsing the files of the project
+  pub load: Duration,
+  /// Preparing the macros of the project and expanding its constants
+  pub macros: Duration,
+  /// Tokenizing, linting and sending the results
+  pub encode: Duration,
+}
+impl Breakdown {
+  pub fn total(&self) -> Duration { self.queue + self.load + self.macros + self.encode }
+  fn add(&mut self, other: Breakdown) {
+    self.queue += other.queue;
+    self.load += other.load;
+    self.macros += other.macros;
+    self.encode += other.encode;
+  }
+  fn to_json(self, divisor: u32) -> Value {
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0 / f64::from(divisor.max(1));
+    json!({
+      "queueMs": ms(self.queue),
+      "loadMs": ms(self.load),
+      "macrosMs": ms(self.macros),
+      "encodeMs": ms(self.encode),
+      "totalMs": ms(self.total()),
+    })
+  }
+}
+
+#[derive(Default)]
+struct EditLatency {
+  count: u32,
+  over_budget: u32,
+  sum: Breakdown,
+  max: Duration,
+  last: Option<Breakdown>,
+}
+
+/// Record the phases of an edit to a file, warning if it took longer than
+/// the budget
+pub fn record(session: &Session, file: &str, phases: Breakdown) {
+  let mut g = session.lock();
+  let budget = g.get::<LatencyConfig>().copied().unwrap_or_default().budget;
+  let over_budget = !budget.is_zero() && budget < phases.total();
+  if over_budget {
+    let Breakdown { queue, load, macros, encode } = phases;
+    log!(
+      Warn,
+      "Edit to {file} took {:?}, over the budget of {budget:?}: queued {queue:?}, loaded in \
+       {load:?}, macros prepared in {macros:?}, results sent in {encode:?}",
+      phases.total()
+    );
+  }
+  if g.get::<EditLatency>().is_none() {
+    g.set(EditLatency::default());
+  }
+  let stats = g.get_mut::<EditLatency>().unwrap();
+  stats.count += 1;
+  stats.over_budget += u32::from(over_budget);
+  stats.sum.add(phases);
+  stats.max = stats.max.max(phases.total());
+  stats.last = Some(phases);
+}
+
+/// Statistics of the edits so far for `orchid/status`
+pub fn report(g: &SessionGuard) -> Value {
+  let budget = g.get::<LatencyConfig>().copied().unwrap_or_default().budget;
+  let Some(stats) = g.get::<EditLatency>() else {
+    return json!({ "count": 0, "budgetMs": budget.as_millis() as u64 });
+  };
+  json!({
+    "count": stats.count,
+    "overBudget": stats.over_budget,
+    "budgetMs": budget.as_millis() as u64,
+    "maxMs": stats.max.as_secs_f64() * 1000.0,
+    "mean": stats.sum.to_json(stats.count),
+    "last": stats.last.map(|last| last.to_json(1)),
+  })
+}
@@ -0,0 +1,73 @@
+//! `textDocument/codeLens`, which shows statistics of modules above their
+//! headers when the `moduleStats` option is on
+
+use std::ops::Range;
+
+use anyhow::Context;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::fs::{snapshot, Snapshot};
+use crate::jrpc::JrpcServer;
+use crate::orc::members::{members, MemberKind};
+use crate::orc::rules::source_rules;
+use crate::orc::stats::{module_stats, ModuleStats};
+use crate::protocol::document::FileUri;
+use crate::protocol::error::LSPErrCode;
+
+/// `moduleStats` initialization option and setting
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LensConfig {
+  pub module_stats: bool,
+}
+
+/// Read the flags from the initialization options or the `orchidls` settings
+/// section, defaulting missing flags to the base
+pub fn lens_config(opts: &Value, base: LensConfig) -> LensConfig {
+  LensConfig { module_stats: opts["moduleStats"].as_bool().unwrap_or(base.module_stats) }
+}
+
+fn title(stats: ModuleStats) -> String {
+  let plural = |n: usize, what: &str| format!("{n} {what}{}", if n == 1 { "" } else { "s" });
+  format!(
+    "{} · {} · {} from other modules",
+    plural(stats.constants, "constant"),
+    plural(stats.rules, "rule"),
+    plural(stats.incoming, "reference"),
+  )
+}
+
+pub fn attach(srv: &mut JrpcServer) {
+  srv.on_req_sync("textDocument/codeLens", |params, session| {
+    let params = params.unwrap_or(&Value::Null);
+    let uri = FileUri::deserialize(&params["textDocument"]["uri"])
+      .context(LSPErrCode::InvalidParams)?;
+    if !session.lock().get::<LensConfig>().is_some_and(|c| c.module_stats) {
+      return Ok(json!([]));
+    }
+    let Some(Snapshot { path: Some(path), loaded: Some(lpr), .. }) = snapshot(&session, &uri) else {
+      return Ok(json!([]));
+    };
+    let (Some(text), Some(lines)) = (lpr.source(&path), lpr.line_index(&path)) else {
+      return Ok(json!([]));
+    };
+    let (refs, rules) = (lpr.references(), source_rules(&lpr));
+    let stats =
+      |inner: Option<(&str, Range<usize>)>| module_stats(&lpr, &refs, &rules, &path, inner);
+    let mut lenses = Vec::new();
+    let mut lens = |range, stats: ModuleStats| {
+      let command = json!({ "title": title(stats), "command": "" });
+      lenses.push(json!({ "range": lines.doc_range(range), "command": command }))
+    };
+    if let Some(file_stats) = stats(None) {
+      lens(0..0, file_stats);
+    }
+    let modules = members(&text).into_iter();
+    for module in modules.filter(|m| m.kind == MemberKind::Module) {
+      if let Some(module_stats) = stats(Some((&module.name, module.range.clone()))) {
+        lens(module.header..module.name_range.end, module_stats);
+      }
+    }
+    Ok(Value::Array(lenses))
+  });
+}
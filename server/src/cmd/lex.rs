@@ -0,0 +1,32 @@
+//! `orchid/lex`, the lexemes of a document as the server splits them. Clients
+//! can highlight and match brackets from this instead of a grammar of their
+//! own, and it shows how the server sees text that is misclassified.
+
+use anyhow::Context;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::fs::snapshot;
+use crate::jrpc::JrpcServer;
+use crate::orc::lexical::lex;
+use crate::protocol::document::FileUri;
+use crate::protocol::error::LSPErrCode;
+
+pub fn attach(srv: &mut JrpcServer) {
+  srv.on_req_sync("orchid/lex", |params, session| {
+    let params = params.unwrap_or(&Value::Null);
+    let uri = FileUri::deserialize(&params["textDocument"]["uri"])
+      .context(LSPErrCode::InvalidParams)?;
+    let Some(snap) = snapshot(&session, &uri) else { return Ok(json!([])) };
+    let (Some(text), Some(lines)) = (snap.store.text(&uri), snap.store.line_index(&uri)) else {
+      return Ok(json!([]));
+    };
+    let lexemes = (lex(text).into_iter())
+      .map(|(kind, range)| {
+        let doc_range = lines.doc_range(range.clone());
+        json!({ "kind": kind.name(), "range": doc_range, "text": &text[range] })
+      })
+      .collect();
+    Ok(Value::Array(lexemes))
+  });
+}
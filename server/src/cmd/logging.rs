@@ -1,6 +1,12 @@
-use serde_json::json;
+//! `$/logTrace`, enabled and made verbose by `$/setTrace`. Messages are
+//! throttled by [Throttle] so that verbose tracing stays usable on large
+//! workspaces.
 
-use crate::jrpc::{JrpcServer, Session};
+use std::time::Instant;
+
+use crate::jrpc::{JrpcServer, SessionGuard};
+use crate::protocol::messages::{LogTrace, LogTraceParams};
+use crate::trace::{truncate, Throttle};
 
 enum TraceValue {
   Off,
@@ -8,14 +14,35 @@ enum TraceValue {
   Verbose,
 }
 
-#[allow(unused)] // TODO: convert some long-lived eprintln lines to this
-pub fn log(session: Session, message: &str, verbose: impl FnOnce() -> String) {
-  let msg = match session.lock().get() {
+/// Trace a message if the client asked for it. The verbose payload is only
+/// built if the message is sent with one.
+pub fn log(g: &mut SessionGuard, message: &str, verbose: impl FnOnce() -> String) {
+  let verbose_on = match g.get() {
     Some(TraceValue::Off) | None => return,
-    Some(TraceValue::Messages) => json!({ "message": message }),
-    Some(TraceValue::Verbose) => json!({ "message": message, "verbose": verbose()}),
+    Some(TraceValue::Messages) => false,
+    Some(TraceValue::Verbose) => true,
   };
-  session.notify("$/logTrace", msg);
+  if g.get::<Throttle>().is_none() {
+    g.set(Throttle::default())
+  }
+  let admitted = g.get_mut::<Throttle>().unwrap().admit(message, Instant::now());
+  if let Some(summary) = admitted.summary {
+    g.send::<LogTrace>(LogTraceParams { message: summary, verbose: None });
+  }
+  // Summarize the run even if no other message ends it
+  if let Some(at) = admitted.flush_at {
+    g.scheduler().after(at.saturating_duration_since(Instant::now()), |session| {
+      let mut g = session.lock();
+      let Some(summary) = g.get_mut::<Throttle>().and_then(|thr| thr.flush(Instant::now())) else {
+        return;
+      };
+      g.send::<LogTrace>(LogTraceParams { message: summary, verbose: None });
+    });
+  }
+  if let Some(message) = admitted.message {
+    let verbose = verbose_on.then(|| truncate(verbose()));
+    g.send::<LogTrace>(LogTraceParams { message, verbose });
+  }
 }
 
 pub fn attach(srv: &mut JrpcServer) {
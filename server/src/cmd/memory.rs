@@ -0,0 +1,66 @@
+//! `orchid/memory`, a report on the size of the server's caches, and the
+//! `orchid.gc` command which drops them
+
+use anyhow::Context;
+use hashbrown::HashSet;
+use itertools::Itertools;
+use serde_json::{json, Value};
+
+use super::fs::{workspace, WorkspaceCtx};
+use crate::jrpc::{JrpcServer, Session};
+use crate::protocol::error::LSPErrCode;
+
+pub const GC_COMMAND: &str = "orchid.gc";
+
+fn report(wctx: &WorkspaceCtx) -> Value {
+  // Every path segment of every file read from disk is interned, so the
+  // segments held by the disk caches approximate the growth of the interner
+  let mut segments = HashSet::new();
+  let workspaces = (wctx.workspaces())
+    .map(|wsp| {
+      let (open_files, open_bytes) = wsp.store.open_stats();
+      let (disk_entries, disk_bytes, disk_segments) = wsp.store.disk().stats();
+      segments.extend(disk_segments);
+      json!({
+        "name": wsp.name,
+        "openFiles": open_files,
+        "openBytes": open_bytes,
+        "diskEntries": disk_entries,
+        "diskBytes": disk_bytes,
+      })
+    })
+    .collect_vec();
+  let projects = (wctx.projects())
+    .map(|p| {
+      let (files, constants) = p.loaded.as_ref().map_or((0, 0), |lpr| lpr.size());
+      json!({
+        "path": p.path().to_string(),
+        "loaded": p.loaded.is_some(),
+        "files": files,
+        "constants": constants,
+        "tokenFiles": p.tokens.len(),
+        "tokens": p.tokens.values().map(|t| t.tokens.len()).sum::<usize>(),
+      })
+    })
+    .collect_vec();
+  json!({ "workspaces": workspaces, "projects": projects, "internedSegments": segments.len() })
+}
+
+pub fn attach(srv: &mut JrpcServer) {
+  srv.on_req_sync("orchid/memory", |_, session| {
+    let g = session.lock();
+    Ok(report(workspace(&g)?))
+  });
+}
+
+/// Drop the caches and report the memory use afterwards
+pub fn gc(session: &Session) -> anyhow::Result<Value> {
+  let mut g = session.lock();
+  let wctx = g.get_mut::<WorkspaceCtx>().context(LSPErrCode::ServerNotInitialized)?;
+  wctx.workspaces().for_each(|wsp| wsp.store.disk().clear());
+  wctx.projects_mut().for_each(|p| p.tokens.clear());
+  let report = report(wctx);
+  drop(g);
+  intern_all::sweep();
+  Ok(report)
+}
@@ -1,3 +1,34 @@
+pub mod ast;
+pub mod cache;
+pub mod code;
+pub mod colors;
+pub mod commands;
+pub mod completion;
+pub mod config;
+pub mod diagnostics;
+pub mod folding;
 pub mod fs;
+pub mod hotspots;
 pub mod init;
+pub mod inline;
+pub mod journal;
+pub mod latency;
+pub mod lens;
+pub mod lex;
 pub mod logging;
+pub mod memory;
+pub mod nav;
+pub mod on_type;
+pub mod progress;
+pub mod registry;
+pub mod rename;
+pub mod semtok;
+pub mod settings;
+pub mod status;
+pub mod sweep;
+pub mod symbols;
+pub mod telemetry;
+pub mod viewport;
+pub mod watch;
+pub mod watchdog;
+pub mod window;
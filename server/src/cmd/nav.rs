@@ -0,0 +1,350 @@
+//! Hover, go-to-definition, highlighting and linked editing for names and
+//! macro rules
+
+use std::ops::Range;
+use std::sync::Arc;
+use std::{iter, slice};
+
+use anyhow::Context;
+use intern_all::{i, Tok};
+use itertools::Itertools;
+use orchidlang::location::SourceRange;
+use orchidlang::name::{Sym, VPath};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::config::project_config;
+use super::fs::{snapshot, workspace, Snapshot};
+use crate::jrpc::{JrpcServer, Session};
+use crate::orc::gas::{Expansion, Gas};
+use crate::orc::members::{is_exported, members};
+use crate::orc::printer::{expr_nodes, print, PrintConfig};
+use crate::orc::project::LoadedProject;
+use crate::orc::rules::{arrow_text, matched_rule, source_rules};
+use crate::orc::stubs::{parse_stub_uri, stub_uri};
+use crate::protocol::client::ClientProfile;
+use crate::protocol::document::{DocRange, TextDocumentPositionParams};
+use crate::protocol::error::LSPErrCode;
+use crate::protocol::line_index::LineIndex;
+
+/// A position in a file of a loaded project
+#[derive(Clone)]
+pub(super) struct Position {
+  pub lpr: Arc<LoadedProject>,
+  pub lines: Arc<LineIndex>,
+  pub path: VPath,
+  pub offset: usize,
+}
+
+pub(super) fn position(
+  params: Option<&Value>,
+  session: &Session,
+) -> anyhow::Result<Option<Position>> {
+  let params = TextDocumentPositionParams::deserialize(params.unwrap_or(&Value::Null))
+    .context(LSPErrCode::InvalidParams)?;
+  let snap = snapshot(session, &params.text_document.uri);
+  let Some(Snapshot { path: Some(path), loaded: Some(lpr), .. }) = snap else { return Ok(None) };
+  let Some(lines) = lpr.line_index(&path) else { return Ok(None) };
+  let offset = lines.docpos2bpos(params.position).context(LSPErrCode::InvalidParams)?;
+  Ok(Some(Position { lpr, lines, path, offset }))
+}
+
+/// A name under the cursor in a loaded project
+struct Cursor {
+  lpr: Arc<LoadedProject>,
+  lines: Arc<LineIndex>,
+  range: SourceRange,
+  name: Sym,
+}
+impl Cursor {
+  fn new(pos: Position) -> Option<Self> {
+    let (range, name) = pos.lpr.name_at(&pos.path, pos.offset)?;
+    Some(Self { lpr: pos.lpr, lines: pos.lines, range, name })
+  }
+}
+
+/// Where a name is declared
+enum Definition {
+  Source { uri: String, range: DocRange, file: VPath },
+  Stub { uri: String, range: DocRange, system: String },
+}
+
+fn definition(lpr: &LoadedProject, name: &[Tok<String>]) -> Option<Definition> {
+  // Names that are themselves imports or re-exports lead to the original
+  let chain = lpr.alias_chain(&VPath::new([]), name);
+  let name = chain.last().map_or(name, |target| &target[..]);
+  if let Some((stub, range)) = lpr.stubs.locate(name) {
+    return Some(Definition::Stub {
+      uri: stub_uri(&name[..name.len() - 1]),
+      range: LineIndex::new(&stub.text).doc_range(range),
+      system: stub.system.to_string(),
+    });
+  }
+  let val = lpr.get_const(&VPath::new(name.iter().cloned()))?;
+  let file = val.range.path();
+  if file[0].as_str() != "tree" {
+    return None;
+  }
+  let file = VPath::new(file[1..].iter().cloned());
+  Some(Definition::Source {
+    uri: lpr.doc_uri(&file),
+    range: lpr.line_index(&file)?.doc_range(val.range.range()),
+    file,
+  })
+}
+
+/// Whether a constant declared at the top level of a file is exported, based on
+/// the source text
+fn exported(lpr: &LoadedProject, file: &VPath, name: &Sym) -> Option<bool> {
+  if name.len() != file.len() + 2 {
+    return None;
+  }
+  let text = lpr.source(file)?;
+  Some(is_exported(&members(&text), &text, &name.last()))
+}
+
+/// The names the name under the cursor passes through as it's spelled, see
+/// [LoadedProject::alias_chain]
+fn alias_chain(pos: &Position, cur: &Cursor) -> Vec<Vec<Tok<String>>> {
+  let text = cur.range.text();
+  let spelled = text[cur.range.range()].split("::").map(|s| i(s.trim())).collect_vec();
+  let module = pos.path.clone().prefix([i!(str: "tree")]);
+  cur.lpr.alias_chain(&module, &spelled)
+}
+
+/// The imports and re-exports a name was resolved through, with the role of
+/// each name in the chain. Empty if the name is used where it's defined.
+fn resolution(pos: &Position, cur: &Cursor) -> Vec<(String, &'static str)> {
+  let chain = alias_chain(pos, cur);
+  if chain.len() < 2 {
+    return Vec::new();
+  }
+  let last = chain.len() - 1;
+  (chain.iter().enumerate())
+    .map(|(n, name)| {
+      let role = match n {
+        _ if n == last => "definition",
+        0 => "import",
+        _ => "re-export",
+      };
+      (name.iter().join("::"), role)
+    })
+    .collect()
+}
+
+/// The URI and range of every re-export between the import of the name under
+/// the cursor and its definition, nearest to the definition first
+fn reexports(pos: &Position, cur: &Cursor) -> Vec<(String, DocRange)> {
+  let chain = alias_chain(pos, cur);
+  let hops = chain.get(1..chain.len().saturating_sub(1)).unwrap_or_default();
+  (hops.iter().rev())
+    .filter_map(|alias| {
+      let (file, range) = cur.lpr.alias_site(alias)?;
+      let range = cur.lpr.line_index(&file)?.doc_range(range);
+      Some((cur.lpr.doc_uri(&file), range))
+    })
+    .collect()
+}
+
+/// Printed code in hovers is cut to this many lines
+const HOVER_LINES: usize = 20;
+
+/// Cut printed code to [HOVER_LINES]
+fn clipped(code: &str) -> String {
+  let mut lines = code.lines();
+  let mut out = lines.by_ref().take(HOVER_LINES).join("\n");
+  if lines.next().is_some() {
+    out += "\n…";
+  }
+  out
+}
+
+/// A note on the macro steps it takes to expand a constant, if it takes any
+fn gas_note(gas: Gas) -> Option<String> {
+  match gas {
+    Gas { steps, exhausted: true } => Some(format!("Macro expansion gives up after {steps} steps")),
+    Gas { steps: 0, .. } => None,
+    Gas { steps: 1, .. } => Some("Macro expansion takes 1 step".to_string()),
+    Gas { steps, .. } => Some(format!("Macro expansion takes {steps} steps")),
+  }
+}
+
+/// Markdown and plain text hovers describing the literal at a position, and
+/// the range of the literal
+fn atom_hover(pos: &Position) -> Option<(String, String, Range<usize>)> {
+  let (range, class) = pos.lpr.atom_at(&pos.path, pos.offset)?;
+  let text = range.text();
+  let literal = &text[range.range()];
+  let markdown = format!(
+    "```orchid\n{literal}\n```\n\n{} provided by the `{}` system",
+    class.label, class.system
+  );
+  let plain = format!("{literal}\n\n{} provided by the {} system", class.label, class.system);
+  Some((markdown, plain, range.range()))
+}
+
+/// Markdown and plain text hovers describing the macro rule declared at a
+/// position, and the range of the rule
+fn rule_hover(pos: &Position, config: &PrintConfig) -> Option<(String, String, Range<usize>)> {
+  let rules = source_rules(&pos.lpr);
+  let rule = (rules.iter()).find(|r| r.file == pos.path && r.range.contains(&pos.offset))?;
+  let prio = *rule.rule.prio;
+  let pattern = print(&expr_nodes(&rule.rule.pattern), config, "rule ".len());
+  let mut decl = format!("rule {pattern} {} ", arrow_text(prio));
+  let column = decl.rsplit('\n').next().unwrap_or("").chars().count();
+  decl += &print(&expr_nodes(&rule.rule.template), config, column);
+  let project = match pos.lpr.files.root.path().as_slice() {
+    [] => "the workspace root".to_string(),
+    root => root.iter().join("/"),
+  };
+  let markdown = format!(
+    "```orchid\n{decl}\n```\n\nPriority `{prio}`\n\nDefined in `{}` of project `{project}`",
+    rule.file
+  );
+  let plain = format!("{decl}\n\nPriority {prio}\n\nDefined in {} of project {project}", rule.file);
+  Some((markdown, plain, rule.range.clone()))
+}
+
+/// The lambda parameter or rule placeholder the name at the position refers
+/// to, or is
+fn local_definition(pos: &Position) -> Option<Value> {
+  let (binder, _) = pos.lpr.scopes(&pos.path)?.local_occurrences(pos.offset)?;
+  let range = pos.lines.doc_range(binder.range.range());
+  Some(json!({ "uri": pos.lpr.doc_uri(&pos.path), "range": range }))
+}
+
+/// `DocumentHighlightKind` values from the protocol
+const READ: u8 = 2;
+const WRITE: u8 = 3;
+
+pub fn attach(srv: &mut JrpcServer) {
+  srv.on_req_sync("textDocument/hover", |params, session| {
+    let Some(pos) = position(params, &session)? else { return Ok(Value::Null) };
+    let profile = session.lock().get::<ClientProfile>().cloned().unwrap_or_default();
+    let config = project_config(&session, &pos.lpr.files.config_dir()).print;
+    let Some(cur) = Cursor::new(pos.clone()) else {
+      let hover = atom_hover(&pos).or_else(|| rule_hover(&pos, &config));
+      let Some((markdown, plain, range)) = hover else { return Ok(Value::Null) };
+      return Ok(json!({
+        "contents": profile.hover_markup(markdown, plain),
+        "range": pos.lines.doc_range(range),
+      }));
+    };
+    let name = cur.name.iter().join("::");
+    let origin = match definition(&cur.lpr, &cur.name) {
+      Some(Definition::Stub { system, .. }) => Some(("Provided by the", system, "system")),
+      Some(Definition::Source { file, .. }) => {
+        let status = match exported(&cur.lpr, &file, &cur.name) {
+          Some(true) => "(exported)",
+          Some(false) => "(private)",
+          None => "",
+        };
+        Some(("Defined in", file.to_string(), status))
+      },
+      None => None,
+    };
+    let value = cur.lpr.get_const(&cur.name);
+    let decl = clipped(&match value {
+      Some(val) => {
+        let column = name.chars().count() + " := ".len();
+        format!("{name} := {}", print(&expr_nodes(slice::from_ref(val)), &config, column))
+      },
+      None => name,
+    });
+    let (mut markdown, mut plain) = (format!("```orchid\n{decl}\n```"), decl);
+    if let Some((pre, subject, post)) = origin {
+      markdown += format!("\n\n{pre} `{subject}` {post}").trim_end();
+      plain += format!("\n\n{pre} {subject} {post}").trim_end();
+    }
+    let chain = resolution(&pos, &cur);
+    if !chain.is_empty() {
+      let hops = |quote: &str| {
+        let hops = chain.iter().map(|(name, role)| format!("{quote}{name}{quote} ({role})"));
+        hops.collect_vec().join(" → ")
+      };
+      markdown += &format!("\n\nResolved through {}", hops("`"));
+      plain += &format!("\n\nResolved through {}", hops(""));
+    }
+    let expansion = value.and_then(|val| cur.lpr.expansion(val));
+    if let Some(note) = expansion.and_then(|exp| gas_note(exp.gas)) {
+      markdown += &format!("\n\n{note}");
+      plain += &format!("\n\n{note}");
+    }
+    // Preview the expansion the load recorded, unless it never finished
+    if let Some(Expansion { gas: Gas { steps: 1.., .. }, output: Some(expanded) }) = expansion {
+      let expanded = clipped(&print(&expr_nodes(slice::from_ref(expanded)), &config, 0));
+      markdown += &format!("\n\nExpands to\n\n```orchid\n{expanded}\n```");
+      plain += &format!("\n\nExpands to\n\n{expanded}");
+    }
+    Ok(json!({
+      "contents": profile.hover_markup(markdown, plain),
+      "range": cur.lines.doc_range(cur.range.range()),
+    }))
+  });
+  srv.on_req_sync("textDocument/definition", |params, session| {
+    let Some(pos) = position(params, &session)? else { return Ok(Value::Null) };
+    if let Some(local) = local_definition(&pos) {
+      return Ok(local);
+    }
+    let Some(cur) = Cursor::new(pos.clone()) else { return Ok(Value::Null) };
+    let profile = session.lock().get::<ClientProfile>().cloned().unwrap_or_default();
+    Ok(match definition(&cur.lpr, &cur.name) {
+      // Links can offer the re-exports as alternatives to the definition
+      Some(Definition::Source { uri, range, .. } | Definition::Stub { uri, range, .. })
+        if profile.definition_links =>
+      {
+        let origin = cur.lines.doc_range(cur.range.range());
+        let link = |(uri, range): (String, DocRange)| {
+          json!({
+            "originSelectionRange": origin,
+            "targetUri": uri,
+            "targetRange": range,
+            "targetSelectionRange": range,
+          })
+        };
+        let links = iter::once((uri, range)).chain(reexports(&pos, &cur));
+        Value::Array(links.map(link).collect())
+      },
+      Some(Definition::Source { uri, range, .. } | Definition::Stub { uri, range, .. }) =>
+        json!({ "uri": uri, "range": range }),
+      // Keywords are defined by the rule that consumed them
+      None => match matched_rule(&pos.lpr, &pos.path, pos.offset) {
+        None => Value::Null,
+        Some(rule) => {
+          let Some(lines) = pos.lpr.line_index(&rule.file) else { return Ok(Value::Null) };
+          let uri = pos.lpr.doc_uri(&rule.file);
+          json!({ "uri": uri, "range": lines.doc_range(rule.pattern) })
+        },
+      },
+    })
+  });
+  srv.on_req_sync("textDocument/documentHighlight", |params, session| {
+    let Some(pos) = position(params, &session)? else { return Ok(Value::Null) };
+    let Some(scopes) = pos.lpr.scopes(&pos.path) else { return Ok(Value::Null) };
+    let Some((binder, uses)) = scopes.occurrences(pos.offset) else { return Ok(Value::Null) };
+    let highlight = |range: &SourceRange, kind| {
+      json!({ "range": pos.lines.doc_range(range.range()), "kind": kind })
+    };
+    let decl = (binder.range.path() == scopes.file).then(|| highlight(&binder.range, WRITE));
+    let uses = uses.into_iter().map(|range| highlight(range, READ));
+    Ok(Value::Array(decl.into_iter().chain(uses).collect()))
+  });
+  srv.on_req_sync("textDocument/linkedEditingRange", |params, session| {
+    let Some(pos) = position(params, &session)? else { return Ok(Value::Null) };
+    let Some(scopes) = pos.lpr.scopes(&pos.path) else { return Ok(Value::Null) };
+    let Some((_, ranges)) = scopes.local_occurrences(pos.offset) else { return Ok(Value::Null) };
+    let ranges = ranges.into_iter().map(|r| pos.lines.doc_range(r)).collect_vec();
+    Ok(json!({ "ranges": ranges }))
+  });
+  srv.on_req_sync("orchid/stub", |params, session| {
+    let uri = params.and_then(|p| p["uri"].as_str()).context(LSPErrCode::InvalidParams)?;
+    let module = parse_stub_uri(uri).context(LSPErrCode::InvalidParams)?;
+    let g = session.lock();
+    let wctx = workspace(&g)?;
+    let module = module.iter().map(|s| i(s.as_str())).collect_vec();
+    let text = (wctx.projects().filter_map(|p| p.loaded.as_ref()))
+      .find_map(|lpr| Some(lpr.stubs.get(&module)?.text.clone()))
+      .context(LSPErrCode::RequestFailed)?;
+    Ok(json!({ "text": text.as_str() }))
+  });
+}
@@ -0,0 +1,57 @@
+//! `textDocument/onTypeFormatting`, which continues comments and indents
+//! within brackets when a newline is typed, and closes opened brackets
+
+use anyhow::Context;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::fs::snapshot;
+use crate::jrpc::JrpcServer;
+use crate::orc::brackets::{close_bracket, indentation};
+use crate::orc::comments::continuation;
+use crate::protocol::document::{TextDocumentPositionParams, TextEdit};
+use crate::protocol::error::LSPErrCode;
+
+/// `continueComments` and `closeBrackets` initialization options and
+/// settings
+#[derive(Clone, Copy, Debug)]
+pub struct OnTypeConfig {
+  pub continue_comments: bool,
+  /// Close brackets and indent lines within them
+  pub close_brackets: bool,
+}
+impl Default for OnTypeConfig {
+  fn default() -> Self { Self { continue_comments: true, close_brackets: true } }
+}
+
+/// Read the flags from the initialization options or the `orchidls` settings
+/// section, defaulting missing flags to the base
+pub fn on_type_config(opts: &Value, base: OnTypeConfig) -> OnTypeConfig {
+  OnTypeConfig {
+    continue_comments: opts["continueComments"].as_bool().unwrap_or(base.continue_comments),
+    close_brackets: opts["closeBrackets"].as_bool().unwrap_or(base.close_brackets),
+  }
+}
+
+pub fn attach(srv: &mut JrpcServer) {
+  srv.on_req_sync("textDocument/onTypeFormatting", |params, session| {
+    let params = params.unwrap_or(&Value::Null);
+    let pos_params = TextDocumentPositionParams::deserialize(params)
+      .context(LSPErrCode::InvalidParams)?;
+    let config = session.lock().get::<OnTypeConfig>().copied().unwrap_or_default();
+    let uri = &pos_params.text_document.uri;
+    let Some(snap) = snapshot(&session, uri) else { return Ok(json!([])) };
+    let (Some(text), Some(lines)) = (snap.store.text(uri), snap.store.line_index(uri)) else {
+      return Ok(json!([]));
+    };
+    let offset = lines.docpos2bpos(pos_params.position).context(LSPErrCode::InvalidParams)?;
+    let edit = match params["ch"].as_str() {
+      Some("\n") => (config.continue_comments.then(|| continuation(text, offset)).flatten())
+        .or_else(|| config.close_brackets.then(|| indentation(text, offset)).flatten()),
+      Some(_) if config.close_brackets => close_bracket(text, offset),
+      _ => None,
+    };
+    let edits = edit.map(|(range, text)| TextEdit::new(lines.doc_range(range), text));
+    Ok(json!(Vec::from_iter(edits)))
+  });
+}
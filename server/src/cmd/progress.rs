@@ -0,0 +1,101 @@
+//! Server-initiated work done progress. Long tasks create a progress bar in
+//! the client, report percentages and messages as they go, and can be
+//! cancelled through `window/workDoneProgress/cancel`.
+
+use std::collections::HashMap;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use super::watchdog;
+use crate::abort::Abort;
+use crate::jrpc::{JrpcServer, Session};
+use crate::protocol::client::ClientProfile;
+use crate::protocol::messages::{WorkDoneProgressCreate, WorkDoneProgressCreateParams};
+
+/// How long to wait for the client to acknowledge a new progress token. The
+/// client may ignore reports for tokens it didn't acknowledge yet.
+const CREATE_TIMEOUT: Duration = Duration::from_secs(2);
+
+static NEXT_TOKEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Tasks the user can cancel from the client, by progress token
+#[derive(Default)]
+struct Cancellable(HashMap<String, Abort>);
+
+/// A progress bar in the client. Reports are discarded if the client doesn't
+/// support server-initiated progress. The progress ends when this is dropped.
+/// Tasks that can be aborted are also watched by [watchdog].
+pub struct WorkProgress {
+  session: Session,
+  token: Option<String>,
+  job: Option<usize>,
+}
+impl WorkProgress {
+  /// Create a progress bar. If an [Abort] is provided, the user can cancel the
+  /// task from the client. This blocks until the client acknowledges the bar,
+  /// so it should be called from a worker thread.
+  pub fn begin(session: &Session, title: &str, abort: Option<Abort>) -> Self {
+    let job = abort.clone().map(|abort| watchdog::register(session, title, abort));
+    let mut this = Self { session: session.clone(), token: None, job };
+    if !session.lock().get::<ClientProfile>().is_some_and(|p| p.work_done_progress) {
+      return this;
+    }
+    let token = format!("orchid-{}", NEXT_TOKEN.fetch_add(1, atomic::Ordering::Relaxed));
+    let (send, recv) = mpsc::channel();
+    let params = WorkDoneProgressCreateParams { token: token.clone() };
+    session.call::<WorkDoneProgressCreate>(params, move |res| drop(send.send(res.is_ok())));
+    if recv.recv_timeout(CREATE_TIMEOUT) != Ok(true) {
+      return this;
+    }
+    let cancellable = abort.is_some();
+    if let Some(abort) = abort {
+      let mut g = session.lock();
+      if g.get::<Cancellable>().is_none() {
+        g.set(Cancellable::default());
+      }
+      g.get_mut::<Cancellable>().unwrap().0.insert(token.clone(), abort);
+    }
+    session.progress(
+      json!(token),
+      json!({ "kind": "begin", "title": title, "cancellable": cancellable, "percentage": 0 }),
+    );
+    this.token = Some(token);
+    this
+  }
+  /// Update the message and percentage of the progress bar
+  pub fn report(&self, message: &str, percentage: u32) {
+    if let Some(job) = self.job {
+      watchdog::phase(&self.session, job, message)
+    }
+    if let Some(token) = &self.token {
+      let percentage = percentage.min(100);
+      let value = json!({ "kind": "report", "message": message, "percentage": percentage });
+      self.session.progress(json!(token), value);
+    }
+  }
+}
+impl Drop for WorkProgress {
+  fn drop(&mut self) {
+    if let Some(job) = self.job {
+      watchdog::finish(&self.session, job)
+    }
+    let Some(token) = self.token.take() else { return };
+    let mut g = self.session.lock();
+    if let Some(cancellable) = g.get_mut::<Cancellable>() {
+      cancellable.0.remove(&token);
+    }
+    g.progress(Value::String(token), json!({ "kind": "end" }));
+  }
+}
+
+pub fn attach(srv: &mut JrpcServer) {
+  srv.on_notif("window/workDoneProgress/cancel", |params, session| {
+    let Some(token) = params.and_then(|p| p["token"].as_str()) else { return };
+    if let Some(abort) = session.lock().get::<Cancellable>().and_then(|c| c.0.get(token)) {
+      abort.abort()
+    }
+  });
+}
@@ -0,0 +1,132 @@
+//! Capabilities registered at runtime with `client/registerCapability`, so
+//! that they can also be withdrawn while the server is running
+
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use crate::abort::Abort;
+use crate::chain::Chain;
+use crate::jrpc::Session;
+use crate::log;
+use crate::protocol::client::ClientProfile;
+use crate::protocol::legend::TokenLegend;
+use crate::protocol::messages::{
+  MessageType, RegisterCapability, Registration, RegistrationParams, ShowMessage,
+  ShowMessageParams, UnregisterCapability, Unregistration, UnregistrationParams,
+};
+
+/// Registration ID of semantic tokens
+pub const SEMANTIC_TOKENS: &str = "semantic-tokens";
+
+/// Active registrations by ID, with the method they're registered for.
+/// Registrations are recorded when they're sent and forgotten if the client
+/// rejects them.
+#[derive(Default)]
+pub struct Registrations(HashMap<String, String>);
+
+/// Register a capability unless a registration with the same ID is active
+pub fn register(session: &Session, id: &str, method: &str, options: Value) {
+  let mut g = session.lock();
+  let regs = g.get_mut::<Registrations>().expect("Set during initialization");
+  if regs.0.insert(id.to_string(), method.to_string()).is_some() {
+    return;
+  }
+  let (id, session2) = (id.to_string(), session.clone());
+  let registration = Registration {
+    id: id.clone(),
+    method: method.to_string(),
+    register_options: options,
+  };
+  let params = RegistrationParams { registrations: vec![registration] };
+  g.call::<RegisterCapability>(params, move |res| {
+    if let Err(e) = res {
+      log!(Error, "Client rejected registration {id}: {e:?}");
+      session2.lock().get_mut::<Registrations>().unwrap().0.remove(&id);
+    }
+  })
+}
+
+/// Withdraw a capability if it's registered
+pub fn unregister(session: &Session, id: &str) {
+  let mut g = session.lock();
+  let regs = g.get_mut::<Registrations>().expect("Set during initialization");
+  let Some(method) = regs.0.remove(id) else { return };
+  let unregistration = Unregistration { id: id.to_string(), method };
+  let params = UnregistrationParams { unregistrations: vec![unregistration] };
+  g.call::<UnregisterCapability>(params, |res| {
+    if let Err(e) = res {
+      log!(Error, "Client rejected unregistration: {e:?}")
+    }
+  })
+}
+
+/// Replace the options of an active registration. The capability is
+/// registered again only after the client withdrew it, so that it's never
+/// registered twice.
+pub fn reregister(session: &Session, id: &str, options: Value) {
+  let g = session.lock();
+  let Some(method) = g.get::<Registrations>().and_then(|r| r.0.get(id)).cloned() else { return };
+  drop(g);
+  let unregistration = Unregistration { id: id.to_string(), method: method.clone() };
+  let unregistrations = UnregistrationParams { unregistrations: vec![unregistration] };
+  let registration = Registration { id: id.to_string(), method, register_options: options };
+  let registrations = RegistrationParams { registrations: vec![registration] };
+  let (id, session2) = (id.to_string(), session.clone());
+  Chain::call::<UnregisterCapability>(unregistrations)
+    .then(|()| Chain::call::<RegisterCapability>(registrations))
+    .run(session, &Abort::new(), move |res| {
+      if let Err(e) = res {
+        log!(Error, "Client rejected registering {id} again: {e:?}");
+        session2.lock().get_mut::<Registrations>().unwrap().0.remove(&id);
+      }
+    })
+}
+
+/// Register or withdraw semantic tokens. Clients that can't register them
+/// dynamically receive them statically in `initialize` and keep them.
+pub fn set_semantic_tokens(session: &Session, enabled: bool) {
+  let g = session.lock();
+  let dynamic = g.get::<ClientProfile>().is_some_and(|p| p.dynamic_semantic_tokens);
+  let legend = g.get::<TokenLegend>().cloned().expect("Set during initialization");
+  drop(g);
+  match (dynamic, enabled) {
+    (false, _) => (),
+    (true, true) => register(
+      session,
+      SEMANTIC_TOKENS,
+      "textDocument/semanticTokens",
+      super::semtok::options(&legend, true),
+    ),
+    (true, false) => unregister(session, SEMANTIC_TOKENS),
+  }
+}
+
+/// The last legend [set_legend] refused
+struct RefusedLegend(TokenLegend);
+
+/// Replace the token legend after a change to the `tokenTypes` setting, and
+/// register semantic tokens again with it if they're registered. Clients that
+/// received semantic tokens statically can't change their legend, so the
+/// change is refused. Returns whether the legend changed.
+pub fn set_legend(session: &Session, legend: TokenLegend) -> bool {
+  let mut g = session.lock();
+  if g.get::<TokenLegend>() == Some(&legend) {
+    return false;
+  }
+  let dynamic = g.get::<ClientProfile>().is_some_and(|p| p.dynamic_semantic_tokens);
+  if !dynamic {
+    // Shown once for each refused legend
+    if g.get::<RefusedLegend>().is_none_or(|r| r.0 != legend) {
+      g.set(RefusedLegend(legend));
+      let message = "Changes to tokenTypes apply after the server is restarted".to_string();
+      log!(Warn, "{message}");
+      g.send::<ShowMessage>(ShowMessageParams { typ: MessageType::Warning, message });
+    }
+    return false;
+  }
+  g.set(legend.clone());
+  drop(g);
+  reregister(session, SEMANTIC_TOKENS, super::semtok::options(&legend, true));
+  true
+}
@@ -0,0 +1,93 @@
+//! `orchid/renameKeyword`, which renames a macro keyword or operator across a
+//! project. The result is a preview that the client applies after the user
+//! confirmed it, along with the occurrences of the new keyword that it would
+//! collide with. Renames that would edit generated files are refused.
+//!
+//! `textDocument/rename` renames lambda parameters and rule placeholders, which
+//! are only visible within the file.
+
+use anyhow::{anyhow, Context};
+use itertools::Itertools;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::config::project_config;
+use super::fs::analyze;
+use super::nav::position;
+use crate::jrpc::JrpcServer;
+use crate::orc::keyword::{is_token, rename_keyword};
+use crate::orc::lexical::is_identifier;
+use crate::protocol::document::{FileUri, TextEdit, WorkspaceEdit};
+use crate::protocol::error::LSPErrCode;
+
+/// Marks the edits of the rename so that the client asks before applying them
+const ANNOTATION: &str = "renameKeyword";
+
+pub fn attach(srv: &mut JrpcServer) {
+  srv.on_req_sync("orchid/renameKeyword", |params, session| {
+    let params = params.unwrap_or(&Value::Null);
+    let uri = FileUri::deserialize(&params["textDocument"]["uri"])
+      .context(LSPErrCode::InvalidParams)?;
+    let from = params["keyword"].as_str().context(LSPErrCode::InvalidParams)?;
+    let to = (params["newName"].as_str())
+      .filter(|s| is_token(s))
+      .context(LSPErrCode::InvalidParams)?;
+    let lpr = analyze(&session, &uri)?;
+    let rename = rename_keyword(&lpr, from, to).context(LSPErrCode::RequestFailed)?;
+    let config = project_config(&session, &lpr.files.config_dir());
+    let generated = (rename.edits.iter()).map(|(file, _)| file).filter(|f| config.is_generated(f));
+    let generated = generated.map(|f| f.to_string()).collect_vec();
+    if !generated.is_empty() {
+      let files = generated.join(", ");
+      let message = format!("Renaming {from} would edit generated files: {files}");
+      return Err(anyhow!(LSPErrCode::RequestFailed).context(message));
+    }
+    let label = format!("Rename {from} to {to}");
+    let mut edit = WorkspaceEdit::default().annotation(ANNOTATION, label, true);
+    for (file, ranges) in rename.edits.iter() {
+      let Some(lines) = lpr.line_index(file) else { continue };
+      for range in ranges {
+        let text_edit = TextEdit::new(lines.doc_range(range.clone()), to).annotated(ANNOTATION);
+        edit = edit.edit(lpr.files.versioned(file), text_edit);
+      }
+    }
+    let conflicts = (rename.conflicts.iter())
+      .filter_map(|(file, range)| {
+        let lines = lpr.line_index(file)?;
+        let uri = lpr.doc_uri(file);
+        Some(json!({ "uri": uri, "range": lines.doc_range(range.clone()) }))
+      })
+      .collect_vec();
+    Ok(json!({ "edit": edit, "conflicts": conflicts }))
+  });
+  srv.on_req_sync("textDocument/prepareRename", |params, session| {
+    let Some(pos) = position(params, &session)? else { return Ok(Value::Null) };
+    let Some(scopes) = pos.lpr.scopes(&pos.path) else { return Ok(Value::Null) };
+    let Some((binder, ranges)) = scopes.local_occurrences(pos.offset) else {
+      let message = "Only lambda parameters and placeholders can be renamed here";
+      return Err(anyhow!(LSPErrCode::RequestFailed).context(message));
+    };
+    let at = ranges.into_iter().find(|r| r.start <= pos.offset && pos.offset <= r.end);
+    let range = at.context(LSPErrCode::RequestFailed)?;
+    let placeholder = binder.name.last().to_string();
+    Ok(json!({ "range": pos.lines.doc_range(range), "placeholder": placeholder }))
+  });
+  srv.on_req_sync("textDocument/rename", |params, session| {
+    let to = (params.and_then(|p| p["newName"].as_str()))
+      .filter(|s| is_identifier(s))
+      .context(LSPErrCode::InvalidParams)?
+      .to_string();
+    let Some(pos) = position(params, &session)? else { return Ok(Value::Null) };
+    let Some(scopes) = pos.lpr.scopes(&pos.path) else { return Ok(Value::Null) };
+    let (_, ranges) = scopes.local_occurrences(pos.offset).context(LSPErrCode::RequestFailed)?;
+    if project_config(&session, &pos.lpr.files.config_dir()).is_generated(&pos.path) {
+      let message = format!("{} is generated", pos.path);
+      return Err(anyhow!(LSPErrCode::RequestFailed).context(message));
+    }
+    let doc = pos.lpr.files.versioned(&pos.path);
+    let edit = (ranges.into_iter())
+      .map(|r| TextEdit::new(pos.lines.doc_range(r), to.clone()))
+      .fold(WorkspaceEdit::default(), |edit, text_edit| edit.edit(doc.clone(), text_edit));
+    Ok(json!(edit))
+  });
+}
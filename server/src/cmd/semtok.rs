@@ -1 +1,62 @@
-pub 
\ No newline at end of file
+//! Standard LSP semantic tokens, served from the per-file token cache
+
+use anyhow::Context;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::fs::{content_id, encode_tokens, workspace, FileTokens, WorkspaceCtx};
+use crate::jrpc::JrpcServer;
+use crate::protocol::client::ClientProfile;
+use crate::protocol::document::FileUri;
+use crate::protocol::error::LSPErrCode;
+use crate::protocol::legend::TokenLegend;
+use crate::protocol::tokens::delta_encode;
+
+/// Options of the semantic tokens capability. Dynamic registrations must also
+/// select the documents they apply to.
+pub fn options(legend: &TokenLegend, dynamic: bool) -> Value {
+  let mut opts = json!({
+    "legend": { "tokenTypes": legend.types(), "tokenModifiers": [] },
+    "full": true,
+  });
+  if dynamic {
+    opts["documentSelector"] = json!([{ "language": "orchid" }]);
+  }
+  opts
+}
+
+pub fn attach(srv: &mut JrpcServer) {
+  srv.on_req_sync("textDocument/semanticTokens/full", |params, session| {
+    let uri = FileUri::deserialize(&params.unwrap_or(&Value::Null)["textDocument"]["uri"])
+      .context(LSPErrCode::InvalidParams)?;
+    let (path, lpr, cached, multiline, legend) = {
+      let g = session.lock();
+      let multiline = g.get::<ClientProfile>().is_some_and(|p| p.multiline_tokens);
+      let legend = g.get::<TokenLegend>().cloned().expect("Set during initialization");
+      let wctx = workspace(&g)?;
+      let Some((path, _, proj)) = wctx.get_proj(&uri) else { return Ok(Value::Null) };
+      let Some(lpr) = proj.loaded.clone() else { return Ok(Value::Null) };
+      let Some(text) = lpr.source(&path) else { return Ok(Value::Null) };
+      let cached = proj.cached_tokens(&path, &text);
+      (path, lpr, cached.ok_or(text), multiline, legend)
+    };
+    let tokens = match cached {
+      Ok(tokens) => tokens,
+      Err(text) => {
+        // computed outside the lock, the project is immutable once loaded
+        let ftoks = FileTokens::compute(&lpr, &path, &text);
+        let mut g = session.lock();
+        let wctx = g.get_mut::<WorkspaceCtx>().unwrap();
+        // A load that completed in the meantime may make these stale
+        if let Some((_, _, proj)) = wctx.get_proj_mut(&uri) {
+          if proj.loaded.as_ref().is_some_and(|cur| cur.token_context() == ftoks.context) {
+            proj.tokens.insert(path, ftoks.clone());
+          }
+        }
+        ftoks.tokens
+      },
+    };
+    let data = delta_encode(encode_tokens(tokens.iter().cloned(), multiline, &legend));
+    Ok(json!({ "resultId": content_id(&data), "data": data }))
+  });
+}
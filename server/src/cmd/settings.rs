@@ -0,0 +1,66 @@
+//! Settings the client pushes at runtime with
+//! `workspace/didChangeConfiguration`, under the `orchidls` section. The
+//! section replaces the previous one, so settings it leaves out take their
+//! defaults. Changes apply to all work started afterwards, and loaded projects
+//! are reloaded if their analysis is affected or the token legend changed.
+//! There is no worker pool to resize, every load runs on a thread of its own.
+
+use std::time::Duration;
+
+use serde_json::Value;
+
+use super::colors::ColorConfig;
+use super::config::{self, validated, ProjectConfig};
+use super::fs::{cold_load_config, debounce_config, token_legend, ColdLoadConfig, Debounce};
+use super::latency::{latency_config, LatencyConfig};
+use super::lens::{lens_config, LensConfig};
+use super::on_type::{on_type_config, OnTypeConfig};
+use super::watchdog::{watchdog_config, WatchdogConfig};
+use super::{fs, journal, registry, telemetry};
+use crate::jrpc::{JrpcServer, SLOW_THRESHOLD};
+use crate::log;
+use crate::protocol::client::ClientProfile;
+use crate::protocol::messages::CodeLensRefresh;
+
+pub fn attach(srv: &mut JrpcServer) {
+  srv.on_notif("workspace/didChangeConfiguration", |params, session| {
+    let settings = &params.unwrap_or(&Value::Null)["settings"]["orchidls"];
+    let settings = &validated(&session, "settings", settings);
+    let known = session.lock().get::<ClientProfile>().and_then(|p| p.token_types.clone());
+    let legend_changed = registry::set_legend(&session, token_legend(known.as_deref(), settings));
+    registry::set_semantic_tokens(&session, settings["semanticTokens"].as_bool().unwrap_or(true));
+    telemetry::set_enabled(&session, settings["telemetry"].as_bool().unwrap_or(false));
+    journal::set_journal_size(&session, settings);
+    let mut g = session.lock();
+    let slow = settings["slowRequestMs"].as_u64().map_or(SLOW_THRESHOLD, Duration::from_millis);
+    g.metrics().slow_threshold = slow;
+    g.set(ColorConfig::new(settings));
+    let project = ProjectConfig::default().merged(settings);
+    let reload = g.get::<ProjectConfig>() != Some(&project);
+    g.set(project);
+    if reload {
+      config::forget_project_config(&mut g, None);
+    }
+    let lens = lens_config(settings, LensConfig::default());
+    let lens_changed = g.get::<LensConfig>() != Some(&lens);
+    g.set(lens);
+    g.set(on_type_config(settings, OnTypeConfig::default()));
+    g.set(watchdog_config(settings, WatchdogConfig::default()));
+    g.set(cold_load_config(settings, ColdLoadConfig::default()));
+    g.set(debounce_config(settings, Debounce::default()));
+    g.set(latency_config(settings, LatencyConfig::default()));
+    let can_refresh = g.get::<ClientProfile>().is_some_and(|p| p.code_lens_refresh);
+    if can_refresh && lens_changed {
+      g.call::<CodeLensRefresh>((), |res| {
+        if let Err(e) = res {
+          log!(Error, "Client failed to refresh code lenses: {e:?}")
+        }
+      })
+    }
+    drop(g);
+    // Syntactic tokens are pushed with the legend of the load
+    if reload || legend_changed {
+      fs::reload_all(&session)
+    }
+  })
+}
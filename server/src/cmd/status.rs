@@ -0,0 +1,22 @@
+//! `orchid/status`, a diagnostic report on the state of the server and how
+//! quickly it responds
+
+use itertools::Itertools;
+use serde_json::json;
+
+use super::fs::workspace;
+use super::latency;
+use crate::jrpc::JrpcServer;
+
+pub fn attach(srv: &mut JrpcServer) {
+  srv.on_req_sync("orchid/status", |_, session| {
+    let mut g = session.lock();
+    let requests = g.metrics().report();
+    let edits = latency::report(&g);
+    let wctx = workspace(&g)?;
+    let projects = (wctx.projects())
+      .map(|p| json!({ "path": p.path().to_string(), "loaded": p.loaded.is_some() }))
+      .collect_vec();
+    Ok(json!({ "requests": requests, "edits": edits, "projects": projects }))
+  });
+}
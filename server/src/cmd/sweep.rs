@@ -0,0 +1,89 @@
+//! `orchid.lintWorkspace`, which lints every project of the workspace and
+//! publishes the findings, then summarizes them by lint and by file for the
+//! client to show. Projects without open documents are loaded for the sweep
+//! and their findings stay published until a file of the project changes or
+//! the project is loaded again.
+
+use std::sync::Arc;
+use std::thread;
+
+use itertools::Itertools;
+use serde_json::{json, Value};
+
+use super::config::project_config;
+use super::diagnostics;
+use super::fs::WorkspaceCtx;
+use super::progress::WorkProgress;
+use crate::abort::Abort;
+use crate::jrpc::{AsyncReq, Session};
+use crate::orc::project::{LoadedProject, ProjectFiles};
+use crate::protocol::messages::Diagnostic;
+use crate::published::summary;
+
+pub const LINT_COMMAND: &str = "orchid.lintWorkspace";
+
+/// Run the sweep on a worker thread, since loading projects takes a while
+pub fn lint_command(req: AsyncReq) {
+  thread::Builder::new()
+    .name("lint-workspace".into())
+    .stack_size(1 << 26)
+    .spawn(move || {
+      let summary = sweep(req.session(), req.abort());
+      // Cancelled sweeps are answered with the reason when dropped
+      if let Some(summary) = summary {
+        req.resolve(Ok(summary))
+      }
+    })
+    .expect("Failed to spawn lint thread");
+}
+
+/// Lint and publish each project in turn. [None] if cancelled.
+fn sweep(session: &Session, abort: &Abort) -> Option<Value> {
+  let progress = WorkProgress::begin(session, "Linting the workspace", Some(abort.clone()));
+  let projects = {
+    let g = session.lock();
+    let wctx = g.get::<WorkspaceCtx>()?;
+    (wctx.workspaces())
+      .flat_map(|wsp| {
+        let roots = wsp.projects.iter().map(|p| p.root.clone()).collect::<Arc<[_]>>();
+        (wsp.projects.iter())
+          .map(move |p| (p.files(wsp.store.clone()), p.loaded.is_some(), roots.clone()))
+      })
+      .collect_vec()
+  };
+  let total = projects.len().max(1);
+  let mut failed = 0;
+  let mut findings = Vec::<(String, Vec<Diagnostic>)>::new();
+  for (i, (files, loaded, roots)) in projects.into_iter().enumerate() {
+    progress.report(&format!("Linting {}", files.root.path()), (100 * i / total) as u32);
+    let uri = files.file_uri(&[]);
+    // Loaded projects published their findings when they loaded
+    if !loaded {
+      let config = project_config(session, &files.config_dir());
+      let ProjectFiles { patches, root } = files.clone();
+      let (gas, abort2) = (config.macro_gas, abort.clone());
+      let diagnostics = match LoadedProject::new(patches, root, gas, abort2, &mut |_, _| ()) {
+        Ok(lpr) => diagnostics::compute(&lpr, &config, &[], &roots),
+        Err(_) if abort.aborted() => return None,
+        Err(errors) => {
+          failed += 1;
+          diagnostics::load_errors(&files, &errors, &[])
+        },
+      };
+      let mut g = session.lock();
+      // A project loaded in the meantime published fresher findings
+      let proj = g.get::<WorkspaceCtx>().and_then(|wctx| wctx.get_proj(&uri));
+      if proj.is_some_and(|(_, _, proj)| proj.loaded.is_none()) {
+        diagnostics::publish(&mut g, &uri, diagnostics);
+      }
+    }
+    let g = session.lock();
+    // The project may have been removed in the meantime
+    let Some((_, _, proj)) = g.get::<WorkspaceCtx>().and_then(|wctx| wctx.get_proj(&uri)) else {
+      continue;
+    };
+    findings.extend(proj.diagnosed.iter().map(|(uri, (_, diags))| (uri.clone(), diags.clone())));
+  }
+  Some(summary(&findings, failed))
+}
+
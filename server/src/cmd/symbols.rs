@@ -0,0 +1,183 @@
+//! `textDocument/documentSymbol` listing the top-level members of a file.
+//! Exported members are marked in the detail field.
+//!
+//! `workspace/symbol` searches the top-level members of every project. Clients
+//! that can resolve symbols get them without a range, which is only computed
+//! in `workspaceSymbol/resolve` for the symbol the user picks, so the search
+//! doesn't index the lines of every file. Loaded projects keep the members of
+//! their files, others are split on every search. The search runs on its own
+//! thread so the client can cancel it, and the watchdog stops it like any
+//! other job if it runs past `jobTimeoutMs`, in which case the client may
+//! retry.
+
+use std::sync::Arc;
+use std::thread;
+
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::fs::{snapshot, workspace, WorkspaceCtx};
+use super::watchdog;
+use crate::abort::Abort;
+use crate::jrpc::{JrpcServer, Session};
+use crate::orc::members::{is_exported, members, Member, MemberKind};
+use crate::orc::project::{LoadedProject, ProjectFiles};
+use crate::protocol::client::ClientProfile;
+use crate::protocol::document::FileUri;
+use crate::protocol::error::LSPErrCode;
+
+/// Most workspace symbols returned for a query
+const MAX_WORKSPACE_SYMBOLS: usize = 500;
+
+/// `SymbolKind` from the LSP spec
+fn symbol_kind(kind: MemberKind) -> Option<u8> {
+  match kind {
+    MemberKind::Module => Some(2),
+    MemberKind::Const => Some(14),
+    _ => None,
+  }
+}
+
+/// Whether the characters of the query occur in the name in order, ignoring
+/// case
+fn fuzzy_match(query: &str, name: &str) -> bool {
+  let mut chars = name.chars().flat_map(char::to_lowercase);
+  query.chars().flat_map(char::to_lowercase).all(|q| chars.any(|c| c == q))
+}
+
+/// The files and the loaded state of every project in every workspace
+fn all_projects(session: &Session) -> Vec<(ProjectFiles, Option<Arc<LoadedProject>>)> {
+  let g = session.lock();
+  let Some(wctx) = g.get::<WorkspaceCtx>() else { return Vec::new() };
+  (wctx.workspaces())
+    .flat_map(|wsp| {
+      (wsp.projects.iter()).map(|proj| (proj.files(wsp.store.clone()), proj.loaded.clone()))
+    })
+    .collect()
+}
+
+/// A member that can be listed as a workspace symbol
+fn listed(member: &Member) -> Option<u8> {
+  if member.name.is_empty() { None } else { symbol_kind(member.kind) }
+}
+
+/// Search the top-level members of every project. Returns None if aborted.
+fn search(session: &Session, query: &str, abort: &Abort) -> Option<Vec<Value>> {
+  let lazy = session.lock().get::<ClientProfile>().is_some_and(|p| p.resolve_symbol_range);
+  let mut symbols = Vec::new();
+  for (files, loaded) in all_projects(session) {
+    let split;
+    let file_members = match &loaded {
+      Some(lpr) => lpr.members(),
+      None => {
+        let mut file_members = Vec::new();
+        for path in files.source_files() {
+          if abort.aborted() {
+            return None;
+          }
+          let Some(text) = files.source(&path) else { continue };
+          file_members.push((path, members(&text)));
+        }
+        split = file_members;
+        &split[..]
+      },
+    };
+    for (path, members) in file_members {
+      if abort.aborted() {
+        return None;
+      }
+      let found = (members.iter())
+        .filter_map(|m| Some((listed(m)?, m)))
+        .filter(|(_, m)| fuzzy_match(query, &m.name))
+        .collect::<Vec<_>>();
+      if found.is_empty() {
+        continue;
+      }
+      let uri = files.doc_uri(&path);
+      let lines = if lazy { None } else { files.line_index(path) };
+      for (kind, m) in found {
+        let location = match &lines {
+          Some(lines) => json!({ "uri": uri, "range": lines.doc_range(m.name_range.clone()) }),
+          None => json!({ "uri": uri }),
+        };
+        symbols.push(json!({
+          "name": m.name,
+          "kind": kind,
+          "containerName": path.to_string(),
+          "location": location,
+          "data": { "name": m.name },
+        }));
+        if MAX_WORKSPACE_SYMBOLS <= symbols.len() {
+          return Some(symbols);
+        }
+      }
+    }
+  }
+  Some(symbols)
+}
+
+pub fn attach(srv: &mut JrpcServer) {
+  srv.on_req_sync("textDocument/documentSymbol", |params, session| {
+    let params = params.unwrap_or(&Value::Null);
+    let uri = FileUri::deserialize(&params["textDocument"]["uri"])
+      .context(LSPErrCode::InvalidParams)?;
+    let Some(snap) = snapshot(&session, &uri) else { return Ok(json!([])) };
+    let (Some(text), Some(lines)) = (snap.store.text(&uri), snap.store.line_index(&uri)) else {
+      return Ok(json!([]));
+    };
+    let members = members(text);
+    let symbols = (members.iter())
+      .filter(|m| !m.name.is_empty())
+      .filter_map(|m| {
+        let exported = is_exported(&members, text, &m.name);
+        Some(json!({
+          "name": m.name,
+          "detail": if exported { "export" } else { "" },
+          "kind": symbol_kind(m.kind)?,
+          "range": lines.doc_range(m.header..m.range.end),
+          "selectionRange": lines.doc_range(m.name_range.clone()),
+        }))
+      })
+      .collect::<Vec<_>>();
+    Ok(Value::Array(symbols))
+  });
+  srv.on_req_async("workspace/symbol", |req| {
+    let Some(query) = req.params().and_then(|p| p["query"].as_str()).map(str::to_string) else {
+      return req.resolve(Err(anyhow!("Missing query").context(LSPErrCode::InvalidParams)));
+    };
+    thread::Builder::new()
+      .name("workspace-symbol".into())
+      .spawn(move || {
+        let session = req.session().clone();
+        let job = watchdog::register(&session, "Workspace symbol search", req.abort().clone());
+        let symbols = search(&session, &query, req.abort());
+        watchdog::finish(&session, job);
+        // Aborted requests are answered with the reason when dropped
+        if let Some(symbols) = symbols {
+          req.resolve(Ok(Value::Array(symbols)))
+        }
+      })
+      .expect("Failed to spawn workspace symbol thread");
+  });
+  srv.on_req_sync("workspaceSymbol/resolve", |params, session| {
+    let mut symbol = params.cloned().context(LSPErrCode::InvalidParams)?;
+    let uri = FileUri::deserialize(&symbol["location"]["uri"]).context(LSPErrCode::InvalidParams)?;
+    let name = symbol["data"]["name"].as_str().context(LSPErrCode::InvalidParams)?.to_string();
+    let (files, path) = {
+      let g = session.lock();
+      let wctx = workspace(&g)?;
+      let Some((path, wsp, proj)) = wctx.get_proj(&uri) else { return Ok(symbol) };
+      (proj.files(wsp.store.clone()), path)
+    };
+    // The file may have changed since the search, in which case it's
+    // returned as it was
+    let Some(text) = files.source(&path) else { return Ok(symbol) };
+    let found = members(&text).into_iter().find(|m| {
+      m.name == name && listed(m).is_some_and(|kind| symbol["kind"] == kind)
+    });
+    let (Some(m), Some(lines)) = (found, files.line_index(&path)) else { return Ok(symbol) };
+    symbol["location"]["range"] = json!(lines.doc_range(m.name_range));
+    Ok(symbol)
+  });
+}
@@ -0,0 +1,35 @@
+//! Opt-in usage counters sent to the client as `telemetry/event`. Events only
+//! carry counts, durations and locations in the server's own source, never
+//! paths, names or text from the user's workspace.
+
+use std::panic;
+
+use crate::jrpc::{Session, SessionGuard};
+use crate::protocol::messages::{TelemetryEvent, TelemetryParams};
+
+/// Whether the user consented to telemetry. Off unless set.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Telemetry {
+  pub enabled: bool,
+}
+
+pub fn set_enabled(session: &Session, enabled: bool) { session.set(Telemetry { enabled }) }
+
+/// Send an event if telemetry is enabled
+pub fn emit(g: &mut SessionGuard, event: TelemetryParams) {
+  if g.get::<Telemetry>().is_some_and(|t| t.enabled) {
+    g.send::<TelemetryEvent>(event)
+  }
+}
+
+/// Report the location of panics before the default handling. If the panicking
+/// thread holds the session lock the crash is not reported.
+pub fn install_crash_hook(session: Session) {
+  let prev = panic::take_hook();
+  panic::set_hook(Box::new(move |info| {
+    if let (Some(loc), Some(mut g)) = (info.location(), session.try_lock()) {
+      emit(&mut g, TelemetryParams::Crash { signature: format!("{}:{}", loc.file(), loc.line()) })
+    }
+    prev(info)
+  }))
+}
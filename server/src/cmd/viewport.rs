@@ -0,0 +1,102 @@
+//! The lines of each document the user can see, so that the result of an edit
+//! can be pushed for them before the rest of the project is encoded. Clients
+//! that send `orchid/didChangeVisibleRanges` report them exactly, for the rest
+//! the neighbourhood of the last code action request is a good guess because
+//! editors request code actions whenever the cursor moves. The push for the
+//! visible lines keeps the tokens last pushed for the rest of the document.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::jrpc::{JrpcServer, Session, SessionGuard};
+use crate::log;
+use crate::protocol::document::{DocRange, FileUri};
+
+/// Lines above and below a requested range assumed to be visible
+const GUESSED_MARGIN: usize = 40;
+
+#[derive(Debug, Default)]
+pub struct Viewports {
+  /// Visible line ranges by document URI, both ends inclusive
+  lines: HashMap<String, Vec<(usize, usize)>>,
+  /// Whether the client reports visible ranges, in which case guesses are not
+  /// recorded
+  reported: bool,
+}
+
+/// Guess the viewport of a document from a range the client requested
+/// something for
+pub fn requested(session: &Session, uri: &FileUri, range: DocRange) {
+  let mut g = session.lock();
+  if g.get::<Viewports>().is_none() {
+    g.set(Viewports::default())
+  }
+  let viewports = g.get_mut::<Viewports>().unwrap();
+  if !viewports.reported {
+    let lines = (range.start.line.saturating_sub(GUESSED_MARGIN), range.end.line + GUESSED_MARGIN);
+    viewports.lines.insert(uri.stringify(true), vec![lines]);
+  }
+}
+
+/// Line, character, length and legend index of a pushed token
+type Token = (usize, usize, usize, usize);
+
+/// The tokens last pushed for each open document by URI
+#[derive(Debug, Default)]
+struct Pushed(HashMap<String, Vec<Token>>);
+
+/// Record the tokens pushed for an open document
+pub fn pushed(g: &mut SessionGuard, uri: &str, tokens: &[Token]) {
+  if g.get::<Pushed>().is_none() {
+    g.set(Pushed::default())
+  }
+  g.get_mut::<Pushed>().unwrap().0.insert(uri.to_string(), tokens.to_vec());
+}
+
+/// The tokens of the visible lines together with those last pushed for the
+/// lines that aren't visible, in order
+pub fn with_hidden(
+  g: &SessionGuard,
+  uri: &str,
+  visible: impl Fn(usize) -> bool,
+  tokens: Vec<Token>,
+) -> Vec<Token> {
+  let previous = g.get::<Pushed>().and_then(|p| p.0.get(uri)).map_or(&[][..], |t| &t[..]);
+  let hidden = previous.iter().filter(|(line, ..)| !visible(*line)).copied();
+  let mut merged = tokens.into_iter().chain(hidden).collect::<Vec<_>>();
+  merged.sort_unstable();
+  merged
+}
+
+/// Forget the viewports and the pushed tokens of every document
+pub fn reset(g: &mut SessionGuard) {
+  g.remove::<Viewports>();
+  g.remove::<Pushed>();
+}
+
+/// Whether a line of the document is known to be visible
+pub fn visible(g: &SessionGuard, uri: &FileUri) -> Option<impl Fn(usize) -> bool> {
+  let lines = g.get::<Viewports>()?.lines.get(&uri.stringify(true))?.clone();
+  Some(move |line| lines.iter().any(|(start, end)| (*start..=*end).contains(&line)))
+}
+
+pub fn attach(srv: &mut JrpcServer) {
+  srv.on_notif("orchid/didChangeVisibleRanges", |params, session| {
+    let params = params.unwrap_or(&Value::Null);
+    let uri = FileUri::deserialize(&params["textDocument"]["uri"]);
+    let (Ok(uri), Ok(ranges)) = (uri, <Vec<DocRange>>::deserialize(&params["ranges"])) else {
+      log!(Error, "Malformed visible ranges {params}");
+      return;
+    };
+    let mut g = session.lock();
+    if g.get::<Viewports>().is_none() {
+      g.set(Viewports::default())
+    }
+    let viewports = g.get_mut::<Viewports>().unwrap();
+    viewports.reported = true;
+    let lines = ranges.iter().map(|r| (r.start.line, r.end.line)).collect();
+    viewports.lines.insert(uri.stringify(true), lines);
+  });
+}
@@ -0,0 +1,122 @@
+//! Changes to files on disk, either reported by the client through
+//! `workspace/didChangeWatchedFiles` or, if the client can't watch files for
+//! us, by a watcher in the server. Both feed [files_changed]. Project
+//! configuration files are watched too so that their changes apply right away.
+
+use std::path::Path;
+use std::sync::Mutex;
+
+use notify::event::{ModifyKind, RemoveKind, RenameMode};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde_json::{json, Value};
+
+use super::fs::{file_moved, files_changed, WorkspaceCtx};
+use super::{config, registry};
+use crate::jrpc::{JrpcServer, Session, SessionGuard};
+use crate::log;
+use crate::protocol::client::ClientProfile;
+use crate::protocol::document::FileUri;
+
+/// Keeps the server-side watcher alive
+#[allow(dead_code)] // only held, never read
+struct FallbackWatcher(Mutex<RecommendedWatcher>);
+
+fn is_source(path: &Path) -> bool { path.extension().is_some_and(|ext| ext == "orc") }
+fn is_watched(path: &Path) -> bool { is_source(path) || path.ends_with(config::FILE_NAME) }
+
+/// `FileChangeType` values from the protocol
+const CREATED: u64 = 1;
+const DELETED: u64 = 3;
+
+/// Clients report renames as a deletion and a creation. Files deleted and
+/// created under the same name in one batch were moved, and a single deletion
+/// with a single creation is a rename.
+fn renames(changes: &[Value]) -> Vec<(FileUri, FileUri)> {
+  let of_type = |typ| {
+    (changes.iter())
+      .filter(|c| c["type"].as_u64() == Some(typ))
+      .filter_map(|c| FileUri::parse(c["uri"].as_str()?).ok())
+      .collect::<Vec<_>>()
+  };
+  let (mut deleted, mut created) = (of_type(DELETED), of_type(CREATED));
+  let mut pairs = Vec::new();
+  deleted.retain(|from| {
+    let name = from.to_path().file_name().map(|n| n.to_owned());
+    let same_name = created.iter().position(|to| to.to_path().file_name() == name.as_deref());
+    let Some(i) = same_name else { return true };
+    pairs.push((from.clone(), created.remove(i)));
+    false
+  });
+  if let ([from], [to]) = (&deleted[..], &created[..]) {
+    pairs.push((from.clone(), to.clone()))
+  }
+  pairs
+}
+
+/// Watch the workspace folders on the server
+fn start_fallback(session: &Session) -> notify::Result<()> {
+  let session2 = session.clone();
+  let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+    let event = match res {
+      Ok(event) => event,
+      Err(e) => return log!(Error, "File watcher error: {e}"),
+    };
+    match (event.kind, &event.paths[..]) {
+      (EventKind::Modify(ModifyKind::Name(RenameMode::Both)), [from, to]) if is_source(from) => {
+        if let (Some(from), Some(to)) = (FileUri::from_path(from), FileUri::from_path(to)) {
+          file_moved(&session2, &from, &to)
+        }
+      },
+      _ => (),
+    }
+    if let EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) = event.kind {
+      // Folders are reported only when they are deleted, by which point their
+      // contents can't be checked
+      let folder = matches!(event.kind, EventKind::Remove(RemoveKind::Folder));
+      let uris = (event.paths.iter().filter(|p| folder || is_watched(p))).filter_map(|path| {
+        let uri = FileUri::from_path(path);
+        if uri.is_none() {
+          log!(Warn, "Watched path {} can't be a URI", path.display())
+        }
+        uri
+      });
+      files_changed(uris, session2.clone())
+    }
+  })?;
+  let mut g = session.lock();
+  let wctx = g.get::<WorkspaceCtx>().expect("Set during initialization");
+  for root in wctx.roots() {
+    watcher.watch(&root.to_path(), RecursiveMode::Recursive)?;
+  }
+  g.set(FallbackWatcher(Mutex::new(watcher)));
+  Ok(())
+}
+
+/// Ask the client to report changes to source and configuration files, or
+/// watch them on the server if it can't
+pub fn watch_files(session: &Session) {
+  let dynamic = session.lock().get::<ClientProfile>().is_some_and(|p| p.dynamic_watchers);
+  if dynamic {
+    let config = format!("**/{}", config::FILE_NAME);
+    let options = json!({ "watchers": [{ "globPattern": "**/*.orc" }, { "globPattern": config }] });
+    registry::register(session, "file-watcher", "workspace/didChangeWatchedFiles", options);
+  } else if let Err(e) = start_fallback(session) {
+    log!(Error, "Failed to start the server-side file watcher: {e}")
+  }
+}
+
+/// Stop the server-side watcher if there is one
+pub fn stop_fallback(g: &mut SessionGuard) { g.remove::<FallbackWatcher>(); }
+
+pub fn attach(srv: &mut JrpcServer) {
+  srv.on_notif("workspace/didChangeWatchedFiles", |params, session| {
+    let changes = params.and_then(|p| p["changes"].as_array()).map_or(&[][..], |v| &v[..]);
+    for (from, to) in renames(changes) {
+      file_moved(&session, &from, &to)
+    }
+    let uris = (changes.iter().filter_map(|c| c["uri"].as_str())).filter_map(|uri| {
+      FileUri::parse(uri).inspect_err(|e| log!(Warn, "Ignoring change to {uri}: {e}")).ok()
+    });
+    files_changed(uris, session)
+  });
+}
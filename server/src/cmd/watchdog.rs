@@ -0,0 +1,125 @@
+//! A timer that stops jobs running far longer than expected, such as a load
+//! stuck in a pathological macro expansion, so they don't keep a worker busy
+//! forever. Jobs are registered by [super::progress::WorkProgress] and by slow
+//! requests, and can only be stopped at their next abort check. Stopped
+//! requests fail with `ServerCancelled`.
+
+use std::collections::HashMap;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use itertools::Itertools;
+use serde_json::Value;
+
+use crate::abort::Abort;
+use crate::jrpc::Session;
+use crate::log;
+use crate::protocol::messages::{MessageType, ShowMessage, ShowMessageParams};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Phases kept for the report of a stuck job
+const PHASE_HISTORY: usize = 16;
+
+/// `jobTimeoutMs` initialization option and setting. Zero turns the watchdog
+/// off.
+#[derive(Clone, Copy, Debug)]
+pub struct WatchdogConfig {
+  pub timeout: Duration,
+}
+impl Default for WatchdogConfig {
+  fn default() -> Self { Self { timeout: Duration::from_secs(120) } }
+}
+
+/// Read the timeout from the initialization options or the `orchidls` settings
+/// section, defaulting to the base
+pub fn watchdog_config(opts: &Value, base: WatchdogConfig) -> WatchdogConfig {
+  let timeout = opts["jobTimeoutMs"].as_u64().map(Duration::from_millis);
+  WatchdogConfig { timeout: timeout.unwrap_or(base.timeout) }
+}
+
+struct Job {
+  title: String,
+  thread: String,
+  start: Instant,
+  abort: Abort,
+  /// The most recent phases the job reported, with the time they began
+  phases: Vec<(Duration, String)>,
+}
+
+#[derive(Default)]
+struct Jobs {
+  next_id: usize,
+  running: HashMap<usize, Job>,
+}
+
+/// Watch a job that can be aborted. Returns an id for [phase] and [finish].
+pub fn register(session: &Session, title: &str, abort: Abort) -> usize {
+  let mut g = session.lock();
+  if g.get::<Jobs>().is_none() {
+    g.set(Jobs::default());
+  }
+  let jobs = g.get_mut::<Jobs>().unwrap();
+  let id = jobs.next_id;
+  jobs.next_id += 1;
+  let thread = thread::current().name().unwrap_or("unnamed").to_string();
+  let start = Instant::now();
+  jobs.running.insert(id, Job { title: title.to_string(), thread, start, abort, phases: vec![] });
+  id
+}
+
+/// Note the phase a job entered, for the report if it gets stuck
+pub fn phase(session: &Session, id: usize, phase: &str) {
+  let mut g = session.lock();
+  let Some(job) = g.get_mut::<Jobs>().and_then(|j| j.running.get_mut(&id)) else { return };
+  if job.phases.last().is_some_and(|(_, last)| last == phase) {
+    return;
+  }
+  if PHASE_HISTORY <= job.phases.len() {
+    job.phases.remove(0);
+  }
+  job.phases.push((job.start.elapsed(), phase.to_string()));
+}
+
+/// Stop watching a job
+pub fn finish(session: &Session, id: usize) {
+  if let Some(jobs) = session.lock().get_mut::<Jobs>() {
+    jobs.running.remove(&id);
+  }
+}
+
+fn report(job: &Job) -> String {
+  let phases = (job.phases.iter())
+    .map(|(at, phase)| format!("  {:>8.1}s {phase}", at.as_secs_f64()))
+    .join("\n");
+  let (title, thread, elapsed) = (&job.title, &job.thread, job.start.elapsed().as_secs_f64());
+  format!("Job \"{title}\" on thread {thread} stuck for {elapsed:.1}s, phases:\n{phases}")
+}
+
+/// Stop the jobs that exceeded the timeout and schedule the next check
+fn check(session: Session) {
+  session.scheduler().after(CHECK_INTERVAL, check);
+  let mut g = session.lock();
+  let timeout = g.get::<WatchdogConfig>().copied().unwrap_or_default().timeout;
+  let Some(jobs) = g.get_mut::<Jobs>() else { return };
+  if timeout.is_zero() {
+    return;
+  }
+  let stuck = (jobs.running.iter())
+    .filter(|(_, job)| timeout < job.start.elapsed())
+    .map(|(id, _)| *id)
+    .collect_vec();
+  let stuck = stuck.into_iter().filter_map(|id| jobs.running.remove(&id)).collect_vec();
+  for job in stuck {
+    log!(Warn, "{}", report(&job));
+    job.abort.abort();
+    let message = format!(
+      "{} took longer than {}s and was stopped. The server log has details.",
+      job.title,
+      timeout.as_secs(),
+    );
+    g.send::<ShowMessage>(ShowMessageParams { typ: MessageType::Warning, message });
+  }
+}
+
+/// Start checking for stuck jobs on the main loop
+pub fn start(session: Session) { session.scheduler().after(CHECK_INTERVAL, check); }
@@ -0,0 +1,36 @@
+//! Server-initiated navigation for commands that produce a document to look at
+
+use crate::jrpc::Session;
+use crate::log;
+use crate::protocol::client::ClientProfile;
+use crate::protocol::document::DocRange;
+use crate::protocol::messages::{
+  MessageType, ShowDocument, ShowDocumentParams, ShowMessage, ShowMessageParams,
+};
+
+/// Open a document in the editor and focus it with the selection highlighted.
+/// Clients that can't show documents get a message pointing at the URI
+/// instead, as do clients that fail to open it.
+pub fn show_document(session: &Session, uri: String, selection: Option<DocRange>) {
+  let supported = session.lock().get::<ClientProfile>().is_some_and(|p| p.show_document);
+  if !supported {
+    return show_fallback(session, &uri);
+  }
+  let params = ShowDocumentParams {
+    uri: uri.clone(),
+    external: None,
+    take_focus: Some(true),
+    selection,
+  };
+  let session2 = session.clone();
+  session.call::<ShowDocument>(params, move |res| match res {
+    Ok(res) if res.success => (),
+    Ok(_) => show_fallback(&session2, &uri),
+    Err(e) => log!(Error, "Failed to show {uri}: {e:?}"),
+  })
+}
+
+fn show_fallback(session: &Session, uri: &str) {
+  let message = format!("See {uri}");
+  session.send::<ShowMessage>(ShowMessageParams { typ: MessageType::Info, message })
+}
@@ -3,24 +3,26 @@ use std::iter;
 
 use serde_json::Value;
 
+use crate::log;
+
 /// Lock stdin and read LSP header-data blocks from it. Because stdin doesn't
 /// offer packets, it's critically important that messages end at exactly the
 /// specified number of bytes.
 pub fn stdin_ingress() -> impl Iterator<Item = Value> {
   let mut stdin = stdin().lock();
   return iter::from_fn(move || {
-    eprintln!("\nPolling for input");
+    log!(Debug, "Polling for input");
     let mut length = None;
     // process all headers
     loop {
       let mut buf = String::new();
       stdin.read_line(&mut buf).unwrap();
-      eprint!("Received header: {buf}");
+      log!(Debug, "Received header: {}", buf.trim_end());
       match buf.trim().split_once(':') {
         Some(("Content-Type", ct)) => match ct.trim().split_once("; charset=") {
           Some(("application/vscode-jsonrpc", "utf-8" | "utf8")) => (),
           // not a hard error because most likely the stream is standard LSP ASCII anyway
-          _ => eprintln!("Unrecognized Content-Type header: \"{ct}\""),
+          _ => log!(Warn, "Unrecognized Content-Type header: \"{ct}\""),
         },
         Some(("Content-Length", cl)) => length = Some(cl.trim().parse().unwrap()),
         None if buf.trim().is_empty() => break,
@@ -32,7 +34,7 @@ pub fn stdin_ingress() -> impl Iterator<Item = Value> {
     stdin.read_exact(&mut line).unwrap();
     // This should fail if we accidentally block on an extra character
     let val = serde_json::from_slice(&line).unwrap();
-    eprintln!("Received message {val}");
+    log!(Debug, "Received message {val}");
     Some(val)
   });
 }
@@ -21,4 +21,8 @@ impl CtxMap {
     let val = self.items.get_mut(&TypeId::of::<T>())?;
     Some(val.downcast_mut().expect("keyed with TypeId"))
   }
+  pub fn remove<T: Ctx>(&mut self) -> Option<T> {
+    let val = self.items.remove(&TypeId::of::<T>())?;
+    Some(*val.downcast().expect("keyed with TypeId"))
+  }
 }
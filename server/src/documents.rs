@@ -0,0 +1,287 @@
+//! The documents open in the editor. The client owns their text, which arrives
+//! through `didOpen` and `didChange` instead of being read from disk. Changes
+//! are queued as they arrive and applied together when a load takes a
+//! snapshot, so each load sees a single version of the document no matter how
+//! edits interleave with it.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::{fmt, mem};
+
+use crate::abort::Abort;
+use crate::protocol::docpos::PositionError;
+use crate::protocol::document::{ContentChange, FileUri};
+use crate::protocol::line_index::LineIndex;
+
+/// A version of a document. Snapshots are never modified, later versions are
+/// new snapshots.
+#[derive(Clone, Debug)]
+pub struct DocSnapshot {
+  pub version: u64,
+  pub text: Arc<String>,
+  pub lines: Arc<LineIndex>,
+}
+impl DocSnapshot {
+  pub fn new(version: u64, text: String) -> Self {
+    let lines = Arc::new(LineIndex::new(&text));
+    Self { version, text: Arc::new(text), lines }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DocError {
+  NotOpen,
+  /// The changes aren't newer than the last ones received
+  Stale { version: u64, got: u64 },
+  /// A change, counted from the first one pending, is out of the document
+  Position(usize, PositionError),
+  /// Changes couldn't be applied before, so only the full text can be synced
+  Desynced,
+}
+impl fmt::Display for DocError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::NotOpen => write!(f, "The document isn't open"),
+      Self::Stale { version, got } => write!(f, "Got version {got} after version {version}"),
+      Self::Position(i, e) => write!(f, "Change {i} is out of the document: {e}"),
+      Self::Desynced => write!(f, "The document is out of sync until it's reopened or replaced"),
+    }
+  }
+}
+impl std::error::Error for DocError {}
+
+struct Document {
+  current: DocSnapshot,
+  /// The version after the pending changes
+  version: u64,
+  pending: Vec<ContentChange>,
+  /// Pending changes failed to apply, so the client's text is unknown until
+  /// it's sent in full. [DocumentStore::snapshot] serves the last good version
+  /// meanwhile.
+  desynced: bool,
+  /// Triggered when the document changes or closes
+  superseded: Abort,
+}
+
+/// The text, version and unapplied changes of every open document
+#[derive(Default)]
+pub struct DocumentStore {
+  docs: HashMap<FileUri, Document>,
+}
+impl DocumentStore {
+  /// Start tracking a document. Returns whether it was already open, in which
+  /// case its text and pending changes are replaced.
+  pub fn open(&mut self, uri: FileUri, version: u64, text: String) -> bool {
+    let current = DocSnapshot::new(version, text);
+    let superseded = Abort::new();
+    let doc = Document { current, version, pending: Vec::new(), desynced: false, superseded };
+    let Some(old) = self.docs.insert(uri, doc) else { return false };
+    old.superseded.abort();
+    true
+  }
+
+  /// Stop tracking a document. Returns whether it was open.
+  pub fn close(&mut self, uri: &FileUri) -> bool {
+    let Some(doc) = self.docs.remove(uri) else { return false };
+    doc.superseded.abort();
+    true
+  }
+
+  pub fn is_open(&self, uri: &FileUri) -> bool { self.docs.contains_key(uri) }
+
+  /// The URIs of the open documents
+  pub fn uris(&self) -> impl Iterator<Item = &FileUri> { self.docs.keys() }
+
+  /// Queue changes that bring the document to a new version. They're applied
+  /// in order, each to the text produced by the previous one. Once a change
+  /// failed to apply, only changes that replace the whole text are accepted.
+  pub fn change(
+    &mut self,
+    uri: &FileUri,
+    version: u64,
+    changes: impl IntoIterator<Item = ContentChange>,
+  ) -> Result<(), DocError> {
+    let doc = self.docs.get_mut(uri).ok_or(DocError::NotOpen)?;
+    if version <= doc.version {
+      return Err(DocError::Stale { version: doc.version, got: version });
+    }
+    let mut changes = changes.into_iter().collect::<Vec<_>>();
+    if doc.desynced {
+      let full = changes.iter().rposition(|c| c.range.is_none()).ok_or(DocError::Desynced)?;
+      changes.drain(..full);
+      doc.desynced = false;
+    }
+    doc.version = version;
+    mem::replace(&mut doc.superseded, Abort::new()).abort();
+    for change in changes {
+      // Replacing the whole text makes the earlier changes irrelevant
+      if change.range.is_none() {
+        doc.pending.clear();
+      }
+      doc.pending.push(change);
+    }
+    Ok(())
+  }
+
+  /// An [Abort] triggered when the document next changes or closes, for
+  /// requests to the client made on behalf of its current version
+  pub fn superseded(&self, uri: &FileUri) -> Option<Abort> {
+    Some(self.docs.get(uri)?.superseded.clone())
+  }
+
+  /// The latest version of a document with every pending change applied. If a
+  /// change can't be applied, the pending changes are dropped and the document
+  /// stays at the last version that was built until the client sends the full
+  /// text or reopens it.
+  pub fn snapshot(&mut self, uri: &FileUri) -> Result<DocSnapshot, DocError> {
+    let doc = self.docs.get_mut(uri).ok_or(DocError::NotOpen)?;
+    if doc.pending.is_empty() {
+      return Ok(doc.current.clone());
+    }
+    match apply(&doc.current, mem::take(&mut doc.pending)) {
+      Ok((text, lines)) => {
+        let (text, lines) = (Arc::new(text), Arc::new(lines));
+        doc.current = DocSnapshot { version: doc.version, text, lines };
+        Ok(doc.current.clone())
+      },
+      Err(e) => {
+        doc.desynced = true;
+        Err(e)
+      },
+    }
+  }
+}
+
+/// Apply changes in order to the text of a snapshot
+fn apply(snap: &DocSnapshot, changes: Vec<ContentChange>) -> Result<(String, LineIndex), DocError> {
+  let (mut text, mut lines) = (snap.text.to_string(), (*snap.lines).clone());
+  for (i, change) in changes.into_iter().enumerate() {
+    let Some(range) = change.range else {
+      lines.update(&text, &change.text);
+      text = change.text;
+      continue;
+    };
+    let start = lines.docpos2bpos(range.start).map_err(|e| DocError::Position(i, e))?;
+    let end = lines.docpos2bpos(range.end).map_err(|e| DocError::Position(i, e))?.max(start);
+    text.replace_range(start..end, &change.text);
+    lines.edit(start..end, change.text.len(), &text);
+  }
+  Ok((text, lines))
+}
+
+#[cfg(test)]
+mod test {
+  use std::ops::Range;
+
+  use super::{DocError, DocumentStore};
+  use crate::protocol::docpos::{DocPos, PositionError};
+  use crate::protocol::document::{ContentChange, DocRange, FileUri};
+  use crate::protocol::line_index::LineIndex;
+  use crate::testing::{boundaries, naive_pos, Rng};
+
+  fn uri() -> FileUri { FileUri::parse("file:///w/main.orc").unwrap() }
+
+  fn change(text: &str, range: Range<usize>, new: &str) -> ContentChange {
+    let range = DocRange { start: naive_pos(text, range.start), end: naive_pos(text, range.end) };
+    ContentChange { range: Some(range), text: new.to_string() }
+  }
+
+  #[test]
+  fn versions() {
+    let mut docs = DocumentStore::default();
+    assert!(!docs.open(uri(), 1, "const a := 1".to_string()));
+    let text = "const a := 1";
+    docs.change(&uri(), 2, [change(text, 6..7, "b")]).unwrap();
+    docs.change(&uri(), 3, [change("const b := 1", 11..12, "2\r\n")]).unwrap();
+    let stale = docs.change(&uri(), 3, [change(text, 0..0, "x")]);
+    assert_eq!(stale, Err(DocError::Stale { version: 3, got: 3 }));
+    let snap = docs.snapshot(&uri()).unwrap();
+    assert_eq!((snap.version, snap.text.as_str()), (3, "const b := 2\r\n"));
+    assert_eq!(*snap.lines, LineIndex::new(&snap.text));
+    let full = ContentChange { range: None, text: "é".to_string() };
+    docs.change(&uri(), 4, [change(&snap.text, 0..0, "x"), full, change("é", 2..2, "!")]).unwrap();
+    assert_eq!(docs.snapshot(&uri()).unwrap().text.as_str(), "é!", "Full text resets");
+    let far = DocRange { start: DocPos::new(5, 0), end: DocPos::new(5, 0) };
+    let bad = ContentChange { range: Some(far), text: String::new() };
+    docs.change(&uri(), 5, [change("é!", 0..0, "a"), bad]).unwrap();
+    let err = PositionError::PastEnd { pos: DocPos::new(5, 0), lines: 1 };
+    assert_eq!(docs.snapshot(&uri()).unwrap_err(), DocError::Position(1, err));
+    assert_eq!(docs.snapshot(&uri()).unwrap().version, 4, "The failed changes are dropped");
+    assert!(docs.close(&uri()));
+    assert_eq!(docs.snapshot(&uri()).unwrap_err(), DocError::NotOpen);
+  }
+
+  #[test]
+  fn desync() {
+    let mut docs = DocumentStore::default();
+    docs.open(uri(), 1, "const a := 1".to_string());
+    let far = DocRange { start: DocPos::new(5, 0), end: DocPos::new(5, 0) };
+    docs.change(&uri(), 2, [ContentChange { range: Some(far), text: "x".to_string() }]).unwrap();
+    docs.snapshot(&uri()).unwrap_err();
+    let edit = docs.change(&uri(), 3, [change("const a := 1", 0..0, "x")]);
+    assert_eq!(edit, Err(DocError::Desynced), "The edit was made to a text the server lacks");
+    let snap = docs.snapshot(&uri()).unwrap();
+    assert_eq!((snap.version, snap.text.as_str()), (1, "const a := 1"), "The last good version");
+    let full = ContentChange { range: None, text: "const b := 2".to_string() };
+    let changes = [change("const a := 1", 0..0, "x"), full, change("const b := 2", 11..12, "3")];
+    docs.change(&uri(), 4, changes).unwrap();
+    let snap = docs.snapshot(&uri()).unwrap();
+    assert_eq!((snap.version, snap.text.as_str()), (4, "const b := 3"), "The full text resyncs");
+    docs.change(&uri(), 5, [change("const b := 3", 6..7, "c")]).unwrap();
+    assert_eq!(docs.snapshot(&uri()).unwrap().text.as_str(), "const c := 3");
+    docs.change(&uri(), 6, [ContentChange { range: Some(far), text: "x".to_string() }]).unwrap();
+    docs.snapshot(&uri()).unwrap_err();
+    docs.open(uri(), 7, "const d := 4".to_string());
+    docs.change(&uri(), 8, [change("const d := 4", 6..7, "e")]).unwrap();
+    assert_eq!(docs.snapshot(&uri()).unwrap().text.as_str(), "const e := 4", "Reopening resyncs");
+  }
+
+  #[test]
+  fn superseding() {
+    let mut docs = DocumentStore::default();
+    docs.open(uri(), 1, "const a := 1".to_string());
+    let first = docs.superseded(&uri()).unwrap();
+    docs.change(&uri(), 1, [change("const a := 1", 0..0, "x")]).unwrap_err();
+    assert!(first.is_valid(), "Stale changes don't supersede the version");
+    docs.change(&uri(), 2, [change("const a := 1", 11..12, "2")]).unwrap();
+    assert!(!first.is_valid());
+    let second = docs.superseded(&uri()).unwrap();
+    assert!(second.is_valid(), "Taking a snapshot doesn't change the version");
+    docs.snapshot(&uri()).unwrap();
+    assert!(second.is_valid());
+    docs.close(&uri());
+    assert!(!second.is_valid());
+    assert!(docs.superseded(&uri()).is_none());
+  }
+
+  #[test]
+  fn random_changes() {
+    let mut rng = Rng::new(0xd0c5);
+    for round in 0..200 {
+      let lines = rng.below(5) + 1;
+      let mut text = rng.text(lines);
+      let mut docs = DocumentStore::default();
+      docs.open(uri(), 0, text.clone());
+      for version in 1..rng.below(4) as u64 + 2 {
+        let changes = (0..rng.below(3) + 1)
+          .map(|_| {
+            let bounds = boundaries(&text);
+            let (a, b) = (bounds[rng.below(bounds.len())], bounds[rng.below(bounds.len())]);
+            let lines = rng.below(2) + 1;
+            let new = rng.text(lines);
+            let change = change(&text, a.min(b)..a.max(b), &new);
+            text.replace_range(a.min(b)..a.max(b), &new);
+            change
+          })
+          .collect::<Vec<_>>();
+        docs.change(&uri(), version, changes).unwrap();
+        if rng.below(2) == 0 {
+          assert_eq!(docs.snapshot(&uri()).unwrap().text.as_str(), text, "Round {round}");
+        }
+      }
+      let snap = docs.snapshot(&uri()).unwrap();
+      assert_eq!(snap.text.as_str(), text, "Round {round}");
+      assert_eq!(*snap.lines, LineIndex::new(&text), "Round {round}");
+    }
+  }
+}
@@ -0,0 +1,213 @@
+//! The main loop of the server. Messages from the client, timers and tasks
+//! posted by background threads are all handled on the main thread in the
+//! order they become ready, so a timer can't race the message handlers and a
+//! background job can hand its result back without locking around them.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use crate::jrpc::{JrpcServer, Session};
+
+/// Work run on the main thread between messages. Tasks must not block for
+/// long, since messages wait for them.
+pub type Task = Box<dyn FnOnce(Session) + Send>;
+
+/// Identifies a timer so that it can be cancelled
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TimerId(u64);
+
+/// Items with a deadline. Cancelled timers stay in the heap until they reach
+/// the top.
+struct Timers<T> {
+  next_id: u64,
+  heap: BinaryHeap<Reverse<(Instant, u64)>>,
+  items: HashMap<u64, T>,
+}
+impl<T> Default for Timers<T> {
+  fn default() -> Self { Self { next_id: 0, heap: BinaryHeap::new(), items: HashMap::new() } }
+}
+impl<T> Timers<T> {
+  fn add(&mut self, at: Instant, item: T) -> TimerId {
+    let id = self.next_id;
+    self.next_id += 1;
+    self.heap.push(Reverse((at, id)));
+    self.items.insert(id, item);
+    TimerId(id)
+  }
+
+  fn cancel(&mut self, id: TimerId) -> bool { self.items.remove(&id.0).is_some() }
+
+  /// The earliest deadline of a timer that wasn't cancelled
+  fn deadline(&mut self) -> Option<Instant> {
+    while let Some(Reverse((at, id))) = self.heap.peek() {
+      if self.items.contains_key(id) {
+        return Some(*at);
+      }
+      self.heap.pop();
+    }
+    None
+  }
+
+  /// Take the earliest timer that expired by `now`. Timers with the same
+  /// deadline expire in the order they were added.
+  fn pop_due(&mut self, now: Instant) -> Option<T> {
+    let at = self.deadline()?;
+    if now < at {
+      return None;
+    }
+    let Reverse((_, id)) = self.heap.pop().expect("deadline checked the top");
+    self.items.remove(&id)
+  }
+}
+
+#[derive(Default)]
+struct Pending {
+  /// The next message, taken before the input thread reads another one
+  message: Option<Value>,
+  closed: bool,
+  tasks: VecDeque<Task>,
+  timers: Timers<Task>,
+}
+
+type Shared = Arc<(Mutex<Pending>, Condvar)>;
+
+/// Posts tasks and sets timers on the main loop from any thread. Obtained from
+/// [Session::scheduler].
+#[derive(Clone, Default)]
+pub struct Scheduler(Shared);
+impl Scheduler {
+  /// Run a task on the main loop as soon as possible
+  pub fn post(&self, task: impl FnOnce(Session) + Send + 'static) {
+    let (lock, cvar) = &*self.0;
+    lock.lock().unwrap().tasks.push_back(Box::new(task));
+    cvar.notify_all();
+  }
+
+  /// Run a task on the main loop once the delay elapsed
+  pub fn after(&self, delay: Duration, task: impl FnOnce(Session) + Send + 'static) -> TimerId {
+    let (lock, cvar) = &*self.0;
+    let id = lock.lock().unwrap().timers.add(Instant::now() + delay, Box::new(task));
+    cvar.notify_all();
+    id
+  }
+
+  /// Stop a timer. Returns false if it already fired or was cancelled.
+  pub fn cancel(&self, id: TimerId) -> bool { self.0 .0.lock().unwrap().timers.cancel(id) }
+}
+
+pub enum Event {
+  Message(Value),
+  Task(Task),
+}
+
+/// The receiving end of a [Scheduler] together with the message input
+pub struct EventLoop(Shared);
+impl EventLoop {
+  /// Read messages on a separate thread, which waits for each message to be
+  /// taken before reading the next so that a flood of messages stays in the
+  /// input queue.
+  pub fn new(scheduler: Scheduler, input: impl Iterator<Item = Value> + Send + 'static) -> Self {
+    let shared = scheduler.0;
+    let shared2 = shared.clone();
+    thread::Builder::new()
+      .name("event-input".into())
+      .spawn(move || {
+        let (lock, cvar) = &*shared2;
+        for msg in input {
+          let mut p = cvar.wait_while(lock.lock().unwrap(), |p| p.message.is_some()).unwrap();
+          p.message = Some(msg);
+          cvar.notify_all();
+        }
+        lock.lock().unwrap().closed = true;
+        cvar.notify_all();
+      })
+      .expect("Failed to spawn event input thread");
+    Self(shared)
+  }
+
+  /// Dispatch events to the server until the input ends
+  pub fn run(self, srv: &mut JrpcServer) {
+    for event in self {
+      match event {
+        Event::Message(msg) => srv.recv(msg),
+        Event::Task(task) => task(srv.session().clone()),
+      }
+    }
+  }
+}
+impl Iterator for EventLoop {
+  type Item = Event;
+  /// Wait for the next event. Expired timers and posted tasks come before
+  /// messages. Returns [None] once the input has ended.
+  fn next(&mut self) -> Option<Event> {
+    let (lock, cvar) = &*self.0;
+    let mut p = lock.lock().unwrap();
+    loop {
+      if let Some(task) = p.timers.pop_due(Instant::now()) {
+        return Some(Event::Task(task));
+      }
+      if let Some(task) = p.tasks.pop_front() {
+        return Some(Event::Task(task));
+      }
+      if let Some(msg) = p.message.take() {
+        cvar.notify_all();
+        return Some(Event::Message(msg));
+      }
+      if p.closed {
+        return None;
+      }
+      p = match p.timers.deadline() {
+        None => cvar.wait(p).unwrap(),
+        Some(at) => cvar.wait_timeout(p, at.saturating_duration_since(Instant::now())).unwrap().0,
+      };
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::sync::mpsc;
+  use std::time::{Duration, Instant};
+
+  use serde_json::json;
+
+  use super::{Event, EventLoop, Scheduler, Timers};
+
+  #[test]
+  fn timers() {
+    let (mut timers, t0) = (Timers::default(), Instant::now());
+    let ms = Duration::from_millis;
+    timers.add(t0 + ms(20), "late");
+    let early = timers.add(t0 + ms(10), "early");
+    timers.add(t0 + ms(10), "early too");
+    assert_eq!(timers.deadline(), Some(t0 + ms(10)));
+    assert_eq!(timers.pop_due(t0), None, "Nothing expired yet");
+    assert!(timers.cancel(early));
+    assert!(!timers.cancel(early), "Already cancelled");
+    assert_eq!(timers.pop_due(t0 + ms(30)), Some("early too"));
+    assert_eq!(timers.pop_due(t0 + ms(30)), Some("late"));
+    assert_eq!(timers.deadline(), None);
+  }
+
+  #[test]
+  fn order() {
+    let (scheduler, start) = (Scheduler::default(), Instant::now());
+    let (tx, rx) = mpsc::channel();
+    scheduler.after(Duration::from_millis(50), |_| ());
+    let mut events = EventLoop::new(scheduler.clone(), rx.into_iter());
+    tx.send(json!({ "method": "first" })).unwrap();
+    let Some(Event::Message(msg)) = events.next() else { panic!("Expected the message") };
+    assert_eq!(msg["method"], "first");
+    scheduler.post(|_| ());
+    assert!(matches!(events.next(), Some(Event::Task(_))), "Posted task");
+    assert!(matches!(events.next(), Some(Event::Task(_))), "Timer");
+    assert!(Duration::from_millis(50) <= start.elapsed(), "Waited for the timer");
+    drop(tx);
+    assert!(events.next().is_none(), "Input closed");
+  }
+}
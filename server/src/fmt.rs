@@ -0,0 +1,135 @@
+//! Formatting the files under a directory without an editor. Run with
+//! `orchid-ls fmt <path> [--check]`. Files are rewritten by [format], which the
+//! editor's "Format document" source action runs as well, with the
+//! configuration of their project, so the command line and the editor can't
+//! disagree. With `--check` nothing is written, the changes are printed
+//! instead and the exit code is nonzero if there are any. Generated files are
+//! left alone, and so are files that aren't plain UTF-8, because writing back
+//! their decoded text would change the encoding or lose the invalid bytes.
+//!
+//! The values of constants are laid out by the expression printer, after
+//! sorting the members if `sortMembers.onFormat` is set. Further
+//! document-level rewrites go in [format].
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use orchidlang::name::VPath;
+
+use crate::cmd::config::{read_project_file, ProjectConfig};
+use crate::orc::encoding::Encoding;
+use crate::orc::members::{sort_members, Edit};
+use crate::orc::printer::layout_consts;
+use crate::orc::project::{find_all_projects, ProjectFiles};
+use crate::patches::PatchStore;
+use crate::protocol::document::FileUri;
+use crate::protocol::edits::{order, splice};
+
+/// Apply edits that don't overlap to a text
+pub fn apply(text: &str, edits: Vec<Edit>) -> String {
+  splice(text, &order(edits).expect("Rewrites of a document overlap"))
+}
+
+/// The formatted text of a file in a project with the given configuration
+pub fn format(text: &str, config: &ProjectConfig) -> String {
+  let sorted = match config.sort_on_format {
+    true => apply(text, sort_members(text, config.order)),
+    false => text.to_string(),
+  };
+  apply(&sorted, layout_consts(&sorted, &config.print))
+}
+
+/// The changed lines between two texts as a single hunk in the unified diff
+/// format, or None if they're equal
+pub fn hunk(old: &str, new: &str) -> Option<String> {
+  let (old, new) = (old.lines().collect::<Vec<_>>(), new.lines().collect::<Vec<_>>());
+  let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+  let rest = old.len().min(new.len()) - prefix;
+  let suffix = (old.iter().rev().zip(new.iter().rev())).take(rest).take_while(|(a, b)| a == b);
+  let suffix = suffix.count();
+  let (removed, added) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+  if removed.is_empty() && added.is_empty() {
+    return None;
+  }
+  let start = prefix + 1;
+  let mut out = format!("@@ -{start},{} +{start},{} @@\n", removed.len(), added.len());
+  removed.iter().for_each(|line| out += &format!("-{line}\n"));
+  added.iter().for_each(|line| out += &format!("+{line}\n"));
+  Some(out)
+}
+
+fn file_path(uri: &FileUri) -> PathBuf {
+  let mut path = uri.to_path().into_os_string();
+  path.push(".orc");
+  path.into()
+}
+
+/// Format every file under the path. Returns the exit code.
+pub fn run(path: &Path, check: bool) -> i32 {
+  let dir = if path.is_file() { path.parent().unwrap_or(path) } else { path };
+  let Some(root) = dir.canonicalize().ok().and_then(|dir| FileUri::from_path(&dir)) else {
+    eprintln!("Cannot format {}: not a directory", path.display());
+    return 2;
+  };
+  let store = PatchStore::new(root.clone());
+  let vfs = store.clone().mk_vfs(&root).expect("The root is in the store");
+  let (mut changed, mut failed) = (0, false);
+  for project in find_all_projects(VPath::new([]), &vfs) {
+    let files = ProjectFiles { patches: store.clone(), root: project };
+    let (opts, _) = read_project_file(&files.config_dir()).unwrap_or_default();
+    let config = ProjectConfig::default().merged(&opts);
+    // Generated files are read-only, the build step that writes them would
+    // undo the formatting
+    for file in files.source_files().into_iter().filter(|file| !config.is_generated(file)) {
+      let Some(text) = files.source(&file) else { continue };
+      let path = file_path(&files.file_uri(&file));
+      // Only files that aren't plain UTF-8 have an encoding, once read
+      if let Some(enc) = files.encoding(&file) {
+        let enc = match enc {
+          Encoding::Invalid(offset) => format!("invalid UTF-8 at byte {offset}"),
+          enc => format!("encoded as {enc:?}"),
+        };
+        eprintln!("Skipping {}: {enc}", path.display());
+        continue;
+      }
+      let formatted = format(&text, &config);
+      if formatted == *text {
+        continue;
+      }
+      changed += 1;
+      if check {
+        println!("--- {}\n+++ {}", path.display(), path.display());
+        print!("{}", hunk(&text, &formatted).unwrap_or_default());
+      } else if let Err(e) = fs::write(&path, formatted) {
+        eprintln!("Failed to write {}: {e}", path.display());
+        failed = true;
+      }
+    }
+  }
+  match check {
+    true => eprintln!("{changed} files would be reformatted"),
+    false => eprintln!("{changed} files reformatted"),
+  }
+  i32::from(failed || (check && 0 < changed))
+}
+
+#[cfg(test)]
+mod test {
+  use super::{apply, hunk};
+
+  #[test]
+  fn edits() {
+    let text = "const a := 1\nconst b := 2\n";
+    let edits = vec![(19..25, "a := 1".to_string()), (6..12, "b := 2".to_string())];
+    assert_eq!(apply(text, edits), "const b := 2\nconst a := 1\n");
+  }
+
+  #[test]
+  fn diff() {
+    assert_eq!(hunk("a\nb\n", "a\nb\n"), None);
+    let old = "import x\nconst b := 2\nconst a := 1\nexport ::b\n";
+    let new = "import x\nconst a := 1\nconst b := 2\nexport ::b\n";
+    let expected = "@@ -2,2 +2,2 @@\n-const b := 2\n-const a := 1\n+const a := 1\n+const b := 2\n";
+    assert_eq!(hunk(old, new).as_deref(), Some(expected));
+  }
+}
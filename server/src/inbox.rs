@@ -0,0 +1,212 @@
+//! Queue between the input stream and the server that keeps a flooding client
+//! from exhausting memory. A queued change is superseded by a later change to
+//! the same document that only consists of full-text replacements.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use crate::log;
+
+/// Notifications that may carry the full text of a document
+const SUPERSEDABLE: &[&str] = &["textDocument/didChange"];
+
+/// Whether every change of a change notification replaces the whole text, so
+/// that the changes before it don't matter
+fn replaces_text(msg: &Value) -> bool {
+  let changes = msg["params"]["contentChanges"].as_array();
+  changes.is_some_and(|c| !c.is_empty() && c.iter().all(|change| change.get("range").is_none()))
+}
+
+/// Token bucket parameters for an inbound method
+#[derive(Clone, Copy, Debug)]
+pub struct Limit {
+  /// Number of messages let through in quick succession
+  pub burst: u32,
+  /// Sustained rate of messages per second
+  pub per_sec: f64,
+}
+
+struct Bucket {
+  limit: Limit,
+  tokens: f64,
+  last: Instant,
+}
+impl Bucket {
+  fn new(limit: Limit) -> Self { Self { limit, tokens: limit.burst.into(), last: Instant::now() } }
+  /// Take a token, or report how long until one is available
+  fn take(&mut self, now: Instant) -> Result<(), Duration> {
+    let refill = now.saturating_duration_since(self.last).as_secs_f64() * self.limit.per_sec;
+    self.tokens = (self.tokens + refill).min(self.limit.burst.into());
+    self.last = now;
+    if 1.0 <= self.tokens {
+      self.tokens -= 1.0;
+      return Ok(());
+    }
+    Err(Duration::from_secs_f64((1.0 - self.tokens) / self.limit.per_sec))
+  }
+}
+
+#[derive(Default)]
+struct Queue {
+  msgs: VecDeque<Value>,
+  closed: bool,
+}
+impl Queue {
+  /// Enqueue a message, replacing an older change to the same document unless
+  /// a request or response was received since, which may depend on the
+  /// intermediate state, or another notification about the document such as
+  /// closing and reopening it, which starts a new sequence of versions.
+  fn push(&mut self, msg: Value) {
+    let method = msg["method"].as_str().filter(|m| SUPERSEDABLE.contains(m));
+    let Some(method) = method.filter(|_| replaces_text(&msg)) else {
+      return self.msgs.push_back(msg);
+    };
+    let uri = &msg["params"]["textDocument"]["uri"];
+    for old in self.msgs.iter_mut().rev() {
+      if old.get("id").is_some() {
+        break;
+      }
+      if old["params"]["textDocument"]["uri"] != *uri {
+        continue;
+      }
+      if old["method"] != method {
+        break;
+      }
+      log!(Debug, "Dropping superseded {method} to {uri}");
+      *old = msg;
+      return;
+    }
+    self.msgs.push_back(msg)
+  }
+}
+
+/// Bounded message queue filled by a reader thread. Messages are dispatched in
+/// order, and methods with a [Limit] wait for their bucket to refill, during
+/// which further changes can supersede them.
+pub struct Inbox {
+  queue: Arc<(Mutex<Queue>, Condvar)>,
+  buckets: HashMap<String, Bucket>,
+}
+impl Inbox {
+  /// Start reading messages on a separate thread. The reader blocks while
+  /// `cap` messages are waiting.
+  pub fn spawn<I: Iterator<Item = Value>>(
+    source: impl FnOnce() -> I + Send + 'static,
+    cap: usize,
+  ) -> Self {
+    let queue = Arc::new((Mutex::new(Queue::default()), Condvar::new()));
+    let queue2 = queue.clone();
+    thread::Builder::new()
+      .name("inbox-reader".into())
+      .spawn(move || {
+        let (lock, cvar) = &*queue2;
+        for msg in source() {
+          let mut q = cvar.wait_while(lock.lock().unwrap(), |q| cap <= q.msgs.len()).unwrap();
+          q.push(msg);
+          cvar.notify_all();
+        }
+        lock.lock().unwrap().closed = true;
+        cvar.notify_all();
+      })
+      .expect("Failed to spawn inbox reader thread");
+    Self { queue, buckets: HashMap::new() }
+  }
+
+  /// Rate limit a method
+  pub fn limit(&mut self, method: &str, limit: Limit) {
+    self.buckets.insert(method.to_string(), Bucket::new(limit));
+  }
+}
+impl Iterator for Inbox {
+  type Item = Value;
+  fn next(&mut self) -> Option<Self::Item> {
+    let (lock, cvar) = &*self.queue;
+    let mut q = lock.lock().unwrap();
+    loop {
+      let Some(front) = q.msgs.front() else {
+        if q.closed {
+          return None;
+        }
+        q = cvar.wait(q).unwrap();
+        continue;
+      };
+      let bucket = front["method"].as_str().and_then(|m| self.buckets.get_mut(m));
+      if let Some(Err(wait)) = bucket.map(|b| b.take(Instant::now())) {
+        q = cvar.wait_timeout(q, wait).unwrap().0;
+        continue;
+      }
+      let msg = q.msgs.pop_front();
+      cvar.notify_all();
+      return msg;
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::time::{Duration, Instant};
+
+  use serde_json::{json, Value};
+
+  use super::{Bucket, Limit, Queue};
+
+  fn change(uri: &str, text: &str) -> Value {
+    json!({
+      "method": "textDocument/didChange",
+      "params": { "textDocument": { "uri": uri }, "contentChanges": [{ "text": text }] },
+    })
+  }
+
+  #[test]
+  fn supersede() {
+    let mut q = Queue::default();
+    q.push(change("a", "1"));
+    q.push(change("b", "1"));
+    q.push(change("a", "2"));
+    assert_eq!(q.msgs, [change("a", "2"), change("b", "1")], "Replaced in place");
+    q.push(json!({ "method": "textDocument/hover", "id": 0 }));
+    q.push(change("a", "3"));
+    assert_eq!(q.msgs.len(), 4, "Changes before a request are kept");
+    assert_eq!(q.msgs[3], change("a", "3"));
+    let pos = json!({ "line": 0, "character": 0 });
+    let edit = json!({
+      "method": "textDocument/didChange",
+      "params": {
+        "textDocument": { "uri": "a" },
+        "contentChanges": [{ "range": { "start": pos, "end": pos }, "text": "4" }],
+      },
+    });
+    q.push(edit.clone());
+    assert_eq!(q.msgs.len(), 5, "An edit doesn't supersede");
+    q.push(change("a", "5"));
+    assert_eq!(q.msgs[4], change("a", "5"), "Replacing the text supersedes an edit");
+    let notif = |method: &str| {
+      json!({ "method": method, "params": { "textDocument": { "uri": "a" } } })
+    };
+    q.push(notif("textDocument/didClose"));
+    q.push(notif("textDocument/didOpen"));
+    q.push(change("a", "6"));
+    assert_eq!(q.msgs.len(), 8, "Changes don't move across a close and reopen");
+    assert_eq!(q.msgs[4], change("a", "5"));
+    let mut mixed = change("a", "7");
+    let ranged = edit["params"]["contentChanges"][0].clone();
+    mixed["params"]["contentChanges"].as_array_mut().unwrap().insert(0, ranged);
+    q.push(mixed.clone());
+    assert_eq!(q.msgs[8], mixed, "Only full-text changes supersede");
+  }
+
+  #[test]
+  fn bucket() {
+    let mut b = Bucket::new(Limit { burst: 2, per_sec: 10.0 });
+    let t0 = Instant::now();
+    assert_eq!(b.take(t0), Ok(()));
+    assert_eq!(b.take(t0), Ok(()));
+    let wait = b.take(t0).unwrap_err();
+    assert!(wait <= Duration::from_millis(100), "Waits for one token");
+    assert_eq!(b.take(t0 + Duration::from_millis(100)), Ok(()), "Refilled");
+  }
+}
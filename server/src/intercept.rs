@@ -0,0 +1,78 @@
+//! Built-in [Interceptor]s
+
+use std::time::Duration;
+
+use anyhow::anyhow;
+use serde_json::Value;
+
+use crate::jrpc::{Interceptor, Outcome, Session};
+use crate::log;
+use crate::protocol::document::FileUri;
+use crate::protocol::error::LSPErrCode;
+use crate::protocol::messages::{MessageType, ShowMessage, ShowMessageParams};
+
+/// Record the time spent handling each request and notification in the
+/// session's [crate::metrics::Metrics], which logs the slow ones. Async
+/// requests are recorded once they're answered.
+pub struct Timing(Session);
+impl Timing {
+  pub fn new(session: Session) -> Self { Self(session) }
+}
+impl Interceptor for Timing {
+  fn after(&mut self, msg: &Value, elapsed: Duration, outcome: Outcome) {
+    let ok = match outcome {
+      Outcome::Handled | Outcome::Answered(Ok(())) => true,
+      Outcome::Answered(Err(_)) => false,
+      Outcome::Deferred | Outcome::Unhandled => return,
+    };
+    let method = msg["method"].as_str().expect("Only messages with a method are intercepted");
+    self.0.lock().metrics().record(method, elapsed, ok, msg.get("params"))
+  }
+}
+
+/// Log the method, ID and outcome of every incoming request and notification
+#[allow(unused)] // attach in main for debugging
+pub struct TraceLog;
+impl Interceptor for TraceLog {
+  fn before(&mut self, msg: &Value) -> anyhow::Result<()> {
+    match (msg["method"].as_str(), &msg["id"]) {
+      (Some(method), Value::Null) => log!(Debug, "--> {method}"),
+      (Some(method), id) => log!(Debug, "--> {method} #{id}"),
+      (None, id) => log!(Debug, "--> response #{id}"),
+    }
+    Ok(())
+  }
+  fn after(&mut self, msg: &Value, elapsed: Duration, outcome: Outcome) {
+    let method = msg["method"].as_str().unwrap_or_default();
+    log!(Debug, "<-- {method} {outcome:?} in {elapsed:?}")
+  }
+}
+
+/// Reject messages about documents whose URI the server can't map to a file,
+/// such as unsaved documents or ones on a remote host. Requests fail with
+/// [LSPErrCode::InvalidParams] naming the URI. The first rejection is also
+/// explained to the user, since otherwise the document just silently lacks
+/// every feature.
+pub struct UriCheck {
+  session: Session,
+  hinted: bool,
+}
+impl UriCheck {
+  pub fn new(session: Session) -> Self { Self { session, hinted: false } }
+}
+impl Interceptor for UriCheck {
+  fn before(&mut self, msg: &Value) -> anyhow::Result<()> {
+    let Some(uri) = msg["params"]["textDocument"]["uri"].as_str() else { return Ok(()) };
+    let Err(e) = FileUri::parse(uri) else { return Ok(()) };
+    if !self.hinted {
+      self.hinted = true;
+      let message = format!(
+        "Orchid can't analyze {uri} because its URI doesn't name a local file ({e}). Save it \
+         to a local folder to get diagnostics and navigation."
+      );
+      self.session.send::<ShowMessage>(ShowMessageParams { typ: MessageType::Info, message });
+    }
+    let message = format!("Unsupported document URI {uri}: {e}. Expected a file:/// URI.");
+    Err(anyhow!(LSPErrCode::InvalidParams).context(message))
+  }
+}
@@ -0,0 +1,192 @@
+//! A ring buffer of the most recent messages exchanged with the client, kept by
+//! the JSON-RPC layer so that a transcript can be attached to bug reports.
+//! Document text, and anything derived from it such as hovers and completions,
+//! is redacted as messages are recorded.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Map, Value};
+
+/// Fields that hold the text of documents or edits to them
+const REDACTED_FIELDS: &[&str] = &["text", "newText", "insertText"];
+
+/// Fields whose strings are all taken from documents, such as hover contents
+/// and the documentation of completion items. Markup kinds are kept.
+const REDACTED_TREES: &[&str] = &["contents", "documentation"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+  Inbound,
+  Outbound,
+}
+impl Direction {
+  pub fn name(self) -> &'static str {
+    match self {
+      Self::Inbound => "in",
+      Self::Outbound => "out",
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+  direction: Direction,
+  elapsed: Duration,
+  message: Value,
+}
+impl Entry {
+  fn to_json(&self) -> Value {
+    let ms = self.elapsed.as_millis() as u64;
+    json!({ "direction": self.direction.name(), "ms": ms, "message": self.message })
+  }
+}
+
+/// The last [Journal::capacity] messages with the time they were recorded.
+/// The `initialize` request is kept regardless so that transcripts can be
+/// replayed from the start.
+#[derive(Debug)]
+pub struct Journal {
+  capacity: usize,
+  start: Instant,
+  init: Option<Entry>,
+  entries: VecDeque<Entry>,
+}
+impl Journal {
+  pub fn new(capacity: usize) -> Self {
+    Self { capacity, start: Instant::now(), init: None, entries: VecDeque::new() }
+  }
+
+  pub fn capacity(&self) -> usize { self.capacity }
+
+  /// Change the number of messages kept, dropping the oldest ones if it
+  /// shrinks. Zero turns the journal off.
+  pub fn set_capacity(&mut self, capacity: usize) {
+    self.capacity = capacity;
+    let excess = self.entries.len().saturating_sub(capacity);
+    self.entries.drain(..excess);
+  }
+
+  pub fn record(&mut self, direction: Direction, message: &Value) {
+    let is_init = self.init.is_none() && message["method"] == "initialize";
+    if self.capacity == 0 && !is_init {
+      return;
+    }
+    let entry = Entry { direction, elapsed: self.start.elapsed(), message: redact(message) };
+    if is_init {
+      self.init = Some(entry);
+      return;
+    }
+    if self.entries.len() == self.capacity {
+      self.entries.pop_front();
+    }
+    self.entries.push_back(entry);
+  }
+
+  /// One JSON object per line, oldest first
+  pub fn lines(&self) -> Vec<String> {
+    (self.init.iter().chain(&self.entries)).map(|e| e.to_json().to_string()).collect()
+  }
+
+  pub fn dump(&self, path: &Path) -> io::Result<()> {
+    let mut file = fs::File::create(path)?;
+    for line in self.lines() {
+      writeln!(file, "{line}")?;
+    }
+    Ok(())
+  }
+}
+
+/// The placeholder that replaces a redacted string
+fn placeholder(s: &str) -> Value { json!(format!("<{} bytes redacted>", s.len())) }
+
+/// Replace every string in a value except markup kinds
+fn redact_all(value: &Value) -> Value {
+  match value {
+    Value::String(s) => placeholder(s),
+    Value::Array(items) => Value::Array(items.iter().map(redact_all).collect()),
+    Value::Object(fields) => Value::Object(
+      (fields.iter())
+        .map(|(k, v)| (k.clone(), if k == "kind" { v.clone() } else { redact_all(v) }))
+        .collect::<Map<_, _>>(),
+    ),
+    _ => value.clone(),
+  }
+}
+
+/// Replace the text of documents and edits in a message with its length
+pub fn redact(message: &Value) -> Value {
+  match message {
+    Value::Array(items) => Value::Array(items.iter().map(redact).collect()),
+    Value::Object(fields) => Value::Object(
+      (fields.iter())
+        .map(|(k, v)| match v {
+          Value::String(s) if REDACTED_FIELDS.contains(&k.as_str()) => (k.clone(), placeholder(s)),
+          _ if REDACTED_TREES.contains(&k.as_str()) => (k.clone(), redact_all(v)),
+          _ => (k.clone(), redact(v)),
+        })
+        .collect::<Map<_, _>>(),
+    ),
+    _ => message.clone(),
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use serde_json::{json, Value};
+
+  use super::{redact, Direction, Journal};
+
+  fn messages(journal: &Journal) -> Vec<Value> {
+    let entries = journal.lines().into_iter().map(|l| serde_json::from_str::<Value>(&l).unwrap());
+    entries.map(|e| e["message"]["method"].clone()).collect()
+  }
+
+  #[test]
+  fn ring() {
+    let mut journal = Journal::new(0);
+    journal.record(Direction::Inbound, &json!({ "id": 0, "method": "initialize" }));
+    journal.record(Direction::Inbound, &json!({ "method": "initialized" }));
+    assert_eq!(messages(&journal), [json!("initialize")]);
+    journal.set_capacity(2);
+    for method in ["a", "b", "c"] {
+      journal.record(Direction::Outbound, &json!({ "method": method }));
+    }
+    assert_eq!(messages(&journal), [json!("initialize"), json!("b"), json!("c")]);
+    journal.set_capacity(1);
+    assert_eq!(messages(&journal), [json!("initialize"), json!("c")]);
+  }
+
+  #[test]
+  fn redaction() {
+    let change = json!({
+      "textDocument": { "uri": "file:///a.orc", "version": 2 },
+      "contentChanges": [{ "text": "const a := 1" }],
+    });
+    assert_eq!(
+      redact(&change),
+      json!({
+        "textDocument": { "uri": "file:///a.orc", "version": 2 },
+        "contentChanges": [{ "text": "<12 bytes redacted>" }],
+      })
+    );
+    let edit = json!([{ "range": null, "newText": "x", "text": 1 }]);
+    let redacted = json!([{ "range": null, "newText": "<1 bytes redacted>", "text": 1 }]);
+    assert_eq!(redact(&edit), redacted);
+    let hover = json!({ "contents": { "kind": "markdown", "value": "ab" } });
+    let redacted = json!({ "contents": { "kind": "markdown", "value": "<2 bytes redacted>" } });
+    assert_eq!(redact(&hover), redacted);
+    let item = json!({ "label": "a", "insertText": "abc", "documentation": ["x", "yz"] });
+    assert_eq!(
+      redact(&item),
+      json!({
+        "label": "a",
+        "insertText": "<3 bytes redacted>",
+        "documentation": ["<1 bytes redacted>", "<2 bytes redacted>"],
+      })
+    );
+  }
+}
@@ -1,36 +1,27 @@
 use std::collections::HashMap;
 use std::ops::{Deref, DerefMut};
 use std::sync::atomic::AtomicI64;
-use std::sync::{atomic, Arc, Mutex, MutexGuard};
-use std::{fmt, mem};
+use std::sync::{atomic, mpsc, Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+use std::{fmt, mem, thread};
 
 use anyhow::anyhow;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use trait_set::trait_set;
 
+use crate::abort::Abort;
 use crate::ctx_map::{Ctx, CtxMap};
+use crate::event_loop::Scheduler;
+use crate::journal::{Direction, Journal};
+use crate::log;
+use crate::metrics::Metrics;
 use crate::protocol::error::LSPErrCode;
+use crate::protocol::messages::{Notification, Progress, ProgressParams, Request};
 
 static NEXT_REQ: AtomicI64 = AtomicI64::new(0);
-
-#[derive(Clone)]
-pub struct Abort(Arc<atomic::AtomicBool>);
-impl Abort {
-  pub fn new() -> Self { Self(Arc::new(atomic::AtomicBool::new(false))) }
-  pub fn abort(&self) { self.0.store(true, atomic::Ordering::Release) }
-  /// Check if the abort flag has been set with relaxed memory ordering. The
-  /// relaxed order avoids blocking optimizations, so this can be called
-  /// frequently in the processing pipeline to ensure timely abort detection.
-  pub fn aborted(&self) -> bool { self.0.load(atomic::Ordering::Relaxed) }
-  /// Check if the abort flag has been set with strict memory ordering. This
-  /// prevents many optimizations across the load so it should be used
-  /// sparingly, but it enables the abort to double as an expiry marker. If the
-  /// task locks a shared work tracker then calls this function, it can be
-  /// certain that no other task has fulfilled its task, provided that doing so
-  /// would involve aborting this task.
-  pub fn is_valid(&self) -> bool { !self.0.load(atomic::Ordering::Acquire) }
-}
+/// Default latency above which handlers are logged, see [Metrics]
+pub const SLOW_THRESHOLD: Duration = Duration::from_millis(500);
 
 pub struct AsyncReq {
   name: String,
@@ -38,28 +29,49 @@ pub struct AsyncReq {
   params: Option<Value>,
   abort: Abort,
   resolved: bool,
+  start: Instant,
   comm: Session,
 }
-#[allow(dead_code)] // this struct isn't being used
+#[allow(dead_code)] // not every accessor is used
 impl AsyncReq {
   pub fn name(&self) -> &str { self.name.as_str() }
   pub fn params(&self) -> Option<&Value> { self.params.as_ref() }
   pub fn aborted(&self) -> bool { self.abort.aborted() }
+  /// The flag set when the request is cancelled. The server may also set it to
+  /// stop a request it deems too slow, for example through the watchdog.
+  pub fn abort(&self) -> &Abort { &self.abort }
   pub fn session(&self) -> &Session { &self.comm }
   pub fn resolve(mut self, result: anyhow::Result<Value>) { self.resolve_impl(result) }
   fn resolve_impl(&mut self, result: anyhow::Result<Value>) {
     self.resolved = true;
-    self.comm.0.lock().unwrap().send_resp(self.id, result)
+    let status = if result.is_ok() { "resolved" } else { "failed" };
+    log::handling(&self.name, Some(self.id), self.start, || log!(Debug, "{} {status}", self.name));
+    let mut state = self.comm.0.lock().unwrap();
+    state.ingress.remove(&self.id);
+    let params = self.params.clone().unwrap_or_default();
+    let msg = with_params(json!({ "id": self.id, "method": self.name }), params);
+    let outcome = Outcome::Answered(result.as_ref().map(|_| ()).map_err(err_code));
+    state.answered.push((msg, self.start.elapsed(), outcome));
+    state.send_resp(self.id, result)
   }
 }
 impl Drop for AsyncReq {
+  /// Aborted requests are answered when dropped. Requests the client cancelled
+  /// are no longer tracked, the rest were stopped by the server and the client
+  /// may retry them.
   fn drop(&mut self) {
     if !self.resolved {
       if self.abort.aborted() {
-        let err = anyhow!("Request cancelled by client");
-        self.resolve_impl(Err(err.context(LSPErrCode::RequestCancelled)))
+        let state = self.comm.0.lock().unwrap();
+        let by_client = !state.shut_down && !state.ingress.contains_key(&self.id);
+        mem::drop(state);
+        let err = match by_client {
+          true => anyhow!("Request cancelled by client").context(LSPErrCode::RequestCancelled),
+          false => anyhow!("Request cancelled by the server").context(LSPErrCode::ServerCancelled),
+        };
+        return self.resolve_impl(Err(err));
       }
-      eprintln!("Dangling request {self:?} dropped")
+      log!(Warn, "Dangling request {self:?} dropped")
     }
   }
 }
@@ -76,6 +88,7 @@ trait_set! {
   pub trait NotifHandler = for<'a, 'b> FnMut(Option<&'a Value>, Session) + 'static;
   pub trait SendCB = FnMut(Value) + Send + 'static;
   pub trait ResHandler = FnMut(Result<Value, ResponseError>) + Send + 'static;
+  trait Job = FnOnce() + Send + 'static;
 }
 
 #[derive(Debug)]
@@ -85,26 +98,78 @@ pub struct ResponseError {
   pub data: Option<Value>,
 }
 
+/// The error code of a handler error, [LSPErrCode::RequestFailed] unless the
+/// handler attached a different one as context
+fn err_code(e: &anyhow::Error) -> LSPErrCode {
+  e.downcast_ref::<LSPErrCode>().copied().unwrap_or(LSPErrCode::RequestFailed)
+}
+
+/// What happened to an incoming request or notification
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+  /// A notification was handled or a request was cancelled
+  Handled,
+  /// A request was answered, possibly with an error
+  Answered(Result<(), LSPErrCode>),
+  /// A request was passed to an async handler which will answer it later
+  Deferred,
+  /// There is no handler for the method
+  Unhandled,
+}
+
+/// Hooks around the dispatch of incoming messages, for concerns that apply to
+/// every handler such as metrics and logging. Attached with
+/// [JrpcServer::intercept].
+pub trait Interceptor: 'static {
+  /// Called with every incoming message including responses before it's
+  /// dispatched. If this returns an error, the message is dropped and requests
+  /// are answered with the error.
+  fn before(&mut self, _msg: &Value) -> anyhow::Result<()> { Ok(()) }
+  /// Called with a request or notification after its handler returned. Async
+  /// requests are reported again with their answer when the next message is
+  /// received.
+  fn after(&mut self, _msg: &Value, _elapsed: Duration, _outcome: Outcome) {}
+}
+
 struct State {
   ingress: HashMap<i64, Abort>,
   egress: HashMap<i64, Box<dyn ResHandler>>,
   context: CtxMap,
   send: Box<dyn SendCB>,
+  /// Written by [crate::intercept::Timing]
+  metrics: Metrics,
+  /// Async requests answered since the last message was received, for
+  /// [Interceptor::after]
+  answered: Vec<(Value, Duration, Outcome)>,
+  /// Shared with panic hooks, which can't wait for this lock
+  journal: Arc<Mutex<Journal>>,
+  /// Response callbacks are sent here to run outside the lock
+  dispatch: mpsc::Sender<Box<dyn Job>>,
+  /// Set by [Session::shutdown], afterwards only `exit` is accepted
+  shut_down: bool,
+  scheduler: Scheduler,
 }
 
 impl State {
-  fn new(send: impl SendCB) -> Self {
+  fn new(send: impl SendCB, dispatch: mpsc::Sender<Box<dyn Job>>) -> Self {
     Self {
+      dispatch,
+      shut_down: false,
       context: CtxMap::new(),
       egress: HashMap::new(),
       ingress: HashMap::new(),
       send: Box::new(send),
+      metrics: Metrics::new(SLOW_THRESHOLD),
+      answered: Vec::new(),
+      journal: Arc::new(Mutex::new(Journal::new(0))),
+      scheduler: Scheduler::default(),
     }
   }
 
   fn send(&mut self, mut data: Value) {
     data["jsonrpc"] = json!("2.0");
-    eprintln!("Sending {data}");
+    log!(Debug, "Sending {data}");
+    self.journal.lock().unwrap_or_else(PoisonError::into_inner).record(Direction::Outbound, &data);
     (self.send)(data)
   }
   fn send_resp(&mut self, id: i64, result: anyhow::Result<Value>) {
@@ -114,7 +179,7 @@ impl State {
         "result": val
       }),
       Err(e) => {
-        let code = e.downcast_ref::<LSPErrCode>().copied().unwrap_or(LSPErrCode::RequestFailed);
+        let code = err_code(&e);
         json!({
           "id": id,
           "error": {
@@ -129,15 +194,15 @@ impl State {
   pub fn send_request(&mut self, method: &str, params: Value, callback: impl ResHandler) {
     let id = NEXT_REQ.fetch_add(1, atomic::Ordering::Relaxed);
     self.egress.insert(id, Box::new(callback));
-    self.send(json!({ "id": id, "method": method, "params": params }))
+    self.send(with_params(json!({ "id": id, "method": method }), params))
   }
   pub fn send_notif(&mut self, method: &str, params: Value) {
-    self.send(json!({ "method": method, "params": params }))
-  }
-  pub fn send_progress(&mut self, token: Value, value: Value) {
-    self.send_notif("$/progress", json!({ "token": token, "value": value }))
+    self.send(with_params(json!({ "method": method }), params))
   }
-  fn handle_resp(&mut self, msg: Value) {
+  /// Find the callback associated with a response. The callback must only be
+  /// called after the state lock is released. Callbacks of requests pending at
+  /// shutdown have already been called.
+  fn take_resp(&mut self, msg: &Value) -> Option<impl Job> {
     let req_id = msg["id"].as_i64().unwrap();
     let res = msg.get("result").ok_or_else(|| {
       let err = msg.get("error").unwrap().as_object().unwrap();
@@ -147,9 +212,40 @@ impl State {
         message: err["message"].as_str().unwrap().to_string(),
       }
     });
-    let mut cb =
-      (self.egress.remove(&req_id)).expect("Responses must have had an associated request");
-    cb(res.cloned())
+    let res = res.cloned();
+    let Some(mut cb) = self.egress.remove(&req_id) else {
+      assert!(self.shut_down, "Responses must have had an associated request");
+      return None;
+    };
+    Some(move || cb(res))
+  }
+}
+
+/// JSON-RPC requires params to be structured if present, so parameterless
+/// messages omit them
+fn with_params(mut msg: Value, params: Value) -> Value {
+  if !params.is_null() {
+    msg["params"] = params;
+  }
+  msg
+}
+
+fn params_json(params: impl Serialize) -> Value {
+  serde_json::to_value(params).expect("Message parameters are plain data")
+}
+
+/// Wrap a callback that expects the result type of `R`
+fn typed_callback<R: Request>(
+  mut callback: impl FnMut(Result<R::Result, ResponseError>) + Send + 'static,
+) -> impl ResHandler {
+  move |res: Result<Value, ResponseError>| {
+    callback(res.and_then(|val| {
+      serde_json::from_value(val).map_err(|e| ResponseError {
+        code: LSPErrCode::InternalError,
+        message: format!("Malformed result for {}: {e}", R::METHOD),
+        data: None,
+      })
+    }))
   }
 }
 
@@ -159,7 +255,37 @@ impl<'b> SessionGuard<'b> {
     self.0.send_request(method, params, callback)
   }
   pub fn notify(&mut self, method: &str, params: Value) { self.0.send_notif(method, params) }
-  pub fn progress(&mut self, token: Value, value: Value) { self.0.send_progress(token, value) }
+  pub fn metrics(&mut self) -> &mut Metrics { &mut self.0.metrics }
+  pub fn scheduler(&self) -> Scheduler { self.0.scheduler.clone() }
+  pub fn progress(&mut self, token: Value, value: Value) {
+    self.send::<Progress>(ProgressParams { token, value })
+  }
+  /// Send a notification listed in [crate::protocol::messages]
+  pub fn send<N: Notification>(&mut self, params: N::Params) {
+    self.notify(N::METHOD, params_json(params))
+  }
+  /// Send a request listed in [crate::protocol::messages]
+  pub fn call<R: Request>(
+    &mut self,
+    params: R::Params,
+    callback: impl FnMut(Result<R::Result, ResponseError>) + Send + 'static,
+  ) {
+    self.request(R::METHOD, params_json(params), typed_callback::<R>(callback))
+  }
+  /// Send a request made on behalf of a state that may be superseded, such as
+  /// a version of a document. If the abort is triggered before the response
+  /// arrives, the callback is dropped without being called.
+  pub fn call_while<R: Request>(
+    &mut self,
+    abort: Abort,
+    params: R::Params,
+    mut callback: impl FnMut(Result<R::Result, ResponseError>) + Send + 'static,
+  ) {
+    self.call::<R>(params, move |res| match abort.is_valid() {
+      true => callback(res),
+      false => log!(Debug, "Dropped the response to {} made for a superseded state", R::METHOD),
+    })
+  }
 }
 impl<'a> Deref for SessionGuard<'a> {
   type Target = CtxMap;
@@ -169,19 +295,77 @@ impl<'a> DerefMut for SessionGuard<'a> {
   fn deref_mut(&mut self) -> &mut Self::Target { &mut self.0.context }
 }
 
+/// Shared handle to the connection state and the context map.
+///
+/// Every method of [Session] locks the state for the duration of the call, and
+/// [Session::lock] holds it until the guard is dropped. The lock is not
+/// reentrant, so a [SessionGuard] must not be held while calling a method of
+/// the same session on the same thread. The server never holds the lock while
+/// running handlers or response callbacks, so these can use any method of
+/// [Session] freely. Response callbacks run on a dedicated dispatch thread in
+/// the order the responses arrived, so they must not block for long.
 #[derive(Clone)]
 pub struct Session(Arc<Mutex<State>>);
 impl Session {
-  fn new(send: impl SendCB) -> Self { Self(Arc::new(Mutex::new(State::new(send)))) }
+  fn new(send: impl SendCB, dispatch: mpsc::Sender<Box<dyn Job>>) -> Self {
+    Self(Arc::new(Mutex::new(State::new(send, dispatch))))
+  }
+
+  /// Abort all async requests and fail all requests awaiting a response from
+  /// the client with [LSPErrCode::ServerCancelled]. Messages are written
+  /// synchronously, so nothing remains to be flushed once this returns. Any
+  /// further requests except `exit` are rejected.
+  pub fn shutdown(&self) {
+    let mut state = self.0.lock().unwrap();
+    state.shut_down = true;
+    state.ingress.drain().for_each(|(_, abort)| abort.abort());
+    for (_, mut cb) in mem::take(&mut state.egress) {
+      let err = ResponseError {
+        code: LSPErrCode::ServerCancelled,
+        message: "Server shutting down".to_string(),
+        data: None,
+      };
+      // the dispatch thread outlives the session
+      let _ = state.dispatch.send(Box::new(move || cb(Err(err))));
+    }
+  }
+
+  /// Abort all async requests. Their handlers answer them with
+  /// [LSPErrCode::ServerCancelled] as they stop.
+  pub fn abort_requests(&self) {
+    self.0.lock().unwrap().ingress.values().for_each(|abort| abort.abort())
+  }
 
   pub fn request(&self, method: &str, params: Value, callback: impl ResHandler) {
     self.lock().request(method, params, callback)
   }
   pub fn notify(&self, method: &str, params: Value) { self.lock().notify(method, params) }
-  #[allow(unused)] // we definitely need this but definitely not now
   pub fn progress(&self, token: Value, value: Value) { self.lock().progress(token, value) }
+  pub fn send<N: Notification>(&self, params: N::Params) { self.lock().send::<N>(params) }
+  pub fn call<R: Request>(
+    &self,
+    params: R::Params,
+    callback: impl FnMut(Result<R::Result, ResponseError>) + Send + 'static,
+  ) {
+    self.lock().call::<R>(params, callback)
+  }
+  pub fn call_while<R: Request>(
+    &self,
+    abort: Abort,
+    params: R::Params,
+    callback: impl FnMut(Result<R::Result, ResponseError>) + Send + 'static,
+  ) {
+    self.lock().call_while::<R>(abort, params, callback)
+  }
   pub fn set<U: Ctx>(&self, ctx: U) { self.0.lock().unwrap().context.set(ctx) }
+  /// The journal of recent messages, off until its capacity is set
+  pub fn journal(&self) -> Arc<Mutex<Journal>> { self.0.lock().unwrap().journal.clone() }
+  /// Tasks and timers on the main loop. They only run if the server is driven
+  /// by an [crate::event_loop::EventLoop].
+  pub fn scheduler(&self) -> Scheduler { self.lock().scheduler() }
   pub fn lock(&self) -> SessionGuard<'_> { SessionGuard(self.0.lock().unwrap()) }
+  /// Lock the session unless it's locked or poisoned
+  pub fn try_lock(&self) -> Option<SessionGuard<'_>> { self.0.try_lock().ok().map(SessionGuard) }
 }
 
 pub struct JrpcServer {
@@ -189,17 +373,26 @@ pub struct JrpcServer {
   async_hands: HashMap<String, Box<dyn AsyncReqHandler>>,
   notif_hands: HashMap<String, Box<dyn NotifHandler>>,
   comm: Session,
+  interceptors: Vec<Box<dyn Interceptor>>,
 }
 impl JrpcServer {
   pub fn new(send: impl SendCB) -> Self {
+    let (dispatch, jobs) = mpsc::channel::<Box<dyn Job>>();
+    thread::Builder::new()
+      .name("response-dispatch".into())
+      .spawn(move || jobs.into_iter().for_each(|job| job()))
+      .expect("Failed to spawn response dispatch thread");
     Self {
       notif_hands: HashMap::new(),
       sync_hands: HashMap::new(),
       async_hands: HashMap::new(),
-      comm: Session::new(send),
+      comm: Session::new(send, dispatch),
+      interceptors: Vec::new(),
     }
   }
 
+  pub fn session(&self) -> &Session { &self.comm }
+
   pub fn on_req_sync(&mut self, name: &str, handler: impl ReqHandler) {
     self.sync_hands.insert(name.to_string(), Box::new(handler));
   }
@@ -208,70 +401,133 @@ impl JrpcServer {
     self.notif_hands.insert(name.to_string(), Box::new(handler));
   }
 
-  #[allow(dead_code)] // not being used yet
   pub fn on_req_async(&mut self, name: &str, handler: impl AsyncReqHandler) {
     self.async_hands.insert(name.to_string(), Box::new(handler));
   }
 
+  /// Add an interceptor to the end of the chain. Interceptors see messages in
+  /// the order they were added.
+  pub fn intercept(&mut self, interceptor: impl Interceptor) {
+    self.interceptors.push(Box::new(interceptor))
+  }
+
   pub fn recv(&mut self, message: Value) {
-    // eprintln!("Received {message}");
+    let journal = self.comm.journal();
+    journal.lock().unwrap_or_else(PoisonError::into_inner).record(Direction::Inbound, &message);
+    let answered = mem::take(&mut self.comm.0.lock().unwrap().answered);
+    for (msg, elapsed, outcome) in answered {
+      self.interceptors.iter_mut().for_each(|icpt| icpt.after(&msg, elapsed, outcome))
+    }
+    for icpt in self.interceptors.iter_mut() {
+      if let Err(e) = icpt.before(&message) {
+        log!(Warn, "Message rejected: {e}");
+        if let (Some(_), Some(id)) = (message.get("method"), message["id"].as_i64()) {
+          self.comm.0.lock().unwrap().send_resp(id, Err(e))
+        }
+        return;
+      }
+    }
+    let start = Instant::now();
+    let Some(outcome) = self.route(&message, start) else { return };
+    let elapsed = start.elapsed();
+    let method = message["method"].as_str().expect("Only messages with a method are routed");
+    if !matches!(outcome, Outcome::Deferred) {
+      let id = message["id"].as_i64();
+      log::handling(method, id, start, || log!(Debug, "{method} finished: {outcome:?}"));
+    }
+    for icpt in self.interceptors.iter_mut() {
+      icpt.after(&message, elapsed, outcome)
+    }
+  }
+
+  /// Pass the message to its handler. Returns [None] for responses, which are
+  /// processed on the dispatch thread.
+  fn route(&mut self, message: &Value, start: Instant) -> Option<Outcome> {
+    // log!(Debug, "Received {message}");
     let mut comm_guard = self.comm.0.lock().unwrap();
     let obj = message.as_object().expect("All messages are objects");
     let id = obj.get("id").map(|id| id.as_i64().expect("If ID exists, it's an uint"));
-    match obj.get("method").map(|m| m.as_str().unwrap()) {
-      None => comm_guard.handle_resp(message),
-      Some(name) => {
-        let params = obj.get("params");
-        match id {
-          None => match self.notif_hands.get_mut(name) {
-            None => eprintln!("Unrecognized notification {name}"),
-            Some(handler) => {
-              mem::drop(comm_guard);
-              handler(params, self.comm.clone());
-            },
-          },
-          Some(id) =>
-            if name == "$/cancelRequest" {
-              let cancel_id = params.unwrap()["id"].as_i64().unwrap();
-              if let Some(abort) = comm_guard.ingress.get(&cancel_id) {
-                abort.abort();
-              }
-            } else if let Some(handler) = self.sync_hands.get_mut(name) {
-              mem::drop(comm_guard);
-              let res = handler(params, self.comm.clone());
-              self.comm.0.lock().unwrap().send_resp(id, res);
-            } else if let Some(handler) = self.async_hands.get_mut(name) {
-              let abort = Abort::new();
-              comm_guard.ingress.insert(id, abort.clone());
-              mem::drop(comm_guard);
-              handler(AsyncReq {
-                abort,
-                id,
-                name: name.to_owned(),
-                params: params.cloned(),
-                resolved: false,
-                comm: self.comm.clone(),
-              })
-            } else if name.starts_with("$/") {
-              eprintln!("Unrecognized optional request {name}");
-              let err = anyhow::anyhow!("Unsupported request");
-              comm_guard.send_resp(id, Err(err.context(LSPErrCode::MethodNotFound)))
-            } else {
-              panic!("Unrecognized request {name}")
-            },
+    let Some(name) = obj.get("method").map(|m| m.as_str().unwrap()) else {
+      if let Some(job) = comm_guard.take_resp(message) {
+        // Sending only fails if the dispatch thread panicked in a callback
+        comm_guard.dispatch.send(Box::new(job)).expect("Response dispatch thread died");
+      }
+      return None;
+    };
+    let params = obj.get("params");
+    if comm_guard.shut_down && name != "exit" {
+      log!(Warn, "Received {name} after shutdown");
+      if let Some(id) = id {
+        let err = anyhow!("The server is shutting down");
+        comm_guard.send_resp(id, Err(err.context(LSPErrCode::InvalidRequest)));
+      }
+      return Some(Outcome::Unhandled);
+    }
+    Some(match id {
+      None if name == "$/cancelRequest" => {
+        let cancel_id = params.and_then(|p| p["id"].as_i64());
+        if let Some(abort) = cancel_id.and_then(|id| comm_guard.ingress.remove(&id)) {
+          abort.abort();
         }
+        Outcome::Handled
       },
-    }
+      None => match self.notif_hands.get_mut(name) {
+        None => {
+          log!(Warn, "Unrecognized notification {name}");
+          Outcome::Unhandled
+        },
+        Some(handler) => {
+          mem::drop(comm_guard);
+          log::handling(name, None, start, || handler(params, self.comm.clone()));
+          Outcome::Handled
+        },
+      },
+      Some(id) =>
+        if let Some(handler) = self.sync_hands.get_mut(name) {
+          mem::drop(comm_guard);
+          let res = log::handling(name, Some(id), start, || handler(params, self.comm.clone()));
+          let status = res.as_ref().map(|_| ()).map_err(err_code);
+          self.comm.0.lock().unwrap().send_resp(id, res);
+          Outcome::Answered(status)
+        } else if let Some(handler) = self.async_hands.get_mut(name) {
+          let abort = Abort::new();
+          comm_guard.ingress.insert(id, abort.clone());
+          mem::drop(comm_guard);
+          let req = AsyncReq {
+            abort,
+            id,
+            name: name.to_owned(),
+            params: params.cloned(),
+            resolved: false,
+            start: Instant::now(),
+            comm: self.comm.clone(),
+          };
+          log::handling(name, Some(id), start, || handler(req));
+          Outcome::Deferred
+        } else if name.starts_with("$/") {
+          log!(Info, "Unrecognized optional request {name}");
+          let err = anyhow::anyhow!("Unsupported request");
+          comm_guard.send_resp(id, Err(err.context(LSPErrCode::MethodNotFound)));
+          Outcome::Unhandled
+        } else {
+          panic!("Unrecognized request {name}")
+        },
+    })
   }
 }
 
 #[cfg(test)]
 mod test {
-  use std::sync::{Arc, Mutex};
+  use std::mem;
+  use std::sync::{mpsc, Arc, Mutex};
+  use std::time::Duration;
 
+  use anyhow::anyhow;
   use serde_json::{json, Value};
 
-  use super::JrpcServer;
+  use super::{Interceptor, JrpcServer, Outcome};
+  use crate::protocol::error::LSPErrCode;
+  use crate::protocol::messages::{RegisterCapability, RegistrationParams};
 
   #[test]
   fn notif() {
@@ -298,4 +554,148 @@ mod test {
     assert_eq!(reps[0]["id"].as_i64(), Some(0));
     assert_eq!(reps[0]["result"], Value::String("World!".to_string()))
   }
+
+  #[test]
+  fn reentrant_callbacks() {
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let sent2 = sent.clone();
+    let mut srv = JrpcServer::new(move |m| sent2.lock().unwrap().push(m));
+    let (done_tx, done) = mpsc::channel();
+    srv.on_notif("initialized", move |_, session| {
+      let (session2, done_tx) = (session.clone(), done_tx.clone());
+      session.request("client/registerCapability", Value::Null, move |res| {
+        session2.notify("registered", res.unwrap());
+        done_tx.send(()).unwrap();
+      })
+    });
+    srv.recv(json!({ "method": "initialized" }));
+    let id = sent.lock().unwrap()[0]["id"].clone();
+    srv.recv(json!({ "id": id, "result": "ok" }));
+    done.recv_timeout(Duration::from_secs(5)).expect("Response callback deadlocked");
+    let sent = sent.lock().unwrap();
+    assert_eq!(sent[1]["method"], "registered");
+    assert_eq!(sent[1]["params"], "ok");
+  }
+
+  /// Rejects requests to `forbidden` and records the outcome of the rest
+  struct Gate(Arc<Mutex<Vec<(String, Outcome)>>>);
+  impl Interceptor for Gate {
+    fn before(&mut self, msg: &Value) -> anyhow::Result<()> {
+      match msg["method"] == "forbidden" {
+        true => Err(anyhow!("Rejected").context(LSPErrCode::RequestFailed)),
+        false => Ok(()),
+      }
+    }
+    fn after(&mut self, msg: &Value, _: Duration, outcome: Outcome) {
+      self.0.lock().unwrap().push((msg["method"].as_str().unwrap().to_string(), outcome))
+    }
+  }
+
+  #[test]
+  fn interceptors() {
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let sent2 = sent.clone();
+    let mut srv = JrpcServer::new(move |m| sent2.lock().unwrap().push(m));
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    srv.intercept(Gate(seen.clone()));
+    srv.on_req_sync("forbidden", |_, _| panic!("Rejected requests must not be dispatched"));
+    srv.on_req_sync("fails", |_, _| Err(anyhow!("Nope").context(LSPErrCode::InvalidParams)));
+    srv.on_notif("hello", |_, _| ());
+    srv.on_req_async("later", |req| req.resolve(Ok(Value::Null)));
+    srv.recv(json!({ "method": "forbidden", "id": 0 }));
+    srv.recv(json!({ "method": "fails", "id": 1 }));
+    srv.recv(json!({ "method": "later", "id": 2 }));
+    srv.recv(json!({ "method": "hello" }));
+    let sent = sent.lock().unwrap();
+    assert_eq!(sent[0]["error"]["message"], "RequestFailed", "Rejection is an error response");
+    assert_eq!(sent[1]["id"], 1);
+    assert_eq!(*seen.lock().unwrap(), [
+      ("fails".to_string(), Outcome::Answered(Err(LSPErrCode::InvalidParams))),
+      ("later".to_string(), Outcome::Deferred),
+      ("later".to_string(), Outcome::Answered(Ok(()))),
+      ("hello".to_string(), Outcome::Handled),
+    ]);
+  }
+
+  #[test]
+  fn typed_call() {
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let sent2 = sent.clone();
+    let mut srv = JrpcServer::new(move |m| sent2.lock().unwrap().push(m));
+    let (res_tx, res) = mpsc::channel();
+    let params = RegistrationParams { registrations: vec![] };
+    srv.comm.call::<RegisterCapability>(params, move |r| res_tx.send(r).unwrap());
+    let req = sent.lock().unwrap()[0].clone();
+    assert_eq!(req["method"], "client/registerCapability");
+    assert_eq!(req["params"], json!({ "registrations": [] }));
+    srv.recv(json!({ "id": req["id"], "result": 5 }));
+    let err = res.recv_timeout(Duration::from_secs(5)).unwrap().unwrap_err();
+    assert_eq!(err.code, LSPErrCode::InternalError, "Result should be null");
+  }
+
+  #[test]
+  fn cancel() {
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let sent2 = sent.clone();
+    let mut srv = JrpcServer::new(move |m| sent2.lock().unwrap().push(m));
+    let reqs = Arc::new(Mutex::new(Vec::new()));
+    let reqs2 = reqs.clone();
+    srv.on_req_async("slow", move |req| reqs2.lock().unwrap().push(req));
+    srv.recv(json!({ "method": "slow", "id": 0 }));
+    srv.recv(json!({ "method": "slow", "id": 1 }));
+    srv.recv(json!({ "method": "$/cancelRequest", "params": { "id": 0 } }));
+    let mut reqs = mem::take(&mut *reqs.lock().unwrap());
+    let stopped = reqs.pop().unwrap();
+    assert!(!stopped.aborted(), "Only the cancelled request is aborted");
+    stopped.abort().abort();
+    mem::drop(stopped);
+    let cancelled = reqs.pop().unwrap();
+    assert!(cancelled.aborted());
+    mem::drop(cancelled);
+    let sent = sent.lock().unwrap();
+    let answers = sent.iter().map(|m| (m["id"].clone(), m["error"]["code"].clone()));
+    let answers = answers.collect::<Vec<_>>();
+    assert_eq!(answers, [(json!(1), json!(-32802)), (json!(0), json!(-32800))]);
+  }
+
+  #[test]
+  fn abort_requests() {
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let sent2 = sent.clone();
+    let mut srv = JrpcServer::new(move |m| sent2.lock().unwrap().push(m));
+    let reqs = Arc::new(Mutex::new(Vec::new()));
+    let reqs2 = reqs.clone();
+    srv.on_req_async("slow", move |req| reqs2.lock().unwrap().push(req));
+    srv.recv(json!({ "method": "slow", "id": 0 }));
+    srv.comm.abort_requests();
+    let req = reqs.lock().unwrap().pop().unwrap();
+    assert!(req.aborted());
+    mem::drop(req);
+    let code = sent.lock().unwrap()[0]["error"]["code"].clone();
+    assert_eq!(code, json!(-32802), "Answered as cancelled by the server");
+    srv.recv(json!({ "method": "slow", "id": 1 }));
+    assert!(!reqs.lock().unwrap()[0].aborted(), "Later requests are unaffected");
+  }
+
+  #[test]
+  fn shutdown() {
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let sent2 = sent.clone();
+    let mut srv = JrpcServer::new(move |m| sent2.lock().unwrap().push(m));
+    let (res_tx, res) = mpsc::channel();
+    srv.comm.request("pending", Value::Null, move |r| res_tx.send(r).unwrap());
+    srv.on_req_sync("shutdown", |_, session| {
+      session.shutdown();
+      Ok(Value::Null)
+    });
+    srv.recv(json!({ "method": "shutdown", "id": 0 }));
+    let err = res.recv_timeout(Duration::from_secs(5)).unwrap().unwrap_err();
+    assert_eq!(err.code, LSPErrCode::ServerCancelled);
+    let id = sent.lock().unwrap()[0]["id"].clone();
+    srv.recv(json!({ "id": id, "result": null }));
+    srv.recv(json!({ "method": "textDocument/hover", "id": 1 }));
+    let sent = sent.lock().unwrap();
+    assert_eq!(sent[2]["id"], 1);
+    assert_eq!(sent[2]["error"]["code"], -32600, "Requests after shutdown are invalid");
+  }
 }
@@ -0,0 +1,44 @@
+//! The analysis core of the language server, for tools that need the same
+//! analysis without speaking LSP, such as a formatter or build tooling. It
+//! covers reading the files of a workspace folder, finding and loading
+//! projects, and mapping between byte offsets and editor positions.
+//! The request handlers and the JSON-RPC transport stay in the binary.
+//!
+//! ```no_run
+//! use orchid_ls::{find_all_projects, Abort, FileUri, LoadedProject, PatchStore};
+//! use orchidlang::name::VPath;
+//!
+//! let root = FileUri::from_path("/home/me/project".as_ref()).unwrap();
+//! let store = PatchStore::new(root.clone());
+//! let vfs = store.clone().mk_vfs(&root).unwrap();
+//! for project in find_all_projects(VPath::new([]), &vfs) {
+//!   let loaded = LoadedProject::new(store.clone(), project, 10_000, Abort::new(), &mut |_, _| ());
+//!   match loaded {
+//!     Ok(lpr) => println!("{} files", lpr.source_files().len()),
+//!     Err(errors) => println!("{} errors", errors.len()),
+//!   }
+//! }
+//! ```
+//!
+//! Editors change files before saving them; [PatchStore::patch] makes loads
+//! read a [documents::DocSnapshot] of such a file instead of the disk.
+
+pub mod abort;
+pub mod documents;
+pub mod log;
+// Shared with the server binary, the stable API is the items re-exported below
+#[doc(hidden)]
+pub mod orc;
+pub mod patches;
+#[doc(hidden)]
+pub mod protocol;
+#[cfg(test)]
+mod testing;
+
+pub use abort::Abort;
+pub use orc::project::{find_all_projects, LoadedProject, ProjectFiles, ProjectRoot};
+pub use patches::PatchStore;
+pub use protocol::docpos::DocPos;
+pub use protocol::document::FileUri;
+pub use protocol::line_index::LineIndex;
+pub use protocol::tokens::SemToken;
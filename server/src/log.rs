@@ -0,0 +1,200 @@
+//! Internal log lines, written to stderr. By default a line is just its
+//! message. With `--log-format json` every line is a JSON record that also
+//! carries the time, the level, the module and the message being handled, so
+//! that the log of a slow session can be analyzed with standard tools. Lines
+//! below `--log-level`, `info` by default, are left out.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{stderr, Write};
+use std::sync::OnceLock;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+  Text,
+  Json,
+}
+impl Format {
+  pub fn parse(name: &str) -> Option<Self> {
+    match name {
+      "text" => Some(Self::Text),
+      "json" => Some(Self::Json),
+      _ => None,
+    }
+  }
+}
+
+static FORMAT: OnceLock<Format> = OnceLock::new();
+
+/// Choose the format of log lines. Only the first call has an effect.
+pub fn set_format(format: Format) { let _ = FORMAT.set(format); }
+
+/// Ordered from the most to the least severe
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+  Error,
+  Warn,
+  Info,
+  Debug,
+}
+impl Level {
+  pub fn parse(name: &str) -> Option<Self> {
+    [Self::Error, Self::Warn, Self::Info, Self::Debug].into_iter().find(|l| l.name() == name)
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Self::Error => "error",
+      Self::Warn => "warn",
+      Self::Info => "info",
+      Self::Debug => "debug",
+    }
+  }
+}
+
+static LEVEL: OnceLock<Level> = OnceLock::new();
+
+/// Choose the least severe level that is logged. Only the first call has an
+/// effect.
+pub fn set_level(level: Level) { let _ = LEVEL.set(level); }
+
+/// The message the current thread is handling
+#[derive(Clone, Debug)]
+struct Handling {
+  method: String,
+  id: Option<i64>,
+  start: Instant,
+}
+
+thread_local! {
+  static HANDLING: RefCell<Option<Handling>> = const { RefCell::new(None) };
+}
+
+/// Attribute the lines logged while running the callback to a request, or to
+/// a notification if there's no ID. Durations are measured from the start.
+pub fn handling<T>(method: &str, id: Option<i64>, start: Instant, f: impl FnOnce() -> T) -> T {
+  struct Restore(Option<Handling>);
+  impl Drop for Restore {
+    fn drop(&mut self) { HANDLING.set(self.0.take()) }
+  }
+  let handling = Handling { method: method.to_string(), id, start };
+  let _restore = Restore(HANDLING.replace(Some(handling)));
+  f()
+}
+
+/// Format a time as RFC 3339 in UTC with milliseconds
+fn timestamp(time: SystemTime) -> String {
+  let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+  let (days, secs) = ((since.as_secs() / 86400) as i64, since.as_secs() % 86400);
+  // Civil date from the number of days since the epoch, counting in 400 year
+  // eras that start on the 1st of March so that the leap day is last
+  let z = days + 719468;
+  let era = z.div_euclid(146097);
+  let doe = z - era * 146097;
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = doy - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = yoe + era * 400 + i64::from(month <= 2);
+  let (h, m, s, ms) = (secs / 3600, secs / 60 % 60, secs % 60, since.subsec_millis());
+  format!("{year:04}-{month:02}-{day:02}T{h:02}:{m:02}:{s:02}.{ms:03}Z")
+}
+
+/// A line in the JSON format
+fn record(
+  level: Level,
+  module: &str,
+  message: &str,
+  handling: Option<&Handling>,
+  now: (SystemTime, Instant),
+) -> Value {
+  let mut record = json!({
+    "timestamp": timestamp(now.0),
+    "level": level.name(),
+    "module": module,
+    "message": message,
+  });
+  if let Some(h) = handling {
+    record["method"] = json!(h.method);
+    if let Some(id) = h.id {
+      record["request"] = json!(id);
+    }
+    record["durationMs"] = json!(now.1.duration_since(h.start).as_secs_f64() * 1000.0);
+  }
+  record
+}
+
+/// Write a line to stderr. Use through [crate::log!].
+pub fn emit(level: Level, module: &str, args: fmt::Arguments) {
+  if *LEVEL.get().unwrap_or(&Level::Info) < level {
+    return;
+  }
+  let line = match FORMAT.get() {
+    None | Some(Format::Text) => args.to_string(),
+    Some(Format::Json) => HANDLING.with_borrow(|handling| {
+      let now = (SystemTime::now(), Instant::now());
+      record(level, module, &args.to_string(), handling.as_ref(), now).to_string()
+    }),
+  };
+  let _ = writeln!(stderr().lock(), "{line}");
+}
+
+/// Log a line at a [Level], like `log!(Warn, "Ignoring {uri}")`
+#[macro_export]
+macro_rules! log {
+  ($level:ident, $($arg:tt)+) => {
+    $crate::log::emit($crate::log::Level::$level, module_path!(), format_args!($($arg)+))
+  };
+}
+
+#[cfg(test)]
+mod test {
+  use std::time::{Duration, Instant, UNIX_EPOCH};
+
+  use serde_json::json;
+
+  use super::{record, timestamp, Handling, Level};
+
+  #[test]
+  fn timestamps() {
+    assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+    let leap_day = UNIX_EPOCH + Duration::from_millis(951_782_400_042);
+    assert_eq!(timestamp(leap_day), "2000-02-29T00:00:00.042Z");
+    let later = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    assert_eq!(timestamp(later), "2023-11-14T22:13:20.000Z");
+    let new_year = UNIX_EPOCH + Duration::from_secs(1_735_689_599);
+    assert_eq!(timestamp(new_year), "2024-12-31T23:59:59.000Z");
+  }
+
+  #[test]
+  fn levels() {
+    assert_eq!(Level::parse("warn"), Some(Level::Warn));
+    assert_eq!(Level::parse("Warn"), None);
+    assert!(Level::Error < Level::Debug, "Ordered by severity");
+  }
+
+  #[test]
+  fn records() {
+    let start = Instant::now();
+    let now = (UNIX_EPOCH, start + Duration::from_millis(12));
+    let plain = record(Level::Warn, "orchid_ls::cmd::fs", "Malformed didClose", None, now);
+    assert_eq!(plain, json!({
+      "timestamp": "1970-01-01T00:00:00.000Z",
+      "level": "warn",
+      "module": "orchid_ls::cmd::fs",
+      "message": "Malformed didClose",
+    }));
+    let handling = Handling { method: "textDocument/hover".to_string(), id: Some(7), start };
+    let req = record(Level::Debug, "orchid_ls::jrpc", "Resolved", Some(&handling), now);
+    assert_eq!(req["method"], "textDocument/hover");
+    assert_eq!(req["request"], 7);
+    assert_eq!(req["durationMs"], 12.0);
+    let notif = Handling { id: None, ..handling };
+    let notif = record(Level::Info, "orchid_ls::jrpc", "Opened", Some(&notif), now);
+    assert!(notif.get("request").is_none());
+  }
+}
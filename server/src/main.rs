@@ -1,27 +1,121 @@
+mod cache;
+mod capabilities;
+mod chain;
+mod check;
 mod cmd;
 mod comm;
 mod ctx_map;
+mod event_loop;
+mod fmt;
+mod inbox;
+mod intercept;
+mod journal;
 mod jrpc;
-mod orc;
-mod protocol;
+mod metrics;
+mod published;
+mod replay;
+mod trace;
 
-use std::process;
+use std::path::Path;
+use std::{env, process};
 
-use crate::cmd::{fs, init, logging};
+use orchid_ls::{abort, documents, log, orc, patches, protocol};
+
+use crate::cmd::{
+  ast, code, colors, commands, completion, diagnostics, folding, fs, hotspots, init, inline, lens,
+  lex, logging, memory, nav, on_type, progress, rename, semtok, settings, status, symbols, viewport,
+  watch,
+};
 use crate::comm::{stdin_ingress, stdout_write};
-use crate::jrpc::JrpcServer;
+use crate::event_loop::EventLoop;
+use crate::inbox::{Inbox, Limit};
+use crate::intercept::{Timing, UriCheck};
+use crate::jrpc::{JrpcServer, SendCB};
 
-fn main() {
-  eprintln!("Starting Orchid LSP server");
-  let mut srv = JrpcServer::new(stdout_write);
+/// A server with every handler attached
+fn server(send: impl SendCB) -> JrpcServer {
+  let mut srv = JrpcServer::new(send);
+  let session = srv.session().clone();
+  srv.intercept(UriCheck::new(session.clone()));
+  srv.intercept(Timing::new(session));
   init::attach(&mut srv);
+  ast::attach(&mut srv);
+  code::attach(&mut srv);
+  colors::attach(&mut srv);
+  commands::attach(&mut srv);
+  completion::attach(&mut srv);
+  diagnostics::attach(&mut srv);
+  inline::attach(&mut srv);
+  lens::attach(&mut srv);
+  lex::attach(&mut srv);
   logging::attach(&mut srv);
+  memory::attach(&mut srv);
+  folding::attach(&mut srv);
   fs::attach(&mut srv);
-  // code::attach(&mut srv);
-  eprintln!("srv initialized");
-  for message in stdin_ingress() {
-    srv.recv(message)
+  hotspots::attach(&mut srv);
+  nav::attach(&mut srv);
+  on_type::attach(&mut srv);
+  progress::attach(&mut srv);
+  rename::attach(&mut srv);
+  semtok::attach(&mut srv);
+  settings::attach(&mut srv);
+  status::attach(&mut srv);
+  symbols::attach(&mut srv);
+  viewport::attach(&mut srv);
+  watch::attach(&mut srv);
+  srv
+}
+
+/// Remove an option that applies to every mode from the arguments, returning
+/// its value
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+  let i = args.iter().position(|arg| arg == name)?;
+  let value = args.get(i + 1).cloned().unwrap_or_default();
+  args.drain(i..(i + 2).min(args.len()));
+  Some(value)
+}
+
+fn main() {
+  let mut args = env::args().collect::<Vec<_>>();
+  if let Some(name) = take_option(&mut args, "--log-format") {
+    let Some(format) = log::Format::parse(&name) else {
+      eprintln!("--log-format must be followed by \"text\" or \"json\"");
+      process::exit(2)
+    };
+    log::set_format(format);
+  }
+  if let Some(name) = take_option(&mut args, "--log-level") {
+    let Some(level) = log::Level::parse(&name) else {
+      eprintln!("--log-level must be followed by \"error\", \"warn\", \"info\" or \"debug\"");
+      process::exit(2)
+    };
+    log::set_level(level);
+  }
+  let (flag, arg) = (args.get(1).cloned(), args.get(2).cloned());
+  match (flag.as_deref(), arg) {
+    (Some("--replay"), Some(transcript)) =>
+      process::exit(replay::run(Path::new(&transcript), server)),
+    (Some("--capabilities"), caps) => {
+      let opts = args.get(3).map(Path::new);
+      process::exit(capabilities::run(caps.as_deref().map(Path::new), opts))
+    },
+    (Some("check"), Some(path)) => {
+      let json = args.iter().skip(3).any(|arg| arg == "--json");
+      let format = if json { check::Format::Json } else { check::Format::Human };
+      process::exit(check::run(Path::new(&path), format))
+    },
+    (Some("fmt"), Some(path)) => {
+      let check = args.iter().skip(3).any(|arg| arg == "--check");
+      process::exit(fmt::run(Path::new(&path), check))
+    },
+    _ => (),
   }
-  eprintln!("stdin closed unexpectedly");
+  log!(Info, "Starting Orchid LSP server");
+  let mut srv = server(stdout_write);
+  log!(Debug, "srv initialized");
+  let mut inbox = Inbox::spawn(stdin_ingress, 256);
+  inbox.limit("textDocument/didChange", Limit { burst: 10, per_sec: 20.0 });
+  EventLoop::new(srv.session().scheduler(), inbox).run(&mut srv);
+  log!(Error, "stdin closed unexpectedly");
   process::exit(1);
 }
@@ -0,0 +1,100 @@
+//! Per-method request statistics kept by the JSON-RPC layer
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::log;
+
+/// Maximum length of the params summary in slow request logs
+const SUMMARY_LEN: usize = 200;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MethodStats {
+  pub count: usize,
+  pub errors: usize,
+  pub total: Duration,
+  pub max: Duration,
+}
+
+/// Counts, latencies and error rates of every handled request and
+/// notification. Messages that take longer than [Metrics::slow_threshold] are
+/// logged along with a summary of their params.
+#[derive(Clone, Debug)]
+pub struct Metrics {
+  methods: HashMap<String, MethodStats>,
+  pub slow_threshold: Duration,
+}
+impl Metrics {
+  pub fn new(slow_threshold: Duration) -> Self { Self { methods: HashMap::new(), slow_threshold } }
+
+  pub fn record(&mut self, method: &str, elapsed: Duration, ok: bool, params: Option<&Value>) {
+    let stats = self.methods.entry(method.to_string()).or_default();
+    stats.count += 1;
+    stats.errors += usize::from(!ok);
+    stats.total += elapsed;
+    stats.max = stats.max.max(elapsed);
+    if self.slow_threshold <= elapsed {
+      log!(Warn, "Slow request: {method} took {elapsed:?} with params {}", summarize(params));
+    }
+  }
+
+  /// Aggregates per method for `orchid/status`
+  pub fn report(&self) -> Value {
+    let methods = self.methods.iter().map(|(name, s)| {
+      let report = json!({
+        "count": s.count,
+        "errors": s.errors,
+        "errorRate": s.errors as f64 / s.count as f64,
+        "meanMs": s.total.as_secs_f64() * 1000.0 / s.count as f64,
+        "maxMs": s.max.as_secs_f64() * 1000.0,
+      });
+      (name.clone(), report)
+    });
+    Value::Object(methods.collect())
+  }
+}
+
+/// Serialized params truncated to a loggable length
+fn summarize(params: Option<&Value>) -> String {
+  let text = params.map_or_else(|| "none".to_string(), |p| p.to_string());
+  if text.len() <= SUMMARY_LEN {
+    return text;
+  }
+  let end = (0..=SUMMARY_LEN).rev().find(|i| text.is_char_boundary(*i)).unwrap_or(0);
+  format!("{}... ({} bytes)", &text[..end], text.len())
+}
+
+#[cfg(test)]
+mod test {
+  use std::time::Duration;
+
+  use serde_json::json;
+
+  use super::{summarize, MethodStats, Metrics};
+
+  #[test]
+  fn aggregates() {
+    let mut metrics = Metrics::new(Duration::MAX);
+    metrics.record("hover", Duration::from_millis(10), true, None);
+    metrics.record("hover", Duration::from_millis(30), false, None);
+    let stats = MethodStats {
+      count: 2,
+      errors: 1,
+      total: Duration::from_millis(40),
+      max: Duration::from_millis(30),
+    };
+    assert_eq!(metrics.methods["hover"], stats);
+    let report = metrics.report();
+    assert_eq!(report["hover"]["errorRate"], 0.5);
+    assert_eq!(report["hover"]["meanMs"], 20.0);
+  }
+
+  #[test]
+  fn summary() {
+    assert_eq!(summarize(Some(&json!({ "a": 1 }))), r#"{"a":1}"#);
+    let long = summarize(Some(&json!("ő".repeat(300))));
+    assert!(long.ends_with("... (602 bytes)"), "Truncated with total size");
+  }
+}
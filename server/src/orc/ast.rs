@@ -0,0 +1,95 @@
+//! The parsed tree around a range of a file, for clients that edit by structure
+//! rather than by text. The tree is the one the project was loaded with,
+//! before macros ran, so every node spans text of the file, except for the
+//! parentheses the parser wraps the value of a constant in, which are skipped.
+
+use std::ops::Range;
+
+use intern_all::i;
+use orchidlang::name::PathSlice;
+use orchidlang::parse::parsed;
+
+use super::project::LoadedProject;
+
+/// A short name for the syntax of a node
+pub fn kind(ex: &parsed::Expr) -> &'static str {
+  match &ex.value {
+    parsed::Clause::Name(_) => "name",
+    parsed::Clause::Atom(_) => "literal",
+    parsed::Clause::S(..) => "group",
+    parsed::Clause::Lambda(..) => "lambda",
+    parsed::Clause::Placeh(_) => "placeholder",
+  }
+}
+
+/// The nodes directly below a node in source order. The parameter of a lambda
+/// precedes its body.
+pub fn children(ex: &parsed::Expr) -> Vec<&parsed::Expr> {
+  match &ex.value {
+    parsed::Clause::S(_, body) => body.iter().collect(),
+    parsed::Clause::Lambda(arg, body) => arg.iter().chain(body.iter()).collect(),
+    _ => Vec::new(),
+  }
+}
+
+/// The descendants of a node that contain something, from the smallest to a
+/// child of the node
+fn descend<'a, T>(
+  root: &'a T,
+  children: impl Fn(&'a T) -> Vec<&'a T>,
+  contains: impl Fn(&T) -> bool,
+) -> Vec<&'a T> {
+  let mut chain = Vec::new();
+  let mut next = children(root).into_iter().find(|node| contains(node));
+  while let Some(node) = next {
+    chain.push(node);
+    next = children(node).into_iter().find(|node| contains(node));
+  }
+  chain.reverse();
+  chain
+}
+
+/// The nodes that contain a byte range of a file, from the smallest to the
+/// outermost node of the constant's value. The path is relative to the
+/// project root. Empty if the range isn't in an expression of a constant.
+pub fn enclosing<'a>(
+  lpr: &'a LoadedProject,
+  path: &PathSlice,
+  range: Range<usize>,
+) -> Vec<&'a parsed::Expr> {
+  let module = path.to_vpath().prefix([i!(str: "tree")]);
+  let contains = |ex: &parsed::Expr| {
+    let in_file = ex.range.path().iter().eq(module.iter());
+    in_file && ex.range.start() <= range.start && range.end <= ex.range.end()
+  };
+  let Some(consts) = lpr.module_consts(&module) else { return Vec::new() };
+  let Some(value) = consts.into_iter().find(|ex| contains(ex)) else { return Vec::new() };
+  // The value is the synthetic group
+  descend(value, children, contains)
+}
+
+#[cfg(test)]
+mod test {
+  use std::ops::Range;
+
+  use super::descend;
+
+  struct Node(Range<usize>, Vec<Node>);
+
+  fn children(node: &Node) -> Vec<&Node> { node.1.iter().collect() }
+
+  fn ranges(tree: &Node, range: Range<usize>) -> Vec<Range<usize>> {
+    let contains = |node: &Node| node.0.start <= range.start && range.end <= node.0.end;
+    descend(tree, children, contains).into_iter().map(|node| node.0.clone()).collect()
+  }
+
+  #[test]
+  fn chain() {
+    // f (g x) y, wrapped in the group the parser adds
+    let group = Node(2..7, vec![Node(3..4, vec![]), Node(5..6, vec![])]);
+    let tree = Node(0..9, vec![Node(0..1, vec![]), group, Node(8..9, vec![])]);
+    assert_eq!(ranges(&tree, 5..5), [5..6, 2..7], "Innermost first, without the root");
+    assert_eq!(ranges(&tree, 2..7).len(), 1, "The group contains itself");
+    assert_eq!(ranges(&tree, 1..3), Vec::<Range<usize>>::new(), "Only the root contains it");
+  }
+}
@@ -0,0 +1,49 @@
+//! Classification of the atoms that systems parse literals into. Each loaded
+//! system registers the atom types it produces with the semantic token type
+//! and hover label they should be displayed with.
+
+use orchidlang::foreign::atom::{Atom, Atomic};
+use orchidlang::foreign::inert::Inert;
+use orchidlang::libs::std::string::OrcString;
+use ordered_float::NotNan;
+
+/// How atoms of a type are displayed
+#[derive(Clone, Copy, Debug)]
+pub struct AtomClass {
+  /// Semantic token type, one of the internal types of
+  /// [crate::protocol::legend::TokenLegend]
+  pub token: &'static str,
+  /// The kind of value for hovers, such as "Natural number"
+  pub label: &'static str,
+  /// The system that defines the type
+  pub system: &'static str,
+}
+
+fn is<T: Atomic>(atom: &Atom) -> bool { atom.is::<T>() }
+
+/// Atom types by the test that recognizes them. The first match wins.
+pub struct AtomRegistry(Vec<(fn(&Atom) -> bool, AtomClass)>);
+impl AtomRegistry {
+  pub fn register<T: Atomic>(&mut self, class: AtomClass) { self.0.push((is::<T>, class)) }
+
+  /// The atom types of the systems the language server loads
+  pub fn with_loaded_systems() -> Self {
+    let mut atoms = Self(Vec::new());
+    let std = |token, label| AtomClass { token, label, system: "std" };
+    atoms.register::<Inert<usize>>(std("number", "Natural number"));
+    atoms.register::<Inert<NotNan<f64>>>(std("number", "Float"));
+    atoms.register::<Inert<bool>>(std("keyword", "Boolean"));
+    atoms.register::<Inert<OrcString>>(std("string", "String"));
+    atoms
+  }
+
+  pub fn classify(&self, atom: &Atom) -> Option<&AtomClass> {
+    self.0.iter().find(|(test, _)| test(atom)).map(|(_, class)| class)
+  }
+
+  /// The semantic token type of an atom. Unknown atoms are most often
+  /// string-like payloads of custom lexers, so they're highlighted as strings.
+  pub fn token(&self, atom: &Atom) -> &'static str {
+    self.classify(atom).map_or("string", |c| c.token)
+  }
+}
@@ -0,0 +1,128 @@
+//! Bracket balance and indentation of the member being edited. Only the
+//! member around the cursor is scanned so that an unbalanced bracket doesn't
+//! affect the rest of the file.
+
+use std::ops::Range;
+
+use super::lexical::{lex, LexKind};
+use super::members::Edit;
+
+const PAIRS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
+/// The start of the top-level member around an offset, which is the last line
+/// before it that isn't indented or a comment
+fn member_start(text: &str, offset: usize) -> usize {
+  let mut start = offset.min(text.len());
+  loop {
+    start = text[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line = &text[start..];
+    if start == 0 || !(line.starts_with(char::is_whitespace) || line.starts_with("--")) {
+      return start;
+    }
+    start -= 1;
+  }
+}
+
+/// Offsets of the brackets opened in the range and not closed before its end
+fn unclosed(text: &str, range: Range<usize>) -> Vec<usize> {
+  let mut stack = Vec::new();
+  for (kind, lexeme) in lex(&text[range.clone()]) {
+    match kind {
+      LexKind::Open => stack.push(range.start + lexeme.start),
+      LexKind::Close => {
+        stack.pop();
+      },
+      _ => (),
+    }
+  }
+  stack
+}
+
+fn closer(open: char) -> Option<char> {
+  PAIRS.iter().find(|(o, _)| *o == open).map(|(_, close)| *close)
+}
+
+fn indent_at(text: &str, offset: usize) -> &str {
+  let line = &text[text[..offset].rfind('\n').map_or(0, |i| i + 1)..];
+  &line[..line.len() - line.trim_start().len()]
+}
+
+/// The start of the first top-level line after the one containing `offset`
+fn next_member(text: &str, offset: usize) -> usize {
+  let mut pos = text[offset..].find('\n').map_or(text.len(), |i| offset + i + 1);
+  for line in text[pos..].split_inclusive('\n') {
+    if !(line.trim().is_empty() || line.starts_with(char::is_whitespace)) {
+      break;
+    }
+    pos += line.len();
+  }
+  pos
+}
+
+/// The edit that closes the bracket just typed before `offset`, if nothing
+/// in the member closes it yet
+pub fn close_bracket(text: &str, offset: usize) -> Option<Edit> {
+  let open = text.get(..offset)?.chars().next_back()?;
+  let close = closer(open)?;
+  let member = member_start(text, offset)..next_member(text, offset);
+  let opened = offset - open.len_utf8();
+  unclosed(text, member).contains(&opened).then(|| (offset..offset, close.to_string()))
+}
+
+/// The edit that indents the line a newline was just inserted before `offset`
+/// to one level deeper than the innermost open bracket, or to the level of the
+/// bracket if the line starts by closing it
+pub fn indentation(text: &str, offset: usize) -> Option<Edit> {
+  let line_start = text.get(..offset)?.rfind('\n')? + 1;
+  let stack = unclosed(text, member_start(text, line_start - 1)..line_start);
+  let line = &text[line_start..];
+  let line = &line[..line.find('\n').unwrap_or(line.len())];
+  let content = line.trim_start();
+  let closing = content.chars().take_while(|c| PAIRS.iter().any(|(_, cl)| cl == c)).count();
+  let (innermost, level) = match closing.min(stack.len()) {
+    0 => (*stack.last()?, "  "),
+    n => (stack[stack.len() - n], ""),
+  };
+  let indent = format!("{}{level}", indent_at(text, innermost));
+  let current = line_start..line_start + line.len() - content.len();
+  (text[current.clone()] != indent).then_some((current, indent))
+}
+
+#[cfg(test)]
+mod test {
+  use super::{close_bracket, indentation};
+  use crate::orc::members::Edit;
+
+  fn apply(text: &str, edit: Option<Edit>) -> Option<String> {
+    let (range, new) = edit?;
+    Some(format!("{}{new}{}", &text[..range.start], &text[range.end..]))
+  }
+
+  #[test]
+  fn closing() {
+    let typed = |before: &str, after: &str| {
+      let text = format!("{before}{after}");
+      apply(&text, close_bracket(&text, before.len()))
+    };
+    assert_eq!(typed("const a := f (", " x").unwrap(), "const a := f () x");
+    assert_eq!(typed("const a := f [", "]"), None);
+    assert_eq!(typed("const a := f (", "\n  g)"), None);
+    assert_eq!(typed("const a := \"(", "\""), None);
+    // An unbalanced member further down isn't affected
+    assert_eq!(typed("const a := (", "\nconst b := x)").unwrap(), "const a := ()\nconst b := x)");
+  }
+
+  #[test]
+  fn indenting() {
+    let entered = |before: &str, after: &str| {
+      let text = format!("{before}{after}");
+      apply(&text, indentation(&text, before.len()))
+    };
+    assert_eq!(entered("const a := f (\n", "x").unwrap(), "const a := f (\n  x");
+    let nested = "module m (\n  const a := (\n";
+    assert_eq!(entered(nested, "").unwrap(), format!("{nested}    "));
+    assert_eq!(entered("const a := (\n  x\n  ", ")").unwrap(), "const a := (\n  x\n)");
+    assert_eq!(entered("const a := 1\n", ""), None);
+    assert_eq!(entered("const a := (\n", "  x"), None);
+  }
+}
@@ -0,0 +1,159 @@
+//! Recognize color literals in source text so editors can show swatches.
+//! Two conventions are recognized: hex strings such as `"#ff8000"` and calls
+//! to a configurable set of constructors such as `rgb 255 128 0` with an
+//! optional alpha between 0 and 1.
+
+use std::ops::Range;
+
+use super::lexical::name_char;
+
+/// A color with all channels between 0 and 1, as LSP represents them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rgba {
+  pub red: f64,
+  pub green: f64,
+  pub blue: f64,
+  pub alpha: f64,
+}
+
+/// A color literal and its byte range in the text
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColorLiteral {
+  pub range: Range<usize>,
+  pub color: Rgba,
+}
+
+/// Constructor names may be qualified
+fn is_name_char(c: char) -> bool { name_char(c) || c == ':' }
+
+/// Parse the digits of a hex color in the `rgb`, `rgba`, `rrggbb` or
+/// `rrggbbaa` format
+fn parse_hex(digits: &str) -> Option<Rgba> {
+  if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+    return None;
+  }
+  let channels: Vec<f64> = match digits.len() {
+    3 | 4 => (digits.chars())
+      .map(|c| c.to_digit(16).unwrap() as f64 * 17.0 / 255.0)
+      .collect(),
+    6 | 8 => (0..digits.len() / 2)
+      .map(|i| u8::from_str_radix(&digits[2 * i..2 * i + 2], 16).unwrap() as f64 / 255.0)
+      .collect(),
+    _ => return None,
+  };
+  let alpha = channels.get(3).copied().unwrap_or(1.0);
+  Some(Rgba { red: channels[0], green: channels[1], blue: channels[2], alpha })
+}
+
+fn hex_literals(text: &str) -> impl Iterator<Item = ColorLiteral> + '_ {
+  text.match_indices("\"#").filter_map(|(start, _)| {
+    let len = text[start + 2..].find('"')?;
+    let color = parse_hex(&text[start + 2..start + 2 + len])?;
+    Some(ColorLiteral { range: start..start + len + 3, color })
+  })
+}
+
+/// Whether the text on either side of a word ends it
+fn word_bounds(before: &str, after: &str) -> bool {
+  before.chars().next_back().is_none_or(|c| !is_name_char(c))
+    && after.chars().next().is_none_or(|c| !is_name_char(c))
+}
+
+/// Parse whitespace-separated numbers at the start of the text, returning them
+/// along with the length of the consumed text
+fn leading_numbers(text: &str, max: usize) -> (Vec<f64>, usize) {
+  let (mut nums, mut pos) = (Vec::new(), 0);
+  while nums.len() < max {
+    let rest = &text[pos..];
+    let start = rest.len() - rest.trim_start_matches([' ', '\t']).len();
+    let len = rest[start..].find(|c: char| !c.is_ascii_digit() && c != '.');
+    let end = start + len.unwrap_or(rest.len() - start);
+    match rest[start..end].parse::<f64>() {
+      Ok(num) if 0 < start && word_bounds("", &rest[end..]) => nums.push(num),
+      _ => break,
+    }
+    pos += end;
+  }
+  (nums, pos)
+}
+
+/// Calls to `name` followed by 3 channels between 0 and 255 and optionally an
+/// alpha between 0 and 1
+fn constructor_calls<'a>(text: &'a str, name: &'a str) -> impl Iterator<Item = ColorLiteral> + 'a {
+  text.match_indices(name).filter_map(move |(start, _)| {
+    let name_end = start + name.len();
+    if !word_bounds(&text[..start], &text[name_end..]) {
+      return None;
+    }
+    let (args, len) = leading_numbers(&text[name_end..], 4);
+    let channel = |c: &f64| (0.0..=255.0).contains(c) && c.fract() == 0.0;
+    let alpha = match args.get(3) {
+      None => 1.0,
+      Some(a) if (0.0..=1.0).contains(a) => *a,
+      // a fourth number that isn't a valid alpha is not part of the color
+      Some(_) => return None,
+    };
+    match args.get(..3)? {
+      [red, green, blue] if [red, green, blue].into_iter().all(channel) => Some(ColorLiteral {
+        range: start..name_end + len,
+        color: Rgba { red: red / 255.0, green: green / 255.0, blue: blue / 255.0, alpha },
+      }),
+      _ => None,
+    }
+  })
+}
+
+/// Find all color literals in a text, sorted by position
+pub fn find_colors(text: &str, constructors: &[String]) -> Vec<ColorLiteral> {
+  let mut colors = hex_literals(text).collect::<Vec<_>>();
+  colors.extend(constructors.iter().flat_map(|name| constructor_calls(text, name)));
+  colors.sort_by_key(|c| c.range.start);
+  colors
+}
+
+fn channel_u8(c: f64) -> u8 { (c.clamp(0.0, 1.0) * 255.0).round() as u8 }
+
+/// Render a color as a hex string literal, omitting opaque alpha
+pub fn hex_literal(c: Rgba) -> String {
+  let channels = [c.red, c.green, c.blue].into_iter().chain((c.alpha < 1.0).then_some(c.alpha));
+  let digits = channels.map(|ch| format!("{:02x}", channel_u8(ch))).collect::<String>();
+  format!("\"#{digits}\"")
+}
+
+/// Render a color as a call to a constructor, omitting opaque alpha
+pub fn constructor_call(name: &str, c: Rgba) -> String {
+  let [r, g, b] = [c.red, c.green, c.blue].map(channel_u8);
+  let mut call = format!("{name} {r} {g} {b}");
+  if c.alpha < 1.0 {
+    call += &format!(" {}", (c.alpha * 100.0).round() / 100.0);
+  }
+  call
+}
+
+#[cfg(test)]
+mod test {
+  use super::{constructor_call, find_colors, hex_literal, Rgba};
+
+  #[test]
+  fn literals() {
+    let text = "const a := \"#ff8000\"\nconst b := rgb 0 255 0 0.5\nconst c := hrgb 1 2 3";
+    let colors = find_colors(text, &["rgb".to_string()]);
+    assert_eq!(colors.len(), 2, "hrgb is not rgb");
+    assert_eq!(&text[colors[0].range.clone()], "\"#ff8000\"");
+    assert_eq!(hex_literal(colors[0].color), "\"#ff8000\"");
+    assert_eq!(&text[colors[1].range.clone()], "rgb 0 255 0 0.5");
+    let green = Rgba { red: 0.0, green: 1.0, blue: 0.0, alpha: 0.5 };
+    assert_eq!(colors[1].color, green);
+    assert_eq!(constructor_call("rgb", green), "rgb 0 255 0 0.5");
+  }
+
+  #[test]
+  fn rejects() {
+    let none = |text: &str| find_colors(text, &["rgb".to_string()]).is_empty();
+    assert!(none("\"#12345\" \"#ggg\""), "Wrong length or not hex");
+    assert!(!none("\"#ff80\""), "4 digits is rgba");
+    assert!(none("rgb 256 0 0"), "Out of range channel");
+    assert!(none("rgb 1 2"), "Too few channels");
+    assert!(none("rgb 1.5 2 3"), "Fractional channel");
+  }
+}
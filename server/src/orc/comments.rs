@@ -0,0 +1,79 @@
+//! Carrying comments over to the next line as the user types. Line comments
+//! repeat their leader, block comments keep the indentation of their text.
+
+use std::ops::Range;
+
+use super::lexical::{lex, LexKind};
+
+/// The block comment an offset falls in, from its opening `--[` to its
+/// closing `]--` or the end of the text
+fn block_at(text: &str, offset: usize) -> Option<Range<usize>> {
+  (lex(text).into_iter())
+    .take_while(|(_, range)| range.start < offset)
+    .filter(|(kind, range)| *kind == LexKind::Comment && text[range.clone()].starts_with("--["))
+    .map(|(_, range)| range)
+    .find(|range| offset < range.end || !(6 <= range.len() && text[range.clone()].ends_with("]--")))
+}
+
+fn indent(line: &str) -> &str { &line[..line.len() - line.trim_start().len()] }
+
+/// The edit that continues the comment of the previous line on the line a
+/// newline was just inserted before `offset`. It replaces the whitespace the
+/// editor placed at the start of the new line.
+pub fn continuation(text: &str, offset: usize) -> Option<(Range<usize>, String)> {
+  let line_start = text.get(..offset)?.rfind('\n')? + 1;
+  let prev_start = text[..line_start - 1].rfind('\n').map_or(0, |i| i + 1);
+  let prev = text[prev_start..line_start - 1].trim_end_matches('\r');
+  let line = &text[line_start..];
+  let line = &line[..line.find('\n').unwrap_or(line.len())];
+  let replaced = line_start..line_start + indent(line).len().min(offset - line_start);
+  if let Some(block) = block_at(text, offset) {
+    if line.trim_start().starts_with("]--") {
+      return None;
+    }
+    let leader = match block.start < prev_start {
+      // The text of the comment lines up with the previous line
+      true => {
+        let text = prev.trim_start();
+        let bullet = ["* ", "*"].into_iter().find(|b| text.starts_with(b)).unwrap_or("");
+        format!("{}{bullet}", indent(prev))
+      },
+      // The first line after the opener is indented by one level
+      false => format!("{}  ", indent(&text[prev_start..block.start])),
+    };
+    return Some((replaced, leader));
+  }
+  let comment = prev.trim_start().strip_prefix("--").filter(|c| !c.starts_with('['))?;
+  let spacing = &comment[..comment.len() - comment.trim_start().len()];
+  let spacing = if spacing.is_empty() { " " } else { spacing };
+  Some((replaced, format!("{}--{spacing}", indent(prev))))
+}
+
+#[cfg(test)]
+mod test {
+  use super::continuation;
+
+  fn continued(before: &str, after: &str) -> Option<String> {
+    let text = format!("{before}{after}");
+    let (range, leader) = continuation(&text, before.len())?;
+    Some(format!("{}{leader}{}", &text[..range.start], &text[range.end..]))
+  }
+
+  #[test]
+  fn line_comments() {
+    assert_eq!(continued("  -- docs\n  ", "").unwrap(), "  -- docs\n  -- ");
+    assert_eq!(continued("--   wide\n", "rest").unwrap(), "--   wide\n--   rest");
+    assert_eq!(continued("const a := 1 -- trailing\n", ""), None);
+    assert_eq!(continued("const a := \"--\"\n", ""), None);
+  }
+
+  #[test]
+  fn block_comments() {
+    let opened = "  --[\n";
+    assert_eq!(continued(opened, "\n]--").unwrap(), "  --[\n    \n]--");
+    let text = "--[\n  * one\n";
+    assert_eq!(continued(text, "").unwrap(), "--[\n  * one\n  * ");
+    assert_eq!(continued("--[\n  text\n", "]--"), None);
+    assert_eq!(continued("--[ closed ]--\n", ""), None);
+  }
+}
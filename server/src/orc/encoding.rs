@@ -0,0 +1,79 @@
+//! Decoding source files read from disk. Orchid source is UTF-8, but editors
+//! on some platforms add a byte order mark or save as UTF-16, and a single
+//! stray byte shouldn't take the whole project out of analysis. Such files are
+//! decoded as well as possible and the problem is reported as a diagnostic.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+  Utf8,
+  /// UTF-8 preceded by a byte order mark, which is dropped
+  Utf8Bom,
+  Utf16Le,
+  Utf16Be,
+  /// Not valid in any encoding that was recognized. Invalid sequences were
+  /// replaced with U+FFFD, the first of them at the byte offset in the
+  /// decoded text.
+  Invalid(usize),
+}
+
+fn decode_utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> String {
+  let units = bytes.chunks(2).map(|c| unit([c[0], c.get(1).copied().unwrap_or(0)]));
+  char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)).collect()
+}
+
+fn decode_utf8(bytes: &[u8]) -> (String, Encoding) {
+  match String::from_utf8(bytes.to_vec()) {
+    Ok(text) => (text, Encoding::Utf8),
+    Err(e) => {
+      let valid = e.utf8_error().valid_up_to();
+      (String::from_utf8_lossy(bytes).into_owned(), Encoding::Invalid(valid))
+    },
+  }
+}
+
+/// Decode the contents of a source file
+pub fn decode(bytes: &[u8]) -> (String, Encoding) {
+  if let Some(rest) = bytes.strip_prefix(b"\xEF\xBB\xBF") {
+    return match decode_utf8(rest) {
+      (text, Encoding::Utf8) => (text, Encoding::Utf8Bom),
+      invalid => invalid,
+    };
+  }
+  if let Some(rest) = bytes.strip_prefix(b"\xFF\xFE") {
+    return (decode_utf16(rest, u16::from_le_bytes), Encoding::Utf16Le);
+  }
+  if let Some(rest) = bytes.strip_prefix(b"\xFE\xFF") {
+    return (decode_utf16(rest, u16::from_be_bytes), Encoding::Utf16Be);
+  }
+  decode_utf8(bytes)
+}
+
+#[cfg(test)]
+mod test {
+  use super::{decode, Encoding};
+
+  #[test]
+  fn utf8() {
+    assert_eq!(decode(b"const a := 1"), ("const a := 1".to_string(), Encoding::Utf8));
+    let bom = decode(b"\xEF\xBB\xBFconst a := 1");
+    assert_eq!(bom, ("const a := 1".to_string(), Encoding::Utf8Bom));
+  }
+
+  #[test]
+  fn utf16() {
+    let le = decode(b"\xFF\xFEa\0:\0=\0");
+    assert_eq!(le, ("a:=".to_string(), Encoding::Utf16Le));
+    let be = decode(b"\xFE\xFF\0a\0\xe9");
+    assert_eq!(be, ("aé".to_string(), Encoding::Utf16Be));
+  }
+
+  #[test]
+  fn invalid() {
+    let (text, enc) = decode(b"const \xe9 := 1\n\xff");
+    assert_eq!(text, "const \u{FFFD} := 1\n\u{FFFD}");
+    assert_eq!(enc, Encoding::Invalid(6));
+    assert_eq!(&text[6..9], "\u{FFFD}", "The offset points at the replacement");
+    let (_, enc) = decode(b"\xEF\xBB\xBFa\xff");
+    assert_eq!(enc, Encoding::Invalid(1), "The offset is in the text without the mark");
+  }
+}
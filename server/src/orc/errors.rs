@@ -0,0 +1,52 @@
+//! Translation of the errors reported by the Orchid pipeline into [Lint]s, so
+//! they're located and published like any other finding
+
+use orchidlang::error::ProjectErrorObj;
+use orchidlang::location::CodeOrigin;
+use orchidlang::name::VPath;
+
+use super::lint::{Lint, Severity};
+use super::rules::file_of;
+
+/// Locate an error in the project. The first position in a file of the
+/// project becomes the location of the finding and the rest are attached as
+/// related information. Errors that only point at generated code or at other
+/// projects yield [None].
+pub fn error_lint(err: &ProjectErrorObj, severity: Severity) -> Option<Lint> {
+  let description = err.description().to_string();
+  let mut positions = (err.positions().into_iter()).filter_map(|pos| match pos.origin {
+    CodeOrigin::Source(range) => Some((file_of(&range.path())?, range.range(), pos.message)),
+    _ => None,
+  });
+  let (file, range, message) = positions.next()?;
+  let related = positions
+    .map(|(file, range, msg)| (file, range, msg.unwrap_or_else(|| description.clone())))
+    .collect();
+  let message = match message {
+    Some(msg) => format!("{description}: {msg}"),
+    None => err.message(),
+  };
+  Some(Lint { file, range, severity, code: "load-error", message, related })
+}
+
+/// Whether the load can go on despite the error. Errors that point at files of
+/// the project only take those files out of the tree, but other errors may
+/// leave the tree incomplete in ways no one would notice.
+pub fn recoverable(err: &ProjectErrorObj) -> bool { error_lint(err, Severity::Warning).is_some() }
+
+/// Locate a list of errors. Errors that can't be located in the project are
+/// placed at the start of the fallback file, which is the project file.
+pub fn error_lints(errors: &[ProjectErrorObj], severity: Severity, fallback: &VPath) -> Vec<Lint> {
+  (errors.iter())
+    .map(|err| {
+      error_lint(err, severity).unwrap_or_else(|| Lint {
+        file: fallback.clone(),
+        range: 0..0,
+        severity,
+        code: "load-error",
+        message: err.message(),
+        related: Vec::new(),
+      })
+    })
+    .collect()
+}
@@ -0,0 +1,154 @@
+//! Folding ranges of a source file. Members and brackets spanning several
+//! lines fold as plain regions, while the block of imports and runs of
+//! comments are classified so that editors can fold them all at once. This
+//! works on the text so that files that don't parse still fold.
+
+use std::cmp::Reverse;
+
+use itertools::Itertools;
+
+/// The classification of a fold, `FoldingRangeKind` in the protocol
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FoldKind {
+  Comment,
+  Imports,
+}
+impl FoldKind {
+  pub fn lsp_name(self) -> &'static str {
+    match self {
+      Self::Comment => "comment",
+      Self::Imports => "imports",
+    }
+  }
+}
+
+/// A range of lines, both ends inclusive. The first line stays visible when
+/// it's folded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fold {
+  pub start: usize,
+  pub end: usize,
+  pub kind: Option<FoldKind>,
+}
+
+/// A run of lines that folds if it ends up spanning more than one
+#[derive(Default)]
+struct Run(Option<(usize, usize)>);
+impl Run {
+  fn extend(&mut self, line: usize) { self.0 = Some((self.0.map_or(line, |r| r.0), line)) }
+  fn close(&mut self, kind: Option<FoldKind>, folds: &mut Vec<Fold>) {
+    if let Some((start, end)) = self.0.take().filter(|(start, end)| start < end) {
+      folds.push(Fold { start, end, kind })
+    }
+  }
+}
+
+pub fn folds(text: &str) -> Vec<Fold> {
+  let mut folds = Vec::new();
+  let (mut brackets, mut block, mut in_str) = (Vec::new(), None, false);
+  let (mut comments, mut imports, mut member) = (Run::default(), Run::default(), Run::default());
+  let mut member_is_import = false;
+  for (n, line) in text.lines().enumerate() {
+    let trimmed = line.trim_start();
+    let in_code = block.is_none() && !in_str;
+    let is_comment = in_code && trimmed.starts_with("--") && !trimmed.starts_with("--[");
+    if is_comment {
+      comments.extend(n);
+    } else {
+      comments.close(Some(FoldKind::Comment), &mut folds);
+    }
+    if in_code && brackets.is_empty() && !line.starts_with(char::is_whitespace) && !is_comment {
+      if !line.is_empty() && !line.starts_with("--[") {
+        match member_is_import {
+          true => member.0.take().into_iter().for_each(|(_, end)| imports.extend(end)),
+          false => member.close(None, &mut folds),
+        }
+        member_is_import = line.starts_with("import") || line.starts_with("export import");
+        if !member_is_import {
+          imports.close(Some(FoldKind::Imports), &mut folds);
+        }
+        member.extend(n);
+      }
+    } else if !is_comment && block.is_none() && !trimmed.is_empty() && member.0.is_some() {
+      member.extend(n);
+    }
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+      let rest = &line[i..];
+      if let Some(start) = block {
+        if rest.starts_with("]--") {
+          block = None;
+          chars.nth(1);
+          Run(Some((start, n))).close(Some(FoldKind::Comment), &mut folds);
+        }
+      } else if in_str {
+        match c {
+          '\\' => drop(chars.next()),
+          '"' => in_str = false,
+          _ => (),
+        }
+      } else if rest.starts_with("--[") {
+        block = Some(n);
+        chars.nth(1);
+      } else if rest.starts_with("--") {
+        break;
+      } else {
+        match c {
+          '"' => in_str = true,
+          '(' | '[' | '{' => brackets.push(n),
+          ')' | ']' | '}' => {
+            let Some(start) = brackets.pop() else { continue };
+            // Keep the closing bracket visible if it starts its line
+            let end = if line[..i].trim().is_empty() { n.saturating_sub(1) } else { n };
+            Run(Some((start, end))).close(None, &mut folds);
+          },
+          _ => (),
+        }
+      }
+    }
+  }
+  comments.close(Some(FoldKind::Comment), &mut folds);
+  match member_is_import {
+    true => member.0.take().into_iter().for_each(|(_, end)| imports.extend(end)),
+    false => member.close(None, &mut folds),
+  }
+  imports.close(Some(FoldKind::Imports), &mut folds);
+  // Editors keep one fold per line, so the outermost one is kept
+  (folds.into_iter())
+    .sorted_by_key(|f| (f.start, Reverse(f.end)))
+    .dedup_by(|a, b| a.start == b.start)
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+  use super::{folds, Fold, FoldKind};
+
+  #[test]
+  fn kinds() {
+    let text = [
+      "import std::list",        // 0
+      "import std::(",           // 1
+      "  option, tuple",         // 2
+      ")",                       // 3
+      "",                        // 4
+      "-- Two lines of",         // 5
+      "-- documentation",        // 6
+      "const main := map (",     // 7
+      "  \\x. x -- comment (",   // 8
+      ") \"(\"",                 // 9
+      "--[ block",               // 10
+      "  comment ]--",           // 11
+      "const single := 1",       // 12
+    ]
+    .join("\n");
+    let fold = |start, end, kind| Fold { start, end, kind };
+    assert_eq!(folds(&text), [
+      fold(0, 3, Some(FoldKind::Imports)),
+      fold(1, 2, None),
+      fold(5, 6, Some(FoldKind::Comment)),
+      fold(7, 9, None),
+      fold(10, 11, Some(FoldKind::Comment)),
+    ]);
+  }
+}
@@ -0,0 +1,98 @@
+//! Macro gas, the number of steps the macro runner takes to expand a constant.
+//! A few constants that take many steps can dominate the time of every
+//! analysis of a project, so the steps are recorded while the project loads to
+//! point users at them and at the rules likely responsible.
+
+use hashbrown::HashMap;
+use intern_all::{i, Tok};
+use orchidlang::facade::macro_runner::MacroRunner;
+use orchidlang::location::SourceRange;
+use orchidlang::parse::parsed;
+use orchidlang::pipeline::project::{ItemKind, ProjItem, ProjectMod, ProjectTree};
+use orchidlang::tree::ModMember;
+
+use super::rules::{names, SrcRule};
+
+/// The macro steps taken to expand a constant
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Gas {
+  pub steps: usize,
+  /// Expansion was abandoned at the limit
+  pub exhausted: bool,
+}
+
+/// The output of the macro runner for a constant and the steps it took
+#[derive(Clone, Debug)]
+pub struct Expansion {
+  pub gas: Gas,
+  /// [None] if the expansion was abandoned at the limit
+  pub output: Option<parsed::Expr>,
+}
+
+/// Expand a constant with at most `limit` steps, as the macro runner would
+pub fn expand(macros: &MacroRunner, expr: &parsed::Expr, limit: usize) -> Expansion {
+  // Like the macro runner, allow one more step to tell running out of gas from
+  // finishing on the last step
+  let (output, left) = macros.repo.long_step(expr, limit + 1);
+  let gas = Gas { steps: (limit + 1 - left).min(limit), exhausted: left == 0 };
+  Expansion { gas, output: (!gas.exhausted).then_some(output) }
+}
+
+/// The gas consumed by a constant declared in the project
+#[derive(Clone, Debug)]
+pub struct Hotspot {
+  /// Fully qualified name
+  pub name: Vec<Tok<String>>,
+  /// Location of the value
+  pub range: SourceRange,
+  pub gas: Gas,
+}
+
+/// The gas of every constant in the project's files, given their expansions
+/// by the location of the value
+pub fn hotspots(tree: &ProjectTree, expansions: &HashMap<SourceRange, Expansion>) -> Vec<Hotspot> {
+  let (mut out, root) = (Vec::new(), i!(str: "tree"));
+  if let Some(ModMember::Sub(module)) = tree.0.entries.get(&root).map(|ent| &ent.member) {
+    collect(module, expansions, &mut vec![root], &mut out);
+  }
+  out
+}
+
+fn collect(
+  module: &ProjectMod,
+  expansions: &HashMap<SourceRange, Expansion>,
+  path: &mut Vec<Tok<String>>,
+  out: &mut Vec<Hotspot>,
+) {
+  for (key, ent) in module.entries.iter() {
+    match &ent.member {
+      ModMember::Sub(sub) => {
+        path.push(key.clone());
+        collect(sub, expansions, path, out);
+        path.pop();
+      },
+      ModMember::Item(ProjItem { kind: ItemKind::Const(val) }) => {
+        let Some(expansion) = expansions.get(&val.range) else { continue };
+        out.push(Hotspot {
+          name: path.iter().chain([key]).cloned().collect(),
+          range: val.range.clone(),
+          gas: expansion.gas,
+        })
+      },
+      _ => (),
+    }
+  }
+}
+
+/// The rules that can fire on the code of a constant as written, because all
+/// of their keywords occur in it. Rules that only match the output of other
+/// rules aren't found.
+pub fn suspects<'a>(rules: &'a [SrcRule], expr: &parsed::Expr) -> Vec<&'a SrcRule> {
+  let names = names([expr]);
+  (rules.iter())
+    .filter(|r| {
+      let keywords = r.keywords();
+      !keywords.is_empty() && keywords.iter().all(|k| names.contains(k))
+    })
+    .collect()
+}
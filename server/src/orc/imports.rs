@@ -0,0 +1,192 @@
+//! Find the names a piece of code uses that the file doesn't declare or
+//! import, and the imports that would provide them. This serves code pasted
+//! from another file, so it works on the text rather than the parsed tree.
+//! The same reading of imports locates the re-exports navigation passes
+//! through.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Range;
+
+use itertools::Itertools;
+
+use super::lexical::{lex, name_char, LexKind};
+use super::members::{is_exported, members, Edit, MemberKind};
+
+/// The names an import line brings into scope, and the modules it imports
+/// everything from
+pub fn imported_names(path: &str) -> (Vec<&str>, Vec<&str>) {
+  let (mut names, mut globs) = (Vec::new(), Vec::new());
+  let (module, last) = path.rsplit_once("::").unwrap_or(("", path));
+  match last.trim() {
+    "*" => globs.push(module),
+    _ if last.starts_with('(') => {
+      let items = last.trim_matches(|c| c == '(' || c == ')').split(',').map(str::trim);
+      for item in items.filter(|i| !i.is_empty()) {
+        if item == "*" { globs.push(module) } else { names.push(item) }
+      }
+    },
+    name => names.push(name),
+  }
+  (names, globs)
+}
+
+/// Where a file passes on a name it doesn't declare: the entry of an export
+/// list naming it, or else the name in the import that brings it into scope.
+/// Names only imported through a glob aren't found.
+pub fn alias_site(text: &str, name: &str) -> Option<Range<usize>> {
+  let members = members(text);
+  let listed = members.iter().flat_map(|m| m.exported_names(text)).find(|(n, _)| *n == name);
+  if let Some((_, range)) = listed {
+    return Some(range);
+  }
+  let import = (members.iter().filter(|m| m.kind == MemberKind::Import))
+    .find(|m| imported_names(&m.name).0.contains(&name))?;
+  // The imported names follow the module, so the last occurrence is the name
+  let (at, _) = (import.name.rmatch_indices(name)).find(|(at, _)| {
+    let end = at + name.len();
+    !import.name[..*at].ends_with(name_char) && !import.name[end..].starts_with(name_char)
+  })?;
+  let start = import.name_range.start + at;
+  Some(start..start + name.len())
+}
+
+/// The unqualified names in the code in order of first occurrence. Names that
+/// are part of a path, bound by a lambda or placeholders aren't included.
+pub fn free_names(code: &str) -> Vec<&str> {
+  let (mut names, mut bound) = (Vec::new(), HashSet::new());
+  let lexemes = lex(code);
+  let kind_at = |i: Option<usize>| i.and_then(|i| lexemes.get(i)).map(|(kind, _)| *kind);
+  for (idx, (kind, range)) in lexemes.iter().enumerate() {
+    if *kind != LexKind::Name {
+      continue;
+    }
+    let (before, after) = (kind_at(idx.checked_sub(1)), kind_at(Some(idx + 1)));
+    if before == Some(LexKind::Lambda) {
+      bound.insert(&code[range.clone()]);
+    } else if ![before, after].contains(&Some(LexKind::Namespace)) {
+      names.push(&code[range.clone()]);
+    }
+  }
+  names.into_iter().filter(|n| !bound.contains(n)).unique().collect()
+}
+
+/// Exported names mapped to the modules that export them
+pub type ExportIndex = HashMap<String, Vec<String>>;
+
+/// Index the constants and modules exported by files, given the module path
+/// and the text of each file
+pub fn export_index<'a>(files: impl IntoIterator<Item = (String, &'a str)>) -> ExportIndex {
+  let mut index = ExportIndex::new();
+  for (module, text) in files {
+    let members = members(text);
+    let exports = (members.iter())
+      .filter(|m| matches!(m.kind, MemberKind::Const | MemberKind::Module))
+      .filter(|m| is_exported(&members, text, &m.name));
+    for member in exports {
+      index.entry(member.name.clone()).or_default().push(module.clone());
+    }
+  }
+  index
+}
+
+/// The edit that imports the names a range of the text uses but the file
+/// doesn't declare or import. Names exported by more than one module of the
+/// index are left alone.
+pub fn missing_imports(
+  text: &str,
+  range: Range<usize>,
+  index: &ExportIndex,
+) -> Option<(Vec<String>, Edit)> {
+  let members = members(text);
+  let mut declared = HashSet::new();
+  let mut globs = HashSet::new();
+  for member in members.iter() {
+    match member.kind {
+      MemberKind::Import => {
+        let (names, glob) = imported_names(&member.name);
+        declared.extend(names);
+        globs.extend(glob);
+      },
+      _ => drop(declared.insert(member.name.as_str())),
+    }
+  }
+  let mut imports = BTreeMap::<&str, Vec<&str>>::new();
+  for name in free_names(text.get(range)?) {
+    if declared.contains(name) {
+      continue;
+    }
+    match index.get(name).map(|modules| &modules[..]) {
+      Some([module]) if !globs.contains(module.as_str()) =>
+        imports.entry(module).or_default().push(name),
+      _ => (),
+    }
+  }
+  if imports.is_empty() {
+    return None;
+  }
+  let names = imports.values().flatten().map(|n| n.to_string()).collect_vec();
+  let lines = (imports.into_iter())
+    .map(|(module, names)| match &names[..] {
+      [name] => format!("import {module}::{name}\n"),
+      _ => format!("import {module}::({})\n", names.iter().sorted().join(", ")),
+    })
+    .join("");
+  // After the last import, or above everything else
+  let at = match members.iter().rfind(|m| m.kind == MemberKind::Import) {
+    Some(import) => import.range.end + usize::from(text[import.range.end..].starts_with('\n')),
+    None => members.first().map_or(0, |m| m.range.start),
+  };
+  Some((names, (at..at, lines)))
+}
+
+#[cfg(test)]
+mod test {
+  use std::collections::HashMap;
+
+  use super::{alias_site, export_index, free_names, imported_names, missing_imports};
+
+  #[test]
+  fn names() {
+    assert_eq!(imported_names("std::(list, option)"), (vec!["list", "option"], vec![]));
+    assert_eq!(imported_names("foo::bar"), (vec!["bar"], vec![]));
+    assert_eq!(imported_names("foo::*"), (vec![], vec!["foo"]));
+    let code = "\\x. add x (std::list::new 1.5) -- comment\n$y ...$z \"str\" add";
+    assert_eq!(free_names(code), ["add"]);
+  }
+
+  #[test]
+  fn imports() {
+    let text = "import std::list\n\nconst local := 1\n\nconst main := map local fold (sum 1)\n";
+    let index = HashMap::from([
+      ("map".to_string(), vec!["tree::util".to_string()]),
+      ("fold".to_string(), vec!["tree::util".to_string()]),
+      ("sum".to_string(), vec!["tree::a".to_string(), "tree::b".to_string()]),
+      ("local".to_string(), vec!["tree::util".to_string()]),
+    ]);
+    let start = text.find("map").unwrap();
+    let (names, (range, lines)) = missing_imports(text, start..text.len(), &index).unwrap();
+    assert_eq!(names, ["map", "fold"]);
+    assert_eq!(lines, "import tree::util::(fold, map)\n");
+    assert_eq!(range, 17..17, "inserted after the existing import");
+  }
+
+  #[test]
+  fn exports() {
+    let util = "export const map := 1\nconst fold := 2\nexport ::(fold)\nconst hidden := 3\n";
+    let a = "export const map := 4";
+    let index = export_index([("tree::util".to_string(), util), ("tree::a".to_string(), a)]);
+    assert_eq!(index["map"], ["tree::util", "tree::a"]);
+    assert_eq!(index["fold"], ["tree::util"], "Export lists count");
+    assert!(!index.contains_key("hidden"));
+  }
+
+  #[test]
+  fn alias_sites() {
+    let text = "import a::x\nimport b::(x2, x)\nexport ::(y, x2)\nimport c::*\n\nconst x3 := x\n";
+    let site = |name| alias_site(text, name).map(|r| (&text[r.clone()], r.start));
+    assert_eq!(site("x"), Some(("x", 10)), "The first import naming it");
+    assert_eq!(site("x2"), Some(("x2", 43)), "Export lists take precedence");
+    assert_eq!(site("y"), Some(("y", 40)));
+    assert_eq!(site("z"), None, "Only imported through a glob");
+  }
+}
@@ -0,0 +1,91 @@
+//! Ghost text suggestions derived from the text around the cursor, without any
+//! knowledge of the project. Two patterns are recognized:
+//!
+//! - brackets opened on the current line are closed
+//! - a line that starts like the previous two lines, which also start alike,
+//!   is completed like the previous line
+
+/// The matching closer of an opening bracket
+fn closer(c: char) -> Option<char> {
+  match c {
+    '(' => Some(')'),
+    '[' => Some(']'),
+    '{' => Some('}'),
+    _ => None,
+  }
+}
+
+/// Brackets left open in a line of code in order of opening. Strings and line
+/// comments are skipped.
+fn open_brackets(line: &str) -> Vec<char> {
+  let (mut stack, mut chars) = (Vec::new(), line.chars().peekable());
+  while let Some(c) = chars.next() {
+    match c {
+      '"' => while let Some(c) = chars.next() {
+        match c {
+          '\\' => drop(chars.next()),
+          '"' => break,
+          _ => (),
+        }
+      },
+      '-' if chars.peek() == Some(&'-') => break,
+      ')' | ']' | '}' if stack.last().and_then(|o| closer(*o)) == Some(c) => drop(stack.pop()),
+      c if closer(c).is_some() => stack.push(c),
+      _ => (),
+    }
+  }
+  stack
+}
+
+/// The first word of a line, which typically determines its structure
+fn head(line: &str) -> Option<&str> { line.split_whitespace().next() }
+
+/// Indentation of a line
+fn indent(line: &str) -> &str { &line[..line.len() - line.trim_start().len()] }
+
+/// Suggest text to insert at a byte offset. Only the end of a line is eligible.
+pub fn suggest(text: &str, pos: usize) -> Vec<String> {
+  let line_start = text[..pos].rfind(['\n', '\r']).map_or(0, |i| i + 1);
+  let rest_of_line = text[pos..].split(['\n', '\r']).next().unwrap_or("");
+  if !rest_of_line.trim().is_empty() {
+    return Vec::new();
+  }
+  let typed = &text[line_start..pos];
+  let mut suggestions = Vec::new();
+  let mut prev = text[..line_start].lines().rev().filter(|l| !l.trim().is_empty());
+  if let (Some(p1), Some(p2)) = (prev.next(), prev.next()) {
+    let repeated = head(p1).is_some_and(|h| head(p2) == Some(h)) && indent(p1) == indent(p2);
+    if repeated && p1.len() > typed.len() && p1.starts_with(typed) {
+      suggestions.push(p1[typed.len()..].to_string());
+    }
+  }
+  let closers = open_brackets(typed).iter().rev().filter_map(|c| closer(*c)).collect::<String>();
+  if !closers.is_empty() {
+    suggestions.push(closers);
+  }
+  suggestions
+}
+
+#[cfg(test)]
+mod test {
+  use super::suggest;
+
+  fn at_end(text: &str) -> Vec<String> { suggest(text, text.len()) }
+
+  #[test]
+  fn closing() {
+    assert_eq!(at_end("const f := (a [b \"(\""), ["])"], "Strings are skipped");
+    assert_eq!(at_end("const f := (a) -- ("), Vec::<String>::new(), "Comments are skipped");
+    assert_eq!(suggest("(a b\n", 2), Vec::<String>::new(), "Only at the end of a line");
+  }
+
+  #[test]
+  fn repetition() {
+    let text = "macro (\n  rule a =0x1p5=> b\n  rule c =0x1p5=> d\n  ";
+    assert_eq!(at_end(text), ["rule c =0x1p5=> d"]);
+    assert_eq!(at_end(&format!("{text}rule ")), ["c =0x1p5=> d"]);
+    assert_eq!(at_end(&format!("{text}x")), Vec::<String>::new(), "Typed text must match");
+    let text = "const a := 1\nexport b := 2\n";
+    assert_eq!(at_end(text), Vec::<String>::new(), "Previous lines must start alike");
+  }
+}
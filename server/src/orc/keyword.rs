@@ -0,0 +1,74 @@
+//! Rename a macro keyword or operator across a project. Keywords aren't
+//! declared anywhere, so every whole-token occurrence in the source is a
+//! candidate, except those the semantic tokens identify as ordinary names.
+
+use std::iter;
+use std::ops::Range;
+
+use hashbrown::HashSet;
+use intern_all::i;
+use orchidlang::name::VPath;
+
+use super::lexical::{is_identifier, is_operator, lex, LexKind};
+use super::project::LoadedProject;
+
+/// Whether the word lexes as a single name or operator
+pub fn is_token(word: &str) -> bool { is_identifier(word) || is_operator(word) }
+
+/// Byte ranges of the whole-token occurrences of a word outside of strings and
+/// comments
+pub fn occurrences(text: &str, word: &str) -> Vec<Range<usize>> {
+  (lex(text).into_iter())
+    .filter(|(kind, range)| {
+      matches!(kind, LexKind::Name | LexKind::Operator) && text[range.clone()] == *word
+    })
+    .map(|(_, range)| range)
+    .collect()
+}
+
+/// The result of a keyword rename. Paths are relative to the project root.
+pub struct KeywordRename {
+  /// Ranges to replace with the new keyword in each file
+  pub edits: Vec<(VPath, Vec<Range<usize>>)>,
+  /// Existing occurrences of the new keyword
+  pub conflicts: Vec<(VPath, Range<usize>)>,
+}
+
+/// Find everything that has to change to rename a keyword, or [None] if the
+/// keyword doesn't occur in the project
+pub fn rename_keyword(lpr: &LoadedProject, from: &str, to: &str) -> Option<KeywordRename> {
+  let names = (lpr.tokens().into_iter())
+    .filter(|tok| !["keyword", "operator"].contains(&tok.typ().as_str()))
+    .map(|tok| (tok.code().path().to_vec(), tok.start()))
+    .collect::<HashSet<_>>();
+  let (mut edits, mut conflicts) = (Vec::new(), Vec::new());
+  for file in lpr.source_files() {
+    let Some(text) = lpr.source(&file) else { continue };
+    let module = iter::once(i!(str: "tree")).chain(file.iter().cloned()).collect::<Vec<_>>();
+    let ranges = (occurrences(&text, from).into_iter())
+      .filter(|r| !names.contains(&(module.clone(), r.start)))
+      .collect::<Vec<_>>();
+    conflicts.extend(occurrences(&text, to).into_iter().map(|r| (file.clone(), r)));
+    if !ranges.is_empty() {
+      edits.push((file, ranges));
+    }
+  }
+  (!edits.is_empty()).then_some(KeywordRename { edits, conflicts })
+}
+
+#[cfg(test)]
+mod test {
+  use super::{is_token, occurrences};
+
+  #[test]
+  fn tokens() {
+    let text = "macro (\n  rule match ...$x =0x1p5=> matcher ...$x\n)\n\
+      const a := match \"match\" -- match\nconst b := std::match";
+    let found = occurrences(text, "match");
+    assert_eq!(found.len(), 3, "Strings, comments and longer names don't count");
+    assert!(found.iter().all(|r| &text[r.clone()] == "match"));
+    assert!(occurrences(text, "=>").is_empty(), "The rule arrow is not an operator");
+    assert_eq!(occurrences("a |> b|>c >|> d", "|>").len(), 2);
+    assert!(is_token("|>") && is_token("match") && !is_token("a b") && !is_token("a+"));
+  }
+}
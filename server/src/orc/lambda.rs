@@ -0,0 +1,160 @@
+//! Rewrites between equivalent lambda spellings. The parsed tree decides which
+//! rewrites apply, and the replacement is assembled from slices of the
+//! original source so the subexpressions keep their exact spelling and remain
+//! valid regardless of how names were resolved.
+//!
+//! Orchid lambdas only ever take a single parameter, so the flattened
+//! multi-parameter form doesn't exist and the only pair of spellings is the
+//! point-free and the explicit parameter form.
+//!
+//! The rewrites happen before macros run, so they're only offered where no
+//! macro can tell the spellings apart: the moved expressions may not contain
+//! operators or the keywords of any rule outside of brackets.
+
+use std::ops::Range;
+
+use hashbrown::HashSet;
+use intern_all::i;
+use orchidlang::name::{PathSlice, Sym};
+use orchidlang::parse::parsed;
+
+use super::printer::{read, Node};
+use super::project::LoadedProject;
+use super::rules::names;
+
+/// A replacement for a range of the source text
+#[derive(Clone, Debug)]
+pub struct Rewrite {
+  pub title: String,
+  pub range: Range<usize>,
+  pub text: String,
+}
+
+fn mentions(exprs: &[parsed::Expr], name: &Sym) -> bool {
+  exprs.iter().any(|ex| {
+    (ex.search_all(&mut |ex| match &ex.value {
+      parsed::Clause::Name(n) if n == name => Some(()),
+      _ => None,
+    }))
+    .is_some()
+  })
+}
+
+/// The last segments of the names that macro rules consume. Names are compared
+/// by their last segment as spelled in the source, which refuses some safe
+/// rewrites but never offers an unsafe one.
+fn keywords(lpr: &LoadedProject) -> HashSet<String> {
+  let rules = lpr.tree.all_rules();
+  names(rules.iter().flat_map(|r| r.pattern.iter())).iter().map(|n| n.last().to_string()).collect()
+}
+
+/// Whether a sequence of expressions spelled in the source can be moved in or
+/// out of a lambda without changing what macros match. Bracketed groups are
+/// safe as a whole, as macros can't match across their boundary.
+fn is_inert(text: &str, keywords: &HashSet<String>) -> bool {
+  let Some(nodes) = read(text) else { return false };
+  !nodes.is_empty()
+    && nodes.iter().all(|node| match node {
+      Node::Token(tok) => !keywords.contains(tok.rsplit("::").next().unwrap_or(tok)),
+      Node::Group(..) => true,
+      Node::Operator(_) | Node::Lambda(..) => false,
+    })
+}
+
+/// `\x. f a x` becomes `(f a)` if `x` doesn't occur in `f a`
+fn eta_reduce(lambda: &parsed::Expr, keywords: &HashSet<String>) -> Option<Rewrite> {
+  let parsed::Clause::Lambda(arg, body) = &lambda.value else { return None };
+  let [parsed::Expr { value: parsed::Clause::Name(param), .. }] = &arg[..] else { return None };
+  let (last, callee) = body.split_last()?;
+  let is_param = matches!(&last.value, parsed::Clause::Name(n) if n == param);
+  if !is_param || callee.is_empty() || mentions(callee, param) {
+    return None;
+  }
+  let source = lambda.range.text();
+  let inner = &source[callee[0].range.start()..callee[callee.len() - 1].range.end()];
+  if !is_inert(inner, keywords) {
+    return None;
+  }
+  let text = if callee.len() == 1 { inner.to_string() } else { format!("({inner})") };
+  let title = format!("Remove parameter `{}`", param.last());
+  Some(Rewrite { title, range: lambda.range.range(), text })
+}
+
+/// A parameter name that doesn't clash with any name in the constant
+fn fresh_name(scope: &parsed::Expr) -> String {
+  let mut taken = HashSet::new();
+  scope.search_all(&mut |ex| {
+    if let parsed::Clause::Name(n) = &ex.value {
+      taken.insert(n.last().to_string());
+    }
+    None::<()>
+  });
+  let mut candidates = (0..).map(|n| if n == 0 { "x".to_string() } else { format!("x{n}") });
+  candidates.find(|n| !taken.contains(n)).expect("The set of names is finite")
+}
+
+/// `f` becomes `(\x. f x)` if `f` isn't a keyword
+fn eta_expand(
+  name: &parsed::Expr,
+  scope: &parsed::Expr,
+  keywords: &HashSet<String>,
+) -> Option<Rewrite> {
+  let source = name.range.text();
+  let callee = &source[name.range.range()];
+  if !is_inert(callee, keywords) {
+    return None;
+  }
+  let param = fresh_name(scope);
+  let text = format!("(\\{param}. {callee} {param})");
+  let title = format!("Add explicit parameter to `{callee}`");
+  Some(Rewrite { title, range: name.range.range(), text })
+}
+
+/// Lambda rewrites applicable at a byte offset in a file. The path is relative
+/// to the project root.
+pub fn rewrites(lpr: &LoadedProject, path: &PathSlice, offset: usize) -> Vec<Rewrite> {
+  let module = path.to_vpath().prefix([i!(str: "tree")]);
+  let contains = |ex: &parsed::Expr| {
+    let in_file = ex.range.path().iter().eq(module.iter());
+    in_file && ex.range.start() <= offset && offset <= ex.range.end()
+  };
+  let Some(consts) = lpr.module_consts(&module) else { return Vec::new() };
+  let Some(scope) = consts.into_iter().find(|c| contains(c)) else { return Vec::new() };
+  let (mut lambda, mut name, mut params) = (None, None, HashSet::new());
+  scope.search_all(&mut |ex| {
+    match &ex.value {
+      // the search visits the outer expression first, so the last match is the innermost
+      parsed::Clause::Lambda(arg, _) if contains(ex) => {
+        params.extend(arg.iter().map(|p| p.range.clone()));
+        lambda = Some(ex.clone())
+      },
+      parsed::Clause::Name(_) if contains(ex) && !params.contains(&ex.range) =>
+        name = Some(ex.clone()),
+      _ => (),
+    }
+    None::<()>
+  });
+  let keywords = keywords(lpr);
+  let reduced = lambda.and_then(|lambda| eta_reduce(&lambda, &keywords));
+  let expanded = name.and_then(|name| eta_expand(&name, scope, &keywords));
+  reduced.into_iter().chain(expanded).collect()
+}
+
+#[cfg(test)]
+mod test {
+  use hashbrown::HashSet;
+
+  use super::is_inert;
+
+  #[test]
+  fn inert() {
+    let keywords = HashSet::from(["if".to_string(), "then".to_string()]);
+    assert!(is_inert("f a \"s\" 1", &keywords));
+    assert!(is_inert("f (a + b) [if c]", &keywords), "Brackets contain macros");
+    assert!(!is_inert("a +", &keywords), "Operators belong to rules");
+    assert!(!is_inert("if c then", &keywords));
+    assert!(!is_inert("std::bool::if c", &keywords), "Names are compared by their last segment");
+    assert!(!is_inert("f -- comment", &keywords));
+    assert!(!is_inert("", &keywords));
+  }
+}
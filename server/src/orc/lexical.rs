@@ -0,0 +1,249 @@
+//! Lexical classes of Orchid source text. Everything in the server that tells
+//! names from operators uses these so the classification is consistent and
+//! follows the Unicode-aware rules of the Orchid lexer rather than ASCII.
+
+use std::ops::Range;
+
+/// Whether a character can start a name, straight from the Orchid lexer
+pub use orchidlang::parse::lexer::namestart as name_start;
+
+/// Whether a character can continue a name
+pub fn name_char(c: char) -> bool { c.is_alphanumeric() || c == '_' }
+
+/// Whether a character separates tokens without being part of any
+pub fn separator(c: char) -> bool { c.is_whitespace() || "()[]{}\"".contains(c) }
+
+/// Whether a name segment is an identifier as opposed to an operator
+pub fn is_identifier(segment: &str) -> bool {
+  segment.starts_with(name_start) && segment.chars().all(name_char)
+}
+
+/// Whether a name segment is an operator, a run of characters that are neither
+/// name characters nor separators
+pub fn is_operator(segment: &str) -> bool {
+  !segment.is_empty() && segment.chars().all(|c| !name_char(c) && !separator(c))
+}
+
+/// Length of the string literal at the start of the text
+pub fn string_len(text: &str) -> usize {
+  let mut chars = text.char_indices().skip(1);
+  while let Some((i, c)) = chars.next() {
+    match c {
+      '\\' => drop(chars.next()),
+      '"' => return i + 1,
+      _ => (),
+    }
+  }
+  text.len()
+}
+
+/// Length of the run of digits at the start of the text, allowing underscores
+/// between digits
+fn digits_len(text: &str, is_digit: impl Fn(char) -> bool) -> usize {
+  let mut len = 0;
+  for (i, c) in text.char_indices() {
+    match c {
+      c if is_digit(c) => len = i + 1,
+      '_' if 0 < len && len == i => (),
+      _ => break,
+    }
+  }
+  len
+}
+
+/// Length of the number literal at the start of the text. Literals may have a
+/// `0x`, `0o` or `0b` base prefix, underscores between digits, a fraction, and
+/// an exponent introduced by `p` or in decimal also `e`.
+pub fn number_len(text: &str) -> Option<usize> {
+  let (prefix, radix) = match text.get(..2) {
+    Some("0x") => (2, 16),
+    Some("0o") => (2, 8),
+    Some("0b") => (2, 2),
+    _ => (0, 10),
+  };
+  let is_digit = |c: char| c.is_digit(radix);
+  let int = digits_len(&text[prefix..], is_digit);
+  if int == 0 {
+    return None;
+  }
+  let mut len = prefix + int;
+  if let Some(frac) = text[len..].strip_prefix('.') {
+    let frac = digits_len(frac, is_digit);
+    if 0 < frac {
+      len += 1 + frac;
+    }
+  }
+  let exp = text[len..].strip_prefix('p').or(text[len..].strip_prefix('e').filter(|_| radix == 10));
+  if let Some(exp) = exp {
+    let sign = usize::from(exp.starts_with(['-', '+']));
+    let digits = digits_len(&exp[sign..], |c| c.is_ascii_digit());
+    if 0 < digits {
+      len += 1 + sign + digits;
+    }
+  }
+  (!text[len..].starts_with(name_char)).then_some(len)
+}
+
+/// The class of a lexeme
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LexKind {
+  Comment,
+  String,
+  Number,
+  Name,
+  Operator,
+  /// `$name`, `..$name` or `...$name` in a macro
+  Placeholder,
+  Open,
+  Close,
+  /// `::`
+  Namespace,
+  /// `:=`
+  Walrus,
+  /// `=>` or `=prio=>`
+  Arrow,
+  /// `\`
+  Lambda,
+  /// `.`
+  Dot,
+}
+impl LexKind {
+  pub fn name(self) -> &'static str {
+    match self {
+      Self::Comment => "comment",
+      Self::String => "string",
+      Self::Number => "number",
+      Self::Name => "name",
+      Self::Operator => "operator",
+      Self::Placeholder => "placeholder",
+      Self::Open => "open",
+      Self::Close => "close",
+      Self::Namespace => "namespace",
+      Self::Walrus => "walrus",
+      Self::Arrow => "arrow",
+      Self::Lambda => "lambda",
+      Self::Dot => "dot",
+    }
+  }
+}
+
+/// Split a file into lexemes. Whitespace is skipped, and nothing is dropped
+/// for being malformed so that partially typed text still lexes.
+pub fn lex(text: &str) -> Vec<(LexKind, Range<usize>)> {
+  let (mut lexemes, mut pos) = (Vec::new(), 0);
+  while let Some(c) = text[pos..].chars().next() {
+    let rest = &text[pos..];
+    let name_len = |s: &str| s.find(|c| !name_char(c)).unwrap_or(s.len());
+    let (kind, len) = if c.is_whitespace() {
+      pos += c.len_utf8();
+      continue;
+    } else if rest.starts_with("--[") {
+      (LexKind::Comment, rest.find("]--").map_or(rest.len(), |i| i + 3))
+    } else if rest.starts_with("--") {
+      (LexKind::Comment, rest.find('\n').unwrap_or(rest.len()))
+    } else if c == '"' {
+      (LexKind::String, string_len(rest))
+    } else if let Some(len) = number_len(rest) {
+      (LexKind::Number, len)
+    } else if name_start(c) {
+      (LexKind::Name, name_len(rest))
+    } else if "([{".contains(c) {
+      (LexKind::Open, 1)
+    } else if ")]}".contains(c) {
+      (LexKind::Close, 1)
+    } else if let Some(len) = arrow_len(rest) {
+      (LexKind::Arrow, len)
+    } else {
+      let run = rest.find(|c| name_char(c) || separator(c)).unwrap_or(rest.len());
+      match &rest[..run] {
+        "$" | "..$" | "...$" if rest[run..].starts_with(name_start) =>
+          (LexKind::Placeholder, run + name_len(&rest[run..])),
+        "::" => (LexKind::Namespace, 2),
+        ":=" => (LexKind::Walrus, 2),
+        "\\" => (LexKind::Lambda, 1),
+        "." => (LexKind::Dot, 1),
+        _ if run == 0 => (LexKind::Operator, c.len_utf8()),
+        _ => (LexKind::Operator, run),
+      }
+    };
+    lexemes.push((kind, pos..pos + len));
+    pos += len;
+  }
+  lexemes
+}
+
+/// Length of the `=>` or `=prio=>` arrow of a rule at the start of the text
+fn arrow_len(text: &str) -> Option<usize> {
+  if text.starts_with("=>") {
+    return Some(2);
+  }
+  let prio = number_len(text.strip_prefix('=')?)?;
+  text[1 + prio..].starts_with("=>").then_some(prio + 3)
+}
+
+#[cfg(test)]
+mod test {
+  use super::{is_identifier, is_operator, lex, number_len, LexKind};
+
+  #[test]
+  fn unicode() {
+    for name in ["foo", "_bar2", "λ", "ñandú", "名前", "Ωmega_1"] {
+      assert!(is_identifier(name) && !is_operator(name), "{name} is an identifier");
+    }
+    for op in ["+", "|>", "→", "≤", "∘", "::"] {
+      assert!(is_operator(op) && !is_identifier(op), "{op} is an operator");
+    }
+    for neither in ["", "2x", "a+", "a b", "(", "٣"] {
+      assert!(!is_identifier(neither), "{neither} is not an identifier");
+    }
+  }
+  #[test]
+  fn numbers() {
+    let cases = [
+      ("123", 3),
+      ("1_000_000", 9),
+      ("0xff_ff)", 7),
+      ("0b1010 rest", 6),
+      ("0o17", 4),
+      ("1.5e3", 5),
+      ("2.5e-3", 6),
+      ("0x1p5=>", 5),
+      ("0x1e", 4),
+      ("1.", 1),
+      ("3.foo", 1),
+    ];
+    for (text, len) in cases {
+      assert_eq!(number_len(text), Some(len), "{text}");
+    }
+    for text in ["_1", "x1", "0xg", "12ab", "1__0"] {
+      assert_eq!(number_len(text), None, "{text}");
+    }
+  }
+  #[test]
+  fn lexemes() {
+    let text = "const f := \\x. std::add x 1.5 -- sum\nmacro ...$a ++ $b =0x1p3=> (\"s\")";
+    let lexemes = lex(text).into_iter().map(|(k, r)| (k, &text[r])).collect::<Vec<_>>();
+    assert_eq!(lexemes, [
+      (LexKind::Name, "const"),
+      (LexKind::Name, "f"),
+      (LexKind::Walrus, ":="),
+      (LexKind::Lambda, "\\"),
+      (LexKind::Name, "x"),
+      (LexKind::Dot, "."),
+      (LexKind::Name, "std"),
+      (LexKind::Namespace, "::"),
+      (LexKind::Name, "add"),
+      (LexKind::Name, "x"),
+      (LexKind::Number, "1.5"),
+      (LexKind::Comment, "-- sum"),
+      (LexKind::Name, "macro"),
+      (LexKind::Placeholder, "...$a"),
+      (LexKind::Operator, "++"),
+      (LexKind::Placeholder, "$b"),
+      (LexKind::Arrow, "=0x1p3=>"),
+      (LexKind::Open, "("),
+      (LexKind::String, "\"s\""),
+      (LexKind::Close, ")"),
+    ]);
+  }
+}
@@ -0,0 +1,430 @@
+//! Project-wide analyses that don't stop the project from loading but point at
+//! likely mistakes. The results are published as diagnostics.
+
+use std::iter;
+use std::ops::Range;
+
+use hashbrown::HashMap;
+use intern_all::{i, Tok};
+use itertools::Itertools;
+use orchidlang::name::{Sym, VPath};
+
+use super::encoding::Encoding;
+use super::members::{is_exported, members, MemberKind};
+use super::project::{LoadedProject, ProjectRoot};
+use super::project_info::{is_path, joining_folders, string_contents, FILE_NAME};
+use super::rules::{conflicts, file_of, generalizes, names, source_rules};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+  Error = 1,
+  Warning = 2,
+  Information = 3,
+  Hint = 4,
+}
+
+/// A finding in a file of the project. Paths are relative to the project root.
+#[derive(Clone, Debug)]
+pub struct Lint {
+  pub file: VPath,
+  pub range: Range<usize>,
+  pub severity: Severity,
+  /// Identifies the kind of lint
+  pub code: &'static str,
+  pub message: String,
+  /// Other locations involved in the finding
+  pub related: Vec<(VPath, Range<usize>, String)>,
+}
+
+/// Which lints run
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LintConfig {
+  /// Exports no other module uses. Libraries should turn this off, as their
+  /// exports are used by other projects.
+  pub unused_exports: bool,
+}
+impl Default for LintConfig {
+  fn default() -> Self { Self { unused_exports: true } }
+}
+
+/// The module path of a file as it appears in the project tree
+fn module_of(file: &VPath) -> Vec<Tok<String>> {
+  iter::once(i!(str: "tree")).chain(file.iter().cloned()).collect()
+}
+
+/// Exported constants that aren't referenced from any other module
+fn unused_exports(lpr: &LoadedProject) -> Vec<Lint> {
+  let refs = lpr.references();
+  let mut lints = Vec::new();
+  for file in lpr.source_files() {
+    let Some(text) = lpr.source(&file) else { continue };
+    let members = members(&text);
+    let module = module_of(&file);
+    // main is the entry point, used by the runtime rather than a module
+    let exports = (members.iter())
+      .filter(|m| m.kind == MemberKind::Const && m.name != "main")
+      .filter(|m| is_exported(&members, &text, &m.name));
+    for member in exports {
+      let name = module.iter().cloned().chain([i(&member.name)]).collect_vec();
+      let used = (refs.get(&name))
+        .is_some_and(|uses| uses.iter().any(|u| u.path()[..] != module[..]));
+      if !used {
+        lints.push(Lint {
+          file: file.clone(),
+          range: member.name_range.clone(),
+          severity: Severity::Hint,
+          code: "unused-export",
+          message: format!("`{}` is exported but no other module uses it", member.name),
+          related: Vec::new(),
+        });
+      }
+    }
+  }
+  lints
+}
+
+/// Rules that can never fire, either because one of their keywords is never
+/// used outside of the rule itself, or because a rule with a higher priority
+/// matches everything they match. Exported rules are left alone, as the code
+/// that uses them may be in other projects.
+fn dead_rules(lpr: &LoadedProject) -> Vec<Lint> {
+  let rules = source_rules(lpr);
+  let used = lpr.references();
+  // The number of rules whose template may produce each name
+  let mut produced = HashMap::<Sym, usize>::new();
+  for rule in lpr.tree.all_rules() {
+    names(&rule.template).into_iter().for_each(|n| *produced.entry(n).or_default() += 1);
+  }
+  let mut lints = Vec::new();
+  for (idx, rule) in rules.iter().enumerate().filter(|(_, r)| !r.exported) {
+    let own = names(&rule.rule.template);
+    let unused = rule.keywords().into_iter().find(|kw| {
+      let producers = produced.get(kw).copied().unwrap_or(0) - own.contains(kw) as usize;
+      !used.contains_key(&kw[..]) && producers == 0
+    });
+    if let Some(kw) = unused {
+      lints.push(Lint {
+        file: rule.file.clone(),
+        range: rule.pattern.clone(),
+        severity: Severity::Warning,
+        code: "dead-rule",
+        message: format!("This rule never fires because `{kw}` doesn't occur outside of it"),
+        related: Vec::new(),
+      });
+      continue;
+    }
+    let shadow = (rules.iter().enumerate())
+      .filter(|(i, r)| *i != idx && rule.rule.prio < r.rule.prio)
+      .find(|(_, r)| generalizes(&r.rule.pattern, &rule.rule.pattern));
+    if let Some((_, shadow)) = shadow {
+      lints.push(Lint {
+        file: rule.file.clone(),
+        range: rule.pattern.clone(),
+        severity: Severity::Warning,
+        code: "dead-rule",
+        message: format!(
+          "This rule never fires because a rule with priority {} matches everything it does",
+          shadow.rule.prio
+        ),
+        related: vec![(shadow.file.clone(), shadow.pattern.clone(), "Shadowing rule".to_string())],
+      });
+    }
+  }
+  lints
+}
+
+/// Rules that tie in priority with another rule matching the same code. Both
+/// rules are flagged, each pointing at the other.
+fn priority_conflicts(lpr: &LoadedProject) -> Vec<Lint> {
+  let rules = source_rules(lpr);
+  let mut lints = Vec::new();
+  for (a, b) in conflicts(&rules) {
+    for (this, other) in [(&rules[a], &rules[b]), (&rules[b], &rules[a])] {
+      lints.push(Lint {
+        file: this.file.clone(),
+        range: this.pattern.clone(),
+        severity: Severity::Warning,
+        code: "priority-conflict",
+        message: format!(
+          "This rule overlaps with another rule of the same priority {}, so the order in which \
+           they apply is unspecified",
+          this.rule.prio
+        ),
+        related: vec![(other.file.clone(), other.pattern.clone(), "Conflicting rule".to_string())],
+      })
+    }
+  }
+  lints
+}
+
+/// A reference to a private constant declared at the top level of another
+/// file of the project
+pub struct PrivateUse {
+  pub file: VPath,
+  pub range: Range<usize>,
+  pub name: Tok<String>,
+  pub decl_file: VPath,
+  /// Byte range of the declared name in [PrivateUse::decl_file]
+  pub decl: Range<usize>,
+}
+
+/// References to constants that other files of the project don't export. See
+/// [LoadedProject::private_uses] for the cached result.
+pub fn find_private_uses(lpr: &LoadedProject) -> Vec<PrivateUse> {
+  let mut found = Vec::new();
+  let mut files = HashMap::new();
+  for (name, uses) in lpr.references() {
+    let Some((last, module)) = name.split_last() else { continue };
+    let Some(decl_file) = file_of(module) else { continue };
+    let members = files.entry(decl_file.clone()).or_insert_with(|| {
+      let text = lpr.source(&decl_file)?;
+      Some((members(&text), text))
+    });
+    let Some((members, text)) = members.as_ref() else { continue };
+    let Some(decl) = (members.iter()).find(|m| m.kind == MemberKind::Const && m.name == **last)
+    else {
+      continue;
+    };
+    if is_exported(members, text, last) {
+      continue;
+    }
+    for range in uses {
+      let Some(file) = file_of(&range.path()).filter(|f| *f != decl_file) else { continue };
+      found.push(PrivateUse {
+        file,
+        range: range.range(),
+        name: last.clone(),
+        decl_file: decl_file.clone(),
+        decl: decl.name_range.clone(),
+      })
+    }
+  }
+  // References come from a hash map, but fixes are offered in this order
+  found.sort_by_cached_key(|u| (u.file.to_string(), u.range.start));
+  found
+}
+
+fn private_references(lpr: &LoadedProject) -> Vec<Lint> {
+  (lpr.private_uses().iter())
+    .map(|u| Lint {
+      file: u.file.clone(),
+      range: u.range.clone(),
+      severity: Severity::Warning,
+      code: "private-reference",
+      message: format!("`{}` isn't exported from {}", u.name, u.decl_file),
+      related: vec![(u.decl_file.clone(), u.decl.clone(), "Declared here".to_string())],
+    })
+    .collect()
+}
+
+/// Code of the finding that offers to create a project file
+pub const LOOSE_FILE: &str = "loose-file";
+
+/// A single file project next to other files, which are likely meant to be
+/// one project. Only reported if a project file could join them, which a
+/// project folder above the file prevents. `roots` are the projects of the
+/// workspace.
+fn loose_file(lpr: &LoadedProject, roots: &[ProjectRoot]) -> Vec<Lint> {
+  let ProjectRoot::File(file) = &lpr.files.root else { return Vec::new() };
+  let projects = roots.iter().map(|r| (&r.path()[..], r.is_file())).collect_vec();
+  if joining_folders(&file[..], &projects).is_empty() {
+    return Vec::new();
+  }
+  let others = match lpr.files.neighbours() {
+    0 => return Vec::new(),
+    1 => "another file".to_string(),
+    n => format!("{n} other files"),
+  };
+  vec![Lint {
+    file: VPath::new([]),
+    range: 0..0,
+    severity: Severity::Information,
+    code: LOOSE_FILE,
+    message: format!(
+      "This file is a project of its own, separate from {others} in its folder. A {FILE_NAME} \
+       makes a folder one project"
+    ),
+    related: Vec::new(),
+  }]
+}
+
+/// Paths in `project_info.orc` that don't exist
+fn missing_paths(lpr: &LoadedProject) -> Vec<Lint> {
+  let file = VPath::new([i!(str: "project_info")]);
+  let Some(text) = lpr.source(&file) else { return Vec::new() };
+  let dir = lpr.file_uri(&[]).to_path();
+  (string_contents(&text).into_iter())
+    .filter(|r| is_path(&text[r.clone()]) && !dir.join(&text[r.clone()]).exists())
+    .map(|range| Lint {
+      message: format!("`{}` doesn't exist in the project folder", &text[range.clone()]),
+      file: file.clone(),
+      range,
+      severity: Severity::Warning,
+      code: "missing-path",
+      related: Vec::new(),
+    })
+    .collect()
+}
+
+/// An import of a module that used to be a file of the project before it was
+/// moved
+pub struct StaleImport {
+  pub file: VPath,
+  /// Byte range of the old module path in the import
+  pub range: Range<usize>,
+  pub old: String,
+  pub new: String,
+}
+
+/// Imports of files that were moved, given the old and new paths of the files
+/// relative to the project root
+pub fn stale_imports(lpr: &LoadedProject, moved: &[(VPath, VPath)]) -> Vec<StaleImport> {
+  // A file created at the old path since makes the import valid again
+  let moved = (moved.iter())
+    .filter(|(old, _)| lpr.source(old).is_none())
+    .map(|(old, new)| (module_of(old).iter().join("::"), module_of(new).iter().join("::")))
+    .collect_vec();
+  let mut found = Vec::new();
+  for file in lpr.source_files() {
+    let Some(text) = lpr.source(&file) else { continue };
+    let members = members(&text);
+    for import in members.iter().filter(|m| m.kind == MemberKind::Import) {
+      let is_stale = |old: &str| import.name.strip_prefix(old).is_some_and(|r| r.starts_with("::"));
+      let Some((old, new)) = moved.iter().find(|(old, _)| is_stale(old)) else { continue };
+      let start = import.name_range.start;
+      found.push(StaleImport {
+        file: file.clone(),
+        range: start..start + old.len(),
+        old: old.clone(),
+        new: new.clone(),
+      })
+    }
+  }
+  found
+}
+
+fn stale_import_lints(lpr: &LoadedProject, moved: &[(VPath, VPath)]) -> Vec<Lint> {
+  (stale_imports(lpr, moved).into_iter())
+    .map(|s| Lint {
+      file: s.file,
+      range: s.range,
+      severity: Severity::Warning,
+      code: "stale-import",
+      message: format!("`{}` was moved to `{}`", s.old, s.new),
+      related: Vec::new(),
+    })
+    .collect()
+}
+
+/// Files on disk that aren't plain UTF-8. Invalid files were loaded with the
+/// invalid sequences replaced, the others were decoded but may trip up other
+/// tools.
+fn encodings(lpr: &LoadedProject) -> Vec<Lint> {
+  (lpr.source_files().into_iter())
+    .filter_map(|file| {
+      let (range, severity, code, message) = match lpr.files.encoding(&file)? {
+        Encoding::Utf8 => return None,
+        Encoding::Invalid(offset) => (
+          offset..offset + char::REPLACEMENT_CHARACTER.len_utf8(),
+          Severity::Warning,
+          "invalid-utf8",
+          "This file isn't valid UTF-8, invalid bytes were read as U+FFFD",
+        ),
+        Encoding::Utf8Bom => (
+          0..0,
+          Severity::Information,
+          "encoding",
+          "This file starts with a byte order mark, which other tools may not expect",
+        ),
+        Encoding::Utf16Le | Encoding::Utf16Be => (
+          0..0,
+          Severity::Information,
+          "encoding",
+          "This file is UTF-16, Orchid source is usually UTF-8",
+        ),
+      };
+      Some(Lint { file, range, severity, code, message: message.to_string(), related: Vec::new() })
+    })
+    .collect()
+}
+
+/// Run the enabled lints on a project. Moved files are those renamed outside of
+/// the editor, see [stale_imports]. `roots` are the projects of the workspace.
+pub fn lint(
+  lpr: &LoadedProject,
+  config: LintConfig,
+  moved: &[(VPath, VPath)],
+  roots: &[ProjectRoot],
+) -> Vec<Lint> {
+  let mut lints = Vec::new();
+  if config.unused_exports {
+    lints.extend(unused_exports(lpr));
+  }
+  lints.extend(missing_paths(lpr));
+  lints.extend(loose_file(lpr, roots));
+  lints.extend(private_references(lpr));
+  lints.extend(stale_import_lints(lpr, moved));
+  lints.extend(dead_rules(lpr));
+  lints.extend(priority_conflicts(lpr));
+  lints.extend(encodings(lpr));
+  lints
+}
+
+#[cfg(test)]
+mod test {
+  use std::ops::Range;
+
+  use itertools::Itertools;
+  use orchidlang::name::VPath;
+
+  use super::{lint, LintConfig};
+  use crate::testing::Fixture;
+
+  const RULES: &str = "\
+macro rule never_used =0x1p5=> 1
+macro rule self_ref =0x1p5=> (self_ref)
+macro rule start =0x1p5=> (step)
+macro rule step =0x1p5=> 1
+const go := start
+macro rule shadowed 1 =0x1p5=> 2
+macro rule shadowed $x =0x1p6=> 3
+const s := shadowed 1
+macro rule tie $x =0x1p5=> 1
+macro rule tie 2 =0x1p5=> 2
+const t := tie 2
+";
+
+  #[test]
+  fn findings() {
+    let fixture = Fixture::new(&[
+      ("project_info", "const src := \"./nowhere\"\n"),
+      ("lib", "export const used := 1\nexport const unused := 2\nconst hidden := 3\n"),
+      ("main", "import super::lib::used\nimport super::lib::hidden\nconst main := used hidden\n"),
+      ("rules", RULES),
+    ]);
+    let lpr = fixture.load();
+    let lints = lint(&lpr, LintConfig::default(), &[], &[]);
+    let spelled = |file: &VPath, range: Range<usize>| lpr.source(file).unwrap()[range].to_string();
+    let found = (lints.iter())
+      .map(|l| (l.file.to_string(), l.code, spelled(&l.file, l.range.clone())))
+      .sorted()
+      .collect_vec();
+    let expected = [
+      ("lib", "unused-export", "unused"),
+      ("main", "private-reference", "hidden"),
+      ("project_info", "missing-path", "./nowhere"),
+      ("rules", "dead-rule", "never_used"),
+      ("rules", "dead-rule", "self_ref"),
+      ("rules", "dead-rule", "shadowed 1"),
+      ("rules", "priority-conflict", "tie $x"),
+      ("rules", "priority-conflict", "tie 2"),
+    ];
+    let expected = expected.map(|(file, code, text)| (file.to_string(), code, text.to_string()));
+    assert_eq!(found, expected, "`step` is produced by `start` so it isn't dead");
+    let shadowed = lints.iter().find(|l| l.message.contains("priority 64")).unwrap();
+    let [(file, range, _)] = &shadowed.related[..] else { panic!("One shadowing rule") };
+    assert_eq!(spelled(file, range.clone()), "shadowed $x");
+    let unused = LintConfig { unused_exports: false };
+    assert!(lint(&lpr, unused, &[], &[]).iter().all(|l| l.code != "unused-export"));
+  }
+}
@@ -0,0 +1,274 @@
+//! Split a source file into its top-level members and sort them. This works
+//! on the text rather than the parsed tree so that members which fail to parse
+//! or are produced by macros are still moved intact, along with the comments
+//! directly above them.
+
+use std::cmp::Reverse;
+use std::ops::Range;
+
+use itertools::Itertools;
+
+use super::lexical::{lex, LexKind};
+
+/// A replacement for a range of the text
+pub type Edit = (Range<usize>, String);
+
+/// Which criteria take precedence when sorting members. Criteria that are
+/// turned off don't affect the order, and ties keep their original order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemberOrder {
+  /// Imports precede all other members
+  pub group_imports: bool,
+  /// Exported members precede private ones
+  pub exports_first: bool,
+  /// Members of the same category are sorted by name
+  pub alphabetical: bool,
+}
+impl Default for MemberOrder {
+  fn default() -> Self { Self { group_imports: true, exports_first: true, alphabetical: true } }
+}
+
+/// What a top-level member declares
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemberKind {
+  Import,
+  Const,
+  Module,
+  Macro,
+  /// `export ::(a, b)`
+  ExportList,
+  Other,
+}
+
+/// A top-level member and the comments directly above it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Member {
+  pub range: Range<usize>,
+  /// Start of the declaration after the comments
+  pub header: usize,
+  pub kind: MemberKind,
+  /// The `export` keyword and the whitespace after it, if present
+  pub export: Option<Range<usize>>,
+  /// The declared name, or the path for imports. Empty for macros and export
+  /// lists.
+  pub name: String,
+  pub name_range: Range<usize>,
+}
+impl Member {
+  fn new(range: Range<usize>, text: &str) -> Self {
+    let body = &text[range.clone()];
+    let decl = lex(body).into_iter().find(|(kind, _)| *kind != LexKind::Comment);
+    let header = range.start + decl.map_or(body.len(), |(_, r)| r.start);
+    let line = text[header..range.end].lines().next().unwrap_or("");
+    let export_len = match line.strip_prefix("export") {
+      Some(rest) if rest.starts_with(char::is_whitespace) || rest.starts_with("::") =>
+        line.len() - rest.trim_start().len(),
+      _ => 0,
+    };
+    let export = (0 < export_len).then_some(header..header + export_len);
+    let (decl, decl_start) = (&line[export_len..], header + export_len);
+    let keyword = decl.split_whitespace().next().unwrap_or("");
+    let kind = match keyword {
+      _ if decl.starts_with("::") => MemberKind::ExportList,
+      "import" => MemberKind::Import,
+      "const" => MemberKind::Const,
+      "module" => MemberKind::Module,
+      "macro" => MemberKind::Macro,
+      _ => MemberKind::Other,
+    };
+    let rest = &decl[keyword.len()..];
+    let name_start = decl_start + keyword.len() + rest.len() - rest.trim_start().len();
+    let name = match kind {
+      MemberKind::Import => rest.trim(),
+      MemberKind::Macro | MemberKind::ExportList => "",
+      _ => {
+        let is_end = |c: char| c.is_whitespace() || ":=(".contains(c);
+        rest.trim_start().split(is_end).next().unwrap_or("")
+      },
+    };
+    let name_range = name_start..name_start + name.len();
+    Self { range, header, kind, export, name: name.to_string(), name_range }
+  }
+
+  /// The names listed by an export list and their ranges
+  pub fn exported_names<'a>(&self, text: &'a str) -> Vec<(&'a str, Range<usize>)> {
+    if self.kind != MemberKind::ExportList {
+      return Vec::new();
+    }
+    let body = &text[self.range.clone()];
+    let (Some(open), Some(close)) = (body.find('('), body.rfind(')')) else { return Vec::new() };
+    let mut entries = Vec::new();
+    let mut start = self.range.start + open + 1;
+    for entry in body[open + 1..close].split(',') {
+      let lead = entry.len() - entry.trim_start().len();
+      let name = entry.trim();
+      if !name.is_empty() {
+        entries.push((name, start + lead..start + lead + name.len()));
+      }
+      start += entry.len() + 1;
+    }
+    entries
+  }
+}
+
+/// Whether a constant or module is exported either by the `export` keyword or
+/// an export list
+pub fn is_exported(members: &[Member], text: &str, name: &str) -> bool {
+  members.iter().any(|m| {
+    (m.export.is_some() && m.name == name)
+      || m.exported_names(text).iter().any(|(listed, _)| *listed == name)
+  })
+}
+
+/// The start offsets of lines that begin at the top level, outside of any
+/// bracket, string or block comment
+fn top_level_lines(text: &str) -> Vec<usize> {
+  let (mut starts, mut depth, mut pos) = (vec![0], 0usize, 0);
+  let lexemes = lex(text).into_iter().chain([(LexKind::Close, text.len()..text.len())]);
+  for (kind, range) in lexemes {
+    if depth == 0 {
+      starts.extend(text[pos..range.start].match_indices('\n').map(|(i, _)| pos + i + 1));
+    }
+    match kind {
+      LexKind::Open => depth += 1,
+      LexKind::Close => depth = depth.saturating_sub(1),
+      _ => (),
+    }
+    pos = range.end;
+  }
+  starts
+}
+
+/// Find the top-level members of a file
+pub fn members(text: &str) -> Vec<Member> {
+  let (mut starts, mut comment) = (Vec::new(), None);
+  for start in top_level_lines(text) {
+    let line = text[start..].lines().next().unwrap_or("");
+    if line.trim().is_empty() {
+      comment = None;
+    } else if line.starts_with("--") {
+      comment.get_or_insert(start);
+    } else if !line.starts_with(char::is_whitespace) {
+      starts.push(comment.take().unwrap_or(start));
+    }
+  }
+  let ends = starts.iter().skip(1).copied().chain([text.len()]);
+  (starts.iter().zip(ends))
+    .map(|(&start, end)| Member::new(start..start + text[start..end].trim_end().len(), text))
+    .collect()
+}
+
+/// The edits that sort the members of a file. Members that are already in
+/// place aren't touched, and the whitespace between members is preserved.
+pub fn sort_members(text: &str, order: MemberOrder) -> Vec<Edit> {
+  let members = members(text);
+  let sorted = (members.iter())
+    .sorted_by_key(|m| {
+      let import = order.group_imports && m.kind == MemberKind::Import;
+      let exported = order.exports_first && m.export.is_some();
+      let name = if order.alphabetical { m.name.as_str() } else { "" };
+      (Reverse(import), Reverse(exported), name)
+    })
+    .collect_vec();
+  (members.iter().zip(sorted))
+    .filter(|(slot, member)| slot.range != member.range)
+    .map(|(slot, member)| (slot.range.clone(), text[member.range.clone()].to_string()))
+    .collect()
+}
+
+/// The edits that export the constant at an offset if it's private or make it
+/// private if it's exported, along with a title for the change. Exports are
+/// removed from both the declaration and any export lists.
+pub fn toggle_export(text: &str, offset: usize) -> Option<(String, Vec<Edit>)> {
+  let members = members(text);
+  let member = (members.iter()).find(|m| {
+    m.kind == MemberKind::Const && m.header <= offset && offset <= m.range.end
+  })?;
+  let mut edits = Vec::new();
+  if let Some(export) = &member.export {
+    edits.push((export.clone(), String::new()));
+  }
+  for list in members.iter().filter(|m| m.kind == MemberKind::ExportList) {
+    let entries = list.exported_names(text);
+    let Some(i) = entries.iter().position(|(name, _)| *name == member.name) else { continue };
+    let range = match entries.len() {
+      // an empty list would be an error, remove it along with its line break
+      1 => list.range.start..list.range.end + usize::from(text[list.range.end..].starts_with('\n')),
+      _ if i + 1 < entries.len() => entries[i].1.start..entries[i + 1].1.start,
+      _ => entries[i - 1].1.end..entries[i].1.end,
+    };
+    edits.push((range, String::new()));
+  }
+  if edits.is_empty() {
+    let edits = vec![(member.header..member.header, "export ".to_string())];
+    return Some((format!("Export `{}`", member.name), edits));
+  }
+  Some((format!("Make `{}` private", member.name), edits))
+}
+
+#[cfg(test)]
+mod test {
+  use super::{
+    is_exported, members, sort_members, toggle_export, Edit, MemberKind, MemberOrder,
+  };
+
+  const TEXT: &str = "import std::conv\n\n-- the answer\nconst b := (\n  42\n)\n\
+    export const c := \"(\"\nimport std::list\nexport macro (\nrule x =0x1p5=> y\n)\n";
+
+  #[test]
+  fn split() {
+    let members = members(TEXT);
+    let names = members.iter().map(|m| m.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, ["std::conv", "b", "c", "std::list", ""]);
+    assert_eq!(&TEXT[members[1].range.clone()], "-- the answer\nconst b := (\n  42\n)");
+    assert!(members[2].export.is_some() && members[4].export.is_some());
+    assert_eq!(members[3].kind, MemberKind::Import);
+    assert_eq!(&TEXT[members[2].name_range.clone()], "c");
+  }
+
+  #[test]
+  fn block_comments() {
+    let text = "--[ notes\nconst a := 1\n]--\nconst b := --[ x ]-- 2\n\n--[ c\n]--\nconst c := 3\n";
+    let members = members(text);
+    let names = members.iter().map(|m| m.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, ["b", "c"], "Commented out lines aren't members");
+    assert_eq!(&text[members[1].range.clone()], "--[ c\n]--\nconst c := 3");
+    assert_eq!(&text[members[1].header..members[1].name_range.start], "const ");
+  }
+
+  #[test]
+  fn sort() {
+    let edits = sort_members(TEXT, MemberOrder::default());
+    let text = apply(TEXT, &edits);
+    let expected = "import std::conv\n\nimport std::list\nexport macro (\nrule x =0x1p5=> y\n)\n\
+      export const c := \"(\"\n-- the answer\nconst b := (\n  42\n)\n";
+    assert_eq!(text, expected);
+    assert_eq!(edits.len(), 4, "The first import stays in place");
+    let order = MemberOrder { group_imports: false, exports_first: false, alphabetical: false };
+    assert!(sort_members(TEXT, order).is_empty());
+  }
+  fn apply(text: &str, edits: &[Edit]) -> String {
+    let mut text = text.to_string();
+    for (range, new) in edits.iter().rev() {
+      text.replace_range(range.clone(), new);
+    }
+    text
+  }
+
+  #[test]
+  fn exports() {
+    let text = "export ::(a, b)\nexport ::(a)\nconst a := 1\nconst b := 2\nexport const c := 3\n";
+    let members = members(text);
+    assert!(["a", "b", "c"].iter().all(|n| is_exported(&members, text, n)));
+    let (title, edits) = toggle_export(text, text.find("const a").unwrap()).unwrap();
+    assert_eq!(title, "Make `a` private");
+    let text = apply(text, &edits);
+    assert_eq!(text, "export ::(b)\nconst a := 1\nconst b := 2\nexport const c := 3\n");
+    let (_, edits) = toggle_export(&text, text.find("const c").unwrap()).unwrap();
+    let text = apply(&text, &edits);
+    let (title, edits) = toggle_export(&text, text.find("const a").unwrap()).unwrap();
+    assert_eq!(title, "Export `a`");
+    let text = apply(&text, &edits);
+    assert_eq!(text, "export ::(b)\nexport const a := 1\nconst b := 2\nconst c := 3\n");
+  }
+}
@@ -1 +1,24 @@
+pub mod ast;
+pub mod atoms;
+pub mod brackets;
+pub mod colors;
+pub mod comments;
+pub mod encoding;
+pub mod errors;
+pub mod folding;
+pub mod gas;
+pub mod imports;
+pub mod inline;
+pub mod keyword;
+pub mod lambda;
+pub mod lexical;
+pub mod lint;
+pub mod members;
+pub mod printer;
 pub mod project;
+pub mod project_info;
+pub mod rules;
+pub mod scopes;
+pub mod stats;
+pub mod stubs;
+pub mod suppress;
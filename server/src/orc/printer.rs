@@ -0,0 +1,466 @@
+//! The canonical printed form of expressions. Source text and parsed trees are
+//! both read into the same nodes, which are printed on one line if they fit
+//! in the configured width, and otherwise broken at brackets and lambda bodies
+//! with continuation lines indented by two spaces. Hovers, the macro expansion
+//! preview, extracted constants and the formatter all print through here so
+//! that an expression looks the same wherever the server shows or writes it.
+
+use std::ops::Range;
+
+use itertools::Itertools;
+use orchidlang::parse::parsed;
+
+use super::lexical::{is_operator, lex, name_char, separator, LexKind};
+use super::members::{members, Edit, MemberKind};
+
+/// Indentation added by each level of continuation
+const INDENT: usize = 2;
+
+/// How operators are separated from their operands
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OperatorSpacing {
+  /// `a + b`
+  Spaced,
+  /// `a+b`, except where the operator would merge with an operand
+  Tight,
+}
+impl OperatorSpacing {
+  pub fn parse(name: &str) -> Option<Self> {
+    match name {
+      "spaced" => Some(Self::Spaced),
+      "tight" => Some(Self::Tight),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PrintConfig {
+  /// Lines are broken to fit in this many characters where brackets and
+  /// lambdas allow
+  pub width: usize,
+  pub operator_spacing: OperatorSpacing,
+}
+impl Default for PrintConfig {
+  fn default() -> Self { Self { width: 100, operator_spacing: OperatorSpacing::Spaced } }
+}
+
+/// An expression as far as its layout is concerned
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Node {
+  /// A name, literal or placeholder, printed as spelled
+  Token(String),
+  /// An operator, or a name whose last segment is one
+  Operator(String),
+  /// Brackets and their contents, by the opening bracket
+  Group(char, Vec<Node>),
+  /// The parameter and body of a lambda. The body extends to the end of the
+  /// enclosing brackets.
+  Lambda(Vec<Node>, Vec<Node>),
+}
+
+fn closer(open: char) -> char {
+  match open {
+    '[' => ']',
+    '{' => '}',
+    _ => ')',
+  }
+}
+
+/// What ends a sequence of nodes being read
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Until {
+  End,
+  Close(char),
+  /// The dot after the parameter of a lambda
+  Dot,
+}
+
+fn read_seq(
+  text: &str,
+  lexemes: &[(LexKind, Range<usize>)],
+  pos: &mut usize,
+  until: Until,
+) -> Option<Vec<Node>> {
+  let mut nodes = Vec::new();
+  while let Some((kind, range)) = lexemes.get(*pos).cloned() {
+    let lexeme = &text[range];
+    *pos += 1;
+    let node = match kind {
+      LexKind::Comment => return None,
+      LexKind::Close => return (until == Until::Close(lexeme.chars().next()?)).then_some(nodes),
+      LexKind::Dot if until == Until::Dot => return Some(nodes),
+      LexKind::Open => {
+        let open = lexeme.chars().next()?;
+        Node::Group(open, read_seq(text, lexemes, pos, Until::Close(closer(open)))?)
+      },
+      LexKind::Lambda => {
+        let param = read_seq(text, lexemes, pos, Until::Dot)?;
+        let body = read_seq(text, lexemes, pos, until)?;
+        nodes.push(Node::Lambda(param, body));
+        return Some(nodes);
+      },
+      LexKind::Name | LexKind::Operator => {
+        let mut name = lexeme.to_string();
+        loop {
+          match &lexemes[*pos..] {
+            [(LexKind::Namespace, sep), (LexKind::Name | LexKind::Operator, seg), ..] => {
+              name += &text[sep.start..seg.end];
+              *pos += 2;
+            },
+            // The separator before an operator segment lexes as part of it
+            [(LexKind::Operator, seg), ..]
+              if text[seg.clone()].strip_prefix("::").is_some_and(is_operator) =>
+            {
+              name += &text[seg.clone()];
+              *pos += 1;
+            },
+            _ => break,
+          }
+        }
+        let last = name.rsplit("::").next().unwrap_or("");
+        if is_operator(last) { Node::Operator(name) } else { Node::Token(name) }
+      },
+      LexKind::Namespace | LexKind::Walrus | LexKind::Arrow | LexKind::Dot =>
+        Node::Operator(lexeme.to_string()),
+      LexKind::String | LexKind::Number | LexKind::Placeholder => Node::Token(lexeme.to_string()),
+    };
+    nodes.push(node);
+  }
+  (until == Until::End).then_some(nodes)
+}
+
+/// Read an expression from source text. Returns [None] if the brackets don't
+/// balance or there are comments, which have no place in the nodes.
+pub fn read(text: &str) -> Option<Vec<Node>> { read_seq(text, &lex(text), &mut 0, Until::End) }
+
+/// The node of a leaf of the parsed tree as it's spelled in the source
+fn spelled(ex: &parsed::Expr) -> Option<Node> {
+  let text = ex.range.text();
+  match read(text.get(ex.range.range())?)?.as_slice() {
+    [node @ (Node::Token(_) | Node::Operator(_))] => Some(node.clone()),
+    _ => None,
+  }
+}
+
+fn expr_node(ex: &parsed::Expr) -> Node {
+  match &ex.value {
+    parsed::Clause::S(open, body) => Node::Group(*open, body.iter().map(expr_node).collect()),
+    parsed::Clause::Lambda(arg, body) =>
+      Node::Lambda(arg.iter().map(expr_node).collect(), body.iter().map(expr_node).collect()),
+    // Names produced by macros may point at a different spelling
+    parsed::Clause::Name(n) => {
+      let matches = |node: &Node| match node {
+        Node::Token(s) | Node::Operator(s) => s.ends_with(n.last().as_str()),
+        _ => false,
+      };
+      spelled(ex).filter(matches).unwrap_or_else(|| {
+        let name = n.iter().join("::");
+        if is_operator(&n.last()) { Node::Operator(name) } else { Node::Token(name) }
+      })
+    },
+    _ => spelled(ex).unwrap_or_else(|| Node::Token("…".to_string())),
+  }
+}
+
+/// Read a sequence from the parsed tree, such as a constant's value or the
+/// pattern of a rule. A single parenthesized sequence is unwrapped, because
+/// the parser puts the value of a constant in parentheses.
+pub fn expr_nodes(exprs: &[parsed::Expr]) -> Vec<Node> {
+  match exprs {
+    [parsed::Expr { value: parsed::Clause::S('(', body), .. }] => expr_nodes(body),
+    _ => exprs.iter().map(expr_node).collect(),
+  }
+}
+
+fn first_char(node: &Node) -> char {
+  match node {
+    Node::Token(s) | Node::Operator(s) => s.chars().next().unwrap_or(' '),
+    Node::Group(open, _) => *open,
+    Node::Lambda(..) => '\\',
+  }
+}
+
+fn last_char(node: &Node) -> char {
+  match node {
+    Node::Token(s) | Node::Operator(s) => s.chars().next_back().unwrap_or(' '),
+    Node::Group(open, _) => closer(*open),
+    Node::Lambda(_, body) => body.last().map_or('.', last_char),
+  }
+}
+
+/// The whitespace between two adjacent nodes
+fn gap(prev: &Node, next: &Node, config: &PrintConfig) -> &'static str {
+  let is_op = |n: &Node| matches!(n, Node::Operator(_));
+  if config.operator_spacing == OperatorSpacing::Tight && is_op(prev) != is_op(next) {
+    let (l, r) = (last_char(prev), first_char(next));
+    let apart = separator(l) || separator(r) || name_char(l) != name_char(r);
+    // `1` followed by `.5` would read as a single number
+    if apart && !(l.is_ascii_digit() && r == '.') {
+      return "";
+    }
+  }
+  " "
+}
+
+/// A sequence of nodes printed on one line
+fn flat(nodes: &[Node], config: &PrintConfig) -> String {
+  let mut out = String::new();
+  for (i, node) in nodes.iter().enumerate() {
+    if 0 < i {
+      out += gap(&nodes[i - 1], node, config);
+    }
+    match node {
+      Node::Token(s) | Node::Operator(s) => out += s,
+      Node::Group(open, inner) => {
+        out.push(*open);
+        out += &flat(inner, config);
+        out.push(closer(*open));
+      },
+      Node::Lambda(param, body) => {
+        out += &format!("\\{}.", flat(param, config));
+        if !body.is_empty() {
+          out += &format!(" {}", flat(body, config));
+        }
+      },
+    }
+  }
+  out
+}
+
+/// The part of a node that stays on the line where it starts if it's broken
+fn head(node: &Node, config: &PrintConfig) -> Option<String> {
+  match node {
+    Node::Group(open, inner) if !inner.is_empty() => Some(open.to_string()),
+    Node::Lambda(param, body) if !body.is_empty() => Some(format!("\\{}.", flat(param, config))),
+    _ => None,
+  }
+}
+
+struct Printer<'a> {
+  config: &'a PrintConfig,
+  out: String,
+  col: usize,
+  /// Indentation of the current line
+  indent: usize,
+}
+impl Printer<'_> {
+  fn push(&mut self, s: &str) {
+    self.out += s;
+    self.col += s.chars().count();
+  }
+
+  fn fits(&self, s: &str) -> bool { self.col + s.chars().count() <= self.config.width }
+
+  fn newline(&mut self, indent: usize) {
+    self.out.push('\n');
+    self.out += &" ".repeat(indent);
+    (self.col, self.indent) = (indent, indent);
+  }
+
+  fn seq(&mut self, nodes: &[Node]) {
+    let cont = self.indent + INDENT;
+    for (i, node) in nodes.iter().enumerate() {
+      let mut gap = if i == 0 { "" } else { gap(&nodes[i - 1], node, self.config) };
+      let text = flat(std::slice::from_ref(node), self.config);
+      if self.fits(&format!("{gap}{text}")) {
+        self.push(gap);
+        self.push(&text);
+        continue;
+      }
+      let head = head(node, self.config);
+      // Broken brackets hang from the line they open on if their head fits
+      if 0 < i && !head.as_ref().is_some_and(|h| self.fits(&format!("{gap}{h}"))) {
+        self.newline(cont);
+        gap = "";
+        if self.fits(&text) {
+          self.push(&text);
+          continue;
+        }
+      }
+      self.push(gap);
+      match head {
+        Some(_) => self.broken(node),
+        None => self.push(&text),
+      }
+    }
+  }
+
+  fn broken(&mut self, node: &Node) {
+    let outer = self.indent;
+    match node {
+      Node::Group(open, inner) => {
+        self.push(&open.to_string());
+        self.newline(outer + INDENT);
+        self.seq(inner);
+        self.newline(outer);
+        self.push(&closer(*open).to_string());
+      },
+      Node::Lambda(param, body) => {
+        self.push(&format!("\\{}.", flat(param, self.config)));
+        self.newline(outer + INDENT);
+        self.seq(body);
+      },
+      Node::Token(s) | Node::Operator(s) => self.push(s),
+    }
+  }
+}
+
+/// Print a sequence of nodes that starts at a column of an unindented line
+pub fn print(nodes: &[Node], config: &PrintConfig, column: usize) -> String {
+  let mut printer = Printer { config, out: String::new(), col: column, indent: 0 };
+  printer.seq(nodes);
+  printer.out
+}
+
+/// The edits that print the value of every constant of a file in the
+/// canonical form. Values that contain comments are left alone.
+pub fn layout_consts(text: &str, config: &PrintConfig) -> Vec<Edit> {
+  let members = members(text);
+  (members.iter().filter(|m| m.kind == MemberKind::Const))
+    .filter_map(|m| {
+      let walrus = m.name_range.end + text[m.name_range.end..m.range.end].find(":=")? + 2;
+      let value = text[walrus..m.range.end].trim_start();
+      let start = m.range.end - value.len();
+      let nodes = read(value).filter(|nodes| !nodes.is_empty())?;
+      let column = text[..start].rsplit('\n').next().unwrap_or("").chars().count();
+      let printed = print(&nodes, config, column);
+      (printed != value).then_some((start..m.range.end, printed))
+    })
+    .collect()
+}
+
+/// Lambda parameters anywhere in the nodes
+fn params(nodes: &[Node], out: &mut Vec<String>) {
+  for node in nodes {
+    match node {
+      Node::Group(_, inner) => params(inner, out),
+      Node::Lambda(param, body) => {
+        out.extend(param.iter().filter_map(|n| match n {
+          Node::Token(name) => Some(name.clone()),
+          _ => None,
+        }));
+        params(body, out)
+      },
+      Node::Token(_) | Node::Operator(_) => (),
+    }
+  }
+}
+
+/// Whether the nodes mention any of the names
+fn mentions(nodes: &[Node], names: &[String]) -> bool {
+  nodes.iter().any(|node| match node {
+    Node::Token(name) => names.contains(name),
+    Node::Group(_, inner) => mentions(inner, names),
+    Node::Lambda(param, body) => mentions(param, names) || mentions(body, names),
+    Node::Operator(_) => false,
+  })
+}
+
+/// Move the parenthesized expression selected in the value of a constant to a
+/// new constant above it, printed in the canonical form. Only whole
+/// parentheses are moved, because macros would see a bare operand sequence
+/// differently once it's a single name, and expressions that mention lambda
+/// parameters of the value are left alone. Returns the name of the new
+/// constant and the edits.
+pub fn extract_const(
+  text: &str,
+  range: Range<usize>,
+  config: &PrintConfig,
+) -> Option<(String, Vec<Edit>)> {
+  let selected = text.get(range.clone())?;
+  let start = range.start + selected.len() - selected.trim_start().len();
+  let range = start..start + selected.trim().len();
+  let lexemes = lex(text);
+  let at = |kind, pos: usize| lexemes.iter().any(|(k, r)| *k == kind && r.contains(&pos));
+  if !at(LexKind::Open, range.start) || !at(LexKind::Close, range.end.checked_sub(1)?) {
+    return None;
+  }
+  let members = members(text);
+  let member = (members.iter())
+    .find(|m| m.kind == MemberKind::Const && m.range.start <= start && range.end <= m.range.end)?;
+  let walrus = member.name_range.end + text[member.name_range.end..member.range.end].find(":=")?;
+  let nodes = read(&text[range.clone()])?;
+  let [Node::Group('(', inner)] = nodes.as_slice() else { return None };
+  let mut bound = Vec::new();
+  params(&read(&text[walrus + 2..member.range.end])?, &mut bound);
+  if range.start < walrus + 2 || inner.is_empty() || mentions(inner, &bound) {
+    return None;
+  }
+  let taken = |name: &str| {
+    lexemes.iter().any(|(kind, r)| *kind == LexKind::Name && text[r.clone()] == *name)
+  };
+  let name = (1..)
+    .map(|i| if i == 1 { "extracted".to_string() } else { format!("extracted{i}") })
+    .find(|name| !taken(name))?;
+  let decl = format!("const {name} := ");
+  let value = print(inner, config, decl.chars().count());
+  let insert = member.range.start..member.range.start;
+  Some((name.clone(), vec![(insert, format!("{decl}{value}\n")), (range, name)]))
+}
+
+#[cfg(test)]
+mod test {
+  use super::{extract_const, layout_consts, print, read, Node, OperatorSpacing, PrintConfig};
+
+  fn tok(s: &str) -> Node { Node::Token(s.to_string()) }
+
+  #[test]
+  fn reading() {
+    let nodes = read("\\x. std::num::+ x (f [1]) -- no").map(|_| ());
+    assert_eq!(nodes, None, "Comments aren't read");
+    assert_eq!(read("(a]"), None);
+    assert_eq!(read("a)"), None);
+    let nodes = read("f (\\x.x+1) \"s\"").unwrap();
+    let lambda = Node::Lambda(vec![tok("x")], vec![tok("x"), Node::Operator("+".into()), tok("1")]);
+    assert_eq!(nodes, vec![tok("f"), Node::Group('(', vec![lambda]), tok("\"s\"")]);
+    assert_eq!(read("std::num::+ a").unwrap()[0], Node::Operator("std::num::+".into()));
+  }
+
+  #[test]
+  fn spacing() {
+    let spaced = PrintConfig::default();
+    let tight = PrintConfig { operator_spacing: OperatorSpacing::Tight, ..spaced };
+    let nodes = read("f  (a+ b)\t*c -> g x.y").unwrap();
+    assert_eq!(print(&nodes, &spaced, 0), "f (a + b) * c -> g x . y");
+    assert_eq!(print(&nodes, &tight, 0), "f (a+b)*c->g x.y");
+    assert_eq!(print(&read("1 .. 2").unwrap(), &tight, 0), "1 ..2", "Not a decimal");
+    assert_eq!(print(&read("\\x. \\y. x").unwrap(), &spaced, 0), "\\x. \\y. x");
+  }
+
+  #[test]
+  fn breaking() {
+    let config = PrintConfig { width: 20, ..PrintConfig::default() };
+    let nodes = read("foo bar (baz quux (a b c d e f)) last").unwrap();
+    let expected = "foo bar (\n  baz quux (\n    a b c d e f\n  )\n) last";
+    assert_eq!(print(&nodes, &config, 0), expected);
+    let nodes = read("\\callback. callback first second third").unwrap();
+    assert_eq!(print(&nodes, &config, 0), "\\callback.\n  callback first\n    second third");
+    assert_eq!(print(&read("a_long_name b").unwrap(), &config, 12), "a_long_name\n  b");
+  }
+
+  #[test]
+  fn consts() {
+    let config = PrintConfig::default();
+    let text = "const a := f  (x+1)\nexport const b:=\n  2\n-- doc\nconst c := g  -- note\n";
+    let edits = layout_consts(text, &config);
+    assert_eq!(edits, vec![(11..19, "f (x + 1)".to_string())], "Only the first changes");
+    assert!(layout_consts("const a := f (x + 1)", &config).is_empty());
+  }
+
+  #[test]
+  fn extract() {
+    let config = PrintConfig::default();
+    let text = "-- doc\nconst a := f  ( g  [x+1] ) 2\n";
+    let (name, edits) = extract_const(text, 20..34, &config).unwrap();
+    assert_eq!(name, "extracted");
+    let expected = vec![(0..0, "const extracted := g [x + 1]\n".to_string()), (21..33, name)];
+    assert_eq!(edits, expected, "Above the comments of the member");
+    assert_eq!(extract_const(text, 21..32, &config), None, "The brackets must be whole");
+    assert_eq!(extract_const(text, 26..31, &config), None, "Only parentheses");
+    let lambda = "const extracted := 1\nconst f := \\x. g (x + 1) (h 2)\n";
+    assert_eq!(extract_const(lambda, 38..45, &config), None, "Mentions the parameter");
+    let (name, edits) = extract_const(lambda, 46..51, &config).unwrap();
+    assert_eq!((name.as_str(), edits[0].1.as_str()), ("extracted2", "const extracted2 := h 2\n"));
+  }
+}
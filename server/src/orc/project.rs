@@ -1,15 +1,18 @@
 use std::collections::VecDeque;
+use std::fmt;
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::io::BufReader;
+use std::ops::Range;
+use std::path::PathBuf;
 use std::rc::Rc;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 
-use hashbrown::HashMap;
-use intern_all::i;
+use hashbrown::{HashMap, HashSet};
+use intern_all::{i, Tok};
 use itertools::Itertools;
 use orchidlang::error::{ProjectErrorObj, Reporter};
 use orchidlang::facade::loader::Loader;
 use orchidlang::facade::macro_runner::MacroRunner;
-use orchidlang::foreign::inert::Inert;
 use orchidlang::libs::asynch::system::AsynchSystem;
 use orchidlang::libs::directfs::DirectFS;
 use orchidlang::libs::io::{IOService, Stream};
@@ -17,54 +20,247 @@ use orchidlang::libs::scheduler::system::SeqScheduler;
 use orchidlang::libs::std::std_system::StdConfig;
 use orchidlang::location::SourceRange;
 use orchidlang::name::{NameLike, PathSlice, Sym, VPath};
-use orchidlang::parse::lexer::namestart;
 use orchidlang::parse::parsed;
-use orchidlang::pipeline::project::{ItemKind, ProjItem, ProjectTree};
+use orchidlang::pipeline::project::{ItemKind, ProjItem, ProjectMod, ProjectTree};
 use orchidlang::tree::{ModMember, ModMemberRef, TreeTransforms};
 use orchidlang::utils::pure_seq::pushed;
 use orchidlang::virt_fs::{DeclTree, Loaded, VirtFS};
-use ordered_float::NotNan;
-use substack::Substack;
 
-use crate::cmd::fs::PatchStore;
-use crate::jrpc::Abort;
+use super::atoms::{AtomClass, AtomRegistry};
+use super::encoding::Encoding;
+use super::errors::recoverable;
+use super::gas::{expand, hotspots, Expansion, Hotspot};
+use super::imports::{alias_site, export_index, ExportIndex};
+use super::lexical::{is_identifier, number_len};
+use super::lint::{find_private_uses, PrivateUse};
+use super::members::{members, Member, MemberKind};
+use super::scopes::{Scope, ScopeKind, ScopeTree};
+use super::stubs::StubIndex;
+use crate::abort::Abort;
+use crate::log;
+use crate::patches::PatchStore;
+use crate::protocol::document::{FileUri, VersionedDocumentRef};
+use crate::protocol::line_index::LineIndex;
 use crate::protocol::tokens::SemToken;
 
+/// An Orchid project found by [find_all_projects]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ProjectRoot {
+  /// A folder containing `project_info.orc`
+  Dir(VPath),
+  /// A file not belonging to any such folder, without the extension
+  File(VPath),
+}
+impl ProjectRoot {
+  pub fn path(&self) -> &VPath {
+    match self {
+      Self::Dir(path) | Self::File(path) => path,
+    }
+  }
+  pub fn is_file(&self) -> bool { matches!(self, Self::File(_)) }
+}
+impl fmt::Display for ProjectRoot {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Dir(path) => write!(f, "{path}/"),
+      Self::File(path) => write!(f, "{path}.orc"),
+    }
+  }
+}
+
 /// Find all Orchid projects in a vfs. An Orchid project is either
 /// - a folder containing `project_info.orc`
 /// - a file not belonging to any such folder
-pub fn find_all_projects(path: VPath, vfs: &impl VirtFS) -> Vec<VPath> {
+pub fn find_all_projects(path: VPath, vfs: &impl VirtFS) -> Vec<ProjectRoot> {
   let mut queue = VecDeque::from([path.clone()]);
   let mut results = Vec::new();
   while let Some(p) = queue.pop_front() {
     match vfs.read(&p) {
       Err(_) => (),
-      Ok(Loaded::Code(_)) => results.push(p),
-      // Ok(Loaded::Code(_)) => continue,
-      Ok(Loaded::Collection(c)) if c.iter().any(|f| &**f == "project_info") => results.push(p),
+      Ok(Loaded::Code(_)) => results.push(ProjectRoot::File(p)),
+      Ok(Loaded::Collection(c)) if c.iter().any(|f| &**f == "project_info") =>
+        results.push(ProjectRoot::Dir(p)),
       Ok(Loaded::Collection(c)) =>
         c.iter().for_each(|item| queue.push_back(p.clone().suffix([item.clone()]))),
     }
   }
-  eprintln!("Projects in {path}:\n{}", results.iter().join(", "));
+  log!(Debug, "Projects in {path}:\n{}", results.iter().join(", "));
   results
 }
 
-pub struct LoadedProject {
+/// The source files of a project as a load sees them, with open documents
+/// taking precedence over the disk
+#[derive(Clone)]
+pub struct ProjectFiles {
   pub patches: Arc<PatchStore>,
-  pub root: VPath,
+  pub root: ProjectRoot,
+}
+impl ProjectFiles {
+  /// Read a file in the project. The path is relative to the project root.
+  pub fn source(&self, path: &PathSlice) -> Option<Arc<String>> {
+    let vfs_root = self.patches.basepath().extended(self.root.path().clone());
+    match self.patches.clone().mk_vfs(&vfs_root)?.read(path) {
+      Ok(Loaded::Code(text)) => Some(text),
+      _ => None,
+    }
+  }
+
+  /// Paths of all source files in the project relative to the project root
+  pub fn source_files(&self) -> Vec<VPath> {
+    let vfs_root = self.patches.basepath().extended(self.root.path().clone());
+    let Some(vfs) = self.patches.clone().mk_vfs(&vfs_root) else { return Vec::new() };
+    let (mut queue, mut files) = (VecDeque::from([VPath::new([])]), Vec::new());
+    while let Some(p) = queue.pop_front() {
+      match vfs.read(&p) {
+        Err(_) => (),
+        Ok(Loaded::Code(_)) => files.push(p),
+        Ok(Loaded::Collection(c)) =>
+          c.iter().for_each(|item| queue.push_back(p.clone().suffix([item.clone()]))),
+      }
+    }
+    // Directory listings come in whatever order the file system keeps
+    files.sort_by_cached_key(|f| f.to_string());
+    files
+  }
+
+  /// The number of source files next to a single file project, which are
+  /// outside of any project folder as well. Zero for folder projects.
+  pub fn neighbours(&self) -> usize {
+    let ProjectRoot::File(path) = &self.root else { return 0 };
+    let Some((_, folder)) = path[..].split_last() else { return 0 };
+    let Some(vfs) = self.patches.clone().mk_vfs(self.patches.basepath()) else { return 0 };
+    let folder = VPath::new(folder.iter().cloned());
+    let Ok(Loaded::Collection(items)) = vfs.read(&folder) else { return 0 };
+    let is_file = |item: &Tok<String>| {
+      matches!(vfs.read(&folder.clone().suffix([item.clone()])), Ok(Loaded::Code(_)))
+    };
+    items.iter().filter(|item| is_file(item)).count().saturating_sub(1)
+  }
+
+  /// The line index of a file in the project. Open files have it cached in the
+  /// patch store, others are indexed on demand.
+  pub fn line_index(&self, path: &PathSlice) -> Option<Arc<LineIndex>> {
+    match self.patches.line_index(&self.file_uri(path)) {
+      Some(lines) => Some(lines),
+      None => Some(Arc::new(LineIndex::new(&self.source(path)?))),
+    }
+  }
+
+  /// How a file read from disk was decoded if it isn't plain UTF-8. Open
+  /// files come from the editor, which already decoded them.
+  pub fn encoding(&self, path: &PathSlice) -> Option<Encoding> {
+    if self.patches.text(&self.file_uri(path)).is_some() {
+      return None;
+    }
+    let full = self.root.path().iter().chain(path.iter()).cloned().collect::<Vec<_>>();
+    self.patches.disk().encoding(&full)
+  }
+
+  /// The file that makes a folder a project, or the file of a single file
+  /// project. The path is relative to the project root.
+  pub fn project_file(&self) -> VPath {
+    match &self.root {
+      ProjectRoot::Dir(_) => VPath::new([i!(str: "project_info")]),
+      ProjectRoot::File(_) => VPath::new([]),
+    }
+  }
+
+  /// The URI of a file given its path relative to the project root
+  pub fn file_uri(&self, path: &[Tok<String>]) -> FileUri {
+    self.patches.basepath().extended(self.root.path().as_slice().iter().chain(path.iter()))
+  }
+
+  /// The URI of a source file for messages to the client. Source files are
+  /// always files, unlike the root of a folder project.
+  pub fn doc_uri(&self, path: &[Tok<String>]) -> String { self.file_uri(path).stringify(true) }
+
+  /// A source file at the version this snapshot of the project has, for edits
+  pub fn versioned(&self, path: &[Tok<String>]) -> VersionedDocumentRef {
+    let version = self.patches.version(&self.file_uri(path));
+    VersionedDocumentRef { uri: self.doc_uri(path), version }
+  }
+
+  /// A hash of the path and text of every source file, which differs between
+  /// any two states of the files that could load differently
+  pub fn fingerprint(&self) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for path in self.source_files() {
+      path.to_string().hash(&mut hasher);
+      self.source(&path).hash(&mut hasher);
+    }
+    hasher.finish()
+  }
+
+  /// The folder the project file is read from. Files outside of any project
+  /// get the configuration of the folder they're in, which has no project
+  /// file by definition.
+  pub fn config_dir(&self) -> PathBuf {
+    let root = self.file_uri(&[]).to_path();
+    match &self.root {
+      ProjectRoot::Dir(_) => root,
+      ProjectRoot::File(_) => root.parent().map_or_else(|| root.clone(), |dir| dir.to_path_buf()),
+    }
+  }
+}
+
+/// The phase of [LoadedProject::new] that prepares macros and expands every
+/// constant, which is slow for projects with many rules. It's reported again
+/// as the expansion progresses.
+pub const MACRO_PHASE: &str = "Preparing macros";
+/// Longest chain of imports and re-exports followed when resolving a name
+const MAX_ALIAS_DEPTH: usize = 32;
+
+/// Separate the errors of a load phase that the load can go on despite from
+/// fatal ones. If any error is fatal, all errors are returned.
+fn triage(reporter: Reporter) -> Result<Vec<ProjectErrorObj>, Vec<ProjectErrorObj>> {
+  let errors = reporter.into_errors().unwrap_or_default();
+  if errors.iter().all(recoverable) { Ok(errors) } else { Err(errors) }
+}
+
+pub struct LoadedProject {
+  pub files: ProjectFiles,
   pub tree: ProjectTree,
   pub macros: MacroRunner,
+  /// The number of steps after which macro expansion gives up
+  pub gas: usize,
+  /// The gas of each constant, recorded with the expansions
+  hotspots: Vec<Hotspot>,
+  /// Built the first time they're needed, see [Self::exports] and
+  /// [Self::private_uses]
+  exports: OnceLock<ExportIndex>,
+  private_uses: OnceLock<Vec<PrivateUse>>,
+  /// Split the first time they're needed, see [Self::members]
+  members: OnceLock<Vec<(VPath, Vec<Member>)>>,
+  /// Built the first time they're needed, see [Self::scopes]
+  scopes: OnceLock<HashMap<VPath, ScopeTree>>,
+  /// Computed the first time it's needed, see [Self::token_context]
+  token_context: OnceLock<u64>,
+  /// The macro expansion of every constant in the project's files, by the
+  /// location of the value
+  expansions: HashMap<SourceRange, Expansion>,
+  pub stubs: StubIndex,
+  /// Display of the atoms the loaded systems produce
+  pub atoms: AtomRegistry,
+  /// Errors that took parts of the project out of the tree without stopping
+  /// the load
+  pub warnings: Vec<ProjectErrorObj>,
 }
 impl LoadedProject {
+  /// Load a project, reporting the phase and percentage of the load through
+  /// the callback. The load stops between phases if aborted. Errors located
+  /// in the project's files don't stop it, they're kept as warnings. Macro
+  /// expansion gives up after `gas` steps.
   pub fn new(
     patches: Arc<PatchStore>,
-    root: VPath,
+    root: ProjectRoot,
+    gas: usize,
     abort: Abort,
+    progress: &mut dyn FnMut(&str, u32),
   ) -> Result<Self, Vec<ProjectErrorObj>> {
     if abort.aborted() {
       return Err(vec![]);
     }
+    progress("Discovering files", 0);
     let mut asynch = AsynchSystem::new();
     let scheduler = SeqScheduler::new(&mut asynch);
     let std_streams = [
@@ -79,60 +275,294 @@ impl LoadedProject {
       .add_system(IOService::new(scheduler.clone(), std_streams))
       .add_system(DirectFS::new(scheduler.clone()))
       .add_system(scheduler);
-    let vfs_root = patches.basepath().extended(root.clone());
-    eprintln!("{} + {} = {}", patches.basepath(), root, vfs_root);
+    let vfs_root = patches.basepath().extended(root.path().clone());
+    log!(Debug, "{} + {} = {}", patches.basepath(), root, vfs_root);
     let vfs = patches.clone().mk_vfs(&vfs_root).expect("Root not in fs");
     let srctree = DeclTree::ns("tree", [DeclTree::leaf(Rc::new(vfs))]);
     if abort.aborted() {
       return Err(vec![]);
     }
+    progress("Parsing", 10);
     let tree = env.load_project(srctree, &reporter);
-    if reporter.failing() || abort.aborted() {
-      return Err(reporter.into_errors().unwrap_or_default());
+    let mut warnings = triage(reporter)?;
+    if abort.aborted() {
+      return Err(vec![]);
     }
-    let macros = MacroRunner::new(&tree, Some(10_000), &reporter);
-    if reporter.failing() || abort.aborted() {
-      return Err(reporter.into_errors().unwrap_or_default());
+    progress(MACRO_PHASE, 60);
+    let reporter = Reporter::new();
+    let macros = MacroRunner::new(&tree, Some(gas), &reporter);
+    warnings.extend(triage(reporter)?);
+    let mut expansions = HashMap::new();
+    let (consts, mut reported) = (source_consts(&tree), 60);
+    for (i, val) in consts.iter().enumerate() {
+      if abort.aborted() {
+        return Err(vec![]);
+      }
+      let percentage = 60 + (20 * i / consts.len()) as u32;
+      if percentage != reported {
+        progress(MACRO_PHASE, percentage);
+        reported = percentage;
+      }
+      expansions.insert(val.range.clone(), expand(&macros, val, gas));
     }
-    Ok(Self { patches, root, tree, macros })
+    let hotspots = hotspots(&tree, &expansions);
+    let stubs = StubIndex::new(&tree.0);
+    let atoms = AtomRegistry::with_loaded_systems();
+    let files = ProjectFiles { patches, root };
+    Ok(Self {
+      files,
+      tree,
+      macros,
+      gas,
+      hotspots,
+      exports: OnceLock::new(),
+      private_uses: OnceLock::new(),
+      members: OnceLock::new(),
+      scopes: OnceLock::new(),
+      token_context: OnceLock::new(),
+      expansions,
+      stubs,
+      atoms,
+      warnings,
+    })
   }
 
-  pub fn tokens(&self) -> Vec<SemToken> {
-    let mut tokv = vec![];
+  /// The names exported by the files of the project and the modules exporting
+  /// them
+  pub fn exports(&self) -> &ExportIndex {
+    self.exports.get_or_init(|| {
+      let files = (self.source_files().into_iter())
+        .filter_map(|file| Some((format!("tree::{}", file.iter().join("::")), self.source(&file)?)))
+        .collect_vec();
+      export_index(files.iter().map(|(module, text)| (module.clone(), &text[..])))
+    })
+  }
+
+  /// The top-level members of each source file of the project
+  pub fn members(&self) -> &[(VPath, Vec<Member>)] {
+    self.members.get_or_init(|| {
+      (self.source_files().into_iter())
+        .filter_map(|file| Some((file.clone(), members(&self.source(&file)?))))
+        .collect()
+    })
+  }
+
+  /// The lexical scopes of a source file of the project
+  pub fn scopes(&self, path: &VPath) -> Option<&ScopeTree> {
+    self.scopes.get_or_init(|| ScopeTree::all(self)).get(path)
+  }
+
+  /// A hash of what the tokens of a file depend on besides its own text, the
+  /// macros of the project's files and the names they export. Editing the
+  /// constants of one file leaves it unchanged, so the tokens of the other
+  /// files can be reused by the next load.
+  pub fn token_context(&self) -> u64 {
+    *self.token_context.get_or_init(|| {
+      let mut hasher = DefaultHasher::new();
+      for (file, members) in self.members() {
+        let Some(text) = self.source(file) else { continue };
+        for member in members.iter().filter(|m| m.kind == MemberKind::Macro) {
+          (file.to_string(), &text[member.header..member.range.end]).hash(&mut hasher);
+        }
+      }
+      self.exports().iter().sorted().for_each(|export| export.hash(&mut hasher));
+      hasher.finish()
+    })
+  }
+
+  /// References to constants that other files of the project don't export
+  pub fn private_uses(&self) -> &[PrivateUse] {
+    self.private_uses.get_or_init(|| find_private_uses(self))
+  }
+
+  /// The macro expansion of a constant in the project's files, recorded while
+  /// loading
+  pub fn expansion(&self, value: &parsed::Expr) -> Option<&Expansion> {
+    self.expansions.get(&value.range)
+  }
+
+  /// The macro gas consumed by each constant in the project's files
+  pub fn hotspots(&self) -> &[Hotspot] { &self.hotspots }
+
+  /// Number of source files and constants in the project
+  pub fn size(&self) -> (usize, usize) {
+    let (mut files, mut constants) = (HashSet::new(), 0);
     self.tree.0.search_all((), |_, mem, ()| {
       if let ModMemberRef::Item(ProjItem { kind: ItemKind::Const(val) }) = mem {
-        tokv.extend(tokens(val, &val.range.path(), &self.macros).into_iter().flatten())
+        constants += 1;
+        files.insert(val.range.path());
       }
     });
-    tokv
+    (files.len(), constants)
+  }
+
+  /// For each name referenced by a constant, the locations of the references
+  pub fn references(&self) -> HashMap<Vec<Tok<String>>, Vec<SourceRange>> {
+    let mut refs = HashMap::<_, Vec<_>>::new();
+    self.tree.0.search_all((), |_, mem, ()| {
+      if let ModMemberRef::Item(ProjItem { kind: ItemKind::Const(val) }) = mem {
+        val.search_all(&mut |ex| {
+          if let parsed::Clause::Name(n) = &ex.value {
+            refs.entry(n.to_vec()).or_default().push(ex.range.clone());
+          }
+          None::<()>
+        });
+      }
+    });
+    refs
+  }
+
+  /// The tokens of a constant, none if its expansion ran out of gas
+  fn const_tokens(&self, val: &parsed::Expr) -> Vec<SemToken> {
+    let Some(postmacro) = self.expansion(val).and_then(|e| e.output.as_ref()) else {
+      return Vec::new();
+    };
+    tokens(val, &val.range.path(), postmacro, &self.atoms).collect()
+  }
+
+  pub fn tokens(&self) -> Vec<SemToken> {
+    source_consts(&self.tree).into_iter().flat_map(|val| self.const_tokens(val)).collect()
   }
 
   pub fn module_tokens(&self, prefix: &PathSlice) -> Vec<SemToken> {
     if prefix.is_empty() {
       return self.tokens();
     }
-    let (ent, _) = self.tree.0.walk1_ref(&[], prefix, |_| true).expect("Path must be valid");
-    let consts = match &ent.member {
+    // Files that failed to parse are left out of the tree
+    let Some(consts) = self.module_consts(prefix) else { return Vec::new() };
+    consts.into_iter().flat_map(|c| self.const_tokens(c)).collect()
+  }
+
+  /// All constants in the module or constant at the specified path
+  pub fn module_consts(&self, prefix: &PathSlice) -> Option<Vec<&parsed::Expr>> {
+    let (ent, _) = self.tree.0.walk1_ref(&[], prefix, |_| true).ok()?;
+    Some(match &ent.member {
       ModMember::Item(ProjItem { kind: ItemKind::Const(val) }) => vec![val],
-      ModMember::Sub(module) => module.search_all(vec![], |_, mem, consts| match mem {
-        ModMemberRef::Item(ProjItem { kind: ItemKind::Const(val) }) => pushed(consts, val),
-        _ => consts,
-      }),
-      _ => return vec![],
+      ModMember::Sub(module) => all_consts(module),
+      _ => vec![],
+    })
+  }
+
+  /// Find the value of the constant a fully qualified name refers to
+  pub fn get_const(&self, name: &PathSlice) -> Option<&parsed::Expr> {
+    match &self.tree.0.walk1_ref(&[], name, |_| true).ok()?.0.member {
+      ModMember::Item(ProjItem { kind: ItemKind::Const(val) }) => Some(val),
+      _ => None,
+    }
+  }
+
+  /// Read a file in the project from the same snapshot the tree was loaded
+  /// from. The path is relative to the project root.
+  pub fn source(&self, path: &PathSlice) -> Option<Arc<String>> { self.files.source(path) }
+
+  /// Paths of all source files in the project relative to the project root
+  pub fn source_files(&self) -> Vec<VPath> { self.files.source_files() }
+
+  /// See [ProjectFiles::line_index]
+  pub fn line_index(&self, path: &PathSlice) -> Option<Arc<LineIndex>> {
+    self.files.line_index(path)
+  }
+
+  /// The URI of a file given its path relative to the project root
+  pub fn file_uri(&self, path: &[Tok<String>]) -> FileUri { self.files.file_uri(path) }
+
+  /// The URI of a source file for messages to the client
+  pub fn doc_uri(&self, path: &[Tok<String>]) -> String { self.files.doc_uri(path) }
+
+  /// Find the name occurring at a byte offset in a file. The path is relative
+  /// to the project root.
+  pub fn name_at(&self, path: &PathSlice, offset: usize) -> Option<(SourceRange, Sym)> {
+    let module = path.to_vpath().prefix([i!(str: "tree")]);
+    (self.module_consts(&module)?.into_iter()).find_map(|c| {
+      c.search_all(&mut |ex| match &ex.value {
+        parsed::Clause::Name(n)
+          if ex.range.start() <= offset
+            && offset <= ex.range.end()
+            && ex.range.path().iter().eq(module.iter()) =>
+          Some((ex.range.clone(), n.clone())),
+        _ => None,
+      })
+    })
+  }
+
+  /// The names a reference passes through on its way to the definition,
+  /// starting with the spelled name within the module it occurs in and
+  /// following the aliases that imports and re-exports leave in the tree.
+  /// Empty if the spelled name can't be found from the module, for example
+  /// because it's relative.
+  pub fn alias_chain(&self, module: &PathSlice, spelled: &[Tok<String>]) -> Vec<Vec<Tok<String>>> {
+    let mut chain = vec![module.iter().chain(spelled).cloned().collect_vec()];
+    while chain.len() <= MAX_ALIAS_DEPTH {
+      let name = chain.last().expect("Starts with the spelled name");
+      let Ok((ent, _)) = self.tree.0.walk1_ref(&[], name, |_| true) else {
+        return if chain.len() == 1 { Vec::new() } else { chain };
+      };
+      match &ent.member {
+        ModMember::Item(ProjItem { kind: ItemKind::Alias(target) })
+          if !chain.iter().any(|c| c[..] == target[..]) =>
+          chain.push(target.to_vec()),
+        _ => break,
+      }
+    }
+    chain
+  }
+
+  /// The file and range of the import or export list entry that made an alias
+  /// in the tree, if it's at the top level of a file. The path is relative to
+  /// the project root.
+  pub fn alias_site(&self, alias: &[Tok<String>]) -> Option<(VPath, Range<usize>)> {
+    let (name, module) = alias.split_last()?;
+    let file = match module {
+      [tree, file @ ..] if tree.as_str() == "tree" => VPath::new(file.iter().cloned()),
+      _ => return None,
     };
-    (consts.into_iter())
-      .flat_map(|c| tokens(c, &c.range.path(), &self.macros).into_iter().flatten())
-      .collect()
+    let range = alias_site(&self.source(&file)?, name)?;
+    Some((file, range))
+  }
+
+  /// Find the literal at a byte offset in a file and the class of the atom it
+  /// was parsed into. The path is relative to the project root.
+  pub fn atom_at(&self, path: &PathSlice, offset: usize) -> Option<(SourceRange, AtomClass)> {
+    let module = path.to_vpath().prefix([i!(str: "tree")]);
+    (self.module_consts(&module)?.into_iter()).find_map(|c| {
+      c.search_all(&mut |ex| match &ex.value {
+        parsed::Clause::Atom(at)
+          if ex.range.start() <= offset
+            && offset <= ex.range.end()
+            && ex.range.path().iter().eq(module.iter()) =>
+          Some((ex.range.clone(), *self.atoms.classify(&at.run())?)),
+        _ => None,
+      })
+    })
+  }
+}
+
+/// All constants in a module and its submodules
+fn all_consts(module: &ProjectMod) -> Vec<&parsed::Expr> {
+  module.search_all(vec![], |_, mem, consts| match mem {
+    ModMemberRef::Item(ProjItem { kind: ItemKind::Const(val) }) => pushed(consts, val),
+    _ => consts,
+  })
+}
+
+/// The constants declared in the project's files, as opposed to the systems
+fn source_consts(tree: &ProjectTree) -> Vec<&parsed::Expr> {
+  match tree.0.entries.get(&i!(str: "tree")).map(|ent| &ent.member) {
+    Some(ModMember::Sub(module)) => all_consts(module),
+    _ => Vec::new(),
   }
 }
 
+/// The tokens of a constant given its macro expansion
 pub fn tokens(
   expr: &parsed::Expr,
   path: &Sym,
-  macros: &MacroRunner,
-) -> Option<impl Iterator<Item = SemToken>> {
-  let postmacro = macros.process_expr(expr.clone()).ok()?;
-  let n_toks = name_toks(&postmacro, Substack::Bottom, path);
+  postmacro: &parsed::Expr,
+  atoms: &AtomRegistry,
+) -> impl Iterator<Item = SemToken> {
+  let mut scope = Scope::new(ScopeKind::Module, None);
+  scope.add_expr(postmacro, path);
+  let n_toks = name_toks(&scope, path);
   let mut tokens = Vec::new();
   expr.search_all(&mut |ex| {
     if &ex.range.path() != path {
@@ -140,65 +570,87 @@ pub fn tokens(
     }
     match &ex.value {
       parsed::Clause::Name(n) if !n_toks.contains_key(&ex.range) => {
-        let is_name = n.last().starts_with(namestart);
-        let ty = if is_name { i!(str: "keyword") } else { i!(str: "operator") };
+        let ty = if is_identifier(&n.last()) { i!(str: "keyword") } else { i!(str: "operator") };
         tokens.push(SemToken::new(ex.range.clone(), ty));
       },
       parsed::Clause::Atom(at) => {
         let atom = at.run();
-        tokens.push(SemToken::new(
-          ex.range.clone(),
-          if atom.is::<Inert<usize>>() || atom.is::<Inert<NotNan<f64>>>() {
-            i!(str: "number")
-          } else if atom.is::<Inert<bool>>() {
-            i!(str: "keyword")
-          } else {
-            i!(str: "string")
-          },
-        ));
+        // The spelling takes precedence over the atom type because macros may
+        // have replaced the atom, and it pins down the extent of the literal
+        let text = ex.range.text();
+        let spelled = number_len(&text[ex.range.range()]);
+        if let Some(len) = spelled {
+          let range = ex.range.map_range(|r| r.start..r.start + len);
+          tokens.push(SemToken::new(range, i!(str: "number")));
+          return None;
+        }
+        tokens.push(SemToken::new(ex.range.clone(), i(atoms.token(&atom))));
       },
       _ => (),
     }
     None::<()>
   });
-  Some(n_toks.into_values().chain(tokens))
+  n_toks.into_values().chain(tokens)
 }
 
 /// Create tokens for all names that have the same origin path (were not created
 /// by macros) based on whether they appear bound or unbound in the postmacro
 /// tree
-pub fn name_toks(
-  ast: &parsed::Expr,
-  bindings: Substack<Sym>,
-  path: &Sym,
-) -> HashMap<SourceRange, SemToken> {
-  match &ast.value {
-    parsed::Clause::Lambda(arg, body) => {
-      let mut map = HashMap::new();
-      let bindings = match &arg[..] {
-        [parsed::Expr { value: parsed::Clause::Name(n), range }] => {
-          if &range.path() == path {
-            map.insert(range.clone(), SemToken::new(range.clone(), i!(str: "parameter")));
-          }
-          bindings.push(n.clone())
-        },
-        _ => bindings,
-      };
-      for ex in body.iter() {
-        map.extend(name_toks(ex, bindings.clone(), path));
-      }
-      map
-    },
-    parsed::Clause::Name(n) if &ast.range.path() == path => {
-      let is_bound = bindings.iter().any(|b| b == n);
-      let ty = if is_bound { i!(str: "variable") } else { i!(str: "function") };
-      HashMap::from([(ast.range.clone(), SemToken::new(ast.range.clone(), ty))])
-    },
-    parsed::Clause::S(_, b) => {
-      let mut hash = HashMap::new();
-      b.iter().for_each(|x| hash.extend(name_toks(x, bindings.clone(), path)));
-      hash
-    },
-    _ => HashMap::new(),
+pub fn name_toks(scope: &Scope, path: &Sym) -> HashMap<SourceRange, SemToken> {
+  let mut map = HashMap::new();
+  for binder in scope.all_binders() {
+    if binder.kind == ScopeKind::Lambda && &binder.range.path() == path {
+      map.insert(binder.range.clone(), SemToken::new(binder.range.clone(), i!(str: "parameter")));
+    }
+  }
+  for (range, _, binder) in scope.resolved() {
+    if &range.path() != path {
+      continue;
+    }
+    let is_bound = binder.is_some_and(|b| b.kind == ScopeKind::Lambda);
+    let ty = if is_bound { i!(str: "variable") } else { i!(str: "function") };
+    map.insert(range.clone(), SemToken::new(range.clone(), ty));
+  }
+  map
+}
+
+#[cfg(test)]
+mod test {
+  use intern_all::i;
+  use itertools::Itertools;
+  use orchidlang::name::VPath;
+
+  use super::LoadedProject;
+  use crate::testing::Fixture;
+
+  #[test]
+  fn broken_file() {
+    let fixture = Fixture::new(&[("main", "const a := 1\n"), ("broken", "const b := (\n")]);
+    let lpr = fixture.load();
+    assert!(!lpr.warnings.is_empty(), "The parse error is kept as a warning");
+    let broken = VPath::new([i!(str: "tree"), i!(str: "broken")]);
+    assert!(lpr.module_tokens(&broken).is_empty(), "The file isn't in the tree");
+    assert!(!lpr.module_tokens(&VPath::new([i!(str: "tree"), i!(str: "main")])).is_empty());
+  }
+
+  #[test]
+  fn token_context() {
+    let fixture = Fixture::new(&[
+      ("a", "export const a := \\x. x 1\n"),
+      ("b", "export const b := \\x. x\n"),
+    ]);
+    let a = VPath::new([i!(str: "tree"), i!(str: "a")]);
+    let tokens = |lpr: &LoadedProject| {
+      (lpr.module_tokens(&a).into_iter()).map(|t| (t.start(), t.end(), t.typ())).collect_vec()
+    };
+    let first = fixture.load();
+    fixture.write("b", "export const b := \\y. y\n");
+    let second = fixture.load();
+    assert_eq!(first.token_context(), second.token_context(), "Cached tokens of a are reused");
+    assert_eq!(tokens(&first), tokens(&second));
+    fixture.write("b", "export const b := \\y. y\nexport const c := 2\n");
+    assert_ne!(fixture.load().token_context(), first.token_context(), "Exports can bind names");
+    fixture.write("b", "export const b := \\y. y\nmacro rule c =0x1p5=> 2\n");
+    assert_ne!(fixture.load().token_context(), first.token_context(), "Macros can rewrite a");
   }
 }
@@ -0,0 +1,86 @@
+//! `project_info.orc` marks the root of a project and configures it. Besides
+//! being source, its string literals that look like paths point at
+//! dependencies and source roots relative to the project folder, so they're
+//! completed from and checked against the directory structure. Files outside
+//! of any project folder are projects of their own, so the server offers to
+//! create the file for a folder of them.
+
+use std::ops::Range;
+
+use super::lexical::{lex, LexKind};
+
+pub const FILE_NAME: &str = "project_info.orc";
+
+/// The text of a project file created by the server
+pub const TEMPLATE: &str = "-- Every file in this folder belongs to the same project\n";
+
+/// Whether the contents of a string literal are meant as a path rather than a
+/// name or a version
+pub fn is_path(s: &str) -> bool { s.starts_with('.') || s.contains('/') }
+
+/// Byte ranges of the contents of the string literals outside of comments
+pub fn string_contents(text: &str) -> Vec<Range<usize>> {
+  (lex(text).into_iter())
+    .filter(|(kind, _)| *kind == LexKind::String)
+    .map(|(_, range)| {
+      let closed = 1 < range.len() && text[range.clone()].ends_with('"');
+      range.start + 1..range.end - usize::from(closed)
+    })
+    .collect()
+}
+
+/// The contents of the string literal an offset falls in, if any
+pub fn string_at(text: &str, offset: usize) -> Option<Range<usize>> {
+  string_contents(text).into_iter().find(|r| r.start <= offset && offset <= r.end)
+}
+
+/// The folders a project file could be created in to make a file outside of
+/// any project folder one project with the files around it, nearest first.
+/// Folders are given by their depth in the path of the file, along with the
+/// number of single file projects the new project would join. The projects
+/// of the workspace are given by their path and whether they're a single
+/// file. Folders containing a project folder are left out, since the new
+/// project would swallow it.
+pub fn joining_folders<T: PartialEq>(file: &[T], projects: &[(&[T], bool)]) -> Vec<(usize, usize)> {
+  (0..file.len())
+    .rev()
+    .map_while(|depth| {
+      let inside = projects.iter().filter(|(path, _)| path.starts_with(&file[..depth]));
+      if inside.clone().any(|(_, is_file)| !is_file) {
+        return None;
+      }
+      Some((depth, inside.count()))
+    })
+    .filter(|(_, files)| 1 < *files)
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+  use super::{is_path, joining_folders, string_at, string_contents};
+
+  #[test]
+  fn strings() {
+    let text = "const deps := (\"../lib\" \"a\\\"b\") -- \"comment\"\nconst src := \"./sr";
+    let found = string_contents(text).into_iter().map(|r| &text[r]).collect::<Vec<_>>();
+    assert_eq!(found, ["../lib", "a\\\"b", "./sr"]);
+    let offset = text.find("lib").unwrap();
+    assert_eq!(string_at(text, offset).map(|r| &text[r]), Some("../lib"));
+    assert_eq!(string_at(text, text.len()).map(|r| &text[r]), Some("./sr"));
+    assert_eq!(string_at(text, 2), None);
+    assert!(is_path("../lib") && is_path("src/main") && !is_path("0.1.0") && !is_path("std"));
+  }
+
+  #[test]
+  fn folders() {
+    let file = ["src", "util", "list"];
+    let projects = [
+      (&file[..], true),
+      (&["src", "util", "option"][..], true),
+      (&["src", "main"][..], true),
+      (&["lib"][..], false),
+    ];
+    assert_eq!(joining_folders(&file, &projects), [(2, 2), (1, 3)], "Not the root, which has lib");
+    assert_eq!(joining_folders(&["main"], &[(&["main"][..], true)]), [], "Nothing to join");
+  }
+}
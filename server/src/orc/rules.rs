@@ -0,0 +1,231 @@
+//! Macro rules declared in the source files of a project, structural
+//! comparisons between their patterns, and the rule a keyword was consumed by
+
+use std::cmp::Reverse;
+use std::ops::Range;
+
+use hashbrown::HashSet;
+use intern_all::{i, Tok};
+use orchidlang::location::SourceRange;
+use orchidlang::name::{Sym, VPath};
+use orchidlang::parse::parsed::{self, PHClass, Placeholder};
+use orchidlang::pipeline::project::ProjRule;
+
+use super::members::{is_exported, members, MemberKind};
+use super::project::LoadedProject;
+
+/// A rule along with its location. Paths are relative to the project root.
+pub struct SrcRule {
+  pub file: VPath,
+  /// Byte range of the pattern
+  pub pattern: Range<usize>,
+  /// Byte range of the arrow between the pattern and the template, which
+  /// holds the priority
+  pub arrow: Range<usize>,
+  /// Byte range of the pattern, arrow and template
+  pub range: Range<usize>,
+  /// Whether the rule is in an exported macro block, see [is_exported_rule]
+  pub exported: bool,
+  pub rule: ProjRule,
+}
+impl SrcRule {
+  /// The names the pattern consumes. The rule can only fire on sequences that
+  /// contain all of them.
+  pub fn keywords(&self) -> HashSet<Sym> { names(&self.rule.pattern) }
+}
+
+/// The names occurring in a sequence of expressions
+pub fn names<'a>(exprs: impl IntoIterator<Item = &'a parsed::Expr>) -> HashSet<Sym> {
+  let mut names = HashSet::new();
+  for ex in exprs {
+    ex.search_all(&mut |ex| {
+      if let parsed::Clause::Name(n) = &ex.value {
+        names.insert(n.clone());
+      }
+      None::<()>
+    });
+  }
+  names
+}
+
+/// The file of a module path in the project tree
+pub fn file_of(path: &[Tok<String>]) -> Option<VPath> {
+  let (head, file) = path.split_first()?;
+  (**head == "tree").then(|| VPath::new(file.iter().cloned()))
+}
+
+/// Whether the rule at an offset is declared in an exported macro block or any
+/// of its keywords is in an export list. Other projects may use such rules.
+pub fn is_exported_rule<'a>(
+  text: &str,
+  offset: usize,
+  mut keywords: impl Iterator<Item = &'a str>,
+) -> bool {
+  let members = members(text);
+  let block = members.iter().find(|m| m.range.contains(&offset));
+  block.is_some_and(|m| m.kind == MemberKind::Macro && m.export.is_some())
+    || keywords.any(|kw| is_exported(&members, text, kw))
+}
+
+/// All rules defined in the project's own files, in declaration order
+pub fn source_rules(lpr: &LoadedProject) -> Vec<SrcRule> {
+  (lpr.tree.all_rules().into_iter())
+    .filter_map(|rule| {
+      let (first, last) = (rule.pattern.first()?, rule.pattern.last()?);
+      let tpl_start = rule.template.first().map_or(last.range.end(), |ex| ex.range.start());
+      let end = rule.template.last().map_or(last.range.end(), |ex| ex.range.end());
+      let file = file_of(&first.range.path())?;
+      let text = first.range.text();
+      let between = text.get(last.range.end()..tpl_start)?;
+      let arrow_start = last.range.end() + between.find('=')?;
+      let arrow = arrow_start..last.range.end() + between.find("=>")? + 2;
+      let (pattern, range) = (first.range.start()..last.range.end(), first.range.start()..end);
+      let keywords = names(&rule.pattern).into_iter().map(|n| n.last()).collect::<Vec<_>>();
+      let exported = is_exported_rule(&text, first.range.start(), keywords.iter().map(|k| &k[..]));
+      Some(SrcRule { file, pattern, arrow, range, exported, rule })
+    })
+    .collect()
+}
+
+/// Ranges of the expressions a rule's template inserts verbatim. Placeholders
+/// are excluded as they're replaced by the matched code.
+fn template_ranges(rule: &ProjRule) -> Vec<SourceRange> {
+  let mut ranges = Vec::new();
+  for ex in rule.template.iter() {
+    ex.search_all(&mut |ex| {
+      if !matches!(ex.value, parsed::Clause::Placeh(_)) {
+        ranges.push(ex.range.clone())
+      }
+      None::<()>
+    });
+  }
+  ranges
+}
+
+/// The names in the innermost sequence containing the expression at a range,
+/// or [None] if the expression doesn't occur in the tree
+fn siblings(expr: &parsed::Expr, range: &SourceRange) -> Option<HashSet<Sym>> {
+  let parent = expr.search_all(&mut |ex| match &ex.value {
+    parsed::Clause::S(_, body) | parsed::Clause::Lambda(_, body)
+      if body.iter().any(|ex| &ex.range == range) =>
+      Some(names(body.iter())),
+    _ => None,
+  });
+  parent.or_else(|| expr.search_all(&mut |ex| (&ex.range == range).then(|| names([expr]))))
+}
+
+/// The rule that consumed the keyword at an offset in a file. Of the rules
+/// whose keywords all occur next to it, this picks the one with the highest
+/// priority among those whose template shows up in the expansion recorded by
+/// the load.
+pub fn matched_rule(lpr: &LoadedProject, path: &VPath, offset: usize) -> Option<SrcRule> {
+  let (range, kw) = lpr.name_at(path, offset)?;
+  let module = path.clone().prefix([i!(str: "tree")]);
+  let consts = lpr.module_consts(&module)?;
+  let (konst, siblings) = consts.into_iter().find_map(|c| Some((c, siblings(c, &range)?)))?;
+  let mut output = HashSet::new();
+  if let Some(postmacro) = lpr.expansion(konst).and_then(|e| e.output.as_ref()) {
+    postmacro.search_all(&mut |ex| {
+      output.insert(ex.range.clone());
+      None::<()>
+    });
+  }
+  (source_rules(lpr).into_iter())
+    .filter(|r| {
+      let keywords = r.keywords();
+      keywords.contains(&kw) && keywords.iter().all(|k| siblings.contains(k))
+    })
+    .filter(|r| {
+      let tpl = template_ranges(&r.rule);
+      output.is_empty() || tpl.is_empty() || tpl.iter().any(|range| output.contains(range))
+    })
+    .min_by_key(|r| Reverse(r.rule.prio))
+}
+
+/// Whether the general pattern matches every sequence the special pattern
+/// matches. This is conservative; vectorial placeholders only generalize
+/// other vectorial placeholders at the same position.
+pub fn generalizes(general: &[parsed::Expr], special: &[parsed::Expr]) -> bool {
+  general.len() == special.len()
+    && general.iter().zip(special).all(|(g, s)| match (&g.value, &s.value) {
+      (parsed::Clause::Placeh(gph), parsed::Clause::Placeh(sph)) => match (gph.class, sph.class) {
+        (PHClass::Vec { nonzero: gnz, .. }, PHClass::Vec { nonzero: snz, .. }) => !gnz || snz,
+        (PHClass::Vec { .. }, _) | (_, PHClass::Vec { .. }) => false,
+        (PHClass::Name, PHClass::Scalar) => false,
+        _ => true,
+      },
+      (parsed::Clause::Placeh(gph), parsed::Clause::Name(_)) =>
+        !matches!(gph.class, PHClass::Vec { .. }),
+      (parsed::Clause::Placeh(gph), _) => matches!(gph.class, PHClass::Scalar),
+      (parsed::Clause::Name(gn), parsed::Clause::Name(sn)) => gn == sn,
+      (parsed::Clause::S(gp, gb), parsed::Clause::S(sp, sb)) => gp == sp && generalizes(gb, sb),
+      _ => false,
+    })
+}
+
+/// Whether some sequence may match both patterns. This is conservative;
+/// vectorial placeholders are only assumed to overlap with other vectorial
+/// placeholders at the same position.
+pub fn overlaps(a: &[parsed::Expr], b: &[parsed::Expr]) -> bool {
+  let vec = |ph: &Placeholder| matches!(ph.class, PHClass::Vec { .. });
+  a.len() == b.len()
+    && a.iter().zip(b).all(|(a, b)| match (&a.value, &b.value) {
+      (parsed::Clause::Placeh(aph), parsed::Clause::Placeh(bph)) => vec(aph) == vec(bph),
+      (parsed::Clause::Placeh(ph), parsed::Clause::Name(_))
+      | (parsed::Clause::Name(_), parsed::Clause::Placeh(ph)) => !vec(ph),
+      (parsed::Clause::Placeh(ph), _) | (_, parsed::Clause::Placeh(ph)) =>
+        matches!(ph.class, PHClass::Scalar),
+      (parsed::Clause::Name(an), parsed::Clause::Name(bn)) => an == bn,
+      (parsed::Clause::S(ap, ab), parsed::Clause::S(bp, bb)) => ap == bp && overlaps(ab, bb),
+      _ => false,
+    })
+}
+
+/// Pairs of rules with equal priorities and overlapping patterns. Which of
+/// them applies first is unspecified.
+pub fn conflicts(rules: &[SrcRule]) -> Vec<(usize, usize)> {
+  let mut pairs = Vec::new();
+  for (i, a) in rules.iter().enumerate() {
+    for (j, b) in rules.iter().enumerate().skip(i + 1) {
+      if a.rule.prio == b.rule.prio && overlaps(&a.rule.pattern, &b.rule.pattern) {
+        pairs.push((i, j))
+      }
+    }
+  }
+  pairs
+}
+
+/// A priority just above that of a rule which is still below every higher
+/// priority in use, so the rule only overtakes the rules it ties with
+pub fn nudged_prio(rules: &[SrcRule], idx: usize) -> f64 {
+  let prio = *rules[idx].rule.prio;
+  let next = (rules.iter().map(|r| *r.rule.prio)).filter(|p| prio < *p).reduce(f64::min);
+  match next {
+    Some(next) => prio + (next - prio) / 2.0,
+    None if prio + 1.0 != prio => prio + 1.0,
+    None => prio * 2.0,
+  }
+}
+
+/// The rule arrow carrying a priority
+pub fn arrow_text(prio: f64) -> String {
+  match prio {
+    p if p == 0.0 => "=>".to_string(),
+    p if p.abs() < 1e15 => format!("={p}=>"),
+    p => format!("={p:e}=>"),
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::is_exported_rule;
+
+  #[test]
+  fn exported_rules() {
+    let text = "export macro (\n  rule a =0x1p5=> b\n)\nmacro (\n  rule c =0x1p5=> d\n)\n";
+    assert!(is_exported_rule(text, text.find("rule a").unwrap(), [].into_iter()));
+    assert!(!is_exported_rule(text, text.find("rule c").unwrap(), ["c"].into_iter()));
+    let text = "export ::(c)\nmacro rule c =0x1p5=> d\n";
+    assert!(is_exported_rule(text, text.find("rule").unwrap(), ["c"].into_iter()));
+  }
+}
@@ -0,0 +1,287 @@
+//! Lexical scopes of a file: the module, the lambdas of its constants after
+//! macro expansion and its macro rules. Editor features that need to know
+//! which names are visible at a position or what a name refers to query the
+//! tree instead of tracking bindings themselves.
+//!
+//! Lambdas are nested the way the expanded expression nests them, using the
+//! expansion recorded during the load. Macros may move code around, so scopes
+//! built from code outside the file have no range, and offset queries only
+//! descend into scopes that do. The trees are built once per load, see
+//! [LoadedProject::scopes].
+
+use std::ops::Range;
+use std::{iter, ptr};
+
+use hashbrown::HashMap;
+use intern_all::i;
+use orchidlang::location::{SourceCode, SourceRange};
+use orchidlang::name::{Sym, VPath};
+use orchidlang::parse::parsed;
+
+use super::members::{members, MemberKind};
+use super::project::LoadedProject;
+use super::rules::{source_rules, SrcRule};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScopeKind {
+  /// The top level of a file, which binds its constants and modules
+  Module,
+  /// A lambda, which binds its parameter
+  Lambda,
+  /// A macro rule, whose pattern binds its placeholders
+  Rule,
+}
+
+/// A name introduced by a scope
+#[derive(Clone, Debug)]
+pub struct Binder {
+  pub name: Sym,
+  pub kind: ScopeKind,
+  pub range: SourceRange,
+}
+
+#[derive(Clone, Debug)]
+pub struct Scope {
+  pub kind: ScopeKind,
+  /// Byte range in the file, None if the scope came from code elsewhere
+  pub range: Option<Range<usize>>,
+  pub binders: Vec<Binder>,
+  /// Names used directly in the scope and not in a nested one
+  pub uses: Vec<(SourceRange, Sym)>,
+  pub children: Vec<Scope>,
+}
+impl Scope {
+  pub fn new(kind: ScopeKind, range: Option<Range<usize>>) -> Self {
+    Self { kind, range, binders: Vec::new(), uses: Vec::new(), children: Vec::new() }
+  }
+
+  /// Record an expression after macro expansion. Lambdas become nested scopes
+  /// and other names become uses.
+  pub fn add_expr(&mut self, ast: &parsed::Expr, file: &Sym) {
+    match &ast.value {
+      parsed::Clause::Lambda(arg, body) => {
+        let range = (&ast.range.path() == file).then(|| ast.range.range());
+        let mut scope = Scope::new(ScopeKind::Lambda, range);
+        if let [parsed::Expr { value: parsed::Clause::Name(n), range }] = &arg[..] {
+          let binder = Binder { name: n.clone(), kind: ScopeKind::Lambda, range: range.clone() };
+          scope.binders.push(binder);
+        }
+        body.iter().for_each(|ex| scope.add_expr(ex, file));
+        self.children.push(scope)
+      },
+      parsed::Clause::Name(n) => self.uses.push((ast.range.clone(), n.clone())),
+      parsed::Clause::S(_, body) => body.iter().for_each(|ex| self.add_expr(ex, file)),
+      _ => (),
+    }
+  }
+
+  fn contains(&self, offset: usize) -> bool {
+    self.range.as_ref().is_some_and(|r| r.start <= offset && offset <= r.end)
+  }
+
+  /// Every use in this scope and the ones nested in it, with the binder it
+  /// refers to if it's bound by one of them or by `outer`
+  fn resolve<'a>(
+    &'a self,
+    outer: &mut Vec<&'a Binder>,
+    out: &mut Vec<(&'a SourceRange, &'a Sym, Option<&'a Binder>)>,
+  ) {
+    let len = outer.len();
+    outer.extend(self.binders.iter());
+    for (range, name) in self.uses.iter() {
+      out.push((range, name, outer.iter().rev().find(|b| &b.name == name).copied()))
+    }
+    self.children.iter().for_each(|child| child.resolve(outer, out));
+    outer.truncate(len);
+  }
+
+  /// Every use with the binder it refers to, if any
+  pub fn resolved(&self) -> Vec<(&SourceRange, &Sym, Option<&Binder>)> {
+    let mut out = Vec::new();
+    self.resolve(&mut Vec::new(), &mut out);
+    out
+  }
+
+  /// The binders of this scope and every scope nested in it
+  pub fn all_binders(&self) -> Vec<&Binder> {
+    let nested = self.children.iter().flat_map(|c| c.all_binders());
+    self.binders.iter().chain(nested).collect()
+  }
+}
+
+/// The byte range of the last segment of a name within the range of an
+/// occurrence, which also covers the `$` and arity of placeholders
+fn spelled(range: &SourceRange, name: &Sym) -> Option<Range<usize>> {
+  let text = range.text();
+  let start = range.start() + text[range.range()].rfind(name.last().as_str())?;
+  Some(start..start + name.last().len())
+}
+
+/// The scopes of a source file
+pub struct ScopeTree {
+  /// The module path of the file
+  pub file: Sym,
+  pub root: Scope,
+}
+impl ScopeTree {
+  /// Build the scopes of a file given the rules of the project. The path is
+  /// relative to the project root.
+  pub fn new(lpr: &LoadedProject, path: &VPath, rules: &[SrcRule]) -> Option<Self> {
+    let module = path.clone().prefix([i!(str: "tree")]);
+    let file = Sym::new(module.iter().cloned()).ok()?;
+    let text = lpr.source(path)?;
+    let code = SourceCode::new(file.clone(), text.clone());
+    let mut root = Scope::new(ScopeKind::Module, Some(0..text.len()));
+    for m in members(&text) {
+      if m.name.is_empty() || !matches!(m.kind, MemberKind::Const | MemberKind::Module) {
+        continue;
+      }
+      let Ok(name) = Sym::new(module.iter().cloned().chain([i(&m.name)])) else { continue };
+      let range = SourceRange::new(m.name_range, code.clone());
+      root.binders.push(Binder { name, kind: ScopeKind::Module, range });
+    }
+    for c in lpr.module_consts(&module).unwrap_or_default() {
+      let Some(postmacro) = lpr.expansion(c).and_then(|e| e.output.as_ref()) else { continue };
+      root.add_expr(postmacro, &file);
+    }
+    for rule in rules.iter().filter(|r| &r.file == path) {
+      let mut scope = Scope::new(ScopeKind::Rule, Some(rule.range.clone()));
+      let placeholders = |exprs: &[parsed::Expr]| {
+        let mut found = Vec::new();
+        for ex in exprs {
+          ex.search_all(&mut |ex| {
+            if let parsed::Clause::Placeh(ph) = &ex.value {
+              found.extend(Sym::new([ph.name.clone()]).ok().map(|n| (ex.range.clone(), n)));
+            }
+            None::<()>
+          });
+        }
+        found
+      };
+      for (range, name) in placeholders(&rule.rule.pattern) {
+        scope.binders.push(Binder { name, kind: ScopeKind::Rule, range })
+      }
+      scope.uses = placeholders(&rule.rule.template);
+      root.children.push(scope);
+    }
+    Some(Self { file, root })
+  }
+
+  /// Build the scopes of every source file of a project
+  pub fn all(lpr: &LoadedProject) -> HashMap<VPath, Self> {
+    let rules = source_rules(lpr);
+    (lpr.source_files().into_iter())
+      .filter_map(|file| Some((file.clone(), Self::new(lpr, &file, &rules)?)))
+      .collect()
+  }
+
+  /// The scopes that contain the offset, from the outermost
+  fn chain(&self, offset: usize) -> Vec<&Scope> {
+    let (mut chain, mut scope) = (vec![&self.root], &self.root);
+    while let Some(inner) = scope.children.iter().find(|c| c.contains(offset)) {
+      chain.push(inner);
+      scope = inner;
+    }
+    chain
+  }
+
+  /// The names visible at the offset, innermost first. Shadowed names are
+  /// omitted.
+  pub fn visible_at(&self, offset: usize) -> Vec<&Binder> {
+    let mut visible = Vec::<&Binder>::new();
+    for scope in self.chain(offset).into_iter().rev() {
+      for binder in scope.binders.iter() {
+        if !visible.iter().any(|b| b.name == binder.name) {
+          visible.push(binder)
+        }
+      }
+    }
+    visible
+  }
+
+  /// The binder a name used at the offset refers to
+  pub fn binder_of(&self, name: &Sym, offset: usize) -> Option<&Binder> {
+    self.visible_at(offset).into_iter().find(|b| &b.name == name)
+  }
+
+  /// The local binder of the name at the offset and every range within the
+  /// file that spells it, the binder first. None if the name isn't a lambda
+  /// parameter or placeholder, or if macros moved it out of the file.
+  pub fn local_occurrences(&self, offset: usize) -> Option<(&Binder, Vec<Range<usize>>)> {
+    let (binder, uses) = self.occurrences(offset)?;
+    if binder.kind == ScopeKind::Module || binder.range.path() != self.file {
+      return None;
+    }
+    let ranges = iter::once(&binder.range).chain(uses).map(|r| spelled(r, &binder.name));
+    Some((binder, ranges.collect::<Option<_>>()?))
+  }
+
+  /// The binder of the name at the offset, which may be the binder itself or
+  /// a use of it, and the uses of that binder within the file
+  pub fn occurrences(&self, offset: usize) -> Option<(&Binder, Vec<&SourceRange>)> {
+    let in_file = |r: &SourceRange| r.path() == self.file;
+    let at = |r: &SourceRange| in_file(r) && r.start() <= offset && offset <= r.end();
+    let resolved = self.root.resolved();
+    let binder = match self.root.all_binders().into_iter().find(|b| at(&b.range)) {
+      Some(binder) => binder,
+      None => resolved.iter().find(|(range, ..)| at(range)).and_then(|(.., b)| *b)?,
+    };
+    let uses = (resolved.into_iter())
+      .filter(|(range, _, b)| in_file(range) && b.is_some_and(|b| ptr::eq(b, binder)))
+      .map(|(range, ..)| range)
+      .collect();
+    Some((binder, uses))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::ops::Range;
+
+  use intern_all::i;
+  use orchidlang::name::VPath;
+
+  use super::ScopeKind;
+  use crate::testing::Fixture;
+
+  /// The ranges renaming the name at each offset would edit in `main`
+  fn renames(text: &str, offsets: &[usize]) -> Vec<Option<Vec<Range<usize>>>> {
+    let fixture = Fixture::new(&[("main", text)]);
+    let lpr = fixture.load();
+    let scopes = lpr.scopes(&VPath::new([i!(str: "main")])).expect("main is a source file");
+    let rename = |offset| scopes.local_occurrences(offset).map(|(_, ranges)| ranges);
+    offsets.iter().map(|offset| rename(*offset)).collect()
+  }
+
+  #[test]
+  fn shadowing() {
+    let text = "const f := \\x. x (\\x. x)\nconst g := f\n";
+    let [outer, inner, inner_use, module] = &renames(text, &[12, 19, 22, 36])[..] else {
+      panic!("One result per offset")
+    };
+    assert_eq!(outer.as_deref(), Some(&[12..13, 15..16][..]), "The shadowed use is left out");
+    assert_eq!(inner.as_deref(), Some(&[19..20, 22..23][..]));
+    assert_eq!(inner_use, inner, "Uses lead to the same binder");
+    assert_eq!(*module, None, "Constants aren't local");
+  }
+
+  #[test]
+  fn placeholders() {
+    let text = "macro rule double $a =0x1p5=> ($a $a)\n";
+    let [binder, used] = &renames(text, &[19, 32])[..] else { panic!("One result per offset") };
+    assert_eq!(binder.as_deref(), Some(&[19..20, 32..33, 35..36][..]), "Without the $");
+    assert_eq!(used, binder);
+  }
+
+  #[test]
+  fn binders() {
+    let fixture = Fixture::new(&[("main", "const f := \\x. x\n")]);
+    let lpr = fixture.load();
+    let scopes = lpr.scopes(&VPath::new([i!(str: "main")])).unwrap();
+    let (binder, uses) = scopes.occurrences(15).expect("x is used at 15");
+    assert_eq!((binder.kind, binder.range.range()), (ScopeKind::Lambda, 12..13));
+    assert_eq!(uses.iter().map(|r| r.range()).collect::<Vec<_>>(), [15..16]);
+    let (binder, _) = scopes.occurrences(6).expect("f is declared at 6");
+    assert_eq!(binder.kind, ScopeKind::Module);
+  }
+}
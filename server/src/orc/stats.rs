@@ -0,0 +1,50 @@
+//! Size and coupling of modules, an overview of the structure of large
+//! projects
+
+use std::ops::Range;
+
+use hashbrown::HashMap;
+use intern_all::{i, Tok};
+use itertools::Itertools;
+use orchidlang::location::SourceRange;
+use orchidlang::name::VPath;
+
+use super::project::LoadedProject;
+use super::rules::SrcRule;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ModuleStats {
+  /// Constants of the module and its submodules
+  pub constants: usize,
+  /// Macro rules declared in the module and its submodules
+  pub rules: usize,
+  /// References to the module's constants from outside of it
+  pub incoming: usize,
+}
+
+/// Statistics of the module of a file, or of a module declared at the top level
+/// of the file, given its name and the byte range of its declaration. The
+/// references and rules are those of the whole project.
+pub fn module_stats(
+  lpr: &LoadedProject,
+  refs: &HashMap<Vec<Tok<String>>, Vec<SourceRange>>,
+  rules: &[SrcRule],
+  file: &VPath,
+  inner: Option<(&str, Range<usize>)>,
+) -> Option<ModuleStats> {
+  let file_module = file.clone().prefix([i!(str: "tree")]);
+  let module = match &inner {
+    Some((name, _)) => VPath::new(file_module.iter().cloned().chain([i(*name)])),
+    None => file_module.clone(),
+  };
+  let constants = lpr.module_consts(&module)?.len();
+  let in_decl = |offset: usize| inner.as_ref().is_none_or(|(_, decl)| decl.contains(&offset));
+  let rules = rules.iter().filter(|r| r.file == *file && in_decl(r.range.start)).count();
+  let prefix = module.iter().cloned().collect_vec();
+  let incoming = (refs.iter())
+    .filter(|(name, _)| prefix.len() < name.len() && name.starts_with(&prefix))
+    .flat_map(|(_, uses)| uses)
+    .filter(|u| !(u.path().iter().eq(file_module.iter()) && in_decl(u.range().start)))
+    .count();
+  Some(ModuleStats { constants, rules, incoming })
+}
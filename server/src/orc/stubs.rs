@@ -0,0 +1,108 @@
+//! Synthesized declaration documents for constants that have no source in the
+//! workspace, such as the ones injected by systems. These are served to the
+//! client as virtual documents under the [STUB_SCHEME] URI scheme.
+
+use std::fmt::Write;
+use std::ops::Range;
+use std::sync::Arc;
+
+use hashbrown::HashMap;
+use intern_all::Tok;
+use itertools::Itertools;
+use orchidlang::name::VPath;
+use orchidlang::parse::parsed;
+use orchidlang::pipeline::project::{ItemKind, ProjItem, ProjectMod};
+use orchidlang::tree::ModMember;
+
+pub const STUB_SCHEME: &str = "orchid-stub";
+
+/// A generated document listing the system-provided constants of a module
+pub struct Stub {
+  /// The first segment of the module path, which is the namespace the
+  /// providing system registers its constants in
+  pub system: Tok<String>,
+  pub text: Arc<String>,
+  /// Byte ranges of the declared names within [Stub::text]
+  pub decls: HashMap<Tok<String>, Range<usize>>,
+}
+
+/// Stubs for every module outside the `tree` namespace that contains
+/// constants, keyed by the module path
+pub struct StubIndex(HashMap<VPath, Stub>);
+impl StubIndex {
+  pub fn new(root: &ProjectMod) -> Self {
+    let mut consts = HashMap::new();
+    collect(root, &mut Vec::new(), &mut consts);
+    Self(
+      (consts.into_iter())
+        .map(|(path, items)| (VPath::new(path.clone()), render(&path, items)))
+        .collect(),
+    )
+  }
+
+  pub fn get(&self, module: &[Tok<String>]) -> Option<&Stub> {
+    self.0.get(&VPath::new(module.iter().cloned()))
+  }
+
+  /// Find the stub declaring a fully qualified name, and the range of the
+  /// declaration within it
+  pub fn locate(&self, name: &[Tok<String>]) -> Option<(&Stub, Range<usize>)> {
+    let (last, module) = name.split_last()?;
+    let stub = self.get(module)?;
+    Some((stub, stub.decls.get(last)?.clone()))
+  }
+}
+
+type ModuleConsts<'a> = HashMap<Vec<Tok<String>>, Vec<(Tok<String>, &'a parsed::Expr)>>;
+
+fn collect<'a>(module: &'a ProjectMod, path: &mut Vec<Tok<String>>, out: &mut ModuleConsts<'a>) {
+  for (key, ent) in module.entries.iter() {
+    match &ent.member {
+      // Workspace code has real files to point at
+      ModMember::Sub(_) if path.is_empty() && &**key == "tree" => (),
+      ModMember::Sub(sub) => {
+        path.push(key.clone());
+        collect(sub, path, out);
+        path.pop();
+      },
+      ModMember::Item(ProjItem { kind: ItemKind::Const(val) }) if !path.is_empty() =>
+        out.entry(path.clone()).or_default().push((key.clone(), val)),
+      _ => (),
+    }
+  }
+}
+
+fn render(path: &[Tok<String>], items: Vec<(Tok<String>, &parsed::Expr)>) -> Stub {
+  let system = path[0].clone();
+  let mut text = String::new();
+  writeln!(text, "-- Declarations of {} provided by the {system} system.", path.iter().join("::"))
+    .unwrap();
+  writeln!(text, "-- This document is generated by orchid-ls and cannot be edited.\n").unwrap();
+  let mut decls = HashMap::new();
+  for (name, val) in items.into_iter().sorted_unstable_by(|a, b| a.0.as_str().cmp(b.0.as_str())) {
+    text.push_str("export const ");
+    let start = text.len();
+    text.push_str(name.as_str());
+    decls.insert(name, start..text.len());
+    let source = val.range.text();
+    let summary = (source.get(val.range.range()))
+      .and_then(|s| s.lines().next())
+      .filter(|s| !s.trim().is_empty())
+      .unwrap_or("<foreign>");
+    writeln!(text, " := {summary}").unwrap();
+  }
+  Stub { system, text: Arc::new(text), decls }
+}
+
+/// Convert a stub URI (`orchid-stub:///std/number.orc`) to a module path
+pub fn parse_stub_uri(uri: &str) -> Option<Vec<String>> {
+  let path = uri.strip_prefix(STUB_SCHEME)?.strip_prefix(":///")?;
+  let path = path.strip_suffix(".orc").unwrap_or(path);
+  path.split('/').map(|s| urlencoding::decode(s).map(|s| s.into_owned())).try_collect().ok()
+}
+
+/// Convert a module path to a stub URI
+pub fn stub_uri(module: &[Tok<String>]) -> String {
+  let path = module.iter().map(|s| urlencoding::encode(s.as_str())).join("/");
+  format!("{STUB_SCHEME}:///{path}.orc")
+}
@@ -0,0 +1,161 @@
+//! Comments that suppress lints. `-- orchid-ignore: code, ...` on its own line
+//! suppresses the lints with those codes in the member below it if it's among
+//! the comments above a member, and otherwise on the next line of code. After
+//! code on a line, it suppresses them on that line.
+//! `-- orchid-ignore-file: code, ...` suppresses them in the whole file.
+
+use std::ops::Range;
+
+use super::lexical::{lex, LexKind};
+use super::members::{members, Edit};
+
+const LINE: &str = "orchid-ignore:";
+const FILE: &str = "orchid-ignore-file:";
+
+/// A lint code suppressed in a range of the file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Suppression {
+  pub code: String,
+  pub scope: Range<usize>,
+}
+
+/// Whether a line comment is a directive for the whole file, and the codes it
+/// lists
+fn directive(comment: &str) -> Option<(bool, Vec<&str>)> {
+  let text = comment.strip_prefix("--").filter(|c| !c.starts_with('['))?.trim();
+  let (file, codes) = match text.strip_prefix(FILE) {
+    Some(codes) => (true, codes),
+    None => (false, text.strip_prefix(LINE)?),
+  };
+  Some((file, codes.split(',').map(str::trim).filter(|c| !c.is_empty()).collect()))
+}
+
+/// The range of the line around an offset without the line break
+fn line_at(text: &str, offset: usize) -> Range<usize> {
+  let start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+  start..text[offset..].find('\n').map_or(text.len(), |i| offset + i)
+}
+
+/// The suppressions declared in a file
+pub fn suppressions(text: &str) -> Vec<Suppression> {
+  let lexemes = lex(text);
+  let members = members(text);
+  let mut found = Vec::new();
+  for (i, (_, range)) in lexemes.iter().enumerate().filter(|(_, (k, _))| *k == LexKind::Comment) {
+    let Some((file, codes)) = directive(&text[range.clone()]) else { continue };
+    let line = line_at(text, range.start);
+    let above = members.iter().find(|m| m.range.start <= range.start && range.end <= m.header);
+    let scope = if file {
+      0..usize::MAX
+    } else if !text[line.start..range.start].trim().is_empty() {
+      line
+    } else if let Some(m) = above {
+      m.header..m.range.end
+    } else {
+      let next = lexemes[i + 1..].iter().find(|(kind, _)| *kind != LexKind::Comment);
+      next.map_or(0..0, |(_, next)| line_at(text, next.start))
+    };
+    let code = |code: &str| Suppression { code: code.to_string(), scope: scope.clone() };
+    found.extend(codes.into_iter().map(code));
+  }
+  found
+}
+
+/// Whether a finding is suppressed. Findings are attributed to where they start.
+pub fn is_suppressed(suppressions: &[Suppression], code: &str, range: &Range<usize>) -> bool {
+  suppressions.iter().any(|s| s.code == code && s.scope.contains(&range.start))
+}
+
+/// The edit that suppresses a lint on the line of an offset. The code is added
+/// to the directive directly above the line if there is one. On the first line
+/// of a member, where a directive above would cover the whole member, the
+/// directive trails the code unless the line already ends in another comment.
+pub fn suppress_line(text: &str, offset: usize, code: &str) -> Edit {
+  let line = line_at(text, offset);
+  if members(text).iter().any(|m| line.contains(&m.header)) {
+    let end = line.start + text[line.clone()].trim_end().len();
+    match lex(&text[line.start..end]).last() {
+      Some((LexKind::Comment, comment)) => {
+        if directive(&text[line.start + comment.start..end]).is_some_and(|(file, _)| !file) {
+          return (end..end, format!(", {code}"));
+        }
+      },
+      _ => return (end..end, format!(" -- {LINE} {code}")),
+    }
+  }
+  if 0 < line.start {
+    let prev = line_at(text, line.start - 1);
+    let prev_text = text[prev.clone()].trim_end();
+    if directive(prev_text.trim_start()).is_some_and(|(file, _)| !file) {
+      let end = prev.start + prev_text.len();
+      return (end..end, format!(", {code}"));
+    }
+  }
+  let text = &text[line.clone()];
+  let indent = &text[..text.len() - text.trim_start().len()];
+  (line.start..line.start, format!("{indent}-- {LINE} {code}\n"))
+}
+
+/// The edit that suppresses a lint in the whole file. The code is added to a
+/// file directive on the first line if there is one.
+pub fn suppress_file(text: &str, code: &str) -> Edit {
+  let first = text[line_at(text, 0)].trim_end();
+  match directive(first) {
+    Some((true, _)) => (first.len()..first.len(), format!(", {code}")),
+    _ => (0..0, format!("-- {FILE} {code}\n")),
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{is_suppressed, suppress_file, suppress_line, suppressions};
+
+  fn suppressed(text: &str, code: &str, at: &str) -> bool {
+    let offset = text.find(at).unwrap();
+    is_suppressed(&suppressions(text), code, &(offset..offset + at.len()))
+  }
+
+  #[test]
+  fn scopes() {
+    let text = "-- orchid-ignore: a\nconst x := 1\n  + 2\nconst y := 3\n";
+    assert!(suppressed(text, "a", "2"), "Comments above a member cover the member");
+    assert!(!suppressed(text, "a", "3"));
+    assert!(!suppressed(text, "b", "1"), "Other lints are reported");
+    let text = "const x := (\n  -- orchid-ignore: a, b\n  f 1\n  g 2\n)\n";
+    assert!(suppressed(text, "b", "1"), "Inside a member only the next line is covered");
+    assert!(!suppressed(text, "a", "2"));
+    let text = "const x := 1 -- orchid-ignore: a\nconst y := 2\n";
+    assert!(suppressed(text, "a", "1"), "Trailing directives cover their own line");
+    assert!(!suppressed(text, "a", "2"));
+    let text = "const x := 1\n-- orchid-ignore-file: a\nconst y := 2\n";
+    assert!(suppressed(text, "a", "1") && suppressed(text, "a", "2"));
+    assert!(suppressions("--[ orchid-ignore: a ]--\nconst x := 1").is_empty());
+  }
+
+  #[test]
+  fn edits() {
+    let apply = |text: &str, (range, new): (std::ops::Range<usize>, String)| {
+      format!("{}{new}{}", &text[..range.start], &text[range.end..])
+    };
+    let text = "const x := (\n  f 1\n)\n";
+    let once = apply(text, suppress_line(text, text.find('f').unwrap(), "a"));
+    assert_eq!(once, "const x := (\n  -- orchid-ignore: a\n  f 1\n)\n");
+    let twice = apply(&once, suppress_line(&once, once.find('f').unwrap(), "b"));
+    assert_eq!(twice, "const x := (\n  -- orchid-ignore: a, b\n  f 1\n)\n");
+    assert!(suppressed(&twice, "b", "f 1"));
+    let text = "const x := f 1\nconst y := 2\n";
+    let trailing = apply(text, suppress_line(text, text.find('f').unwrap(), "a"));
+    assert_eq!(trailing, "const x := f 1 -- orchid-ignore: a\nconst y := 2\n", "Not the member");
+    let both = apply(&trailing, suppress_line(&trailing, trailing.find('f').unwrap(), "b"));
+    assert_eq!(both, "const x := f 1 -- orchid-ignore: a, b\nconst y := 2\n");
+    assert!(suppressed(&both, "b", "f 1") && !suppressed(&both, "b", "2"));
+    let noted = "const x := f 1 -- note\n";
+    let above = apply(noted, suppress_line(noted, 11, "a"));
+    assert_eq!(above, "-- orchid-ignore: a\nconst x := f 1 -- note\n", "The comment can't hold it");
+    let text = "const x := (\n  f 1\n)\n";
+    let file = apply(text, suppress_file(text, "a"));
+    assert_eq!(file, "-- orchid-ignore-file: a\nconst x := (\n  f 1\n)\n");
+    let file = apply(&file, suppress_file(&file, "b"));
+    assert!(file.starts_with("-- orchid-ignore-file: a, b\n"));
+  }
+}
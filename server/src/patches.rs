@@ -0,0 +1,177 @@
+//! The files of a workspace folder as project loads read them, with the text
+//! of open documents in place of the files on disk
+
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+use hashbrown::{HashMap, HashSet};
+use intern_all::Tok;
+use itertools::Itertools;
+use orchidlang::name::PathSlice;
+use orchidlang::virt_fs::{DirNode, FSResult, Loaded, PrefixFS, VirtFS};
+
+use crate::documents::DocSnapshot;
+use crate::log;
+use crate::orc::encoding::{self, Encoding};
+use crate::protocol::document::FileUri;
+use crate::protocol::line_index::LineIndex;
+
+/// Files and directory listings read from disk, shared by all loads of a
+/// workspace folder. Entries are invalidated when the file changes on disk.
+#[derive(Default)]
+pub struct DiskCache {
+  entries: Mutex<HashMap<Vec<Tok<String>>, Loaded>>,
+  /// Files that weren't plain UTF-8, and how they were decoded
+  encodings: Mutex<HashMap<Vec<Tok<String>>, Encoding>>,
+}
+impl DiskCache {
+  /// Forget a file or directory, everything in it, and the listings of the
+  /// folders that contain it. Creating or deleting a file may also create or
+  /// delete the folders above it, and clients only report the file.
+  pub fn invalidate(&self, path: &[Tok<String>]) {
+    let mut entries = self.entries.lock().unwrap();
+    entries.retain(|k, _| !k.starts_with(path) && !path.starts_with(k));
+    self.encodings.lock().unwrap().retain(|k, _| !k.starts_with(path));
+  }
+  /// How the file was decoded if it was read from disk and isn't plain UTF-8
+  pub fn encoding(&self, path: &[Tok<String>]) -> Option<Encoding> {
+    self.encodings.lock().unwrap().get(path).copied()
+  }
+  /// Number of cached entries, bytes of cached source, and the distinct path
+  /// segments held interned by the cache
+  pub fn stats(&self) -> (usize, usize, HashSet<Tok<String>>) {
+    let entries = self.entries.lock().unwrap();
+    let (mut bytes, mut segments) = (0, HashSet::new());
+    for (path, loaded) in entries.iter() {
+      segments.extend(path.iter().cloned());
+      match loaded {
+        Loaded::Code(text) => bytes += text.len(),
+        Loaded::Collection(items) => segments.extend(items.iter().cloned()),
+      }
+    }
+    (entries.len(), bytes, segments)
+  }
+  pub fn clear(&self) {
+    self.entries.lock().unwrap().clear();
+    self.encodings.lock().unwrap().clear();
+  }
+}
+
+/// The files of a workspace folder as loads see them. Open documents are
+/// represented by the latest [DocSnapshot] taken by a load, other files are
+/// read from disk through the [DiskCache].
+#[derive(Clone)]
+pub struct PatchStore {
+  basepath: FileUri,
+  patches: Vec<(FileUri, DocSnapshot)>,
+  disk: Arc<DiskCache>,
+}
+impl PatchStore {
+  pub fn new(basepath: FileUri) -> Arc<Self> {
+    Arc::new(Self { basepath, patches: Vec::new(), disk: Arc::default() })
+  }
+  pub fn unpack(self: Arc<Self>) -> Self { Arc::unwrap_or_clone(self) }
+  pub fn change(self: &mut Arc<Self>, cb: impl FnOnce(&mut Self)) {
+    take_mut::take(self, |arc| {
+      let mut this = arc.unpack();
+      cb(&mut this);
+      Arc::new(this)
+    })
+  }
+  fn index_of(&self, uri: &FileUri) -> Option<usize> {
+    self.patches.iter().find_position(|(u, _)| u == uri).map(|p| p.0)
+  }
+  pub fn basepath(&self) -> &FileUri { &self.basepath }
+  /// Use a snapshot of an open document instead of the file on disk
+  pub fn patch(&mut self, uri: FileUri, snap: DocSnapshot) {
+    match self.index_of(&uri) {
+      None => self.patches.push((uri, snap)),
+      Some(idx) => self.patches[idx].1 = snap,
+    }
+  }
+  /// The text of a patched file
+  pub fn text(&self, uri: &FileUri) -> Option<&str> {
+    Some(&self.patches[self.index_of(uri)?].1.text)
+  }
+  /// The editor's version of a patched file
+  pub fn version(&self, uri: &FileUri) -> Option<u64> {
+    Some(self.patches[self.index_of(uri)?].1.version)
+  }
+  /// The line index of a patched file
+  pub fn line_index(&self, uri: &FileUri) -> Option<Arc<LineIndex>> {
+    Some(self.patches[self.index_of(uri)?].1.lines.clone())
+  }
+  /// Forget the text of a file so that it's read from disk again. Returns
+  /// whether the file was patched.
+  pub fn unpatch(&mut self, uri: &FileUri) -> bool {
+    let Some(idx) = self.index_of(uri) else { return false };
+    self.patches.remove(idx);
+    true
+  }
+  pub fn disk(&self) -> &DiskCache { &self.disk }
+  /// Number of open files and bytes of their text
+  pub fn open_stats(&self) -> (usize, usize) {
+    (self.patches.len(), self.patches.iter().map(|(_, snap)| snap.text.len()).sum())
+  }
+  /// Whether any file is open under a path relative to the base path
+  pub fn has_open(&self, prefix: &[Tok<String>]) -> bool {
+    (self.patches.iter())
+      .filter_map(|(uri, _)| uri.to_vpath(&self.basepath))
+      .any(|path| path[..].starts_with(prefix))
+  }
+  /// Make the next load read a file from disk again
+  pub fn invalidate(&self, uri: &FileUri) {
+    if let Some(path) = uri.to_vpath(&self.basepath) {
+      self.disk.invalidate(&path[..])
+    }
+  }
+  pub fn mk_vfs(self: Arc<Self>, path: &FileUri) -> Option<impl VirtFS> {
+    let subpath = path.to_vpath(&self.basepath)?;
+    log!(Debug, "Building VFS for {subpath} in {}", self.basepath);
+    Some(PrefixFS::new(PatchFS::new(self), "", subpath.to_string()))
+  }
+}
+
+pub struct PatchFS {
+  basedir: DirNode,
+  store: Arc<PatchStore>,
+}
+impl PatchFS {
+  pub fn new(store: Arc<PatchStore>) -> Self {
+    Self { basedir: DirNode::new(store.basepath().to_path(), ".orc"), store }
+  }
+  /// Read a source file that isn't necessarily UTF-8, see [encoding::decode].
+  /// Returns None for directories and files that can't be read, which are left
+  /// to [DirNode].
+  fn read_source(&self, path: &[Tok<String>]) -> Option<String> {
+    let mut file = self.store.basepath().extended(path.iter().map(|t| t.as_str())).to_path();
+    file.as_mut_os_string().push(".orc");
+    let (text, enc) = encoding::decode(&fs::read(&file).ok()?);
+    if let Encoding::Invalid(offset) = enc {
+      log!(Warn, "{} isn't valid UTF-8 at byte {offset}", file.display());
+    }
+    if enc != Encoding::Utf8 {
+      self.store.disk.encodings.lock().unwrap().insert(path.to_vec(), enc);
+    }
+    Some(text)
+  }
+}
+impl VirtFS for PatchFS {
+  fn get(&self, path: &[Tok<String>], full_path: &PathSlice) -> FSResult {
+    let pbuf = self.store.basepath();
+    if let Some(i) = self.store.index_of(&pbuf.extended(path.iter().map(|t| t.as_str()))) {
+      return Ok(Loaded::Code(self.store.patches[i].1.text.clone()));
+    }
+    if let Some(loaded) = self.store.disk.entries.lock().unwrap().get(path) {
+      return Ok(loaded.clone());
+    }
+    let loaded = match self.read_source(path) {
+      Some(text) => Loaded::Code(Arc::new(text)),
+      // Errors aren't cached, the file may be readable next time
+      None => self.basedir.get(path, full_path)?,
+    };
+    self.store.disk.entries.lock().unwrap().insert(path.to_vec(), loaded.clone());
+    Ok(loaded)
+  }
+  fn display(&self, path: &[Tok<String>]) -> Option<String> { self.basedir.display(path) }
+}
@@ -0,0 +1,81 @@
+//! Client capabilities the server adapts its behaviour to
+
+use serde_json::{json, Value};
+
+/// The relevant subset of the capabilities sent by the client in `initialize`.
+/// Stored in the session so that handlers don't have to query raw JSON, and
+/// features degrade in one place when the client lacks a capability.
+///
+/// Document sync needs no degradation because every client must accept the
+/// full text sync the server asks for.
+#[derive(Clone, Debug, Default)]
+pub struct ClientProfile {
+  /// `textDocument.semanticTokens.multilineTokenSupport`
+  pub multiline_tokens: bool,
+  /// `textDocument.semanticTokens.tokenTypes`, the types the client can
+  /// display. None if the client doesn't say.
+  pub token_types: Option<Vec<String>>,
+  /// `textDocument.semanticTokens.dynamicRegistration`
+  pub dynamic_semantic_tokens: bool,
+  /// `workspace.didChangeWatchedFiles.dynamicRegistration`
+  pub dynamic_watchers: bool,
+  /// `window.showDocument.support`
+  pub show_document: bool,
+  /// `window.workDoneProgress`
+  pub work_done_progress: bool,
+  /// `workspace.workspaceFolders`
+  pub workspace_folders: bool,
+  /// `workspace.codeLens.refreshSupport`
+  pub code_lens_refresh: bool,
+  /// `workspace.symbol.resolveSupport.properties` includes `location.range`.
+  /// Workspace symbols must come with their ranges otherwise.
+  pub resolve_symbol_range: bool,
+  /// `workspace.workspaceEdit.resourceOperations` includes `create`. Edits
+  /// can't create files otherwise.
+  pub create_files: bool,
+  /// `textDocument.definition.linkSupport`. Definitions must be plain
+  /// locations otherwise.
+  pub definition_links: bool,
+  /// `textDocument.hover.contentFormat` includes markdown. Hovers are sent as
+  /// plain text otherwise.
+  pub markdown_hover: bool,
+  /// `textDocument.diagnostic` and `workspace.diagnostics.refreshSupport`.
+  /// Diagnostics must be published otherwise.
+  pub pull_diagnostics: bool,
+}
+impl ClientProfile {
+  pub fn new(caps: &Value) -> Self {
+    let semtok = &caps["textDocument"]["semanticTokens"];
+    Self {
+      multiline_tokens: semtok["multilineTokenSupport"].as_bool().unwrap_or(false),
+      token_types: (semtok["tokenTypes"].as_array())
+        .map(|types| types.iter().filter_map(|t| Some(t.as_str()?.to_string())).collect()),
+      dynamic_semantic_tokens: semtok["dynamicRegistration"].as_bool().unwrap_or(false),
+      dynamic_watchers: (caps["workspace"]["didChangeWatchedFiles"]["dynamicRegistration"])
+        .as_bool()
+        .unwrap_or(false),
+      show_document: caps["window"]["showDocument"]["support"].as_bool().unwrap_or(false),
+      work_done_progress: caps["window"]["workDoneProgress"].as_bool().unwrap_or(false),
+      workspace_folders: caps["workspace"]["workspaceFolders"].as_bool().unwrap_or(false),
+      code_lens_refresh: caps["workspace"]["codeLens"]["refreshSupport"].as_bool().unwrap_or(false),
+      resolve_symbol_range: (caps["workspace"]["symbol"]["resolveSupport"]["properties"].as_array())
+        .is_some_and(|props| props.iter().any(|p| p == "location.range")),
+      create_files: (caps["workspace"]["workspaceEdit"]["resourceOperations"].as_array())
+        .is_some_and(|ops| ops.iter().any(|op| op == "create")),
+      definition_links: (caps["textDocument"]["definition"]["linkSupport"])
+        .as_bool()
+        .unwrap_or(false),
+      markdown_hover: (caps["textDocument"]["hover"]["contentFormat"].as_array())
+        .is_some_and(|formats| formats.iter().any(|f| f == "markdown")),
+      pull_diagnostics: caps["textDocument"]["diagnostic"].is_object()
+        && caps["workspace"]["diagnostics"]["refreshSupport"].as_bool().unwrap_or(false),
+    }
+  }
+  /// `MarkupContent` in the best format the client supports for hovers
+  pub fn hover_markup(&self, markdown: String, plain: String) -> Value {
+    match self.markdown_hover {
+      true => json!({ "kind": "markdown", "value": markdown }),
+      false => json!({ "kind": "plaintext", "value": plain }),
+    }
+  }
+}
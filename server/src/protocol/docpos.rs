@@ -1,3 +1,5 @@
+use std::{fmt, iter};
+
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 
@@ -6,83 +8,106 @@ use serde::{Deserialize, Serialize};
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct DocPos {
   pub line: usize,
-  #[serde(alias = "character")]
+  #[serde(rename = "character")]
   pub char: usize,
 }
 impl DocPos {
   pub fn new(line: usize, char: usize) -> Self { Self { line, char } }
 }
 
+/// Split a text into lines according to LSP. Yields the byte offset, content
+/// and terminator of each line. The terminator is one of `\n`, `\r\n` or `\r`,
+/// except on the last line where it's empty.
+pub fn lsp_lines(text: &str) -> impl Iterator<Item = (usize, &str, &str)> {
+  let mut start = Some(0);
+  iter::from_fn(move || {
+    let line_start = start?;
+    let rest = &text[line_start..];
+    let Some(end) = rest.find(['\r', '\n']) else {
+      start = None;
+      return Some((line_start, rest, ""));
+    };
+    let term_len = if rest[end..].starts_with("\r\n") { 2 } else { 1 };
+    start = Some(line_start + end + term_len);
+    Some((line_start, &rest[..end], &rest[end..end + term_len]))
+  })
+}
+
+/// A position that can't be mapped to the document even with the clamping
+/// rules of LSP
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PositionError {
+  /// The position points between the two utf-16 code units of a character
+  InsideChar(DocPos),
+  /// The position is more than one line past the last line of the document
+  PastEnd { pos: DocPos, lines: usize },
+}
+impl fmt::Display for PositionError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::InsideChar(DocPos { line, char }) =>
+        write!(f, "Position {line}:{char} points inside a character"),
+      Self::PastEnd { pos: DocPos { line, char }, lines } =>
+        write!(f, "Position {line}:{char} is past the end of a document of {lines} lines"),
+    }
+  }
+}
+impl std::error::Error for PositionError {}
+
 /// Convert LSP document positions into utf-8 byte offsets that can index
-/// strings in Rust
+/// strings in Rust. Positions past the end of a line are clamped to the end of
+/// the line, positions on the line after the last are clamped to the end of
+/// the text.
 ///
-/// # Panics
+/// # Errors
 ///
-/// if there are no arguments
-#[allow(unused)]
-// TODO: semantic highlights will use this, but those need some extensions to
-// the macro runner to report which macro consumed a given token
-pub fn docpos2bpos<T>(input: impl IntoIterator<Item = (DocPos, T)>, text: &str) -> Vec<(usize, T)> {
-  assert!(!text.contains('\r'), "Unicode newlines only");
-  let mut sorted = input.into_iter().sorted_unstable_by_key(|p| p.0);
+/// if a position points inside a character or more than one line past the
+/// end of the text
+#[allow(unused)] // requests use LineIndex, this is for batches of positions
+pub fn docpos2bpos<T>(
+  input: impl IntoIterator<Item = (DocPos, T)>,
+  text: &str,
+) -> Result<Vec<(usize, T)>, PositionError> {
+  let mut sorted = input.into_iter().sorted_unstable_by_key(|p| p.0).peekable();
   let mut output = Vec::new();
-  let mut cur = sorted.next().unwrap();
-  let mut prev_lines_bytes = 0;
-  'outer: for (line_i, line) in text.split('\n').enumerate() {
+  let mut lines = 0;
+  for (line_i, (start, line, _)) in lsp_lines(text).enumerate() {
+    lines += 1;
+    let mut chars = line.char_indices().peekable();
     let mut u16cp = 0;
-    let mut line_bytes = 0;
-    for c in line.chars() {
-      if cur.0.line == line_i {
-        assert!(line_i <= cur.0.line, "Points past end of line");
-        if line_i == cur.0.line {
-          assert!(
-            u16cp <= cur.0.char,
-            "Points inside a utf-16 codepoint char={:?}, line={}, cp={}",
-            cur.0,
-            line_i,
-            u16cp
-          );
-          if u16cp == cur.0.char {
-            let bpos = prev_lines_bytes + line_bytes;
-            output.push((bpos, cur.1));
-            'inner: loop {
-              // loop to deal with repeat positions
-              if let Some(next) = sorted.next() {
-                if cur.0 == next.0 {
-                  output.push((bpos, next.1));
-                  continue;
-                }
-                cur = next;
-                break 'inner;
-              }
-              break 'outer;
-            }
-          }
-        }
+    while let Some((pos, val)) = sorted.next_if(|(p, _)| p.line == line_i) {
+      while let Some((_, c)) = chars.next_if(|(_, c)| u16cp + c.len_utf16() <= pos.char) {
         u16cp += c.len_utf16();
-        line_bytes += c.len_utf8();
       }
+      let col = match chars.peek() {
+        Some(_) if u16cp < pos.char => return Err(PositionError::InsideChar(pos)),
+        Some((i, _)) => *i,
+        None => line.len(),
+      };
+      output.push((start + col, val));
     }
-    prev_lines_bytes += line.len() + 1;
   }
-  output
+  for (pos, val) in sorted {
+    if lines < pos.line {
+      return Err(PositionError::PastEnd { pos, lines });
+    }
+    output.push((text.len(), val));
+  }
+  Ok(output)
 }
 
 /// Convert (utf-8) byte positions into LSP document positions.
-///
-/// # Panics
-///
-/// if there are no arguments
+/// Offsets inside a line terminator are mapped to the end of the line.
 pub fn bpos2docpos<T>(input: impl IntoIterator<Item = (usize, T)>, text: &str) -> Vec<(DocPos, T)> {
-  assert!(!text.contains('\r'), "Unicode newlines only");
   let mut sorted = input.into_iter().sorted_unstable_by_key(|p| p.0);
   let mut output = Vec::new();
-  let mut cur = sorted.next().unwrap();
-  let mut bytes = 0;
-  'outer: for (line_i, line) in text.split('\n').enumerate() {
-    while cur.0 < bytes + line.len() + 1 {
+  let Some(mut cur) = sorted.next() else { return output };
+  'outer: for (line_i, (bytes, line, term)) in lsp_lines(text).enumerate() {
+    // the end of the last line is a valid position
+    while cur.0 < bytes + line.len() + term.len().max(1) {
       assert!(bytes <= cur.0, "Skipped over index bytes={bytes}, bpos={}", cur.0);
-      let character: usize = line[..(cur.0 - bytes)].chars().map(|c| c.len_utf16()).sum();
+      let col = (cur.0 - bytes).min(line.len());
+      let character: usize = line[..col].chars().map(|c| c.len_utf16()).sum();
       let pos = DocPos::new(line_i, character);
       output.push((pos, cur.1));
       'inner: loop {
@@ -98,23 +123,78 @@ pub fn bpos2docpos<T>(input: impl IntoIterator<Item = (usize, T)>, text: &str) -
         break 'outer;
       }
     }
-    bytes += line.len() + 1; // for the newline
   }
   output
 }
 
 #[cfg(test)]
 mod test {
-  use super::{bpos2docpos, docpos2bpos, DocPos};
+  use itertools::Itertools;
+
+  use super::{bpos2docpos, docpos2bpos, lsp_lines, DocPos, PositionError};
+  use crate::testing::{boundaries, naive_pos, Rng};
 
   #[test]
   fn doc2b2doc() {
     let doc_poses = [(DocPos::new(0, 5), 0), (DocPos::new(1, 3), 1), (DocPos::new(1, 7), 2)];
     let text = "Lorem ipsum\ndolor sit amet\nconsectetur adipiscing elit";
     let b_poses = [(5, 0), (15, 1), (19, 2)];
-    assert_eq!(docpos2bpos(doc_poses, text), b_poses, "Multiple doc2b");
-    assert_eq!(docpos2bpos([(DocPos::new(0, 9), 0)], "Test szöveg"), [(10, 0)], "unicode");
+    assert_eq!(docpos2bpos(doc_poses, text).unwrap(), b_poses, "Multiple doc2b");
+    let unicode = docpos2bpos([(DocPos::new(0, 9), 0)], "Test szöveg").unwrap();
+    assert_eq!(unicode, [(10, 0)], "unicode");
     assert_eq!(bpos2docpos(b_poses, text), doc_poses, "Multiple b2doc");
     assert_eq!(bpos2docpos([(10, 0)], "Test szöveg"), [(DocPos::new(0, 9), 0)], "unicode");
   }
+
+  #[test]
+  fn empty() {
+    assert_eq!(docpos2bpos::<()>([], "foo").unwrap(), [], "doc2b");
+    assert_eq!(bpos2docpos::<()>([], "foo"), [], "b2doc");
+    assert_eq!(bpos2docpos::<()>([], ""), [], "b2doc in empty text");
+  }
+
+  #[test]
+  fn random_roundtrip() {
+    let mut rng = Rng::new(0x0dd5eed);
+    for _ in 0..500 {
+      let lines = rng.below(6) + 1;
+      let text = rng.text(lines);
+      let bounds = boundaries(&text);
+      let bposes = (0..rng.below(8)).map(|i| (bounds[rng.below(bounds.len())], i)).collect_vec();
+      let doc_poses = bpos2docpos(bposes.clone(), &text);
+      assert_eq!(doc_poses.len(), bposes.len(), "b2doc dropped positions in {text:?}");
+      for (pos, i) in doc_poses.iter() {
+        assert_eq!(*pos, naive_pos(&text, bposes[*i].0), "b2doc mismatch in {text:?}");
+      }
+      let back = docpos2bpos(doc_poses, &text).unwrap();
+      let back = back.into_iter().sorted_by_key(|p| p.1).collect_vec();
+      assert_eq!(back, bposes, "roundtrip mismatch in {text:?}");
+    }
+  }
+
+  #[test]
+  fn line_endings() {
+    let lines = lsp_lines("a\r\nb\rc\n\r\nd").collect_vec();
+    let expected =
+      [(0, "a", "\r\n"), (3, "b", "\r"), (5, "c", "\n"), (7, "", "\r\n"), (9, "d", "")];
+    assert_eq!(lines, expected, "line splitting");
+    let text = "ab\r\ncd\ref";
+    let b_poses = [(1, 0), (5, 1), (8, 2), (3, 3)];
+    let doc_poses = [DocPos::new(0, 1), DocPos::new(1, 1), DocPos::new(2, 1), DocPos::new(0, 2)];
+    let expected = doc_poses.into_iter().zip(0..).collect_vec();
+    let doc_poses = bpos2docpos(b_poses, text).into_iter().sorted_by_key(|p| p.1).collect_vec();
+    assert_eq!(doc_poses, expected, "b2doc with CR");
+    assert_eq!(docpos2bpos(expected[..3].to_vec(), text).unwrap(), b_poses[..3], "doc2b with CR");
+  }
+
+  #[test]
+  fn out_of_range() {
+    let text = "ab\n𝔸";
+    let clamped = [(DocPos::new(0, 5), 0), (DocPos::new(1, 7), 1), (DocPos::new(2, 3), 2)];
+    assert_eq!(docpos2bpos(clamped, text).unwrap(), [(2, 0), (7, 1), (7, 2)], "clamping");
+    let err = docpos2bpos([(DocPos::new(1, 1), ())], text).unwrap_err();
+    assert_eq!(err, PositionError::InsideChar(DocPos::new(1, 1)), "inside a utf-16 pair");
+    let err = docpos2bpos([(DocPos::new(3, 0), ())], text).unwrap_err();
+    assert_eq!(err, PositionError::PastEnd { pos: DocPos::new(3, 0), lines: 2 }, "past the end");
+  }
 }
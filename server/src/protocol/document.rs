@@ -1,5 +1,6 @@
 use std::borrow::Cow;
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
 use std::{fmt, hash};
@@ -24,15 +25,140 @@ pub struct DocRange {
   pub end: DocPos,
 }
 
+#[derive(Deserialize, Clone, Debug)]
+pub struct TextDocumentIdentifier {
+  pub uri: FileUri,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct TextDocumentPositionParams {
+  #[serde(alias = "textDocument")]
+  pub text_document: TextDocumentIdentifier,
+  pub position: DocPos,
+}
+
 #[derive(Deserialize)]
 pub struct TextDocumentItem {
   pub uri: FileUri,
   #[serde(alias = "languageId")]
   pub language_id: String,
-  pub version: i64,
+  pub version: u64,
+  pub text: String,
+}
+
+/// An entry of `contentChanges` in `didChange`
+#[derive(Deserialize, Clone, Debug)]
+pub struct ContentChange {
+  /// The replaced range, or the whole document if missing
+  #[serde(default)]
+  pub range: Option<DocRange>,
   pub text: String,
 }
 
+/// A replacement of a range of a document
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TextEdit {
+  pub range: DocRange,
+  pub new_text: String,
+  /// Key of a [ChangeAnnotation] in the [WorkspaceEdit] the edit is part of
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub annotation_id: Option<String>,
+}
+impl TextEdit {
+  pub fn new(range: DocRange, new_text: impl Into<String>) -> Self {
+    Self { range, new_text: new_text.into(), annotation_id: None }
+  }
+  pub fn annotated(self, id: &str) -> Self { Self { annotation_id: Some(id.to_string()), ..self } }
+}
+
+/// A document at the version an edit was computed for. The client rejects
+/// edits to documents that changed since. [None] means the text on disk.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct VersionedDocumentRef {
+  pub uri: String,
+  pub version: Option<u64>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TextDocumentEdit {
+  pub text_document: VersionedDocumentRef,
+  pub edits: Vec<TextEdit>,
+}
+
+/// Creating, renaming or deleting a file. Requires the client to support
+/// resource operations.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum ResourceOp {
+  Create { uri: String },
+  #[allow(unused)] // renames are sent as text edits
+  Rename { old_uri: String, new_uri: String },
+  #[allow(unused)] // nothing deletes files yet
+  Delete { uri: String },
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum DocumentChange {
+  Edit(TextDocumentEdit),
+  Resource(ResourceOp),
+}
+
+/// A description of edits the client shows when they're applied
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeAnnotation {
+  pub label: String,
+  /// Ask the user before applying the edits
+  pub needs_confirmation: bool,
+}
+
+/// Changes to any number of documents, either as edits by URI or as a list of
+/// changes in the order the client applies them. The latter can target
+/// versions and include resource operations, but not every client supports it.
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceEdit {
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  pub changes: BTreeMap<String, Vec<TextEdit>>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub document_changes: Vec<DocumentChange>,
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  pub change_annotations: BTreeMap<String, ChangeAnnotation>,
+}
+impl WorkspaceEdit {
+  /// Add an edit to [Self::changes]
+  pub fn change(mut self, uri: &str, edit: TextEdit) -> Self {
+    self.changes.entry(uri.to_string()).or_default().push(edit);
+    self
+  }
+  /// Add an edit to [Self::document_changes]. Edits to the same version of a
+  /// document are grouped as long as no other change comes between them.
+  pub fn edit(mut self, doc: VersionedDocumentRef, edit: TextEdit) -> Self {
+    match self.document_changes.last_mut() {
+      Some(DocumentChange::Edit(last)) if last.text_document == doc => last.edits.push(edit),
+      _ => self.document_changes.push(DocumentChange::Edit(TextDocumentEdit {
+        text_document: doc,
+        edits: vec![edit],
+      })),
+    }
+    self
+  }
+  /// Add a resource operation to [Self::document_changes]
+  pub fn resource(mut self, op: ResourceOp) -> Self {
+    self.document_changes.push(DocumentChange::Resource(op));
+    self
+  }
+  /// Describe the edits annotated with the id
+  pub fn annotation(mut self, id: &str, label: impl Into<String>, confirm: bool) -> Self {
+    let annotation = ChangeAnnotation { label: label.into(), needs_confirmation: confirm };
+    self.change_annotations.insert(id.to_string(), annotation);
+    self
+  }
+}
+
 trait_set! {
   pub trait UriSegments<'a> = Iterator<Item = Cow<'a, str>> + Clone + 'a
 }
@@ -52,6 +178,22 @@ trait_set! {
 #[derive(Clone, Debug, Eq)]
 pub struct FileUri(Arc<String>);
 impl FileUri {
+  /// Parse a `file:///` URI, stripping a trailing slash or `.orc` extension
+  pub fn parse(s: &str) -> Result<Self, &'static str> {
+    let path = match s.strip_prefix("file:///") {
+      Some(path) => path,
+      None if s.starts_with("file://") => return Err("FileUri has a host"),
+      None => return Err("FileUri has non-file scheme"),
+    };
+    if path.split('/').any(|seg| urlencoding::decode(seg).is_err()) {
+      return Err("FileUri has a segment that isn't percent-encoded UTF-8");
+    }
+    let path = path.strip_suffix('/').or(path.strip_suffix(".orc")).unwrap_or(path);
+    Ok(Self(Arc::new(path.to_string())))
+  }
+  pub fn from_path(path: &Path) -> Option<Self> {
+    Self::parse(url::Url::from_file_path(path).ok()?.as_str()).ok()
+  }
   pub fn to_path(&self) -> PathBuf {
     url::Url::from_str(&format!("file:///{}", self.0)).unwrap().to_file_path().unwrap()
   }
@@ -81,10 +223,7 @@ impl<'de> Deserialize<'de> for FileUri {
   fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
   where D: serde::Deserializer<'de> {
     let s = String::deserialize(deserializer)?;
-    let path = (s.strip_prefix("file:///"))
-      .ok_or_else(|| serde::de::Error::custom("FileUri has non-file scheme"))?;
-    let path = path.strip_suffix('/').or(path.strip_suffix(".orc")).unwrap_or(path);
-    Ok(Self(Arc::new(path.to_string())))
+    Self::parse(&s).map_err(serde::de::Error::custom)
   }
 }
 impl PartialEq for FileUri {
@@ -93,3 +232,58 @@ impl PartialEq for FileUri {
 impl hash::Hash for FileUri {
   fn hash<H: hash::Hasher>(&self, state: &mut H) { self.segments().for_each(|seg| seg.hash(state)) }
 }
+
+#[cfg(test)]
+mod test {
+  use serde_json::json;
+
+  use super::{DocRange, FileUri, ResourceOp, TextEdit, VersionedDocumentRef, WorkspaceEdit};
+  use crate::protocol::docpos::DocPos;
+
+  fn range(line: usize) -> DocRange {
+    DocRange { start: DocPos { line, char: 0 }, end: DocPos { line, char: 1 } }
+  }
+
+  #[test]
+  fn document_changes() {
+    let doc = |version| VersionedDocumentRef { uri: "file:///a.orc".to_string(), version };
+    let edit = (WorkspaceEdit::default())
+      .edit(doc(Some(3)), TextEdit::new(range(0), "x").annotated("r"))
+      .edit(doc(Some(3)), TextEdit::new(range(1), "y"))
+      .resource(ResourceOp::Rename {
+        old_uri: "file:///a.orc".to_string(),
+        new_uri: "file:///b.orc".to_string(),
+      })
+      .edit(doc(None), TextEdit::new(range(2), "z"))
+      .annotation("r", "Rename", true);
+    let value = serde_json::to_value(&edit).unwrap();
+    let changes = value["documentChanges"].as_array().unwrap();
+    assert_eq!(changes.len(), 3, "Consecutive edits of a version are grouped");
+    assert_eq!(changes[0]["textDocument"], json!({ "uri": "file:///a.orc", "version": 3 }));
+    assert_eq!(changes[0]["edits"][0]["annotationId"], "r");
+    assert_eq!(changes[0]["edits"][1]["newText"], "y");
+    assert_eq!(changes[1]["kind"], "rename");
+    assert_eq!(changes[1]["newUri"], "file:///b.orc");
+    assert_eq!(changes[2]["textDocument"]["version"], json!(null));
+    assert_eq!(value["changeAnnotations"]["r"]["needsConfirmation"], true);
+    assert!(value.get("changes").is_none());
+  }
+
+  #[test]
+  fn changes() {
+    let edit = WorkspaceEdit::default().change("file:///a.orc", TextEdit::new(range(0), "x"));
+    let value = serde_json::to_value(&edit).unwrap();
+    assert_eq!(value["changes"]["file:///a.orc"][0]["newText"], "x");
+    assert!(value["changes"]["file:///a.orc"][0].get("annotationId").is_none());
+    assert!(value.get("documentChanges").is_none());
+  }
+
+  #[test]
+  fn uri_parsing() {
+    let uri = FileUri::parse("file:///w/a%20b.orc").unwrap();
+    assert_eq!(uri.segments().collect::<Vec<_>>(), ["w", "a b"]);
+    assert_eq!(FileUri::parse("untitled:Untitled-1"), Err("FileUri has non-file scheme"));
+    assert_eq!(FileUri::parse("file://host/w/a.orc"), Err("FileUri has a host"));
+    assert!(FileUri::parse("file:///w/%FF.orc").is_err(), "Segments must decode to UTF-8");
+  }
+}
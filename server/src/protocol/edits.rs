@@ -0,0 +1,163 @@
+//! Validating and applying sets of text edits. LSP requires the edits of a
+//! document to not overlap, and applies inserts at the same position in the
+//! order they're listed. These functions enforce the same rules so that edits
+//! computed by the server can be checked and previewed before they're sent.
+
+use std::fmt;
+use std::ops::Range;
+
+/// A reason why a set of edits can't be applied. Edits are identified by their
+/// index in the list.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EditError {
+  /// The edit ends before it starts
+  Reversed(usize),
+  /// The two edits replace some of the same text, or one inserts inside the
+  /// text the other replaces
+  Overlap(usize, usize),
+}
+impl fmt::Display for EditError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Reversed(i) => write!(f, "Edit {i} ends before it starts"),
+      Self::Overlap(i, j) => write!(f, "Edits {i} and {j} overlap"),
+    }
+  }
+}
+impl std::error::Error for EditError {}
+
+/// Sort edits into the order of their ranges, keeping inserts at the same
+/// position in the order they were listed. An insert at the start of a
+/// replaced range goes before it, one at the end after it. Fails if any two
+/// edits overlap.
+pub fn order<T>(edits: Vec<(Range<usize>, T)>) -> Result<Vec<(Range<usize>, T)>, EditError> {
+  let mut edits = edits.into_iter().enumerate().collect::<Vec<_>>();
+  for (i, (range, _)) in edits.iter() {
+    if range.end < range.start {
+      return Err(EditError::Reversed(*i));
+    }
+  }
+  // stable, so equal inserts stay in order
+  edits.sort_by_key(|(_, (range, _))| (range.start, range.end));
+  // Without overlaps the ends are ascending too, so only neighbours can collide
+  for pair in edits.windows(2) {
+    let ((i, (prev, _)), (j, (next, _))) = (&pair[0], &pair[1]);
+    if next.start < prev.end {
+      return Err(EditError::Overlap(*i.min(j), *i.max(j)));
+    }
+  }
+  Ok(edits.into_iter().map(|(_, edit)| edit).collect())
+}
+
+/// Apply ordered edits with byte ranges to a text
+pub fn splice(text: &str, edits: &[(Range<usize>, impl AsRef<str>)]) -> String {
+  let (mut out, mut pos) = (String::with_capacity(text.len()), 0);
+  for (range, new) in edits {
+    out.push_str(&text[pos..range.start]);
+    out.push_str(new.as_ref());
+    pos = range.end;
+  }
+  out.push_str(&text[pos..]);
+  out
+}
+
+#[cfg(test)]
+mod test {
+  use std::ops::Range;
+
+  use itertools::Itertools;
+
+  use super::{order, splice, EditError};
+  use crate::protocol::document::{DocRange, TextEdit};
+  use crate::protocol::line_index::LineIndex;
+  use crate::testing::{boundaries, naive_pos, Rng};
+
+  fn edit(text: &str, range: Range<usize>, new: &str) -> TextEdit {
+    let range = DocRange { start: naive_pos(text, range.start), end: naive_pos(text, range.end) };
+    TextEdit::new(range, new)
+  }
+
+  /// Apply edits to a document the way an LSP client would
+  fn apply(text: &str, edits: &[TextEdit]) -> Result<String, EditError> {
+    let lines = LineIndex::new(text);
+    let offset = |pos| lines.docpos2bpos(pos).expect("Edits are within the text");
+    let edits = edits.iter().map(|e| (offset(e.range.start)..offset(e.range.end), &e.new_text));
+    Ok(splice(text, &order(edits.collect())?))
+  }
+
+  fn shuffle<T>(rng: &mut Rng, items: &mut [T]) {
+    for i in (1..items.len()).rev() {
+      items.swap(i, rng.below(i + 1));
+    }
+  }
+
+  #[test]
+  fn rules() {
+    let text = "const a := 1\r\nconst b := 2";
+    let edits = [edit(text, 20..21, "c"), edit(text, 6..7, "x"), edit(text, 6..6, "y")];
+    assert_eq!(apply(text, &edits).unwrap(), "const yx := 1\r\nconst c := 2");
+    let inserts = [edit(text, 12..12, "a"), edit(text, 12..12, "b"), edit(text, 12..12, "c")];
+    assert_eq!(apply(text, &inserts).unwrap(), "const a := 1abc\r\nconst b := 2", "List order");
+    let touching = [edit(text, 0..5, "let"), edit(text, 5..5, "!"), edit(text, 5..6, "_")];
+    assert_eq!(apply(text, &touching).unwrap(), "let!_a := 1\r\nconst b := 2");
+    let inside = [edit(text, 0..12, ""), edit(text, 11..16, ""), edit(text, 3..3, "x")];
+    assert_eq!(apply(text, &inside), Err(EditError::Overlap(0, 2)));
+    let reversed = vec![(0..3, ""), (1..2, ""), (Range { start: 2, end: 1 }, "")];
+    assert_eq!(order(reversed), Err(EditError::Reversed(2)));
+  }
+
+  #[test]
+  fn random_disjoint() {
+    let mut rng = Rng::new(0xed175);
+    for _ in 0..500 {
+      let lines = rng.below(6) + 1;
+      let text = rng.text(lines);
+      let bounds = boundaries(&text);
+      let mut points = (0..rng.below(4) * 2).map(|_| bounds[rng.below(bounds.len())]).collect_vec();
+      points.sort();
+      let mut ranges = points.chunks(2).map(|c| c[0]..c[1]).collect_vec();
+      // inserts at the same position would apply in a different order once shuffled
+      ranges.dedup_by(|next, prev| prev == next && prev.start == prev.end);
+      let edits = (ranges.into_iter())
+        .map(|r| {
+          let lines = rng.below(2) + 1;
+          (r, rng.text(lines))
+        })
+        .collect_vec();
+      let mut expected = text.clone();
+      for (range, new) in edits.iter().rev() {
+        expected.replace_range(range.clone(), new);
+      }
+      let mut edits = edits.iter().map(|(r, new)| edit(&text, r.clone(), new)).collect_vec();
+      shuffle(&mut rng, &mut edits);
+      assert_eq!(apply(&text, &edits), Ok(expected), "Edits {edits:?} of {text:?}");
+    }
+  }
+
+  #[test]
+  fn random_overlaps() {
+    let mut rng = Rng::new(0x0e1a95);
+    let overlap = |a: &Range<usize>, b: &Range<usize>| {
+      a.start.max(b.start) < a.end.min(b.end)
+        || (a.is_empty() && b.start < a.start && a.start < b.end)
+        || (b.is_empty() && a.start < b.start && b.start < a.end)
+    };
+    for _ in 0..500 {
+      let text = rng.text(3);
+      let bounds = boundaries(&text);
+      let ranges = (0..rng.below(4) + 1)
+        .map(|_| {
+          let (a, b) = (bounds[rng.below(bounds.len())], bounds[rng.below(bounds.len())]);
+          a.min(b)..a.max(b)
+        })
+        .collect_vec();
+      let expected = (0..ranges.len()).any(|j| (0..j).any(|i| overlap(&ranges[i], &ranges[j])));
+      let edits = ranges.iter().map(|r| edit(&text, r.clone(), "x")).collect_vec();
+      let result = apply(&text, &edits);
+      assert_eq!(result.is_err(), expected, "Edits {ranges:?} of {text:?} gave {result:?}");
+      if let Err(EditError::Overlap(i, j)) = result {
+        assert!(i < j && overlap(&ranges[i], &ranges[j]), "Wrong pair for {ranges:?}");
+      }
+    }
+  }
+}
@@ -0,0 +1,105 @@
+//! Translation of the server's token types into the ones a client recognizes.
+//! Clients list the types they know in their capabilities. Internal types the
+//! client doesn't know are sent as the closest type it does, and the user can
+//! map types explicitly with the `tokenTypes` option.
+
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+/// Types to use in order of preference when the client doesn't know one.
+/// Types not listed here or whose fallbacks are all unknown aren't sent.
+const FALLBACKS: &[(&str, &[&str])] = &[
+  ("namespace", &["module", "type", "class"]),
+  ("parameter", &["variable"]),
+  ("function", &["method", "variable"]),
+  ("operator", &["keyword"]),
+  ("number", &["constant", "string"]),
+  ("macro", &["keyword"]),
+  ("string", &["regexp"]),
+];
+
+/// An explicit mapping to this turns a type off
+const OFF: &str = "none";
+
+/// The token types sent to the client and the index of each internal type in
+/// them
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenLegend {
+  types: Vec<String>,
+  internal: HashMap<String, usize>,
+}
+impl TokenLegend {
+  /// Choose the types for each internal type. `known` is the list of types
+  /// the client advertised, if any; clients that don't list them are assumed
+  /// to know every type. `overrides` is the `tokenTypes` option, an object
+  /// mapping internal types to client types, which takes precedence over both.
+  pub fn new(internal: &[&str], known: Option<&[String]>, overrides: &Value) -> Self {
+    let knows = |typ: &str| known.is_none_or(|known| known.iter().any(|k| k == typ));
+    let (mut types, mut indices) = (Vec::<String>::new(), HashMap::new());
+    for typ in internal {
+      let chosen = match overrides[*typ].as_str() {
+        Some(OFF) => None,
+        Some(target) => Some(target),
+        None => {
+          let fallbacks = FALLBACKS.iter().find(|(from, _)| from == typ).map_or(&[][..], |f| f.1);
+          [*typ].into_iter().chain(fallbacks.iter().copied()).find(|t| knows(t))
+        },
+      };
+      let Some(chosen) = chosen else { continue };
+      let idx = match types.iter().position(|t| t == chosen) {
+        Some(idx) => idx,
+        None => {
+          types.push(chosen.to_string());
+          types.len() - 1
+        },
+      };
+      indices.insert(typ.to_string(), idx);
+    }
+    Self { types, internal: indices }
+  }
+
+  /// The legend announced to the client
+  pub fn types(&self) -> &[String] { &self.types }
+
+  /// The index in [TokenLegend::types] of an internal type, or None if tokens
+  /// of the type aren't sent
+  pub fn index(&self, internal: &str) -> Option<usize> { self.internal.get(internal).copied() }
+}
+
+#[cfg(test)]
+mod test {
+  use serde_json::{json, Value};
+
+  use super::TokenLegend;
+
+  const INTERNAL: &[&str] = &["namespace", "variable", "parameter", "operator", "keyword"];
+
+  #[test]
+  fn unrestricted() {
+    let legend = TokenLegend::new(INTERNAL, None, &Value::Null);
+    assert_eq!(legend.types(), INTERNAL);
+    assert_eq!(legend.index("parameter"), Some(2));
+  }
+
+  #[test]
+  fn fallbacks() {
+    let known = ["variable", "keyword", "type"].map(String::from);
+    let legend = TokenLegend::new(INTERNAL, Some(&known), &Value::Null);
+    assert_eq!(legend.types(), ["type", "variable", "keyword"]);
+    assert_eq!(legend.index("parameter"), legend.index("variable"));
+    assert_eq!(legend.index("operator"), Some(2));
+    let legend = TokenLegend::new(INTERNAL, Some(&["string".to_string()]), &Value::Null);
+    assert_eq!((legend.types(), legend.index("keyword")), (&[][..], None));
+  }
+
+  #[test]
+  fn overrides() {
+    let known = ["variable", "keyword"].map(String::from);
+    let overrides = json!({ "keyword": "macro", "variable": "none" });
+    let legend = TokenLegend::new(INTERNAL, Some(&known), &overrides);
+    assert_eq!(legend.types(), ["variable", "keyword", "macro"]);
+    assert_eq!(legend.index("variable"), None);
+    assert_eq!(legend.index("parameter"), Some(0));
+  }
+}
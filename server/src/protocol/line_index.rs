@@ -0,0 +1,200 @@
+//! Precomputed line structure of a document for fast position conversion
+
+use std::cmp;
+use std::ops::Range;
+
+use super::docpos::{DocPos, PositionError};
+use super::document::DocRange;
+
+/// The start of every line and the location of every non-ASCII character in a
+/// text. Converting between byte offsets and [DocPos] takes a binary search
+/// plus a walk over the non-ASCII characters of a single line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LineIndex {
+  /// Byte offset of the start of each line and the length of the line
+  /// terminator before it. Always starts with `(0, 0)`
+  starts: Vec<(usize, usize)>,
+  /// Non-ASCII characters as byte offset, utf-8 length and utf-16 length
+  wide: Vec<(usize, usize, usize)>,
+  /// Length of the text in bytes
+  len: usize,
+}
+impl LineIndex {
+  pub fn new(text: &str) -> Self {
+    let mut this = Self { starts: vec![(0, 0)], wide: Vec::new(), len: 0 };
+    this.edit(0..0, text.len(), text);
+    this
+  }
+
+  pub fn lines(&self) -> usize { self.starts.len() }
+
+  /// Update the index after the byte range `old` was replaced with `new_len`
+  /// bytes, resulting in `text`
+  pub fn edit(&mut self, old: Range<usize>, new_len: usize, text: &str) {
+    assert!(old.start <= old.end && old.end <= self.len, "Edit out of bounds");
+    let shift = |i: usize| i + new_len - old.len();
+    let new = old.start..old.start + new_len;
+    // The status of line starts depends on the preceding 2 characters because a
+    // CR may be joined with an LF
+    let first = self.starts.partition_point(|s| s.0 < old.start.max(1));
+    let last = self.starts.partition_point(|s| s.0 <= old.end + 1);
+    // Line terminators are ASCII so they can be found bytewise
+    let bytes = text.as_bytes();
+    let new_starts = (new.start.saturating_sub(1)..(new.end + 1).min(text.len()))
+      .filter_map(|i| match bytes[i] {
+        b'\n' if i > 0 && bytes[i - 1] == b'\r' => Some((i + 1, 2)),
+        b'\n' => Some((i + 1, 1)),
+        b'\r' if bytes.get(i + 1) != Some(&b'\n') => Some((i + 1, 1)),
+        _ => None,
+      })
+      .filter(|s| new.start.max(1) <= s.0);
+    let tail = self.starts[last..].iter().map(|(s, t)| (shift(*s), *t)).collect::<Vec<_>>();
+    self.starts.splice(first.., new_starts.chain(tail));
+    let first = self.wide.partition_point(|w| w.0 < old.start);
+    let last = self.wide.partition_point(|w| w.0 < old.end);
+    let new_wide = (text[new.clone()].char_indices())
+      .filter(|(_, c)| !c.is_ascii())
+      .map(|(i, c)| (new.start + i, c.len_utf8(), c.len_utf16()));
+    let tail = (self.wide[last..].iter()).map(|(i, l8, l16)| (shift(*i), *l8, *l16));
+    let tail = tail.collect::<Vec<_>>();
+    self.wide.splice(first.., new_wide.chain(tail));
+    self.len = shift(self.len);
+  }
+
+  /// Update the index after the text changed from `old` to `new`. Only the
+  /// region between the common prefix and suffix is reindexed.
+  pub fn update(&mut self, old: &str, new: &str) {
+    debug_assert_eq!(old.len(), self.len, "Index doesn't match old text");
+    let mut pre = old.bytes().zip(new.bytes()).take_while(|(a, b)| a == b).count();
+    while !old.is_char_boundary(pre) || !new.is_char_boundary(pre) {
+      pre -= 1;
+    }
+    let max_suf = old.len().min(new.len()) - pre;
+    let mut suf = (old.bytes().rev().zip(new.bytes().rev()))
+      .take(max_suf)
+      .take_while(|(a, b)| a == b)
+      .count();
+    while !old.is_char_boundary(old.len() - suf) || !new.is_char_boundary(new.len() - suf) {
+      suf -= 1;
+    }
+    self.edit(pre..old.len() - suf, new.len() - suf - pre, new)
+  }
+
+  /// Byte range of a line excluding the line terminator
+  fn line_range(&self, line: usize) -> Range<usize> {
+    let end = self.starts.get(line + 1).map_or(self.len, |(s, t)| s - t);
+    self.starts[line].0..end
+  }
+
+  /// Non-ASCII characters in a byte range
+  fn wide_in(&self, range: Range<usize>) -> &[(usize, usize, usize)] {
+    let first = self.wide.partition_point(|w| w.0 < range.start);
+    let last = self.wide.partition_point(|w| w.0 < range.end);
+    &self.wide[first..last]
+  }
+
+  /// Convert a byte offset to a document position. Offsets inside a line
+  /// terminator are mapped to the end of the line.
+  ///
+  /// # Panics
+  ///
+  /// if the offset is out of bounds
+  pub fn bpos2docpos(&self, bpos: usize) -> DocPos {
+    assert!(bpos <= self.len, "Byte offset out of bounds");
+    let line = self.starts.partition_point(|s| s.0 <= bpos) - 1;
+    let range = self.line_range(line);
+    // offsets inside a CRLF are mapped to the end of the line
+    let bpos = bpos.min(range.end);
+    let start = range.start;
+    let wide = self.wide_in(start..bpos);
+    let char = (bpos - start) + wide.iter().map(|(_, _, l16)| l16).sum::<usize>()
+      - wide.iter().map(|(_, l8, _)| l8).sum::<usize>();
+    DocPos::new(line, char)
+  }
+
+  /// Convert a document position to a byte offset, clamping positions past
+  /// the end of a line or the end of the document like [docpos2bpos]
+  ///
+  /// [docpos2bpos]: super::docpos::docpos2bpos
+  pub fn docpos2bpos(&self, pos: DocPos) -> Result<usize, PositionError> {
+    match pos.line.cmp(&self.starts.len()) {
+      cmp::Ordering::Greater => return Err(PositionError::PastEnd { pos, lines: self.lines() }),
+      cmp::Ordering::Equal => return Ok(self.len),
+      cmp::Ordering::Less => (),
+    }
+    let range = self.line_range(pos.line);
+    let (mut bpos, mut char) = (range.start, 0);
+    for (i, l8, l16) in self.wide_in(range.clone()) {
+      if pos.char <= char + (i - bpos) {
+        break;
+      }
+      char += i - bpos;
+      if pos.char < char + l16 {
+        return Err(PositionError::InsideChar(pos));
+      }
+      (bpos, char) = (i + l8, char + l16);
+    }
+    Ok((bpos + (pos.char - char)).min(range.end))
+  }
+
+  /// Convert a byte range to a document range
+  pub fn doc_range(&self, range: Range<usize>) -> DocRange {
+    DocRange { start: self.bpos2docpos(range.start), end: self.bpos2docpos(range.end) }
+  }
+}
+impl Default for LineIndex {
+  fn default() -> Self { Self::new("") }
+}
+
+#[cfg(test)]
+mod test {
+  use super::LineIndex;
+  use crate::protocol::docpos::{DocPos, PositionError};
+  use crate::testing::{boundaries, naive_pos, Rng};
+
+  #[test]
+  fn conversions() {
+    let text = "Test szöveg\n\n𝔸 b";
+    let li = LineIndex::new(text);
+    assert_eq!(li.lines(), 3);
+    assert_eq!(li.bpos2docpos(10), DocPos::new(0, 9), "after a wide char");
+    assert_eq!(li.bpos2docpos(13), DocPos::new(1, 0), "empty line");
+    assert_eq!(li.bpos2docpos(19), DocPos::new(2, 3), "after a utf-16 pair");
+    assert_eq!(li.docpos2bpos(DocPos::new(2, 3)), Ok(19), "after a utf-16 pair");
+    let err = PositionError::InsideChar(DocPos::new(2, 1));
+    assert_eq!(li.docpos2bpos(DocPos::new(2, 1)), Err(err), "inside a utf-16 pair");
+    assert_eq!(li.docpos2bpos(DocPos::new(0, 11)), Ok(12), "end of line");
+    assert_eq!(li.docpos2bpos(DocPos::new(0, 12)), Ok(12), "past end of line");
+    assert_eq!(li.docpos2bpos(DocPos::new(3, 0)), Ok(20), "line after the end of text");
+    let err = PositionError::PastEnd { pos: DocPos::new(4, 0), lines: 3 };
+    assert_eq!(li.docpos2bpos(DocPos::new(4, 0)), Err(err), "past end of text");
+    let li = LineIndex::new("a\r\nb\rc\n\r\nd");
+    assert_eq!(li.lines(), 5, "mixed terminators");
+    assert_eq!(li.bpos2docpos(2), DocPos::new(0, 1), "inside CRLF");
+    assert_eq!(li.bpos2docpos(4), DocPos::new(1, 1), "before CR");
+    assert_eq!(li.docpos2bpos(DocPos::new(3, 0)), Ok(7), "empty line after LF");
+    assert_eq!(li.docpos2bpos(DocPos::new(4, 1)), Ok(10), "end of text");
+  }
+
+  #[test]
+  fn random_edits() {
+    let mut rng = Rng::new(0x11e5eed);
+    let mut text = rng.text(4);
+    let mut li = LineIndex::new(&text);
+    for _ in 0..500 {
+      let bounds = text.char_indices().map(|(i, _)| i).chain([text.len()]).collect::<Vec<_>>();
+      let (a, b) = (bounds[rng.below(bounds.len())], bounds[rng.below(bounds.len())]);
+      let lines = rng.below(3) + 1;
+      let new = rng.text(lines);
+      let old = text.clone();
+      text.replace_range(a.min(b)..a.max(b), &new);
+      li.update(&old, &text);
+      assert_eq!(li, LineIndex::new(&text), "Incremental update diverged on {text:?}");
+      for bpos in boundaries(&text) {
+        let pos = li.bpos2docpos(bpos);
+        assert_eq!(pos, naive_pos(&text, bpos), "b2doc mismatch in {text:?}");
+        assert_eq!(li.docpos2bpos(pos), Ok(bpos), "doc2b mismatch in {text:?}");
+      }
+    }
+  }
+}
@@ -0,0 +1,322 @@
+//! Method names and parameter types of the messages the server sends, so that
+//! payloads are checked at compile time. Used with `Session::send` and
+//! `Session::call` of the server.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::document::{DocRange, WorkspaceEdit, WspaceEnt};
+
+/// A notification sent to the client
+pub trait Notification {
+  const METHOD: &'static str;
+  type Params: Serialize;
+}
+
+/// A request sent to the client
+pub trait Request: 'static {
+  const METHOD: &'static str;
+  type Params: Serialize;
+  type Result: DeserializeOwned;
+}
+
+/// A document referenced by its URI. Outgoing counterpart of
+/// [super::document::TextDocumentIdentifier]
+#[derive(Serialize, Clone, Debug)]
+pub struct DocumentRef {
+  pub uri: String,
+}
+
+/// `$/logTrace`
+pub enum LogTrace {}
+impl Notification for LogTrace {
+  const METHOD: &'static str = "$/logTrace";
+  type Params = LogTraceParams;
+}
+#[derive(Serialize, Clone, Debug)]
+pub struct LogTraceParams {
+  pub message: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub verbose: Option<String>,
+}
+
+/// `$/progress`
+pub enum Progress {}
+impl Notification for Progress {
+  const METHOD: &'static str = "$/progress";
+  type Params = ProgressParams;
+}
+#[derive(Serialize, Clone, Debug)]
+pub struct ProgressParams {
+  pub token: Value,
+  pub value: Value,
+}
+
+/// `client/syntacticTokens`, the token push consumed by our own VSCode client
+pub enum SyntacticTokens {}
+impl Notification for SyntacticTokens {
+  const METHOD: &'static str = "client/syntacticTokens";
+  type Params = SyntacticTokensParams;
+}
+#[derive(Serialize, Clone, Debug)]
+pub struct SyntacticTokensParams {
+  #[serde(rename = "textDocument")]
+  pub text_document: DocumentRef,
+  /// Derived from the tokens, so an unchanged file gets the same id
+  #[serde(rename = "resultId")]
+  pub result_id: String,
+  /// Line, character, length and legend index of each token
+  pub tokens: Vec<(usize, usize, usize, usize)>,
+  pub legend: Vec<String>,
+}
+
+/// `orchid/analysisStarted`, sent when a project starts loading
+pub enum AnalysisStarted {}
+impl Notification for AnalysisStarted {
+  const METHOD: &'static str = "orchid/analysisStarted";
+  type Params = AnalysisStartedParams;
+}
+#[derive(Serialize, Clone, Debug)]
+pub struct AnalysisStartedParams {
+  /// Identifies the load in the matching `orchid/analysisFinished`. Loads of
+  /// the same project may overlap while the older one is being cancelled.
+  pub id: usize,
+  /// The URI of the project's folder or single file
+  pub project: String,
+}
+
+/// `orchid/analysisFinished`, sent exactly once for every
+/// `orchid/analysisStarted`
+pub enum AnalysisFinished {}
+impl Notification for AnalysisFinished {
+  const METHOD: &'static str = "orchid/analysisFinished";
+  type Params = AnalysisFinishedParams;
+}
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalysisFinishedParams {
+  pub id: usize,
+  pub project: String,
+  pub outcome: AnalysisOutcome,
+  pub duration_ms: u64,
+  /// Zero unless the project loaded
+  pub files: usize,
+  pub constants: usize,
+  /// Diagnostics by severity. Errors without a location are counted as errors.
+  pub errors: usize,
+  pub warnings: usize,
+}
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum AnalysisOutcome {
+  Loaded,
+  /// The project has errors that prevent loading, the previous state remains
+  Failed,
+  /// A newer load or the user stopped this one
+  Cancelled,
+}
+
+/// `client/registerCapability`
+pub enum RegisterCapability {}
+impl Request for RegisterCapability {
+  const METHOD: &'static str = "client/registerCapability";
+  type Params = RegistrationParams;
+  type Result = ();
+}
+#[derive(Serialize, Clone, Debug)]
+pub struct RegistrationParams {
+  pub registrations: Vec<Registration>,
+}
+#[derive(Serialize, Clone, Debug)]
+pub struct Registration {
+  pub id: String,
+  pub method: String,
+  #[serde(rename = "registerOptions")]
+  pub register_options: Value,
+}
+
+/// `client/unregisterCapability`
+pub enum UnregisterCapability {}
+impl Request for UnregisterCapability {
+  const METHOD: &'static str = "client/unregisterCapability";
+  type Params = UnregistrationParams;
+  type Result = ();
+}
+#[derive(Serialize, Clone, Debug)]
+pub struct UnregistrationParams {
+  /// Misspelled in the LSP spec
+  #[serde(rename = "unregisterations")]
+  pub unregistrations: Vec<Unregistration>,
+}
+#[derive(Serialize, Clone, Debug)]
+pub struct Unregistration {
+  pub id: String,
+  pub method: String,
+}
+
+/// `workspace/diagnostic/refresh`
+pub enum DiagnosticRefresh {}
+impl Request for DiagnosticRefresh {
+  const METHOD: &'static str = "workspace/diagnostic/refresh";
+  type Params = ();
+  type Result = ();
+}
+
+/// `workspace/codeLens/refresh`
+pub enum CodeLensRefresh {}
+impl Request for CodeLensRefresh {
+  const METHOD: &'static str = "workspace/codeLens/refresh";
+  type Params = ();
+  type Result = ();
+}
+
+/// `telemetry/event`
+pub enum TelemetryEvent {}
+impl Notification for TelemetryEvent {
+  const METHOD: &'static str = "telemetry/event";
+  type Params = TelemetryParams;
+}
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "event", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum TelemetryParams {
+  /// A project was loaded successfully
+  ProjectLoad { duration_ms: u64, files: usize, constants: usize },
+  /// The server panicked at a location in its own source
+  Crash { signature: String },
+}
+
+/// `workspace/applyEdit`
+pub enum ApplyEdit {}
+impl Request for ApplyEdit {
+  const METHOD: &'static str = "workspace/applyEdit";
+  type Params = ApplyEditParams;
+  type Result = ApplyEditResult;
+}
+#[derive(Serialize, Clone, Debug)]
+pub struct ApplyEditParams {
+  /// Shown in the undo stack
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub label: Option<String>,
+  pub edit: WorkspaceEdit,
+}
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ApplyEditResult {
+  pub applied: bool,
+  pub failure_reason: Option<String>,
+}
+
+/// `window/showDocument`
+pub enum ShowDocument {}
+impl Request for ShowDocument {
+  const METHOD: &'static str = "window/showDocument";
+  type Params = ShowDocumentParams;
+  type Result = ShowDocumentResult;
+}
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ShowDocumentParams {
+  pub uri: String,
+  /// Open the URI in an external program such as a browser
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub external: Option<bool>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub take_focus: Option<bool>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub selection: Option<DocRange>,
+}
+#[derive(Deserialize, Clone, Debug)]
+pub struct ShowDocumentResult {
+  pub success: bool,
+}
+
+/// `window/showMessage`
+pub enum ShowMessage {}
+impl Notification for ShowMessage {
+  const METHOD: &'static str = "window/showMessage";
+  type Params = ShowMessageParams;
+}
+#[derive(Serialize, Clone, Debug)]
+pub struct ShowMessageParams {
+  #[serde(rename = "type")]
+  pub typ: MessageType,
+  pub message: String,
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(unused)] // defined by the protocol
+pub enum MessageType {
+  Error = 1,
+  Warning = 2,
+  Info = 3,
+  Log = 4,
+}
+impl Serialize for MessageType {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where S: serde::Serializer {
+    serializer.serialize_u8(*self as u8)
+  }
+}
+
+/// `window/workDoneProgress/create`
+pub enum WorkDoneProgressCreate {}
+impl Request for WorkDoneProgressCreate {
+  const METHOD: &'static str = "window/workDoneProgress/create";
+  type Params = WorkDoneProgressCreateParams;
+  type Result = ();
+}
+#[derive(Serialize, Clone, Debug)]
+pub struct WorkDoneProgressCreateParams {
+  pub token: String,
+}
+
+/// `workspace/workspaceFolders`
+pub enum WorkspaceFolders {}
+impl Request for WorkspaceFolders {
+  const METHOD: &'static str = "workspace/workspaceFolders";
+  type Params = ();
+  /// [None] if no folder is open
+  type Result = Option<Vec<WspaceEnt>>;
+}
+
+/// `textDocument/publishDiagnostics`
+pub enum PublishDiagnostics {}
+impl Notification for PublishDiagnostics {
+  const METHOD: &'static str = "textDocument/publishDiagnostics";
+  type Params = PublishDiagnosticsParams;
+}
+#[derive(Serialize, Clone, Debug)]
+pub struct PublishDiagnosticsParams {
+  pub uri: String,
+  pub diagnostics: Vec<Diagnostic>,
+}
+#[derive(Serialize, Clone, Debug, Hash, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Diagnostic {
+  pub range: DocRange,
+  /// 1 to 4 for error, warning, information and hint
+  pub severity: u8,
+  pub code: &'static str,
+  pub source: &'static str,
+  pub message: String,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub related_information: Vec<RelatedInformation>,
+  pub data: DiagnosticData,
+}
+/// Preserved by the client between publishing and code actions
+#[derive(Serialize, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct DiagnosticData {
+  /// Derived from the code, range and message so that a finding keeps its
+  /// identity across reloads
+  pub id: String,
+}
+#[derive(Serialize, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct RelatedInformation {
+  pub location: Location,
+  pub message: String,
+}
+#[derive(Serialize, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Location {
+  pub uri: String,
+  pub range: DocRange,
+}
@@ -1,6 +1,12 @@
 //! Types and tables to streamline LSP translation.
 
+pub mod client;
 pub mod docpos;
 pub mod document;
+pub mod edits;
 pub mod error;
+pub mod legend;
+pub mod line_index;
+pub mod messages;
+pub mod schema;
 pub mod tokens;
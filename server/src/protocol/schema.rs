@@ -0,0 +1,164 @@
+//! A typed description of JSON configuration. Options sent by the client are
+//! checked against it before they're read so that a mistyped field falls back
+//! to its default on its own instead of taking the rest of the object with it.
+
+use serde_json::{Map, Value};
+
+#[derive(Clone, Copy, Debug)]
+pub enum Schema {
+  /// Anything, for options the client reads itself
+  Any,
+  Bool,
+  /// A non-negative integer
+  Count,
+  Str,
+  /// One of a fixed set of strings
+  Enum(&'static [&'static str]),
+  List(&'static Schema),
+  /// An object with arbitrary keys and values of one type
+  Map(&'static Schema),
+  /// An object with known fields
+  Object(&'static [(&'static str, Schema)]),
+}
+impl Schema {
+  fn expected(&self) -> String {
+    match self {
+      Self::Any => "anything".to_string(),
+      Self::Bool => "a boolean".to_string(),
+      Self::Count => "a non-negative integer".to_string(),
+      Self::Str => "a string".to_string(),
+      Self::Enum(names) => {
+        let names = names.iter().map(|n| format!("\"{n}\"")).collect::<Vec<_>>();
+        format!("one of {}", names.join(", "))
+      },
+      Self::List(item) => format!("a list of {}", item.expected_plural()),
+      Self::Map(_) | Self::Object(_) => "an object".to_string(),
+    }
+  }
+
+  fn expected_plural(&self) -> String {
+    match self {
+      Self::Bool => "booleans".to_string(),
+      Self::Count => "non-negative integers".to_string(),
+      Self::Str => "strings".to_string(),
+      Self::List(_) => "lists".to_string(),
+      Self::Map(_) | Self::Object(_) => "objects".to_string(),
+      Self::Any | Self::Enum(_) => format!("values that are {}", self.expected()),
+    }
+  }
+}
+
+fn found(value: &Value) -> String {
+  match value {
+    Value::Null => "null".to_string(),
+    Value::Bool(_) => "a boolean".to_string(),
+    Value::Number(n) => format!("{n}"),
+    Value::String(s) => format!("\"{s}\""),
+    Value::Array(_) => "a list".to_string(),
+    Value::Object(_) => "an object".to_string(),
+  }
+}
+
+fn field(path: &str, key: &str) -> String {
+  if path.is_empty() { key.to_string() } else { format!("{path}.{key}") }
+}
+
+fn check(schema: &Schema, value: &Value, path: &str, problems: &mut Vec<String>) -> Option<Value> {
+  let valid = match (schema, value) {
+    // Null fields are treated as missing
+    (_, Value::Null) => return None,
+    (Schema::Any, _) => Some(value.clone()),
+    (Schema::Bool, Value::Bool(_)) | (Schema::Str, Value::String(_)) => Some(value.clone()),
+    (Schema::Count, Value::Number(n)) if n.is_u64() => Some(value.clone()),
+    (Schema::Enum(names), Value::String(s)) if names.contains(&s.as_str()) => Some(value.clone()),
+    (Schema::List(item), Value::Array(items)) => Some(Value::Array(
+      (items.iter().enumerate())
+        .filter_map(|(i, v)| check(item, v, &format!("{path}[{i}]"), problems))
+        .collect(),
+    )),
+    (Schema::Map(item), Value::Object(entries)) => Some(Value::Object(
+      (entries.iter())
+        .filter_map(|(k, v)| Some((k.clone(), check(item, v, &field(path, k), problems)?)))
+        .collect(),
+    )),
+    (Schema::Object(fields), Value::Object(entries)) => {
+      let mut valid = Map::new();
+      for (key, value) in entries {
+        match fields.iter().find(|(name, _)| name == key) {
+          None => problems.push(format!("unknown option `{}`", field(path, key))),
+          Some((_, schema)) => {
+            if let Some(value) = check(schema, value, &field(path, key), problems) {
+              valid.insert(key.clone(), value);
+            }
+          },
+        }
+      }
+      Some(Value::Object(valid))
+    },
+    _ => None,
+  };
+  if valid.is_none() {
+    problems.push(format!("`{path}` should be {}, found {}", schema.expected(), found(value)));
+  }
+  valid
+}
+
+/// Keep the parts of a value that conform to the schema and describe the rest
+/// by their dotted paths. Fields of the wrong type and unknown keys are
+/// dropped, so readers see the field as missing and use its default.
+pub fn sanitize(schema: &Schema, value: &Value) -> (Value, Vec<String>) {
+  let mut problems = Vec::new();
+  let valid = match value {
+    Value::Null => Value::Null,
+    Value::Object(_) => check(schema, value, "", &mut problems).unwrap_or(Value::Null),
+    _ => {
+      problems.push(format!("expected an object, found {}", found(value)));
+      Value::Null
+    },
+  };
+  (valid, problems)
+}
+
+#[cfg(test)]
+mod test {
+  use serde_json::json;
+
+  use super::{sanitize, Schema};
+
+  const SCHEMA: Schema = Schema::Object(&[
+    ("gas", Schema::Count),
+    ("exclude", Schema::List(&Schema::Str)),
+    ("severity", Schema::Map(&Schema::Enum(&["error", "off"]))),
+    ("sort", Schema::Object(&[("alphabetical", Schema::Bool)])),
+  ]);
+
+  #[test]
+  fn valid() {
+    let opts = json!({ "gas": 10, "exclude": ["a"], "sort": { "alphabetical": true } });
+    assert_eq!(sanitize(&SCHEMA, &opts), (opts, vec![]));
+    assert_eq!(sanitize(&SCHEMA, &json!(null)), (json!(null), vec![]));
+  }
+
+  #[test]
+  fn per_field() {
+    let opts = json!({
+      "gas": -1,
+      "exclude": ["a", 2],
+      "severity": { "unused": "off", "stale": "loud" },
+      "sort": { "alphabetical": "yes", "reverse": true },
+      "color": "red",
+    });
+    let (valid, problems) = sanitize(&SCHEMA, &opts);
+    assert_eq!(valid, json!({ "exclude": ["a"], "severity": { "unused": "off" }, "sort": {} }));
+    assert_eq!(problems, [
+      "unknown option `color`",
+      "`exclude[1]` should be a string, found 2",
+      "`gas` should be a non-negative integer, found -1",
+      "`severity.stale` should be one of \"error\", \"off\", found \"loud\"",
+      "`sort.alphabetical` should be a boolean, found \"yes\"",
+      "unknown option `sort.reverse`",
+    ]);
+    let (valid, problems) = sanitize(&SCHEMA, &json!([]));
+    assert_eq!((valid, problems), (json!(null), vec!["expected an object, found a list".into()]));
+  }
+}
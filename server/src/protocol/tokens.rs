@@ -26,34 +26,42 @@ impl SemToken {
   pub fn remap(self, ranges: impl IntoIterator<Item = Range<usize>>) -> impl Iterator<Item = Self> {
     ranges.into_iter().map(move |r| Self::new(self.range.map_range(|_| r), self.typ.clone()))
   }
+  /// Split the token at newlines, as clients without `multilineTokenSupport`
+  /// require
   pub fn split(self) -> impl IntoIterator<Item = Self> {
-    match self.text()[self.start()..self.end()].find('\n') {
+    let text = self.text();
+    match text[self.start()..self.end()].find(['\r', '\n']) {
       None => vec![self],
-      Some(0) => vec![Self::new(self.range.map_range(|r| r.start + 1..r.end), self.typ)],
       Some(sp) => {
-        let pre = self.start()..self.start() + sp;
-        let post = self.start() + sp + 1..self.end();
+        let eol = self.start() + sp;
+        let next = if text[eol..].starts_with("\r\n") { eol + 2 } else { eol + 1 };
+        let post = next.min(self.end())..self.end();
+        if sp == 0 {
+          let rest = Self::new(self.range.map_range(|_| post), self.typ);
+          return rest.split().into_iter().collect();
+        }
+        let pre = self.start()..eol;
         let (h, t) = self.remap([pre, post]).collect_tuple().unwrap();
         iter::once(h).chain(t.split()).collect()
       },
     }
   }
 
-  /// Translate tokens to single-line fragments with absolute line/col positions
-  /// and lengths according to VSCode's rules.
-  ///
-  /// # Panics
-  ///
-  /// if there are no tokens
-  pub fn vscode(tokens: impl IntoIterator<Item = SemToken>) -> Vec<(DocPos, usize, SemToken)> {
+  /// Translate tokens to absolute line/col positions and lengths according to
+  /// VSCode's rules. Unless `multiline` is set, tokens are split into
+  /// single-line fragments.
+  pub fn vscode(
+    tokens: impl IntoIterator<Item = SemToken>,
+    multiline: bool,
+  ) -> Vec<(DocPos, usize, SemToken)> {
     let mut sc = None;
-    // Vector of single-line semantic tokens
+    // Vector of semantic tokens, single-line unless multiline is supported
     let tokens = tokens
       .into_iter()
-      .flat_map(|t| t.split())
+      .flat_map(|t| if multiline { vec![t] } else { t.split().into_iter().collect_vec() })
       .inspect(|t| if let Some(sc) = &sc { assert!(sc == &t.code()) } else { sc = Some(t.code()) })
       .collect_vec();
-    let source = sc.expect("transcode_tokens called on 0 tokens").text();
+    let Some(source) = sc.map(|sc| sc.text()) else { return Vec::new() };
     // Vector of range end numbers paired with a thing that lexically sorts
     // unambiguously
     let halves = (tokens.iter())
@@ -66,13 +74,32 @@ impl SemToken {
         .tuples::<(_, _)>()
         .zip_eq(tokens) // panics if the lengths don't match
         .map(|(((start, _), (end, _)), tok)| {
-          debug_assert_eq!(end.line, start.line, "Broken above");
-          (start, end.char - start.char, tok)
+          debug_assert!(multiline || end.line == start.line, "Broken above");
+          let len = match start.line == end.line {
+            true => end.char - start.char,
+            false => source[tok.start()..tok.end()].encode_utf16().count(),
+          };
+          (start, len, tok)
         }).collect_vec();
     output.sort_unstable_by_key(|(start, ..)| *start);
     output
   }
 }
+
+/// Encode tokens sorted by position into the relative format of LSP's
+/// `SemanticTokens`. Each token is described by its start, length and an index
+/// into the legend.
+pub fn delta_encode(tokens: impl IntoIterator<Item = (DocPos, usize, usize)>) -> Vec<usize> {
+  let mut prev = DocPos::new(0, 0);
+  let mut data = Vec::new();
+  for (pos, len, typ) in tokens {
+    let dchar = if pos.line == prev.line { pos.char - prev.char } else { pos.char };
+    data.extend([pos.line - prev.line, dchar, len, typ, 0]);
+    prev = pos;
+  }
+  data
+}
+
 impl cmp::Ord for SemToken {
   fn cmp(&self, other: &Self) -> cmp::Ordering {
     self.range.start().cmp(&other.range.start()).then(other.range.end().cmp(&self.range.end()))
@@ -93,10 +120,13 @@ mod test {
   use std::sync::Arc;
 
   use intern_all::i;
+  use itertools::Itertools;
   use orchidlang::location::{SourceCode, SourceRange};
   use orchidlang::sym;
 
-  use super::SemToken;
+  use super::{delta_encode, SemToken};
+  use crate::protocol::docpos::DocPos;
+  use crate::testing::{boundaries, naive_delta_decode, naive_fragments, naive_pos, Rng};
 
   fn s(range: Range<usize>, code: &str) -> Vec<Range<usize>> {
     let sr = SourceRange::new(range, SourceCode::new(sym!(foo), Arc::new(code.to_string())));
@@ -109,5 +139,86 @@ mod test {
     assert_eq!(s(1..3, "foobarbaz"), [1..3], "No splitting");
     assert_eq!(s(2..7, "foo\nbar\nbaz"), [2..3, 4..7], "1 split ends before newline");
     assert_eq!(s(2..12, "foo\nbar\n\nbaz"), [2..3, 4..7, 9..12], "2 splits through empty line");
+    assert_eq!(s(2..11, "foo\r\nbar\rbaz"), [2..3, 5..8, 9..11], "CRLF and CR");
+  }
+
+  #[test]
+  fn delta() {
+    let toks = [(DocPos::new(0, 2), 3, 0), (DocPos::new(0, 7), 1, 1), (DocPos::new(2, 4), 2, 0)];
+    assert_eq!(delta_encode(toks), [0, 2, 3, 0, 0, 0, 5, 1, 1, 0, 2, 4, 2, 0, 0]);
+  }
+
+  /// Random texts and tokens within them, some empty or spanning lines
+  fn random_tokens(rng: &mut Rng) -> (Arc<String>, Vec<SemToken>) {
+    let lines = rng.below(6) + 1;
+    let text = Arc::new(rng.text(lines));
+    let code = SourceCode::new(sym!(foo), text.clone());
+    let bounds = boundaries(&text);
+    let toks = (0..rng.below(6))
+      .map(|_| {
+        let (a, b) = (bounds[rng.below(bounds.len())], bounds[rng.below(bounds.len())]);
+        let range = SourceRange::new(a.min(b)..a.max(b), code.clone());
+        SemToken::new(range, [i!(str: "foo"), i!(str: "bar")][rng.below(2)].clone())
+      })
+      .collect_vec();
+    (text, toks)
+  }
+
+  #[test]
+  fn split_random() {
+    let mut rng = Rng::new(0x5b117);
+    for _ in 0..500 {
+      let (text, toks) = random_tokens(&mut rng);
+      for tok in toks {
+        let range = tok.start()..tok.end();
+        let fragments = (tok.split().into_iter())
+          .map(|t| (naive_pos(&text, t.start()), text[t.start()..t.end()].encode_utf16().count()))
+          .collect_vec();
+        assert_eq!(fragments, naive_fragments(&text, range.clone()), "{range:?} of {text:?}");
+      }
+    }
+  }
+
+  #[test]
+  fn delta_random() {
+    let mut rng = Rng::new(0xde17a);
+    for _ in 0..500 {
+      let (text, toks) = random_tokens(&mut rng);
+      let expected = (toks.iter())
+        .flat_map(|t| {
+          let typ = usize::from(t.typ() != i!(str: "foo"));
+          naive_fragments(&text, t.start()..t.end()).into_iter().map(move |(p, l)| (p, l, typ))
+        })
+        .sorted()
+        .collect_vec();
+      let encoded = (SemToken::vscode(toks, false).into_iter())
+        .map(|(pos, len, tok)| (pos, len, usize::from(tok.typ() != i!(str: "foo"))))
+        .collect_vec();
+      let decoded = naive_delta_decode(&delta_encode(encoded.iter().copied()));
+      assert_eq!(decoded, encoded, "Delta roundtrip in {text:?}");
+      assert_eq!(decoded.into_iter().sorted().collect_vec(), expected, "Tokens of {text:?}");
+    }
+  }
+
+  #[test]
+  fn vscode_random() {
+    assert!(SemToken::vscode([], false).is_empty(), "No tokens");
+    let mut rng = Rng::new(0x70c5eed);
+    for _ in 0..500 {
+      let (text, toks) = random_tokens(&mut rng);
+      for (pos, len, tok) in SemToken::vscode(toks.clone(), true) {
+        let frag = &text[tok.start()..tok.end()];
+        assert_eq!(pos, naive_pos(&text, tok.start()), "Multiline position mismatch in {text:?}");
+        assert_eq!(len, frag.encode_utf16().count(), "Multiline length mismatch in {text:?}");
+      }
+      let output = SemToken::vscode(toks, false);
+      assert!(output.windows(2).all(|w| w[0].0 <= w[1].0), "Output not sorted in {text:?}");
+      for (pos, len, tok) in output {
+        let frag = &text[tok.start()..tok.end()];
+        assert!(!frag.contains(['\r', '\n']), "Multiline fragment {frag:?} in {text:?}");
+        assert_eq!(pos, naive_pos(&text, tok.start()), "Position mismatch in {text:?}");
+        assert_eq!(len, frag.encode_utf16().count(), "Length mismatch in {text:?}");
+      }
+    }
   }
 }
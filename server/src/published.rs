@@ -0,0 +1,173 @@
+//! Diagnostics as last published for each file, so that a reload only sends
+//! the files whose findings changed, and the summary of the findings of a
+//! workspace lint sweep
+
+use std::collections::BTreeMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::mem;
+
+use hashbrown::HashMap;
+use itertools::Itertools;
+use serde_json::{json, Value};
+
+use crate::protocol::messages::{Diagnostic, PublishDiagnosticsParams};
+
+/// The most files listed in the summary
+const WORST_FILES: usize = 10;
+
+/// Decide what to send given the hashes of the diagnostics a project last
+/// published for each file. Files whose diagnostics didn't change are
+/// skipped, empty lists are only sent to clear previous findings, and files
+/// that had findings but aren't in the new list are cleared as well.
+pub fn reconcile(
+  owned: &mut HashMap<String, (u64, Vec<Diagnostic>)>,
+  diagnostics: Vec<PublishDiagnosticsParams>,
+) -> Vec<PublishDiagnosticsParams> {
+  let previous = mem::take(owned);
+  let mut files = (previous.keys()).map(|uri| (uri.clone(), Vec::new())).collect::<HashMap<_, _>>();
+  files.extend(diagnostics.into_iter().map(|p| (p.uri, p.diagnostics)));
+  let mut out = Vec::new();
+  for (uri, mut diagnostics) in files {
+    // A stable order without duplicates, so the same findings hash the same
+    diagnostics.sort_by(|a, b| {
+      (a.range.start, a.range.end, &a.data.id).cmp(&(b.range.start, b.range.end, &b.data.id))
+    });
+    diagnostics.dedup();
+    if diagnostics.is_empty() {
+      if previous.contains_key(&uri) {
+        out.push(PublishDiagnosticsParams { uri, diagnostics })
+      }
+      continue;
+    }
+    let mut hasher = DefaultHasher::new();
+    diagnostics.hash(&mut hasher);
+    let hash = hasher.finish();
+    owned.insert(uri.clone(), (hash, diagnostics.clone()));
+    if previous.get(&uri).map(|(prev, _)| *prev) != Some(hash) {
+      out.push(PublishDiagnosticsParams { uri, diagnostics })
+    }
+  }
+  out.sort_by(|a, b| a.uri.cmp(&b.uri));
+  out
+}
+
+/// Count the findings by lint and severity, and list the files with the most
+/// errors and then the most findings
+pub fn summary(findings: &[(String, Vec<Diagnostic>)], failed: usize) -> Value {
+  let mut by_rule = BTreeMap::<&str, usize>::new();
+  let mut by_severity = [0; 4];
+  for diagnostic in findings.iter().flat_map(|(_, diags)| diags) {
+    *by_rule.entry(diagnostic.code).or_default() += 1;
+    by_severity[diagnostic.severity as usize - 1] += 1;
+  }
+  let error_count = |diags: &[Diagnostic]| diags.iter().filter(|d| d.severity == 1).count();
+  let worst = (findings.iter())
+    .filter(|(_, diags)| !diags.is_empty())
+    .sorted_by(|(a_uri, a), (b_uri, b)| {
+      let rank = |diags: &[Diagnostic]| (error_count(diags), diags.len());
+      rank(b).cmp(&rank(a)).then_with(|| a_uri.cmp(b_uri))
+    })
+    .take(WORST_FILES)
+    .map(|(uri, diags)| {
+      json!({ "uri": uri, "errors": error_count(diags), "findings": diags.len() })
+    })
+    .collect_vec();
+  let [errors, warnings, information, hints] = by_severity;
+  json!({
+    "failedProjects": failed,
+    "errors": errors,
+    "warnings": warnings,
+    "information": information,
+    "hints": hints,
+    "byRule": by_rule,
+    "worstFiles": worst,
+  })
+}
+
+#[cfg(test)]
+mod test {
+  use hashbrown::HashMap;
+  use serde_json::json;
+
+  use super::{reconcile, summary};
+  use crate::protocol::docpos::DocPos;
+  use crate::protocol::document::DocRange;
+  use crate::protocol::messages::{Diagnostic, DiagnosticData, PublishDiagnosticsParams};
+
+  fn diag(line: usize, message: &str) -> Diagnostic {
+    let pos = DocPos { line, char: 0 };
+    Diagnostic {
+      range: DocRange { start: pos, end: pos },
+      severity: 2,
+      code: "test",
+      source: "orchid",
+      message: message.to_string(),
+      related_information: Vec::new(),
+      data: DiagnosticData { id: format!("test@{line}") },
+    }
+  }
+  fn params(uri: &str, diagnostics: Vec<Diagnostic>) -> PublishDiagnosticsParams {
+    PublishDiagnosticsParams { uri: uri.to_string(), diagnostics }
+  }
+  fn uris(sent: &[PublishDiagnosticsParams]) -> Vec<(&str, usize)> {
+    sent.iter().map(|p| (p.uri.as_str(), p.diagnostics.len())).collect()
+  }
+
+  #[test]
+  fn added_removed() {
+    let mut owned = HashMap::new();
+    let sent = reconcile(&mut owned, vec![params("a", vec![diag(1, "x")]), params("b", vec![])]);
+    assert_eq!(uris(&sent), [("a", 1)], "Files without findings aren't cleared needlessly");
+    let sent = reconcile(&mut owned, vec![params("a", vec![diag(1, "x"), diag(2, "y")])]);
+    assert_eq!(uris(&sent), [("a", 2)]);
+    let sent = reconcile(&mut owned, vec![params("a", vec![diag(2, "y")])]);
+    assert_eq!(uris(&sent), [("a", 1)]);
+    let sent = reconcile(&mut owned, vec![]);
+    assert_eq!(uris(&sent), [("a", 0)], "Fixed findings are cleared");
+    assert!(owned.is_empty());
+  }
+
+  #[test]
+  fn unchanged() {
+    let mut owned = HashMap::new();
+    reconcile(&mut owned, vec![params("a", vec![diag(1, "x"), diag(2, "y")])]);
+    let reordered = vec![params("a", vec![diag(2, "y"), diag(1, "x"), diag(1, "x")])];
+    assert!(reconcile(&mut owned, reordered).is_empty(), "Order and duplicates don't matter");
+    let sent = reconcile(&mut owned, vec![params("a", vec![diag(1, "x"), diag(2, "z")])]);
+    assert_eq!(uris(&sent), [("a", 2)], "Messages are compared too");
+  }
+
+  #[test]
+  fn renamed() {
+    let mut owned = HashMap::new();
+    reconcile(&mut owned, vec![params("a", vec![diag(1, "x")])]);
+    let sent = reconcile(&mut owned, vec![params("b", vec![diag(1, "x")])]);
+    assert_eq!(uris(&sent), [("a", 0), ("b", 1)], "The old file is cleared");
+    assert_eq!(owned.keys().collect::<Vec<_>>(), ["b"]);
+  }
+
+  #[test]
+  fn summarizing() {
+    let error = |line, code| Diagnostic { severity: 1, code, ..diag(line, "e") };
+    let findings = [
+      ("a".to_string(), vec![diag(1, "x"), diag(2, "y"), diag(3, "z")]),
+      ("b".to_string(), vec![error(1, "other")]),
+      ("c".to_string(), vec![]),
+      ("d".to_string(), vec![diag(1, "x")]),
+    ];
+    let expected = json!({
+      "failedProjects": 1,
+      "errors": 1,
+      "warnings": 4,
+      "information": 0,
+      "hints": 0,
+      "byRule": { "other": 1, "test": 4 },
+      "worstFiles": [
+        { "uri": "b", "errors": 1, "findings": 1 },
+        { "uri": "a", "errors": 0, "findings": 3 },
+        { "uri": "d", "errors": 0, "findings": 1 },
+      ],
+    });
+    assert_eq!(summary(&findings, 1), expected, "Errors rank first, files without findings go");
+  }
+}
@@ -0,0 +1,215 @@
+//! Replaying a transcript of messages against the server, for regression tests
+//! built from bug reports. Run with `orchid-ls --replay <transcript>`.
+//!
+//! Transcripts have the format of the [crate::journal], one JSON object per
+//! line with a `direction` of `in` or `out` and a `message`. Lines that are
+//! empty or start with `//` are skipped so that hand-written transcripts can be
+//! annotated. Inbound messages are sent to the server in order and outbound
+//! ones are the expectations:
+//!
+//! - responses must be sent to the request with the same ID,
+//! - other messages must be sent in order relative to messages of the same
+//!   method, but may be interleaved with anything else.
+//!
+//! Expected messages only need to contain the fields that matter. Redacted
+//! text and the string `"*"` match any value. Inbound messages can't be
+//! redacted, as the server would analyze the placeholders instead of the
+//! documents, so journals have to be edited to include the text first.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{fs, iter, thread};
+
+use serde_json::Value;
+
+use crate::event_loop::EventLoop;
+use crate::jrpc::{JrpcServer, SendCB};
+
+/// Output must be quiet for this long before the next message is sent
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+/// Longest wait for the output to settle, for servers that never go quiet
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Transcript {
+  pub inbound: Vec<Value>,
+  pub expected: Vec<Value>,
+}
+impl Transcript {
+  pub fn parse(text: &str) -> Result<Self, String> {
+    let (mut inbound, mut expected) = (Vec::new(), Vec::new());
+    for (i, line) in text.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with("//") {
+        continue;
+      }
+      let entry = serde_json::from_str::<Value>(line).map_err(|e| format!("line {}: {e}", i + 1))?;
+      match entry["direction"].as_str() {
+        Some("in") if has_redacted(&entry["message"]) =>
+          return Err(format!("line {}: the document text is redacted", i + 1)),
+        Some("in") => inbound.push(entry["message"].clone()),
+        Some("out") => expected.push(entry["message"].clone()),
+        _ => return Err(format!("line {}: direction must be \"in\" or \"out\"", i + 1)),
+      }
+    }
+    Ok(Self { inbound, expected })
+  }
+}
+
+fn is_redacted(s: &str) -> bool { s.starts_with('<') && s.ends_with(" redacted>") }
+
+fn is_wildcard(s: &str) -> bool { s == "*" || is_redacted(s) }
+
+/// Whether any string in the message is a redaction placeholder
+fn has_redacted(message: &Value) -> bool {
+  match message {
+    Value::String(s) => is_redacted(s),
+    Value::Array(items) => items.iter().any(has_redacted),
+    Value::Object(fields) => fields.values().any(has_redacted),
+    _ => false,
+  }
+}
+
+/// Whether the actual message contains everything the expected one does
+pub fn matches(expected: &Value, actual: &Value) -> bool {
+  match (expected, actual) {
+    (Value::String(s), _) if is_wildcard(s) => true,
+    (Value::Object(exp), Value::Object(act)) =>
+      exp.iter().all(|(k, v)| act.get(k).is_some_and(|a| matches(v, a))),
+    (Value::Array(exp), Value::Array(act)) =>
+      exp.len() == act.len() && exp.iter().zip(act).all(|(e, a)| matches(e, a)),
+    _ => expected == actual,
+  }
+}
+
+/// Describe the expected messages that weren't sent
+pub fn compare(expected: &[Value], actual: &[Value]) -> Vec<String> {
+  let mut problems = Vec::new();
+  // Index of the next candidate of each method
+  let mut next = HashMap::<&str, usize>::new();
+  for exp in expected {
+    let Some(method) = exp["method"].as_str() else {
+      let response = actual.iter().find(|a| a.get("method").is_none() && a["id"] == exp["id"]);
+      match response {
+        None => problems.push(format!("No response to #{}", exp["id"])),
+        Some(act) if !matches(exp, act) => {
+          let id = &exp["id"];
+          problems.push(format!("Response to #{id} differs\n  expected {exp}\n  got {act}"))
+        },
+        Some(_) => (),
+      }
+      continue;
+    };
+    let start = next.get(method).copied().unwrap_or(0);
+    let found = (actual.iter().enumerate().skip(start))
+      .find(|(_, a)| a["method"].as_str() == Some(method) && matches(exp, a));
+    match found {
+      Some((i, _)) => drop(next.insert(method, i + 1)),
+      None => problems.push(format!("Expected {method} was not sent\n  expected {exp}")),
+    }
+  }
+  problems
+}
+
+/// Wait until no message was sent for [SETTLE_TIME]
+fn settle(sent: &Mutex<Vec<Value>>) {
+  let start = Instant::now();
+  let mut last = (sent.lock().unwrap().len(), Instant::now());
+  while last.1.elapsed() < SETTLE_TIME && start.elapsed() < SETTLE_TIMEOUT {
+    thread::sleep(SETTLE_TIME / 10);
+    let len = sent.lock().unwrap().len();
+    if len != last.0 {
+      last = (len, Instant::now());
+    }
+  }
+}
+
+/// Replay a transcript against a server built by the callback and print the
+/// differences. Returns the exit code.
+pub fn run(path: &Path, server: impl FnOnce(Box<dyn SendCB>) -> JrpcServer) -> i32 {
+  let transcript = match fs::read_to_string(path).map_err(|e| e.to_string()) {
+    Ok(text) => Transcript::parse(&text),
+    Err(e) => Err(e),
+  };
+  let transcript = match transcript {
+    Ok(transcript) => transcript,
+    Err(e) => {
+      println!("Failed to read {}: {e}", path.display());
+      return 2;
+    },
+  };
+  let sent = Arc::new(Mutex::new(Vec::new()));
+  let sent2 = sent.clone();
+  let mut srv = server(Box::new(move |msg| sent2.lock().unwrap().push(msg)));
+  // Settling happens on the input thread so that timers keep firing meanwhile
+  let sent3 = sent.clone();
+  let input = (transcript.inbound.into_iter())
+    // The server would exit the process
+    .take_while(|message| message["method"] != "exit")
+    .inspect(move |_| settle(&sent3))
+    .chain(iter::from_fn({
+      let sent = sent.clone();
+      move || {
+        settle(&sent);
+        None
+      }
+    }));
+  EventLoop::new(srv.session().scheduler(), input).run(&mut srv);
+  let problems = compare(&transcript.expected, &sent.lock().unwrap());
+  for problem in problems.iter() {
+    println!("{problem}");
+  }
+  let total = transcript.expected.len();
+  println!("{} of {total} expectations met", total - problems.len());
+  i32::from(!problems.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+  use serde_json::json;
+
+  use super::{compare, matches, Transcript};
+
+  #[test]
+  fn parse() {
+    let text = [
+      "// opens a file",
+      r#"{ "direction": "in", "ms": 0, "message": { "id": 0, "method": "initialize" } }"#,
+      "",
+      r#"{ "direction": "out", "message": { "id": 0 } }"#,
+    ]
+    .join("\n");
+    let transcript = Transcript::parse(&text).unwrap();
+    assert_eq!(transcript.inbound, [json!({ "id": 0, "method": "initialize" })]);
+    assert_eq!(transcript.expected, [json!({ "id": 0 })]);
+    assert!(Transcript::parse(r#"{ "message": {} }"#).is_err());
+    let redacted = r#"{ "direction": "in", "message": { "text": "<3 bytes redacted>" } }"#;
+    let err = Transcript::parse(redacted).err().unwrap();
+    assert!(err.contains("redacted"), "Redacted input can't be replayed");
+  }
+
+  #[test]
+  fn matching() {
+    let actual = json!({ "id": 1, "result": { "kind": "markdown", "value": "text" } });
+    assert!(matches(&json!({ "id": 1, "result": { "value": "*" } }), &actual));
+    assert!(matches(&json!({ "result": { "value": "<4 bytes redacted>" } }), &actual));
+    assert!(!matches(&json!({ "result": { "kind": "plaintext" } }), &actual));
+    assert!(!matches(&json!({ "result": [] }), &actual));
+  }
+
+  #[test]
+  fn ordering() {
+    let actual = [
+      json!({ "method": "a", "params": 1 }),
+      json!({ "id": 3, "result": null }),
+      json!({ "method": "b" }),
+      json!({ "method": "a", "params": 2 }),
+    ];
+    let expected = [json!({ "method": "a", "params": 2 }), json!({ "method": "b" })];
+    assert!(compare(&expected, &actual).is_empty());
+    let reordered = [json!({ "method": "a", "params": 2 }), json!({ "method": "a", "params": 1 })];
+    assert_eq!(compare(&reordered, &actual).len(), 1);
+    assert_eq!(compare(&[json!({ "id": 4 })], &actual), ["No response to #4"]);
+  }
+}
@@ -0,0 +1,126 @@
+//! Helpers for randomized tests and for tests that load a project
+
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{env, fs, process};
+
+use orchidlang::name::VPath;
+
+use crate::abort::Abort;
+use crate::orc::project::{LoadedProject, ProjectRoot};
+use crate::orc::project_info::TEMPLATE;
+use crate::patches::PatchStore;
+use crate::protocol::docpos::DocPos;
+use crate::protocol::document::FileUri;
+
+/// Deterministic xorshift generator so that failures are reproducible
+pub struct Rng(u64);
+impl Rng {
+  pub fn new(seed: u64) -> Self { Self(seed.max(1)) }
+  pub fn next_u64(&mut self) -> u64 {
+    self.0 ^= self.0 << 13;
+    self.0 ^= self.0 >> 7;
+    self.0 ^= self.0 << 17;
+    self.0
+  }
+  /// A number in `0..n`
+  pub fn below(&mut self, n: usize) -> usize { (self.next_u64() % n as u64) as usize }
+  /// Short lines of characters with a mix of utf-8 and utf-16 widths, and a mix
+  /// of line terminators
+  pub fn text(&mut self, lines: usize) -> String {
+    const CHARS: &[char] = &['a', 'b', ' ', '(', 'é', 'ő', '字', '𝔸'];
+    const EOLS: &[&str] = &["\n", "\n", "\r\n", "\r"];
+    let mut text = String::new();
+    for i in 0..lines {
+      if i != 0 {
+        text.push_str(EOLS[self.below(EOLS.len())]);
+      }
+      text.extend((0..self.below(8)).map(|_| CHARS[self.below(CHARS.len())]));
+    }
+    text
+  }
+}
+
+/// Char boundaries of a text including its end, except the ones between `\r`
+/// and `\n`
+pub fn boundaries(text: &str) -> Vec<usize> {
+  (text.char_indices().map(|(i, _)| i).chain([text.len()]))
+    .filter(|i| !(text[..*i].ends_with('\r') && text[*i..].starts_with('\n')))
+    .collect()
+}
+
+/// Reference implementation of a byte offset to document position conversion
+pub fn naive_pos(text: &str, bpos: usize) -> DocPos {
+  let before = &text[..bpos];
+  let line_start = before.rfind(['\r', '\n']).map_or(0, |i| i + 1);
+  let line = before.replace("\r\n", "\n").matches(['\r', '\n']).count();
+  DocPos::new(line, before[line_start..].encode_utf16().count())
+}
+
+/// Reference implementation of splitting a token at line terminators. Returns
+/// the position and UTF-16 length of each fragment. Empty fragments are only
+/// kept at the end of the token.
+pub fn naive_fragments(text: &str, range: Range<usize>) -> Vec<(DocPos, usize)> {
+  let (mut fragments, mut start) = (Vec::new(), range.start);
+  loop {
+    let end = text[start..range.end].find(['\r', '\n']).map_or(range.end, |i| start + i);
+    if start < end || end == range.end {
+      fragments.push((naive_pos(text, start), text[start..end].encode_utf16().count()));
+    }
+    if end == range.end {
+      return fragments;
+    }
+    start = if text[end..].starts_with("\r\n") { end + 2 } else { end + 1 };
+  }
+}
+
+/// Reference implementation of decoding LSP's relative token format into
+/// absolute positions, lengths and types
+pub fn naive_delta_decode(data: &[usize]) -> Vec<(DocPos, usize, usize)> {
+  let (mut line, mut char) = (0, 0);
+  (data.chunks(5))
+    .map(|chunk| {
+      if chunk[0] != 0 {
+        char = 0;
+      }
+      line += chunk[0];
+      char += chunk[1];
+      (DocPos::new(line, char), chunk[2], chunk[3])
+    })
+    .collect()
+}
+
+/// A project folder under the temporary directory, removed when dropped
+pub struct Fixture(PathBuf);
+impl Fixture {
+  /// Write the files of a project, given by their path relative to the folder
+  /// without the extension. A project file is added so that they form a single
+  /// project.
+  pub fn new(files: &[(&str, &str)]) -> Self {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let id = NEXT.fetch_add(1, Ordering::Relaxed);
+    let this = Self(env::temp_dir().join(format!("orchid-ls-test-{}-{id}", process::id())));
+    this.write("project_info", TEMPLATE);
+    files.iter().for_each(|(path, text)| this.write(path, text));
+    this
+  }
+
+  /// Replace the text of a file
+  pub fn write(&self, path: &str, text: &str) {
+    let file = self.0.join(format!("{path}.orc"));
+    fs::create_dir_all(file.parent().unwrap()).unwrap();
+    fs::write(file, text).unwrap();
+  }
+
+  /// Load the project as it's currently on disk
+  pub fn load(&self) -> LoadedProject {
+    let root = FileUri::from_path(&self.0.canonicalize().unwrap()).unwrap();
+    let project = ProjectRoot::Dir(VPath::new([]));
+    LoadedProject::new(PatchStore::new(root), project, 10_000, Abort::new(), &mut |_, _| ())
+      .unwrap_or_else(|errors| panic!("The fixture failed to load with {} errors", errors.len()))
+  }
+}
+impl Drop for Fixture {
+  fn drop(&mut self) { let _ = fs::remove_dir_all(&self.0); }
+}
@@ -0,0 +1,147 @@
+//! Throttling of `$/logTrace` messages. Verbose traces can carry whole token
+//! payloads, and a large workspace produces the same message for every file,
+//! so payloads are truncated and runs of a repeated message are sampled and
+//! summarized. Every message is numbered, including the ones that are left
+//! out, so gaps in the sequence show where something was dropped. A run that
+//! isn't followed by another message is summarized once its window passes,
+//! see [Throttle::flush].
+
+use std::time::{Duration, Instant};
+
+/// The longest verbose payload that is sent in full, in bytes
+pub const MAX_VERBOSE: usize = 4096;
+/// Repeats of a message this close after the first one in a run are sampled
+pub const REPEAT_WINDOW: Duration = Duration::from_secs(1);
+/// One in this many repeats within the window is sent
+const SAMPLE_EVERY: usize = 100;
+
+#[derive(Debug)]
+struct Run {
+  message: String,
+  start: Instant,
+  /// Repeats left out since a message of the run was last sent
+  suppressed: usize,
+}
+
+/// What to send for a message
+#[derive(Debug, PartialEq, Eq)]
+pub struct Admitted {
+  /// Counts the repeats left out of the previous run, which has ended
+  pub summary: Option<String>,
+  /// The message with its sequence number, if it isn't left out
+  pub message: Option<String>,
+  /// When to [Throttle::flush] the run, set when the first repeat of the run is
+  /// left out
+  pub flush_at: Option<Instant>,
+}
+
+/// Numbers messages and tracks runs of the same message
+#[derive(Debug, Default)]
+pub struct Throttle {
+  seq: u64,
+  run: Option<Run>,
+}
+impl Throttle {
+  pub fn admit(&mut self, message: &str, now: Instant) -> Admitted {
+    self.seq += 1;
+    let seq = self.seq;
+    if let Some(run) = &mut self.run {
+      if run.message == message && now.duration_since(run.start) < REPEAT_WINDOW {
+        run.suppressed += 1;
+        if run.suppressed < SAMPLE_EVERY {
+          let flush_at = (run.suppressed == 1).then_some(run.start + REPEAT_WINDOW);
+          return Admitted { summary: None, message: None, flush_at };
+        }
+        let message = format!("#{seq} {message} (and {} repeats)", run.suppressed - 1);
+        run.suppressed = 0;
+        return Admitted { summary: None, message: Some(message), flush_at: None };
+      }
+    }
+    let summary = self.run.take().and_then(summarize);
+    self.run = Some(Run { message: message.to_string(), start: now, suppressed: 0 });
+    Admitted { summary, message: Some(format!("#{seq} {message}")), flush_at: None }
+  }
+
+  /// End the run if its window has passed, summarizing the repeats left out
+  /// since a message of it was last sent
+  pub fn flush(&mut self, now: Instant) -> Option<String> {
+    let run = self.run.as_ref()?;
+    if now.duration_since(run.start) < REPEAT_WINDOW {
+      return None;
+    }
+    self.run.take().and_then(summarize)
+  }
+}
+
+fn summarize(run: Run) -> Option<String> {
+  (0 < run.suppressed).then(|| format!("{} repeated {} more times", run.message, run.suppressed))
+}
+
+/// Cut a verbose payload to [MAX_VERBOSE] bytes on a character boundary
+pub fn truncate(mut verbose: String) -> String {
+  if verbose.len() <= MAX_VERBOSE {
+    return verbose;
+  }
+  let end = (0..=MAX_VERBOSE).rev().find(|i| verbose.is_char_boundary(*i)).unwrap_or(0);
+  let omitted = verbose.len() - end;
+  verbose.truncate(end);
+  verbose.push_str(&format!("… ({omitted} more bytes)"));
+  verbose
+}
+
+#[cfg(test)]
+mod test {
+  use std::time::{Duration, Instant};
+
+  use super::{truncate, Admitted, Throttle, MAX_VERBOSE, REPEAT_WINDOW, SAMPLE_EVERY};
+
+  fn sent(msg: &str) -> Admitted {
+    Admitted { summary: None, message: Some(msg.to_string()), flush_at: None }
+  }
+
+  fn left_out() -> Admitted { Admitted { summary: None, message: None, flush_at: None } }
+
+  #[test]
+  fn sampling() {
+    let (mut thr, start) = (Throttle::default(), Instant::now());
+    assert_eq!(thr.admit("push", start), sent("#1 push"));
+    let flush_at = Some(start + REPEAT_WINDOW);
+    assert_eq!(thr.admit("push", start), Admitted { flush_at, ..left_out() });
+    for _ in 2..SAMPLE_EVERY {
+      assert_eq!(thr.admit("push", start), left_out());
+    }
+    let sampled = format!("#{} push (and {} repeats)", SAMPLE_EVERY + 1, SAMPLE_EVERY - 1);
+    assert_eq!(thr.admit("push", start), sent(&sampled));
+    assert_eq!(thr.admit("push", start).flush_at, flush_at, "Flushed again after a sample");
+    thr.admit("push", start);
+    let next = thr.admit("load", start);
+    assert_eq!(next.summary.as_deref(), Some("push repeated 2 more times"));
+    assert_eq!(next.message.as_deref(), Some(format!("#{} load", SAMPLE_EVERY + 4).as_str()));
+    let later = start + Duration::from_secs(2);
+    assert_eq!(thr.admit("load", later), sent(&format!("#{} load", SAMPLE_EVERY + 5)));
+  }
+
+  #[test]
+  fn flush() {
+    let (mut thr, start) = (Throttle::default(), Instant::now());
+    thr.admit("push", start);
+    assert_eq!(thr.flush(start + REPEAT_WINDOW), None, "Nothing left out");
+    thr.admit("push", start + REPEAT_WINDOW);
+    thr.admit("push", start + REPEAT_WINDOW);
+    thr.admit("push", start + REPEAT_WINDOW);
+    assert_eq!(thr.flush(start + REPEAT_WINDOW), None, "The window is still open");
+    let summary = thr.flush(start + REPEAT_WINDOW * 2);
+    assert_eq!(summary.as_deref(), Some("push repeated 2 more times"));
+    assert_eq!(thr.flush(start + REPEAT_WINDOW * 3), None, "Flushed once");
+    assert_eq!(thr.admit("push", start + REPEAT_WINDOW * 3).summary, None);
+  }
+
+  #[test]
+  fn truncation() {
+    assert_eq!(truncate("short".to_string()), "short");
+    let long = "é".repeat(MAX_VERBOSE);
+    let cut = truncate(long);
+    assert!(cut.starts_with(&"é".repeat(MAX_VERBOSE / 2)));
+    assert!(cut.ends_with(&format!("… ({} more bytes)", MAX_VERBOSE)));
+  }
+}